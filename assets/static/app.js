@@ -0,0 +1,11 @@
+"use strict";
+
+const events = new EventSource("/events");
+
+events.addEventListener("status", (event) => {
+  const status = JSON.parse(event.data);
+  document.getElementById("time").textContent =
+    status.time ? status.time.iso8601 : "unsynchronized";
+  document.getElementById("uptime").textContent = `${status.uptime} s`;
+  document.getElementById("free-heap").textContent = `${status.free_heap} bytes`;
+});
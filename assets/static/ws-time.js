@@ -0,0 +1,8 @@
+const time = document.getElementById("time");
+const socket = new WebSocket(`ws://${location.host}/ws/time`);
+socket.onmessage = (event) => {
+  time.textContent = JSON.parse(event.data).iso8601;
+};
+socket.onclose = () => {
+  time.textContent = "Disconnected";
+};
@@ -4,20 +4,127 @@ fn main() {
         println!("cargo:warning=No .env file found");
     }
 
+    wifi_credentials();
+    admin_credentials();
+
     // Pass environment variables to the compilation
-    if let Ok(ssid) = std::env::var("SSID") {
-        println!("cargo:rustc-env=SSID={}", ssid);
+
+    if let Ok(offset) = std::env::var("TEMPERATURE_OFFSET") {
+        println!("cargo:rustc-env=TEMPERATURE_OFFSET={}", offset);
+    }
+
+    if let Ok(contact) = std::env::var("SECURITY_CONTACT") {
+        println!("cargo:rustc-env=SECURITY_CONTACT={}", contact);
     }
-    
-    if let Ok(password) = std::env::var("PASSWORD") {
-        println!("cargo:rustc-env=PASSWORD={}", password);
+
+    if let Ok(expires) = std::env::var("SECURITY_EXPIRES") {
+        println!("cargo:rustc-env=SECURITY_EXPIRES={}", expires);
     }
 
+    compress_static_assets();
+
     linker_be_nice();
     // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
     println!("cargo:rustc-link-arg=-Tlinkall.x");
 }
 
+/// Pass the Wi-Fi credentials to the compilation, failing the build when
+/// the device could not join the network with them
+///
+/// Without an SSID the device serves the provisioning form instead. The
+/// `SSID` and `PASSWORD` variables of older `.env` files are still read.
+fn wifi_credentials() {
+    for name in ["WIFI_SSID", "WIFI_PASSWORD", "SSID", "PASSWORD"] {
+        println!("cargo:rerun-if-env-changed={}", name);
+    }
+
+    let variable = |name: &str, legacy: &str| {
+        let value = std::env::var(name).ok().or_else(|| {
+            let value = std::env::var(legacy).ok()?;
+            println!("cargo:warning={} is deprecated, rename it to {}", legacy, name);
+            Some(value)
+        });
+        value.filter(|value| !value.is_empty())
+    };
+    let ssid = variable("WIFI_SSID", "SSID");
+    let password = variable("WIFI_PASSWORD", "PASSWORD");
+
+    let Some(ssid) = ssid else {
+        if password.is_some() {
+            panic!("WIFI_PASSWORD is set without WIFI_SSID, set both or neither in .env");
+        }
+        println!("cargo:warning=WIFI_SSID is not set, the device will start a provisioning access point");
+        return;
+    };
+    if ssid.len() > 32 {
+        panic!("WIFI_SSID must be at most 32 bytes long, it is {} bytes", ssid.len());
+    }
+    let password = password.unwrap_or_default();
+    if !password.is_empty() && !(8..=64).contains(&password.len()) {
+        panic!("WIFI_PASSWORD must be 8 to 64 characters, or empty for an open network");
+    }
+
+    println!("cargo:rustc-env=WIFI_SSID={}", ssid);
+    println!("cargo:rustc-env=WIFI_PASSWORD={}", password);
+}
+
+/// Pass the credentials of the admin routes to the compilation, failing the
+/// build when either is missing
+///
+/// There is no default, so a device is never flashed with a well-known
+/// password.
+fn admin_credentials() {
+    for name in ["ADMIN_USERNAME", "ADMIN_PASSWORD"] {
+        println!("cargo:rerun-if-env-changed={}", name);
+        let value = std::env::var(name).unwrap_or_default();
+        if value.is_empty() {
+            panic!("{} is not set, set it in .env to protect the admin routes", name);
+        }
+        println!("cargo:rustc-env={}={}", name, value);
+    }
+}
+
+/// Write a gzip-compressed copy and a weak ETag of every static asset to
+/// `OUT_DIR/static`
+fn compress_static_assets() {
+    use std::io::Write as _;
+
+    let source = std::path::Path::new("assets/static");
+    let destination = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("static");
+    std::fs::create_dir_all(&destination).unwrap();
+
+    println!("cargo:rerun-if-changed={}", source.display());
+    for entry in std::fs::read_dir(source).unwrap() {
+        let path = entry.unwrap().path();
+        let content = std::fs::read(&path).unwrap();
+
+        let mut encoder =
+            flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&content).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut name = path.file_name().unwrap().to_owned();
+        name.push(".gz");
+        std::fs::write(destination.join(name), compressed).unwrap();
+
+        let etag = format!(
+            "W/\"{}-{:016x}\"",
+            std::env::var("CARGO_PKG_VERSION").unwrap(),
+            fnv1a(&content)
+        );
+        let mut name = path.file_name().unwrap().to_owned();
+        name.push(".etag");
+        std::fs::write(destination.join(name), etag).unwrap();
+    }
+}
+
+/// Compute the 64-bit FNV-1a hash of some data
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
 fn linker_be_nice() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 1 {
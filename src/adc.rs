@@ -0,0 +1,99 @@
+//! Analog input readings served at `/api/v1/adc`
+//!
+//! A conversion takes time, so a task samples the ADC periodically and
+//! records the readings here. Handlers only read the cached values.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+use heapless::HistoryBuffer;
+use serde::Serialize;
+
+/// Number of readings kept for averaging
+pub const ADC_HISTORY_SIZE: usize = 32;
+
+/// Period between two readings
+pub const ADC_SAMPLING_PERIOD: Duration = Duration::from_millis(100);
+
+/// Largest raw reading of the 12-bit ADC
+const ADC_MAX_RAW: u32 = 4095;
+
+/// Input voltage of the largest raw reading with 11 dB attenuation
+const ADC_FULL_SCALE_MV: u32 = 2500;
+
+/// The most recent readings of an analog input
+pub struct AdcReadings {
+    history: Mutex<CriticalSectionRawMutex, RefCell<HistoryBuffer<u16, ADC_HISTORY_SIZE>>>,
+}
+
+impl AdcReadings {
+    /// Create an empty history
+    pub const fn new() -> Self {
+        Self {
+            history: Mutex::new(RefCell::new(HistoryBuffer::new())),
+        }
+    }
+
+    /// Record a raw reading, dropping the oldest one when full
+    pub fn record(&self, raw: u16) {
+        self.history.lock(|history| history.borrow_mut().write(raw));
+    }
+
+    /// Average the `samples` most recent readings
+    ///
+    /// Return `None` before the first reading. Fewer readings are averaged if
+    /// not enough are available.
+    pub fn average(&self, samples: usize) -> Option<AdcReading> {
+        self.history.lock(|history| {
+            let history = history.borrow();
+            let count = samples.clamp(1, ADC_HISTORY_SIZE).min(history.len());
+            if count == 0 {
+                return None;
+            }
+            let sum: u32 = history
+                .oldest_ordered()
+                .rev()
+                .take(count)
+                .map(|&raw| u32::from(raw))
+                .sum();
+            Some(AdcReading::new(sum / count as u32, count))
+        })
+    }
+}
+
+impl Default for AdcReadings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A reading returned by `/api/v1/adc`
+#[derive(Serialize)]
+pub struct AdcReading {
+    /// Raw 12-bit value
+    pub raw: u16,
+
+    /// Approximate input voltage in millivolts
+    pub millivolts: u32,
+
+    /// Number of readings averaged
+    pub samples: usize,
+}
+
+impl AdcReading {
+    /// Convert a raw value with a linear approximation
+    ///
+    /// The approximation ignores the per-chip calibration and is only
+    /// accurate to a few percent.
+    fn new(raw: u32, samples: usize) -> Self {
+        let raw = raw.min(ADC_MAX_RAW);
+        Self {
+            #[expect(clippy::cast_possible_truncation, reason = "Raw value is at most 4095")]
+            raw: raw as u16,
+            millivolts: raw * ADC_FULL_SCALE_MV / ADC_MAX_RAW,
+            samples,
+        }
+    }
+}
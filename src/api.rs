@@ -0,0 +1,170 @@
+//! Versioned JSON API mounted at [`API_V1_PREFIX`]
+//!
+//! Every route of the group requires an API token, see [`ApiTokenLayer`],
+//! and its responses carry the [`SecurityHeaders::API`] headers.
+//! Adding a route only touches this module: the router below and
+//! [`ROUTES`], which the 405 responses, `/stats` and `/api` read along with
+//! the routes at the root.
+
+use heapless::String;
+use picoserve::extract::Query;
+use picoserve::response::StatusCode;
+use picoserve::routing::{self, PathRouter};
+use serde::{Deserialize, Serialize};
+
+use crate::gpio::PinAction;
+use crate::led::{LedMode, BLINK_PERIOD_RANGE};
+use crate::routes::{route_table, RouteInfo};
+use crate::security_headers::{SecurityHeaders, SecurityHeadersLayer};
+use crate::storage;
+#[cfg(feature = "temperature")]
+use crate::temperature;
+use crate::web::{
+    AdcExtractor, ApiError, ApiTokenLayer, AppState, Bytes, ClockExtractor, GpioExtractor, Json,
+    LedExtractor, NotFound, RequestIdExtractor, SettingsExtractor, StatusEvent, StorageExtractor,
+};
+
+/// Path the API group is mounted at
+pub const API_V1_PREFIX: &str = "/api/v1";
+
+/// Security headers of the API responses
+pub const SECURITY_HEADERS: SecurityHeadersLayer = SecurityHeadersLayer::new(SecurityHeaders::API);
+
+/// Routes of the API, with their full path
+pub const ROUTES: &[RouteInfo] = route_table![
+    "/api/v1/status" => "GET", "Device status";
+    "/api/v1/gpio/{pin}" => "GET", "Level of a GPIO output";
+    "/api/v1/gpio/{pin}/{state}" => "POST", "Set a GPIO output";
+    "/api/v1/led" => "GET", "LED mode";
+    "/api/v1/led/on" => "POST", "Turn the LED on";
+    "/api/v1/led/off" => "POST", "Turn the LED off";
+    "/api/v1/led/blink" => "POST", "Blink the LED";
+    "/api/v1/adc" => "GET", "Analog input reading";
+    "/api/v1/temperature" => "GET", "Chip temperature";
+    "/api/v1/kv/{key}" => "GET, PUT, DELETE", "Read, write or delete a stored value";
+];
+
+/// Query parameters of the `/led/blink` route
+#[derive(Deserialize)]
+pub struct BlinkQuery {
+    /// Duration of a full on/off cycle in milliseconds
+    pub period_ms: Option<u32>,
+}
+
+/// Query parameters of the `/adc` route
+#[derive(Deserialize)]
+pub struct AdcQuery {
+    /// Number of recent readings to average, at most
+    /// [`ADC_HISTORY_SIZE`](crate::adc::ADC_HISTORY_SIZE)
+    pub samples: Option<usize>,
+}
+
+/// JSON body returned by the `/gpio` routes
+#[derive(Serialize)]
+pub struct GpioResponse {
+    /// The pin number
+    pub pin: u8,
+
+    /// Whether the pin is set high
+    pub high: bool,
+}
+
+/// Convert a key-value store error to a response
+fn storage_error(error: storage::Error) -> ApiError {
+    match error {
+        storage::Error::InvalidKey => ApiError::bad_request("invalid_key").with_message("Invalid key"),
+        storage::Error::ValueTooLarge => {
+            ApiError::payload_too_large("value_too_large").with_message("Value too large")
+        }
+        storage::Error::Flash => ApiError::internal("flash_error").with_message("Flash error"),
+    }
+}
+
+/// The error returned for pins missing from the whitelist
+const UNKNOWN_PIN: ApiError = ApiError::not_found("unknown_pin").with_message("Unknown pin");
+
+/// The error returned when the board has no LED
+const NO_LED: ApiError = ApiError::not_found("no_led").with_message("No LED");
+
+/// The error returned before the first sensor reading
+const NO_READING: ApiError = ApiError::service_unavailable("no_reading").with_message("No reading yet");
+
+/// Build the routes of the API, relative to [`API_V1_PREFIX`]
+pub fn api_v1() -> picoserve::Router<impl PathRouter<AppState>, AppState> {
+    let router = picoserve::Router::from_service(NotFound)
+        .route("/status", routing::get(|ClockExtractor(clock)| async move {
+            Json(StatusEvent::current(&clock))
+        }))
+        .route(("/gpio", routing::parse_path_segment::<u8>()), routing::get(|pin: u8, GpioExtractor(gpio)| async move {
+            match gpio.is_set_high(pin) {
+                Some(high) => Ok(Json(GpioResponse { pin, high })),
+                None => Err(UNKNOWN_PIN),
+            }
+        }))
+        .route(
+            ("/gpio", routing::parse_path_segment::<u8>(), routing::parse_path_segment::<String<16>>()),
+            routing::post(|(pin, action): (u8, String<16>), GpioExtractor(gpio), RequestIdExtractor(request_id)| async move {
+                let Ok(action) = action.parse::<PinAction>() else {
+                    return Err(ApiError::bad_request("invalid_state").with_message("State must be on, off or toggle"));
+                };
+                match gpio.apply(pin, action) {
+                    Some(high) => {
+                        crate::log!("[{}] GPIO {} set to {}", request_id, pin, high);
+                        Ok(Json(GpioResponse { pin, high }))
+                    }
+                    None => Err(UNKNOWN_PIN),
+                }
+            }),
+        )
+        .route("/led", routing::get(|LedExtractor(led)| async move {
+            led.state().map(Json).ok_or(NO_LED)
+        }))
+        .route("/led/on", routing::post(|LedExtractor(led)| async move {
+            led.set_mode(LedMode::On).map(Json).ok_or(NO_LED)
+        }))
+        .route("/led/off", routing::post(|LedExtractor(led)| async move {
+            led.set_mode(LedMode::Off).map(Json).ok_or(NO_LED)
+        }))
+        .route("/led/blink", routing::post(|LedExtractor(led), SettingsExtractor(settings), Query(query): Query<BlinkQuery>| async move {
+            let period_ms = query.period_ms.unwrap_or_else(|| settings.blink_period_ms());
+            if !BLINK_PERIOD_RANGE.contains(&period_ms) {
+                return Err(ApiError::bad_request("invalid_period").with_message("period_ms must be between 50 and 10000"));
+            }
+            led.set_mode(LedMode::Blink { period_ms })
+                .map(Json)
+                .ok_or(NO_LED)
+        }))
+        .route("/adc", routing::get(|AdcExtractor(adc), Query(query): Query<AdcQuery>| async move {
+            adc.average(query.samples.unwrap_or(1))
+                .map(Json)
+                .ok_or(NO_READING)
+        }))
+        .route(
+            ("/kv", routing::parse_path_segment::<String<{ storage::MAX_KEY_SIZE }>>()),
+            routing::get(|key: String<{ storage::MAX_KEY_SIZE }>, StorageExtractor(storage)| async move {
+                let mut value = [0; storage::MAX_VALUE_SIZE];
+                match storage.get(&key, &mut value).await {
+                    Ok(Some(length)) => Ok(Bytes::<{ storage::MAX_VALUE_SIZE }>::from_slice(&value[..length])),
+                    Ok(None) => Err(ApiError::not_found("key_not_found").with_message("Key not found")),
+                    Err(error) => Err(storage_error(error)),
+                }
+            })
+            .put(|key: String<{ storage::MAX_KEY_SIZE }>, StorageExtractor(storage), Bytes::<{ storage::MAX_VALUE_SIZE }>(value)| async move {
+                storage.set(&key, &value).await.map_err(storage_error)?;
+                Ok::<_, ApiError>(StatusCode::NO_CONTENT)
+            })
+            .delete(|key: String<{ storage::MAX_KEY_SIZE }>, StorageExtractor(storage)| async move {
+                storage.delete(&key).await.map_err(storage_error)?;
+                Ok::<_, ApiError>(StatusCode::NO_CONTENT)
+            }),
+        );
+
+    #[cfg(feature = "temperature")]
+    let router = router.route("/temperature", routing::get(|| async move {
+        temperature::readings()
+            .map(Json)
+            .ok_or(NO_READING)
+    }));
+
+    router.layer(ApiTokenLayer).layer(SECURITY_HEADERS)
+}
@@ -10,12 +10,18 @@
 
 use embassy_executor::Spawner;
 use embassy_net::Stack;
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Ticker, Timer};
+use esp32c3_embassy_picoserve::adc::AdcReadings;
 use esp32c3_embassy_picoserve::clock::Clock;
+use esp32c3_embassy_picoserve::gpio::GpioOutputs;
 use esp32c3_embassy_picoserve::http::Client;
 use esp32c3_embassy_picoserve::random::RngWrapper;
+use esp_hal::analog::adc::{Adc, AdcConfig, AdcPin, Attenuation};
 use esp_hal::clock::CpuClock;
+use esp_hal::gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull};
+use esp_hal::peripherals::{ADC1, GPIO2};
 use esp_hal::rng::Rng;
+use esp_hal::Blocking;
 use esp_hal::rtc_cntl::Rtc;
 use esp_hal::timer::systimer::SystemTimer;
 use esp_hal::timer::timg::TimerGroup;
@@ -24,7 +30,6 @@ use rtt_target::rprintln;
 use esp_wifi::EspWifiController;
 
 use esp32c3_embassy_picoserve as lib;
-use time::UtcOffset;
 
 
 #[panic_handler]
@@ -52,6 +57,10 @@ async fn main(spawner: Spawner) {
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::_80MHz);
     let peripherals = esp_hal::init(config);
 
+    let boot_count = lib::boot::record_boot();
+    rprintln!("Boot {} after reset {:?}", boot_count, lib::boot::reset_reason());
+    lib::webhooks::notify(lib::webhooks::Event::Boot { boot_count });
+
     esp_alloc::heap_allocator!(size: 64 * 1024);
 
     let timer0 = SystemTimer::new(peripherals.SYSTIMER);
@@ -61,7 +70,7 @@ async fn main(spawner: Spawner) {
 
     let rng = esp_hal::rng::Rng::new(peripherals.RNG);
     let timer1 = TimerGroup::new(peripherals.TIMG0);
-    let _rtc = Rtc::new(peripherals.LPWR);
+    let rtc = &*lib::mk_static!(Rtc<'static>, Rtc::new(peripherals.LPWR));
 
     // let wifi_init = esp_wifi::init(timer1.timer0, rng, peripherals.RADIO_CLK)
     //     .expect("Failed to initialize WIFI/BLE controller");
@@ -73,6 +82,7 @@ async fn main(spawner: Spawner) {
 
     // Spawn a task to print "Hello world!" every second
     spawner.spawn(print_hello_world()).unwrap();
+    spawner.must_spawn(lib::heap::heap_monitor_task());
 
     // NEW code
     let esp_wifi_ctrl = &*lib::mk_static!(
@@ -80,7 +90,32 @@ async fn main(spawner: Spawner) {
         esp_wifi::init(timer1.timer0, rng.clone(), peripherals.RADIO_CLK).unwrap()
     );
 
-    let stack = lib::wifi::start_wifi(esp_wifi_ctrl, peripherals.WIFI, rng, &spawner).await;
+    let storage = &*lib::mk_static!(
+        lib::storage::Storage,
+        lib::storage::Storage::open(esp_storage::FlashStorage::new()).await
+    );
+
+    // The password of the provisioning access point is generated on first
+    // boot
+    lib::provisioning::load_access_point_password(storage, &mut RngWrapper::from(rng)).await;
+
+    // Networks provisioned from a browser take precedence over the one set
+    // at build time in `WIFI_SSID` and `WIFI_PASSWORD`, without any the
+    // device serves the provisioning form. It also does for a while after
+    // failing to connect to all of them too many times in a row.
+    let wifi_config = if lib::wifi::take_provisioning_fallback() {
+        rprintln!("Could not connect before the last restart, starting provisioning");
+        lib::wifi::WifiConfig::fallback(storage)
+    } else {
+        lib::wifi::WifiConfig::load(storage).await
+    };
+    let wifi_config = &*lib::mk_static!(lib::wifi::WifiConfig, wifi_config);
+
+    let wifi =
+        lib::wifi::start_wifi(esp_wifi_ctrl, peripherals.WIFI, rng, wifi_config, &spawner).await;
+    let stack = wifi.stack;
+    let wifi_mode = wifi.mode;
+    rprintln!("Wi-Fi running as {:?}", wifi_mode);
 
     rprintln!("Starting RTC...");
 
@@ -92,21 +127,148 @@ async fn main(spawner: Spawner) {
     .await;
 
     rprintln!("Now is {}", clock.now().unwrap());
+    lib::log_sink::set_clock(clock.clone());
+    spawner.must_spawn(lib::restart::restart_task(clock.clone()));
 
     // let web_app = lib::web::WebApp::default(clock.clone());
-    let web_app = lib::web::WebApp::new_with_clock(clock.clone(), stack);
+    // Output pins that can be driven over HTTP
+    let gpio = GpioOutputs::new()
+        .with_pin(4, Output::new(peripherals.GPIO4, Level::Low, OutputConfig::default()))
+        .with_pin(5, Output::new(peripherals.GPIO5, Level::Low, OutputConfig::default()));
+
+    #[cfg(feature = "tls")]
+    lib::tls::init(peripherals.SHA);
+
+    let files = &*lib::mk_static!(
+        lib::files::FileStore,
+        lib::files::FileStore::new(esp_storage::FlashStorage::new())
+    );
+
+    let hardware = lib::web::Hardware {
+        gpio,
+        led: Some(Output::new(peripherals.GPIO8, Level::Low, OutputConfig::default())),
+        storage: Some(storage),
+        files: Some(files),
+        rtc: Some(rtc),
+    };
+
+    let web_app = lib::web::WebApp::builder()
+        .clock(clock.clone())
+        .hardware(hardware)
+        .port(if cfg!(feature = "tls") { 443 } else { 80 })
+        .captive_portal(wifi_mode == lib::wifi::WifiMode::AccessPoint)
+        .build(stack, RngWrapper::from(rng));
+    // Apply the configuration saved by `PUT /config`
+    web_app.state.config().load().await;
+    spawner.must_spawn(lib::led::led_task(web_app.state.led));
+    spawner.must_spawn(lib::scheduler::scheduler_task(
+        web_app.state.schedule,
+        clock.clone(),
+        web_app.state.gpio,
+    ));
+
+    // Webhook deliveries and clock synchronizations requested at
+    // `/time/sync` take turns on a client, one TLS buffer pair is enough
+    let http_client = &*lib::mk_static!(
+        lib::http::SharedClient,
+        lib::http::SharedClient::new(Client::new(stack, RngWrapper::from(rng)))
+    );
+    // Both pause while the station has no address
+    // Cannot fail, these are the first receivers held past `start_wifi`
+    spawner.must_spawn(lib::time_sync::time_sync_task(
+        http_client,
+        clock.clone(),
+        lib::wifi::state_receiver().unwrap(),
+    ));
+    spawner.must_spawn(lib::webhooks::webhook_task(
+        web_app.state.webhooks,
+        http_client,
+        lib::wifi::state_receiver().unwrap(),
+        web_app.state.stats,
+    ));
+
+    // The BOOT button, reported to the webhooks when pressed and released
+    let button = Input::new(peripherals.GPIO9, InputConfig::default().with_pull(Pull::Up));
+    spawner.must_spawn(gpio_input_task(button, 9));
+
+    // Analog input sampled for `/adc`, change the pin here and in `adc_task`
+    let mut adc_config = AdcConfig::new();
+    let adc_pin = adc_config.enable_pin(peripherals.GPIO2, Attenuation::_11dB);
+    let adc = Adc::new(peripherals.ADC1, adc_config);
+    spawner.must_spawn(adc_task(adc, adc_pin, web_app.state.adc));
+
+    #[cfg(feature = "temperature")]
+    {
+        let sensor = esp_hal::tsens::TemperatureSensor::new(
+            peripherals.TSENS,
+            esp_hal::tsens::Config::default(),
+        )
+        .unwrap();
+        spawner.must_spawn(lib::temperature::temperature_task(sensor));
+    }
 
-    for id in 0..lib::web::WEB_TASK_POOL_SIZE {
+    let buffers: &'static mut [lib::web::TaskBuffers; lib::web::WEB_TASK_POOL_SIZE] =
+        web_app.buffers;
+    for (id, buffers) in buffers.iter_mut().enumerate() {
         spawner.must_spawn(lib::web::web_task(
             id,
             stack,
             web_app.router,
             web_app.config,
             web_app.state,
+            buffers,
+            web_app.port,
         ));
     }
+
+    // A second group of tasks serves the admin surface (settings, restart,
+    // token rotation) on its own port when one is set with
+    // `.admin_port(8080)` above, sharing the state with the first group.
+    // The main port then answers 404 on the admin paths, so a firewall rule
+    // on the admin port is enough to restrict them.
+    if let Some(admin_port) = web_app.admin_port {
+        let admin_buffers: &'static mut [lib::web::TaskBuffers; lib::web::ADMIN_TASK_POOL_SIZE] =
+            web_app.admin_buffers;
+        for (index, buffers) in admin_buffers.iter_mut().enumerate() {
+            spawner.must_spawn(lib::web::admin_web_task(
+                lib::web::WEB_TASK_POOL_SIZE + index,
+                stack,
+                web_app.admin_router,
+                web_app.config,
+                web_app.state,
+                buffers,
+                admin_port,
+            ));
+        }
+    }
+    // With the `ap-sta` feature the access point of the device stays up
+    // while the station is connected, and its own task serves the web app on
+    // it at 192.168.4.1
+    #[cfg(feature = "ap-sta")]
+    if let Some(access_point) = wifi.access_point {
+        let access_point_buffers: &'static mut [
+            lib::web::TaskBuffers;
+            lib::web::ACCESS_POINT_TASK_POOL_SIZE
+        ] = web_app.access_point_buffers;
+        for (index, buffers) in access_point_buffers.iter_mut().enumerate() {
+            spawner.must_spawn(lib::web::access_point_web_task(
+                lib::web::WEB_TASK_POOL_SIZE + lib::web::ADMIN_TASK_POOL_SIZE + index,
+                access_point,
+                web_app.router,
+                web_app.config,
+                web_app.state,
+                buffers,
+                web_app.port,
+            ));
+        }
+    }
     rprintln!("Web server started...");
 
+    // Answer as `<hostname>.local` on the network the station joined
+    if wifi_mode == lib::wifi::WifiMode::Station {
+        spawner.must_spawn(lib::mdns::mdns_task(stack, web_app.state.settings, web_app.port));
+    }
+
     // loop {
     //     rprintln!("Hello world!");
     //     Timer::after(Duration::from_secs(1)).await;
@@ -123,6 +285,50 @@ async fn print_hello_world() {
     }
 }
 
+/// Sample the analog input periodically for `/adc`
+#[embassy_executor::task]
+async fn adc_task(
+    mut adc: Adc<'static, ADC1<'static>, Blocking>,
+    mut pin: AdcPin<GPIO2<'static>, ADC1<'static>>,
+    readings: &'static AdcReadings,
+) {
+    let mut ticker = Ticker::every(lib::adc::ADC_SAMPLING_PERIOD);
+    loop {
+        lib::heartbeat!("adc_task");
+
+        // The conversion is polled so other tasks can run meanwhile
+        let raw = loop {
+            if let Ok(raw) = adc.read_oneshot(&mut pin) {
+                break raw;
+            }
+            embassy_futures::yield_now().await;
+        };
+        readings.record(raw);
+        ticker.next().await;
+    }
+}
+
+/// Time a GPIO input must stay at a level before a change is reported
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(50);
+
+/// Report the level changes of a GPIO input to the webhooks
+#[embassy_executor::task]
+async fn gpio_input_task(mut input: Input<'static>, pin: u8) {
+    let mut high = input.is_high();
+    loop {
+        lib::heartbeat!("gpio_input_task");
+        lib::tasks::beating("gpio_input_task", None, input.wait_for_any_edge()).await;
+
+        // Ignore bounces shorter than the debounce delay
+        Timer::after(DEBOUNCE_DELAY).await;
+        let level = input.is_high();
+        if level != high {
+            high = level;
+            lib::webhooks::notify(lib::webhooks::Event::GpioInput { pin, high });
+        }
+    }
+}
+
 // #[embassy_executor::task]
 // async fn rtc_set_current_date(mut lpwr: LPWR, current_time_us: u64) {
 //     let mut rtc = Rtc::new(&mut lpwr);
@@ -135,23 +341,27 @@ async fn load_clock(
     stack: Stack<'static>,
     rng: Rng,
 ) -> Clock {
-    // let clock = if let Some(clock) = Clock::from_rtc_memory() {
-    //     rprintln!("Clock loaded from RTC memory");
-    //     clock
-    // } else {
-        rprintln!("Synchronize clock from server");
-        let mut http_client = Client::new(stack, RngWrapper::from(rng));
-        let clock = Clock::from_server(&mut http_client).await;
-
-        if let Err(e) = clock {
-            rprintln!("Failed to synchronize clock: {:?}", e);
-            // Fallback to a default clock
-            return Clock::new(0, UtcOffset::UTC);
-        } else {
-            rprintln!("Clock synchronized from server");
-            return clock.unwrap();
-        }
-    // };
+    // The clock is saved to RTC memory before a restart requested over HTTP
+    if let Some(clock) = Clock::from_rtc_memory() {
+        rprintln!("Clock loaded from RTC memory");
+        return clock;
+    }
+
+    // The provisioning access point has no route to the time server
+    if lib::provisioning::is_active() {
+        return Clock::unsynchronized();
+    }
 
-    // clock
+    rprintln!("Synchronize clock from server");
+    let mut http_client = Client::new(stack, RngWrapper::from(rng));
+    let clock = Clock::from_server(&mut http_client).await;
+
+    if let Err(e) = clock {
+        rprintln!("Failed to synchronize clock: {:?}", e);
+        // Fallback to a default clock
+        Clock::unsynchronized()
+    } else {
+        rprintln!("Clock synchronized from server");
+        clock.unwrap()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,54 @@
+//! Boot counter kept in RTC Fast memory
+//!
+//! The counter survives software resets, watchdog resets and deep sleep, but
+//! not a power loss.
+
+use esp_hal::ram;
+use esp_hal::rtc_cntl::SocResetReason;
+use esp_hal::system::Cpu;
+use portable_atomic::{AtomicU32, Ordering};
+
+/// Marker of an initialized boot counter, the memory is random after power-up
+const BOOT_COUNTER_MAGIC: u32 = 0xB007_C0DE;
+
+/// Number of boots since the last power-up, with its initialization marker
+///
+/// This is placed in the RTC Fast memory and is not initialized at boot.
+#[ram(rtc_fast, persistent)]
+static mut BOOT_COUNTER: (u32, u32) = (0, 0);
+
+/// Number of boots since power-up, copied from RTC memory at boot
+static BOOT_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Increment the boot counter
+///
+/// This must be called once, early in `main`.
+pub fn record_boot() -> u32 {
+    let power_on = matches!(reset_reason(), Some(SocResetReason::ChipPowerOn) | None);
+
+    // SAFETY:
+    // This runs once before any task is spawned
+    let count = unsafe {
+        let (magic, count) = BOOT_COUNTER;
+        let count = if power_on || magic != BOOT_COUNTER_MAGIC {
+            1
+        } else {
+            count.saturating_add(1)
+        };
+        BOOT_COUNTER = (BOOT_COUNTER_MAGIC, count);
+        count
+    };
+
+    BOOT_COUNT.store(count, Ordering::Relaxed);
+    count
+}
+
+/// Return the number of boots since power-up
+pub fn boot_count() -> u32 {
+    BOOT_COUNT.load(Ordering::Relaxed)
+}
+
+/// Return the reason of the last reset
+pub fn reset_reason() -> Option<SocResetReason> {
+    esp_hal::rtc_cntl::reset_reason(Cpu::ProCpu)
+}
@@ -0,0 +1,121 @@
+//! Short-lived cache of JSON responses of expensive routes
+//!
+//! Picoserve layers cannot read the body of a response, so a cached route
+//! stores its JSON body itself with [`ResponseCache::store`]. The cache layer
+//! of the web server then answers `GET` requests to the route from the cache
+//! until the entry expires, without calling the handler, and drops the entry
+//! on any other request to the route.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use serde::Serialize;
+
+/// Maximal number of cached routes
+pub const MAX_CACHED_ROUTES: usize = 4;
+
+/// Maximal size of a cached body, larger bodies are not cached
+pub const CACHE_BODY_SIZE: usize = 512;
+
+/// Time a response is served from the cache by default
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(2);
+
+/// Routes cached by default
+pub const DEFAULT_CACHED_ROUTES: &[CachedRoute] = &[CachedRoute::new("/wifi", DEFAULT_CACHE_TTL)];
+
+/// A route whose responses are cached
+#[derive(Clone, Copy, Debug)]
+pub struct CachedRoute {
+    /// Path of the route, without parameters
+    pub path: &'static str,
+
+    /// Time a response is served from the cache
+    pub ttl: Duration,
+}
+
+impl CachedRoute {
+    /// Cache the responses of a route for `ttl`
+    pub const fn new(path: &'static str, ttl: Duration) -> Self {
+        Self { path, ttl }
+    }
+}
+
+/// A cached body
+struct Entry {
+    stored_at: Instant,
+    body: Vec<u8, CACHE_BODY_SIZE>,
+}
+
+/// The cached responses, one entry per cached route
+pub struct ResponseCache {
+    routes: &'static [CachedRoute],
+    entries: Mutex<CriticalSectionRawMutex, RefCell<[Option<Entry>; MAX_CACHED_ROUTES]>>,
+}
+
+impl ResponseCache {
+    /// Create an empty cache for some routes
+    ///
+    /// Routes past [`MAX_CACHED_ROUTES`] are not cached.
+    pub fn new(routes: &'static [CachedRoute]) -> Self {
+        debug_assert!(routes.len() <= MAX_CACHED_ROUTES, "Too many cached routes");
+        Self {
+            routes: &routes[..routes.len().min(MAX_CACHED_ROUTES)],
+            entries: Mutex::new(RefCell::new([const { None }; MAX_CACHED_ROUTES])),
+        }
+    }
+
+    /// Check whether the responses to a path are cached
+    pub fn is_cached(&self, path: &str) -> bool {
+        self.index(path).is_some()
+    }
+
+    /// Store the JSON body of the response to a path
+    ///
+    /// Nothing is stored if the path is not cached or the body is larger
+    /// than [`CACHE_BODY_SIZE`].
+    pub fn store<T: Serialize>(&self, path: &str, value: &T) {
+        let Some(index) = self.index(path) else {
+            return;
+        };
+        let mut body = Vec::new();
+        // The serializer needs the buffer to be initialized
+        let _ = body.resize_default(CACHE_BODY_SIZE);
+        let Ok(length) = serde_json_core::to_slice(value, &mut body) else {
+            return;
+        };
+        body.truncate(length);
+        self.entries.lock(|entries| {
+            entries.borrow_mut()[index] = Some(Entry {
+                stored_at: Instant::now(),
+                body,
+            });
+        });
+    }
+
+    /// Return the cached body of the response to a path, unless it expired
+    pub fn get(&self, path: &str) -> Option<Vec<u8, CACHE_BODY_SIZE>> {
+        let index = self.index(path)?;
+        let ttl = self.routes[index].ttl;
+        self.entries.lock(|entries| {
+            entries.borrow()[index]
+                .as_ref()
+                .filter(|entry| entry.stored_at.elapsed() < ttl)
+                .map(|entry| entry.body.clone())
+        })
+    }
+
+    /// Drop the cached response to a path
+    pub fn invalidate(&self, path: &str) {
+        if let Some(index) = self.index(path) {
+            self.entries.lock(|entries| entries.borrow_mut()[index] = None);
+        }
+    }
+
+    /// Return the index of the entry of a path
+    fn index(&self, path: &str) -> Option<usize> {
+        self.routes.iter().position(|route| route.path == path)
+    }
+}
@@ -0,0 +1,74 @@
+//! `Cache-Control` policies of the responses
+//!
+//! Each route group sets one policy: static assets in their
+//! [`ASSETS`](crate::static_assets::ASSETS) table, the API group in its
+//! [`SecurityHeaders`](crate::security_headers::SecurityHeaders) profile, and
+//! the dashboard on its route. The header is appended by a response writer,
+//! so it is also sent with the 304 responses of [`Conditional`] bodies.
+//!
+//! [`Conditional`]: crate::etag::Conditional
+
+use picoserve::io::Read;
+use picoserve::response::{Connection, IntoResponse, ResponseWriter};
+use picoserve::ResponseSent;
+
+use crate::etag::AppendHeaderWriter;
+
+/// A value of the `Cache-Control` header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CachePolicy(&'static str);
+
+impl CachePolicy {
+    /// Cached for a day without revalidation, for content-hashed assets
+    pub const IMMUTABLE: Self = Self("public, max-age=86400, immutable");
+
+    /// Cached but revalidated on every use, for pages
+    pub const NO_CACHE: Self = Self("no-cache");
+
+    /// Never cached, for API responses
+    pub const NO_STORE: Self = Self("no-store");
+
+    /// Use any header value
+    pub const fn custom(value: &'static str) -> Self {
+        Self(value)
+    }
+
+    /// Return the header value
+    pub const fn value(self) -> &'static str {
+        self.0
+    }
+}
+
+impl core::fmt::Display for CachePolicy {
+    fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        formatter.write_str(self.0)
+    }
+}
+
+/// A response sent with a `Cache-Control` header
+pub struct Cached<T> {
+    policy: CachePolicy,
+    response: T,
+}
+
+impl<T> Cached<T> {
+    /// Send a response with a policy
+    pub const fn new(policy: CachePolicy, response: T) -> Self {
+        Self { policy, response }
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Cached<T> {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let response_writer = AppendHeaderWriter {
+            name: "Cache-Control",
+            value: self.policy,
+            response_writer,
+        };
+        self.response.write_to(connection, response_writer).await
+    }
+}
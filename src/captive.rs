@@ -0,0 +1,43 @@
+//! Connectivity checks of phones and laptops joining the access point
+//!
+//! After joining a network, operating systems fetch a well-known URL and
+//! compare the answer with the one they expect from the internet. Any other
+//! answer makes them open a sign-in sheet on the page they were redirected
+//! to. In provisioning mode every probe is redirected to
+//! [`PROVISIONING_PAGE`], so the sheet shows it.
+//!
+//! The probes are sent to the vendor hosts, e.g.
+//! `connectivitycheck.gstatic.com`, so they only reach the device when its
+//! DNS server answers every name with its own address. The redirect is
+//! relative and stays on the device for the same reason.
+
+/// Page shown in the sign-in sheet, the form of [`crate::provisioning`]
+pub const PROVISIONING_PAGE: &str = crate::provisioning::PROVISIONING_PATH;
+
+/// Probe of Android and ChromeOS
+///
+/// The expected answer is `204 No Content` with an empty body. Any other
+/// status, including `200` with an empty body, is a captive portal.
+pub const ANDROID_PROBE: &str = "/generate_204";
+
+/// Probe of iOS and macOS, from `captive.apple.com`
+///
+/// The expected answer is `200 OK` with exactly
+/// `<HTML><HEAD><TITLE>Success</TITLE></HEAD><BODY>Success</BODY></HTML>`.
+/// Anything else, including a redirect, is a captive portal.
+pub const APPLE_PROBE: &str = "/hotspot-detect.html";
+
+/// Probe of Windows 10 and later, from `www.msftconnecttest.com`
+///
+/// The expected answer is `200 OK` with exactly `Microsoft Connect Test`.
+/// Windows only opens the browser on a redirect.
+pub const WINDOWS_PROBE: &str = "/connecttest.txt";
+
+/// Probe of Windows 7 and 8, from `www.msftncsi.com`
+///
+/// The expected answer is `200 OK` with exactly `Microsoft NCSI`. Windows
+/// only opens the browser on a redirect.
+pub const WINDOWS_NCSI_PROBE: &str = "/ncsi.txt";
+
+/// Every probe path, which must not need normalizing
+pub const PROBE_PATHS: &[&str] = &[ANDROID_PROBE, APPLE_PROBE, WINDOWS_PROBE, WINDOWS_NCSI_PROBE];
@@ -0,0 +1,60 @@
+//! Sequence number of the device state, for long polling
+//!
+//! The number is bumped with [`notify`] whenever a GPIO output, a setting or
+//! the WiFi association changes. Long-polling clients wait with
+//! [`wait_for_change`] until it differs from the last number they saw.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::Watch;
+use embassy_time::{with_timeout, Duration, Timer};
+use portable_atomic::{AtomicU32, Ordering};
+
+use crate::web::WEB_TASK_POOL_SIZE;
+
+/// Longest wait of a long poll
+///
+/// This stays below the 30 second timeout of common HTTP clients and
+/// proxies.
+pub const MAX_POLL_WAIT: Duration = Duration::from_secs(25);
+
+/// The current sequence number
+static SEQUENCE: AtomicU32 = AtomicU32::new(0);
+
+/// Notifies pollers of the new sequence number, one receiver per web task
+static CHANGED: Watch<CriticalSectionRawMutex, u32, WEB_TASK_POOL_SIZE> = Watch::new();
+
+/// Record a change of the device state, waking every poller
+pub fn notify() {
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+    CHANGED.sender().send(sequence);
+}
+
+/// Return the current sequence number
+pub fn sequence() -> u32 {
+    SEQUENCE.load(Ordering::Relaxed)
+}
+
+/// Wait up to `timeout` for the sequence number to differ from `since`
+///
+/// Returns the new sequence number, or `None` on timeout. A number ahead of
+/// the current one, e.g. seen before a restart, differs as well and returns
+/// immediately.
+pub async fn wait_for_change(since: u32, timeout: Duration) -> Option<u32> {
+    // Subscribe before checking, so a change in between is not missed
+    let receiver = CHANGED.receiver();
+    let current = sequence();
+    if current != since {
+        return Some(current);
+    }
+    match receiver {
+        Some(mut receiver) => with_timeout(timeout, receiver.changed_and(|sequence| *sequence != since))
+            .await
+            .ok(),
+        None => {
+            // Cannot happen with one poller per web task, but fall back to a
+            // plain wait rather than failing
+            Timer::after(timeout).await;
+            Some(sequence()).filter(|&current| current != since)
+        }
+    }
+}
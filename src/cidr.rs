@@ -0,0 +1,60 @@
+//! IPv4 address ranges in CIDR notation, for allowlists
+
+use core::fmt;
+use core::net::Ipv4Addr;
+
+/// The private ranges of RFC 1918 and the link-local range
+pub const LAN_RANGES: &[Cidr] = &[
+    Cidr::new(Ipv4Addr::new(10, 0, 0, 0), 8),
+    Cidr::new(Ipv4Addr::new(172, 16, 0, 0), 12),
+    Cidr::new(Ipv4Addr::new(192, 168, 0, 0), 16),
+    Cidr::new(Ipv4Addr::new(169, 254, 0, 0), 16),
+];
+
+/// An IPv4 address range, like `192.168.0.0/16`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    address: Ipv4Addr,
+    prefix_length: u8,
+}
+
+impl Cidr {
+    /// Create a range of the addresses sharing the first `prefix_length`
+    /// bits of `address`
+    ///
+    /// Prefix lengths above 32 are clamped to 32. The host bits of `address`
+    /// are ignored.
+    pub const fn new(address: Ipv4Addr, prefix_length: u8) -> Self {
+        let prefix_length = if prefix_length > 32 { 32 } else { prefix_length };
+        Self {
+            address,
+            prefix_length,
+        }
+    }
+
+    /// Check whether an address is in the range
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        let mask = self.mask();
+        u32::from(address) & mask == u32::from(self.address) & mask
+    }
+
+    /// Return the network mask, all ones in the prefix
+    fn mask(&self) -> u32 {
+        // Shifting a u32 by 32 overflows, so /0 is handled on its own
+        match self.prefix_length {
+            0 => 0,
+            length => u32::MAX << (32 - u32::from(length)),
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+/// Check whether an address is in any range of an allowlist
+pub fn is_allowed(allowlist: &[Cidr], address: Ipv4Addr) -> bool {
+    allowlist.iter().any(|range| range.contains(address))
+}
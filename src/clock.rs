@@ -8,6 +8,11 @@
 
 //! Data types and function for keeping time and synchronizing clock
 
+use portable_atomic::AtomicBool;
+use portable_atomic::AtomicI32;
+use portable_atomic::AtomicU64;
+use portable_atomic::Ordering;
+
 use embassy_time::Duration;
 use embassy_time::Instant;
 
@@ -30,23 +35,69 @@ use crate::http::Client as HttpClient;
 #[ram(rtc_fast)]
 static mut BOOT_TIME: (u64, i32, u64) = (0, 0, 0);
 
+/// The time offset in seconds, shared by all clones of the clock
+///
+/// The offset can be changed at runtime, e.g. from the web server, and the
+/// change must be visible to every task holding a [`Clock`].
+static UTC_OFFSET: AtomicI32 = AtomicI32::new(0);
+
+/// The boot time in Unix epoch, shared by all clones of the clock
+///
+/// Like the offset, it changes when the clock is synchronized again at
+/// runtime.
+static BOOT_TIME_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the time was obtained from a trusted source
+static SYNCHRONIZED: AtomicBool = AtomicBool::new(false);
+
 /// A clock
+///
+/// Clones share the time, so a synchronization is seen by every task.
 #[derive(Clone, Debug)]
 pub struct Clock {
-    /// The boot time in Unix epoch
-    boot_time: u64,
-
-    /// The time offset
-    offset: UtcOffset,
+    _private: (),
 }
 
 impl Clock {
     /// Create a new clock
-    pub fn new(current_time: u64, offset: UtcOffset) -> Self {
+    ///
+    /// The offset is shared by every clock and left untouched, only
+    /// [`Clock::set_offset`] changes it.
+    pub fn new(current_time: u64) -> Self {
+        let clock = Self { _private: () };
+        clock.set_time(current_time);
+        clock
+    }
+
+    /// Create a clock counting from the Unix epoch at boot
+    ///
+    /// This is used as a fallback when the time cannot be synchronized, and
+    /// is synchronized as soon as [`Clock::set_time`] is called.
+    pub fn unsynchronized() -> Self {
+        Self { _private: () }
+    }
+
+    /// Return whether the time was obtained from a trusted source
+    pub fn is_synchronized(&self) -> bool {
+        SYNCHRONIZED.load(Ordering::Relaxed)
+    }
+
+    /// Set the current time in Unix epoch, obtained from a trusted source
+    pub fn set_time(&self, current_time: u64) {
         let from_boot = Instant::now().as_secs();
-        let boot_time = current_time - from_boot;
+        BOOT_TIME_EPOCH.store(current_time.saturating_sub(from_boot), Ordering::Relaxed);
+        SYNCHRONIZED.store(true, Ordering::Relaxed);
+    }
 
-        Self { boot_time, offset }
+    /// Return the current time offset
+    pub fn offset(&self) -> UtcOffset {
+        UtcOffset::from_whole_seconds(UTC_OFFSET.load(Ordering::Relaxed))
+            .unwrap_or(UtcOffset::UTC)
+    }
+
+    /// Change the time offset
+    pub fn set_offset(&self, offset: UtcOffset) {
+        UTC_OFFSET.store(offset.whole_seconds(), Ordering::Relaxed);
     }
 
     /// Return the current time
@@ -55,12 +106,15 @@ impl Clock {
         #[expect(clippy::cast_possible_wrap, reason = "Timestamp will fit an i64")]
         let utc = OffsetDateTime::from_unix_timestamp(epoch as i64)?;
         let local = utc
-            .checked_to_offset(self.offset)
+            .checked_to_offset(self.offset())
             .ok_or(Error::InvalidInOffset)?;
         Ok(local)
     }
 
     /// Create a new clock by synchronizing with a server
+    ///
+    /// The clock takes the offset of the server, until the configuration
+    /// loaded at boot sets its own.
     pub async fn from_server(
         http_client: &mut HttpClient,
         // stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
@@ -77,7 +131,8 @@ impl Clock {
 
         let offset = now.offset();
 
-        let clock = Self::new(current_time, offset);
+        let clock = Self::new(current_time);
+        clock.set_offset(offset);
 
         // Save the clock to RTC memory
         clock.save_to_rtc_memory(Duration::from_secs(0));
@@ -95,7 +150,11 @@ impl Clock {
         if now == 0 {
             None
         } else {
-            offset.map(|offset| Self::new(now, offset))
+            offset.map(|offset| {
+                let clock = Self::new(now);
+                clock.set_offset(offset);
+                clock
+            })
         }
     }
 
@@ -103,7 +162,7 @@ impl Clock {
     pub fn save_to_rtc_memory(&self, expected_sleep_duration: Duration) {
         let now = self.now_as_epoch();
         let then = now + expected_sleep_duration.as_secs();
-        let offset_in_seconds = self.offset.whole_seconds();
+        let offset_in_seconds = self.offset().whole_seconds();
         // SAFETY:
         // There is only one thread
         unsafe {
@@ -124,7 +183,15 @@ impl Clock {
     /// Return current time as a Unix epoch
     pub fn now_as_epoch(&self) -> u64 {
         let from_boot = Instant::now().as_secs();
-        self.boot_time + from_boot
+        BOOT_TIME_EPOCH.load(Ordering::Relaxed) + from_boot
+    }
+
+    /// Return current time as a Unix epoch in milliseconds
+    ///
+    /// The boot time is only known to the second, so this is precise but not
+    /// more accurate than [`Clock::now_as_epoch`].
+    pub fn now_as_epoch_ms(&self) -> u64 {
+        BOOT_TIME_EPOCH.load(Ordering::Relaxed) * 1000 + Instant::now().as_millis()
     }
 
     /// Return time since boot in seconds
@@ -0,0 +1,258 @@
+//! Content coding of dynamic responses
+//!
+//! Handlers wrap large bodies in [`Compressible`], which picks the coding
+//! from the [`Compression`] extractor: with the `gzip` feature, bodies of at
+//! least [`AppState::compression_threshold`] bytes sent to clients accepting
+//! gzip are compressed while they are written, see [`crate::gzip`]. Smaller
+//! bodies, other clients and responses started while the heap is short are
+//! sent as is.
+
+use picoserve::io::{ErrorType, Read, Write};
+use picoserve::response::chunked::{ChunkWriter, ChunkedResponse, Chunks, ChunksWritten};
+use picoserve::response::{
+    Connection, Content, IntoResponse, Response, ResponseWriter, StatusCode,
+};
+use picoserve::ResponseSent;
+
+#[cfg(feature = "gzip")]
+use crate::gzip::{Encoder, GzipWriter};
+use crate::static_assets::accepts_gzip;
+use crate::web::AppState;
+
+/// Smallest body compressed by default, smaller ones barely shrink
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+/// A response body written to a byte stream
+pub trait Body {
+    /// Return the media type of the body
+    fn content_type(&self) -> &'static str;
+
+    /// Return the length of the body, if known before writing it
+    fn content_length(&self) -> Option<usize>;
+
+    /// Return the largest length the body may have, to decide whether it is
+    /// worth compressing
+    fn size_hint(&self) -> usize {
+        self.content_length().unwrap_or(usize::MAX)
+    }
+
+    /// Write the body
+    async fn write_body<W: Write>(self, writer: W) -> Result<(), W::Error>;
+}
+
+/// A [`Content`] as a [`Body`], e.g. [`picoserve::response::Json`]
+pub struct ContentBody<C>(pub C);
+
+impl<C: Content> Body for ContentBody<C> {
+    fn content_type(&self) -> &'static str {
+        self.0.content_type()
+    }
+
+    fn content_length(&self) -> Option<usize> {
+        Some(self.0.content_length())
+    }
+
+    async fn write_body<W: Write>(self, writer: W) -> Result<(), W::Error> {
+        self.0.write_content(writer).await
+    }
+}
+
+/// A body of known length, sent with a `Content-Length` header
+struct SizedBody<B> {
+    body: B,
+    length: usize,
+}
+
+impl<B: Body> Content for SizedBody<B> {
+    fn content_type(&self) -> &'static str {
+        self.body.content_type()
+    }
+
+    fn content_length(&self) -> usize {
+        self.length
+    }
+
+    async fn write_content<W: Write>(self, writer: W) -> Result<(), W::Error> {
+        self.body.write_body(writer).await
+    }
+}
+
+/// A writer sending every write as a chunk
+pub struct ChunkSink<'a, W: Write>(pub &'a mut ChunkWriter<W>);
+
+impl<W: Write> ErrorType for ChunkSink<'_, W> {
+    type Error = W::Error;
+}
+
+impl<W: Write> Write for ChunkSink<'_, W> {
+    async fn write(&mut self, buffer: &[u8]) -> Result<usize, Self::Error> {
+        // An empty chunk would end the body
+        if !buffer.is_empty() {
+            self.0.write_chunk(buffer).await?;
+        }
+        Ok(buffer.len())
+    }
+}
+
+/// The coding of a response body
+pub enum Encoding {
+    /// Sent as is
+    Identity,
+
+    /// Compressed with an encoder
+    #[cfg(feature = "gzip")]
+    Gzip(Encoder),
+}
+
+/// An extractor of the coding allowed for the response
+pub struct Compression {
+    /// Smallest body compressed, `None` to never compress
+    threshold: Option<usize>,
+
+    /// Whether the client accepts gzip
+    accepts_gzip: bool,
+}
+
+impl Compression {
+    /// Allow compression of bodies of at least `threshold` bytes, if the
+    /// client accepts gzip
+    pub const fn new(threshold: Option<usize>, accepts_gzip: bool) -> Self {
+        Self {
+            threshold,
+            accepts_gzip,
+        }
+    }
+
+    /// Check whether a body of `size` bytes is to be compressed
+    ///
+    /// This is always false without the `gzip` feature.
+    pub fn applies(&self, size: usize) -> bool {
+        cfg!(feature = "gzip")
+            && self.accepts_gzip
+            && self.threshold.is_some_and(|threshold| size >= threshold)
+    }
+
+    /// Choose the coding of a body of at most `size` bytes
+    ///
+    /// Bodies are sent as is when the heap cannot spare an encoder.
+    pub fn encoding(&self, size: usize) -> Encoding {
+        if !self.applies(size) {
+            return Encoding::Identity;
+        }
+        #[cfg(feature = "gzip")]
+        if crate::gzip::heap_allows_encoder() {
+            if let Some(encoder) = Encoder::try_new() {
+                return Encoding::Gzip(encoder);
+            }
+        }
+        crate::log!(debug: "Heap too low to compress a response, sending it as is");
+        Encoding::Identity
+    }
+}
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for Compression {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        let accepts_gzip = request_parts
+            .headers()
+            .get("Accept-Encoding")
+            .and_then(|value| value.as_str().ok())
+            .is_some_and(accepts_gzip);
+        Ok(Self::new(state.compression_threshold, accepts_gzip))
+    }
+}
+
+/// A body written as chunks in a coding
+pub struct EncodedChunks<B> {
+    /// The body
+    pub body: B,
+
+    /// The coding of the body
+    pub encoding: Encoding,
+}
+
+impl<B: Body> Chunks for EncodedChunks<B> {
+    fn content_type(&self) -> &'static str {
+        self.body.content_type()
+    }
+
+    async fn write_chunks<W: Write>(
+        self,
+        mut chunk_writer: ChunkWriter<W>,
+    ) -> Result<ChunksWritten, W::Error> {
+        match self.encoding {
+            Encoding::Identity => self.body.write_body(ChunkSink(&mut chunk_writer)).await?,
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip(encoder) => {
+                let mut writer = GzipWriter::new(ChunkSink(&mut chunk_writer), encoder);
+                self.body.write_body(&mut writer).await?;
+                writer.finish().await?;
+            }
+        }
+        chunk_writer.finalize().await
+    }
+}
+
+/// A `200 OK` response compressed when [`Compression`] allows it
+///
+/// Compressed bodies are sent chunked, since their length is only known at
+/// the end. Others keep their `Content-Length` when they have one.
+pub struct Compressible<B> {
+    body: B,
+    compression: Compression,
+}
+
+impl<B: Body> Compressible<B> {
+    /// Send a body, compressed if allowed
+    pub fn new(body: B, compression: Compression) -> Self {
+        Self { body, compression }
+    }
+}
+
+impl<C: Content> Compressible<ContentBody<C>> {
+    /// Send a [`Content`], compressed if allowed
+    pub fn content(content: C, compression: Compression) -> Self {
+        Self::new(ContentBody(content), compression)
+    }
+}
+
+impl<B: Body> IntoResponse for Compressible<B> {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let body = self.body;
+        match self.compression.encoding(body.size_hint()) {
+            #[cfg(feature = "gzip")]
+            encoding @ Encoding::Gzip(_) => {
+                let response = ChunkedResponse::new(EncodedChunks { body, encoding })
+                    .into_response()
+                    .with_header("Content-Encoding", "gzip")
+                    .with_header("Vary", "Accept-Encoding");
+                response_writer.write_response(connection, response).await
+            }
+            Encoding::Identity => match body.content_length() {
+                Some(length) => {
+                    let response = Response::new(StatusCode::OK, SizedBody { body, length })
+                        .with_header("Vary", "Accept-Encoding");
+                    response_writer.write_response(connection, response).await
+                }
+                None => {
+                    let chunks = EncodedChunks {
+                        body,
+                        encoding: Encoding::Identity,
+                    };
+                    let response = ChunkedResponse::new(chunks)
+                        .into_response()
+                        .with_header("Vary", "Accept-Encoding");
+                    response_writer.write_response(connection, response).await
+                }
+            },
+        }
+    }
+}
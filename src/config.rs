@@ -0,0 +1,466 @@
+//! The mutable configuration of the device as a single JSON document
+//!
+//! `GET /config` returns a [`DeviceConfig`] gathered from the clock, the
+//! settings, the webhook registry and the session table. `PUT /config`
+//! replaces all of it at once: the document is validated as a whole, so
+//! either every field is applied or none, and fields missing from it are
+//! errors rather than defaults. Unknown fields are rejected with their name,
+//! so a misspelt key does not silently keep the old value.
+//!
+//! The scalar fields are saved in the key-value store under [`STORAGE_KEY`]
+//! and applied again by [`ConfigHandle::load`] at boot. The webhook URLs do
+//! not fit a single value and are saved by the registry itself. Tasks
+//! interested in changes wait on a [`receiver`].
+
+use core::fmt;
+use core::ops::RangeInclusive;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::{Receiver, Watch};
+use embassy_time::Duration;
+use heapless::{String, Vec};
+use picoserve::io::Read;
+use picoserve::response::{IntoResponse, ResponseWriter, StatusCode};
+use serde::de::{Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use time::UtcOffset;
+
+use crate::clock::Clock;
+use crate::led::{Led, BLINK_PERIOD_RANGE};
+use crate::session::SessionStore;
+use crate::settings::{is_valid_hostname, Settings, MAX_HOSTNAME_SIZE};
+use crate::storage::{self, Storage};
+use crate::web::{Json, SettingsUpdate, OFFSET_MINUTES_RANGE};
+use crate::webhooks::{self, Webhooks, MAX_URL_SIZE, MAX_WEBHOOKS};
+
+/// Key of the scalar fields in the key-value store
+pub const STORAGE_KEY: &str = "config";
+
+/// Largest `PUT /config` body accepted
+pub const MAX_CONFIG_BODY_SIZE: usize = 1024;
+
+/// Valid range for the session idle timeout, in minutes
+pub const SESSION_IDLE_MINUTES_RANGE: RangeInclusive<u16> = 1..=24 * 60;
+
+/// Maximal number of tasks waiting for changes
+pub const MAX_RECEIVERS: usize = 4;
+
+/// Fields of a [`DeviceConfig`] document
+const FIELDS: &[&str] = &[
+    "offset_minutes",
+    "hostname",
+    "blink_period_ms",
+    "webhooks",
+    "auth",
+];
+
+/// Field of a [`DeviceConfig`] document holding the [`AuthConfig`]
+const AUTH_FIELD: &str = "auth";
+
+/// Fields of an [`AuthConfig`] object
+const AUTH_FIELDS: &[&str] = &["session_idle_minutes"];
+
+/// Maximal length of a field name reported in errors, longer ones are cut
+const MAX_FIELD_PATH_SIZE: usize = 48;
+
+/// Size of the encoded scalar fields in the key-value store
+const ENCODED_SIZE: usize = 2 + 4 + 2 + 1 + MAX_HOSTNAME_SIZE;
+
+/// The last applied configuration
+static CONFIG: Watch<CriticalSectionRawMutex, DeviceConfig, MAX_RECEIVERS> = Watch::new();
+
+/// Return a receiver of the applied configurations
+///
+/// There are [`MAX_RECEIVERS`] receivers at most, `None` when they are all
+/// in use.
+pub fn receiver() -> Option<Receiver<'static, CriticalSectionRawMutex, DeviceConfig, MAX_RECEIVERS>>
+{
+    CONFIG.receiver()
+}
+
+/// The mutable configuration of the device
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    /// Offset from UTC in minutes
+    pub offset_minutes: i16,
+
+    /// Name of the device on the network
+    pub hostname: String<MAX_HOSTNAME_SIZE>,
+
+    /// Blink period of the LED in milliseconds
+    pub blink_period_ms: u32,
+
+    /// URLs the device events are POSTed to
+    pub webhooks: Vec<String<MAX_URL_SIZE>, MAX_WEBHOOKS>,
+
+    /// Authentication settings
+    pub auth: AuthConfig,
+}
+
+/// Authentication settings of a [`DeviceConfig`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// Minutes after which an unused login session expires
+    pub session_idle_minutes: u16,
+}
+
+impl DeviceConfig {
+    /// Check every field, and return the UTC offset
+    pub fn validate(&self) -> Result<UtcOffset, ConfigError> {
+        let offset = Some(self.offset_minutes)
+            .filter(|offset| OFFSET_MINUTES_RANGE.contains(offset))
+            .and_then(|offset| UtcOffset::from_whole_seconds(i32::from(offset) * 60).ok())
+            .ok_or(ConfigError::invalid(
+                "offset_minutes",
+                "offset_minutes must be between -720 and 840",
+            ))?;
+        if !is_valid_hostname(&self.hostname) {
+            return Err(ConfigError::invalid(
+                "hostname",
+                "hostname must be 1 to 32 letters, digits or inner hyphens",
+            ));
+        }
+        if !BLINK_PERIOD_RANGE.contains(&self.blink_period_ms) {
+            return Err(ConfigError::invalid(
+                "blink_period_ms",
+                "blink_period_ms must be between 50 and 10000",
+            ));
+        }
+        if !self.webhooks.iter().all(|url| webhooks::is_valid_url(url)) {
+            return Err(ConfigError::invalid(
+                "webhooks",
+                "webhooks must start with http:// or https://",
+            ));
+        }
+        if !SESSION_IDLE_MINUTES_RANGE.contains(&self.auth.session_idle_minutes) {
+            return Err(ConfigError::invalid(
+                "auth.session_idle_minutes",
+                "session_idle_minutes must be between 1 and 1440",
+            ));
+        }
+        Ok(offset)
+    }
+
+    /// Encode the scalar fields for the key-value store
+    pub fn encode(&self) -> Vec<u8, ENCODED_SIZE> {
+        let mut bytes = Vec::new();
+        // Cannot fail, the buffer fits every field
+        let _ = bytes.extend_from_slice(&self.offset_minutes.to_le_bytes());
+        let _ = bytes.extend_from_slice(&self.blink_period_ms.to_le_bytes());
+        let _ = bytes.extend_from_slice(&self.auth.session_idle_minutes.to_le_bytes());
+        #[expect(clippy::cast_possible_truncation, reason = "Hostnames are short")]
+        let _ = bytes.push(self.hostname.len() as u8);
+        let _ = bytes.extend_from_slice(self.hostname.as_bytes());
+        bytes
+    }
+
+    /// Decode the scalar fields encoded by [`DeviceConfig::encode`]
+    ///
+    /// The webhook URLs are not saved with them, and are left empty.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let (offset_minutes, bytes) = bytes.split_first_chunk()?;
+        let (blink_period_ms, bytes) = bytes.split_first_chunk()?;
+        let (session_idle_minutes, bytes) = bytes.split_first_chunk()?;
+        let (&length, hostname) = bytes.split_first()?;
+        let hostname = hostname.get(..usize::from(length))?;
+        Some(Self {
+            offset_minutes: i16::from_le_bytes(*offset_minutes),
+            hostname: String::try_from(core::str::from_utf8(hostname).ok()?).ok()?,
+            blink_period_ms: u32::from_le_bytes(*blink_period_ms),
+            webhooks: Vec::new(),
+            auth: AuthConfig {
+                session_idle_minutes: u16::from_le_bytes(*session_idle_minutes),
+            },
+        })
+    }
+}
+
+/// The parts of the device a [`DeviceConfig`] is read from and applied to
+pub struct ConfigHandle {
+    pub clock: Clock,
+    pub settings: &'static Settings,
+    pub led: &'static Led,
+    pub sessions: &'static SessionStore,
+    pub webhooks: &'static Webhooks,
+    pub storage: Option<&'static Storage>,
+}
+
+impl ConfigHandle {
+    /// Gather the current configuration
+    pub fn current(&self) -> DeviceConfig {
+        #[expect(clippy::cast_possible_truncation, reason = "Offsets are within a day")]
+        let offset_minutes = self.clock.offset().whole_minutes() as i16;
+        let minutes = self.sessions.idle_timeout().as_secs() / 60;
+        DeviceConfig {
+            offset_minutes,
+            hostname: self.settings.hostname(),
+            blink_period_ms: self.settings.blink_period_ms(),
+            webhooks: self
+                .webhooks
+                .urls()
+                .into_iter()
+                .map(|(_, url)| url)
+                .collect(),
+            auth: AuthConfig {
+                session_idle_minutes: u16::try_from(minutes).unwrap_or(u16::MAX),
+            },
+        }
+    }
+
+    /// Validate and apply a whole configuration, and save it
+    pub async fn replace(&self, config: DeviceConfig) -> Result<(), ConfigError> {
+        let offset = config.validate()?;
+        self.apply_scalars(&config, offset);
+        self.webhooks
+            .replace(&config.webhooks)
+            .await
+            .map_err(|_| ConfigError::storage())?;
+        self.save().await
+    }
+
+    /// Save the current scalar fields and notify the receivers
+    ///
+    /// Called after changes made through other routes as well, so they are
+    /// not reverted at the next boot.
+    pub async fn save(&self) -> Result<(), ConfigError> {
+        let config = self.current();
+        if let Some(storage) = self.storage {
+            storage
+                .set(STORAGE_KEY, &config.encode())
+                .await
+                .map_err(|_| ConfigError::storage())?;
+        }
+        CONFIG.sender().send(config);
+        Ok(())
+    }
+
+    /// Apply the scalar fields saved in the key-value store
+    ///
+    /// Saved values that are no longer valid are ignored.
+    pub async fn load(&self) {
+        if let Some(storage) = self.storage {
+            let mut value = [0; storage::MAX_VALUE_SIZE];
+            match storage.get(STORAGE_KEY, &mut value).await {
+                Ok(Some(length)) => match DeviceConfig::decode(&value[..length]) {
+                    Some(config) => match config.validate() {
+                        Ok(offset) => {
+                            self.apply_scalars(&config, offset);
+                            crate::log!("Loaded the configuration");
+                        }
+                        Err(error) => {
+                            crate::log!("Ignored the saved configuration: {}", error.message)
+                        }
+                    },
+                    None => crate::log!("Ignored the saved configuration: malformed"),
+                },
+                Ok(None) => {}
+                Err(error) => crate::log!("Failed to load the configuration: {:?}", error),
+            }
+        }
+        CONFIG.sender().send(self.current());
+    }
+
+    /// Apply every field but the webhook URLs
+    fn apply_scalars(&self, config: &DeviceConfig, offset: UtcOffset) {
+        let update = SettingsUpdate {
+            offset,
+            hostname: config.hostname.clone(),
+            blink_period_ms: config.blink_period_ms,
+        };
+        update.apply(&self.clock, self.settings, self.led);
+        let minutes = u64::from(config.auth.session_idle_minutes);
+        self.sessions
+            .set_idle_timeout(Duration::from_secs(minutes * 60));
+    }
+}
+
+/// Parse a `PUT /config` body
+///
+/// Unknown fields are looked for first, so they are reported by name even
+/// when the rest of the document is also wrong.
+pub fn parse_config(body: &[u8]) -> Result<DeviceConfig, ConfigError> {
+    if body.len() > MAX_CONFIG_BODY_SIZE {
+        return Err(ConfigError::too_large());
+    }
+    let (FieldScan(unknown), _) =
+        serde_json_core::from_slice::<FieldScan>(body).map_err(|_| ConfigError::malformed())?;
+    if let Some(field) = unknown {
+        return Err(ConfigError {
+            field: Some(field),
+            ..ConfigError::new(StatusCode::BAD_REQUEST, "unknown_field", "Unknown field")
+        });
+    }
+    let (config, _) = serde_json_core::from_slice(body).map_err(|_| ConfigError::malformed())?;
+    Ok(config)
+}
+
+/// An extractor for a `PUT /config` body, see [`parse_config`]
+pub struct ConfigBody(pub DeviceConfig);
+
+impl<'r, State> picoserve::extract::FromRequest<'r, State> for ConfigBody {
+    type Rejection = ConfigError;
+
+    async fn from_request<R: Read>(
+        _state: &'r State,
+        _request_parts: picoserve::request::RequestParts<'r>,
+        request_body: picoserve::request::RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        if request_body.content_length() > MAX_CONFIG_BODY_SIZE {
+            return Err(ConfigError::too_large());
+        }
+        let body = request_body
+            .read_all()
+            .await
+            .map_err(|_| ConfigError::malformed())?;
+        parse_config(body).map(Self)
+    }
+}
+
+/// An error of a configuration document
+#[derive(Debug, Serialize)]
+pub struct ConfigError {
+    /// Status code of the response
+    #[serde(skip)]
+    pub status_code: StatusCode,
+
+    /// Machine-readable error code, e.g. `unknown_field`
+    pub code: &'static str,
+
+    /// Human readable description of the error
+    pub message: &'static str,
+
+    /// Offending field, nested fields joined with dots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String<MAX_FIELD_PATH_SIZE>>,
+}
+
+impl ConfigError {
+    /// Create an error about no field in particular
+    const fn new(status_code: StatusCode, code: &'static str, message: &'static str) -> Self {
+        Self {
+            status_code,
+            code,
+            message,
+            field: None,
+        }
+    }
+
+    /// The body is larger than [`MAX_CONFIG_BODY_SIZE`]
+    fn too_large() -> Self {
+        Self::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "body_too_large",
+            "Request body too large",
+        )
+    }
+
+    /// The body is not a complete document
+    fn malformed() -> Self {
+        Self::new(
+            StatusCode::BAD_REQUEST,
+            "malformed_config",
+            "The body must be a complete JSON configuration document",
+        )
+    }
+
+    /// A field has an invalid value
+    fn invalid(field: &str, message: &'static str) -> Self {
+        Self {
+            field: Some(field_path(None, field)),
+            ..Self::new(StatusCode::BAD_REQUEST, "invalid_config", message)
+        }
+    }
+
+    /// The configuration could not be saved
+    fn storage() -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "flash_error",
+            "Flash error",
+        )
+    }
+}
+
+impl IntoResponse for ConfigError {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: picoserve::response::Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        (self.status_code, Json(self))
+            .write_to(connection, response_writer)
+            .await
+    }
+}
+
+/// Join a field name to its parent, cutting it to [`MAX_FIELD_PATH_SIZE`]
+fn field_path(parent: Option<&str>, field: &str) -> String<MAX_FIELD_PATH_SIZE> {
+    let mut path = String::new();
+    let parts = parent.into_iter().flat_map(|parent| [parent, "."]);
+    for character in parts.chain([field]).flat_map(str::chars) {
+        if path.push(character).is_err() {
+            break;
+        }
+    }
+    path
+}
+
+/// The first unknown field of a document, nested ones included
+struct FieldScan(Option<String<MAX_FIELD_PATH_SIZE>>);
+
+/// The first unknown field of the `auth` object of a document
+struct AuthFieldScan(Option<String<MAX_FIELD_PATH_SIZE>>);
+
+impl<'de> Deserialize<'de> for FieldScan {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_map(FieldVisitor {
+                fields: FIELDS,
+                nested: Some(AUTH_FIELD),
+            })
+            .map(Self)
+    }
+}
+
+impl<'de> Deserialize<'de> for AuthFieldScan {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_map(FieldVisitor {
+                fields: AUTH_FIELDS,
+                nested: None,
+            })
+            .map(Self)
+    }
+}
+
+/// A visitor of the keys of an object, skipping the values
+struct FieldVisitor {
+    /// Known fields
+    fields: &'static [&'static str],
+
+    /// Field holding the [`AuthConfig`], whose keys are visited too
+    nested: Option<&'static str>,
+}
+
+impl<'de> Visitor<'de> for FieldVisitor {
+    type Value = Option<String<MAX_FIELD_PATH_SIZE>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a JSON object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut unknown = None;
+        while let Some(key) = map.next_key::<&str>()? {
+            let found = if Some(key) == self.nested {
+                let AuthFieldScan(field) = map.next_value()?;
+                field.map(|field| field_path(Some(key), &field))
+            } else {
+                map.next_value::<IgnoredAny>()?;
+                (!self.fields.contains(&key)).then(|| field_path(None, key))
+            };
+            unknown = unknown.or(found);
+        }
+        Ok(unknown)
+    }
+}
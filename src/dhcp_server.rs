@@ -0,0 +1,383 @@
+//! DHCPv4 server of the access point
+//!
+//! Clients joining the access point of the device get an address from a
+//! pool of [`POOL_SIZE`] addresses after [`ACCESS_POINT_ADDRESS`], with the
+//! device as their router and DNS server. Only `DHCPDISCOVER` and
+//! `DHCPREQUEST` are answered, with `DHCPOFFER` and `DHCPACK`, or `DHCPNAK`
+//! for an address the server did not offer, e.g. one kept from another
+//! network. Everything else is ignored, leases simply expire.
+//!
+//! Leases are kept in a fixed-size table keyed by the client MAC address,
+//! and replies are broadcast since clients have no address yet.
+
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpEndpoint, Ipv4Address, Stack};
+use embassy_time::{Duration, Instant};
+
+use crate::wifi::ACCESS_POINT_ADDRESS;
+
+/// Port the server listens on
+pub const SERVER_PORT: u16 = 67;
+
+/// Port the clients listen on
+pub const CLIENT_PORT: u16 = 68;
+
+/// Number of addresses handed out
+pub const POOL_SIZE: usize = 8;
+
+/// First address handed out, the next ones follow
+pub const POOL_START: Ipv4Address = Ipv4Address::new(192, 168, 4, 2);
+
+/// Time a lease is valid
+pub const LEASE_TIME: Duration = Duration::from_secs(3600);
+
+/// Netmask of the network of the access point
+const NETMASK: Ipv4Address = Ipv4Address::new(255, 255, 255, 0);
+
+/// Size of a message up to the magic cookie
+const HEADER_SIZE: usize = 236;
+
+/// Marker of the options after the header
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// Largest message handled, the minimal size clients must accept
+pub const MAX_MESSAGE_SIZE: usize = 576;
+
+/// `op` of a message from a client
+const BOOT_REQUEST: u8 = 1;
+
+/// `op` of a message from a server
+const BOOT_REPLY: u8 = 2;
+
+/// Hardware type of Ethernet, used by Wi-Fi too
+const HARDWARE_ETHERNET: u8 = 1;
+
+/// Options of the messages
+mod option {
+    pub const PAD: u8 = 0;
+    pub const SUBNET_MASK: u8 = 1;
+    pub const ROUTER: u8 = 3;
+    pub const DNS_SERVERS: u8 = 6;
+    pub const REQUESTED_ADDRESS: u8 = 50;
+    pub const LEASE_TIME: u8 = 51;
+    pub const MESSAGE_TYPE: u8 = 53;
+    pub const SERVER_IDENTIFIER: u8 = 54;
+    pub const END: u8 = 255;
+}
+
+/// Types of the messages
+mod message_type {
+    pub const DISCOVER: u8 = 1;
+    pub const OFFER: u8 = 2;
+    pub const REQUEST: u8 = 3;
+    pub const ACK: u8 = 5;
+    pub const NAK: u8 = 6;
+}
+
+/// An address handed out to a client
+#[derive(Clone, Copy, Debug)]
+struct Lease {
+    /// MAC address of the client
+    mac: [u8; 6],
+
+    /// Time the lease ends, offered addresses get one too
+    expires: Instant,
+}
+
+/// The fields of a client message the server uses
+struct Request<'a> {
+    message_type: u8,
+    transaction_id: &'a [u8],
+    flags: &'a [u8],
+    client_address: Ipv4Address,
+    mac: [u8; 6],
+    client_hardware_address: &'a [u8],
+    requested_address: Option<Ipv4Address>,
+    server_identifier: Option<Ipv4Address>,
+}
+
+impl<'a> Request<'a> {
+    /// Parse a client message, `None` for anything but an Ethernet
+    /// `BOOTREQUEST` with a message type
+    fn parse(message: &'a [u8]) -> Option<Self> {
+        if message.len() < HEADER_SIZE + MAGIC_COOKIE.len()
+            || message[0] != BOOT_REQUEST
+            || message[1] != HARDWARE_ETHERNET
+            || message[2] != 6
+            || message[HEADER_SIZE..HEADER_SIZE + 4] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut message_type = None;
+        let mut requested_address = None;
+        let mut server_identifier = None;
+        let mut options = &message[HEADER_SIZE + 4..];
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                option::PAD => {
+                    options = rest;
+                    continue;
+                }
+                option::END => break,
+                _ => {}
+            }
+            let (&length, rest) = rest.split_first()?;
+            let value = rest.get(..usize::from(length))?;
+            match (code, value) {
+                (option::MESSAGE_TYPE, &[value]) => message_type = Some(value),
+                (option::REQUESTED_ADDRESS, &[a, b, c, d]) => {
+                    requested_address = Some(Ipv4Address::new(a, b, c, d));
+                }
+                (option::SERVER_IDENTIFIER, &[a, b, c, d]) => {
+                    server_identifier = Some(Ipv4Address::new(a, b, c, d));
+                }
+                _ => {}
+            }
+            options = &rest[usize::from(length)..];
+        }
+
+        Some(Self {
+            message_type: message_type?,
+            transaction_id: &message[4..8],
+            flags: &message[10..12],
+            client_address: Ipv4Address::new(message[12], message[13], message[14], message[15]),
+            mac: message[28..34].try_into().ok()?,
+            client_hardware_address: &message[28..44],
+            requested_address,
+            server_identifier,
+        })
+    }
+}
+
+/// The lease table and the message handling of the server
+pub struct DhcpServer {
+    leases: [Option<Lease>; POOL_SIZE],
+}
+
+impl Default for DhcpServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DhcpServer {
+    /// Create a server without leases
+    pub const fn new() -> Self {
+        Self {
+            leases: [None; POOL_SIZE],
+        }
+    }
+
+    /// Handle a client message received at `now`, and write the reply
+    ///
+    /// Return the length of the reply, `None` if the message is ignored.
+    pub fn handle(
+        &mut self,
+        message: &[u8],
+        now: Instant,
+        reply: &mut [u8; MAX_MESSAGE_SIZE],
+    ) -> Option<usize> {
+        let request = Request::parse(message)?;
+        match request.message_type {
+            message_type::DISCOVER => {
+                let address = self.offer(request.mac, now)?;
+                Some(write_reply(&request, message_type::OFFER, address, reply))
+            }
+            message_type::REQUEST => {
+                // The client chose another server
+                if request
+                    .server_identifier
+                    .is_some_and(|server| server != ACCESS_POINT_ADDRESS)
+                {
+                    return None;
+                }
+                let requested = request.requested_address.unwrap_or(request.client_address);
+                match self.acknowledge(request.mac, requested, now) {
+                    Some(address) => Some(write_reply(&request, message_type::ACK, address, reply)),
+                    None => Some(write_reply(
+                        &request,
+                        message_type::NAK,
+                        Ipv4Address::UNSPECIFIED,
+                        reply,
+                    )),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Return the address leased to a client, if any
+    pub fn leased_address(&self, mac: &[u8; 6], now: Instant) -> Option<Ipv4Address> {
+        self.find(mac, now).map(pool_address)
+    }
+
+    /// Reserve an address for a client, the one it already has if any
+    fn offer(&mut self, mac: [u8; 6], now: Instant) -> Option<Ipv4Address> {
+        let index = self.find(&mac, now).or_else(|| {
+            self.leases
+                .iter()
+                .position(|lease| lease.is_none_or(|lease| lease.expires <= now))
+        })?;
+        self.leases[index] = Some(Lease {
+            mac,
+            expires: now + LEASE_TIME,
+        });
+        Some(pool_address(index))
+    }
+
+    /// Confirm the address a client requested, if it is the one reserved for
+    /// it or a free one of the pool
+    fn acknowledge(
+        &mut self,
+        mac: [u8; 6],
+        requested: Ipv4Address,
+        now: Instant,
+    ) -> Option<Ipv4Address> {
+        let index = pool_index(requested)?;
+        let available =
+            self.leases[index].is_none_or(|lease| lease.mac == mac || lease.expires <= now);
+        if !available {
+            return None;
+        }
+        // A client keeps one lease
+        for lease in &mut self.leases {
+            if lease.is_some_and(|lease| lease.mac == mac) {
+                *lease = None;
+            }
+        }
+        self.leases[index] = Some(Lease {
+            mac,
+            expires: now + LEASE_TIME,
+        });
+        Some(requested)
+    }
+
+    /// Return the index of the valid lease of a client
+    fn find(&self, mac: &[u8; 6], now: Instant) -> Option<usize> {
+        self.leases
+            .iter()
+            .position(|lease| lease.is_some_and(|lease| lease.mac == *mac && lease.expires > now))
+    }
+}
+
+/// Return the address of an index of the pool
+fn pool_address(index: usize) -> Ipv4Address {
+    let [a, b, c, d] = POOL_START.octets();
+    // Cannot overflow, the pool is small
+    Ipv4Address::new(a, b, c, d + index as u8)
+}
+
+/// Return the index of an address of the pool
+fn pool_index(address: Ipv4Address) -> Option<usize> {
+    let [a, b, c, d] = address.octets();
+    let [start_a, start_b, start_c, start_d] = POOL_START.octets();
+    if [a, b, c] != [start_a, start_b, start_c] {
+        return None;
+    }
+    let index = usize::from(d.checked_sub(start_d)?);
+    (index < POOL_SIZE).then_some(index)
+}
+
+/// Write a reply to a request, and return its length
+fn write_reply(
+    request: &Request<'_>,
+    message_type: u8,
+    address: Ipv4Address,
+    reply: &mut [u8; MAX_MESSAGE_SIZE],
+) -> usize {
+    reply.fill(0);
+    reply[0] = BOOT_REPLY;
+    reply[1] = HARDWARE_ETHERNET;
+    reply[2] = 6;
+    reply[4..8].copy_from_slice(request.transaction_id);
+    reply[10..12].copy_from_slice(request.flags);
+    reply[16..20].copy_from_slice(&address.octets());
+    reply[20..24].copy_from_slice(&ACCESS_POINT_ADDRESS.octets());
+    reply[28..44].copy_from_slice(request.client_hardware_address);
+    reply[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&MAGIC_COOKIE);
+
+    let mut length = HEADER_SIZE + 4;
+    let mut push = |code: u8, value: &[u8]| {
+        reply[length] = code;
+        reply[length + 1] = value.len() as u8;
+        reply[length + 2..length + 2 + value.len()].copy_from_slice(value);
+        length += 2 + value.len();
+    };
+    push(option::MESSAGE_TYPE, &[message_type]);
+    push(option::SERVER_IDENTIFIER, &ACCESS_POINT_ADDRESS.octets());
+    if message_type != message_type::NAK {
+        // Cannot truncate, the lease time is an hour
+        push(
+            option::LEASE_TIME,
+            &(LEASE_TIME.as_secs() as u32).to_be_bytes(),
+        );
+        push(option::SUBNET_MASK, &NETMASK.octets());
+        push(option::ROUTER, &ACCESS_POINT_ADDRESS.octets());
+        push(option::DNS_SERVERS, &ACCESS_POINT_ADDRESS.octets());
+    }
+    reply[length] = option::END;
+    length + 1
+}
+
+/// Buffers of the server task
+///
+/// They are statically allocated rather than living in the task future, so
+/// they do not grow the executor arena.
+struct Buffers {
+    rx_meta: [PacketMetadata; 2],
+    rx: [u8; MAX_MESSAGE_SIZE * 2],
+    tx_meta: [PacketMetadata; 2],
+    tx: [u8; MAX_MESSAGE_SIZE * 2],
+    message: [u8; MAX_MESSAGE_SIZE],
+    reply: [u8; MAX_MESSAGE_SIZE],
+}
+
+/// Answer the DHCP clients of the access point on `stack`
+#[embassy_executor::task]
+pub async fn dhcp_server_task(stack: Stack<'static>) {
+    let Buffers {
+        rx_meta,
+        rx,
+        tx_meta,
+        tx,
+        message,
+        reply,
+    } = crate::mk_static!(
+        Buffers,
+        Buffers {
+            rx_meta: [PacketMetadata::EMPTY; 2],
+            rx: [0; MAX_MESSAGE_SIZE * 2],
+            tx_meta: [PacketMetadata::EMPTY; 2],
+            tx: [0; MAX_MESSAGE_SIZE * 2],
+            message: [0; MAX_MESSAGE_SIZE],
+            reply: [0; MAX_MESSAGE_SIZE],
+        }
+    );
+    let mut socket = UdpSocket::new(stack, rx_meta, rx, tx_meta, tx);
+    if let Err(error) = socket.bind(SERVER_PORT) {
+        crate::log!(error: "Failed to bind the DHCP server: {:?}", error);
+        return;
+    }
+
+    let mut server = DhcpServer::new();
+    let broadcast = IpEndpoint::new(Ipv4Address::BROADCAST.into(), CLIENT_PORT);
+    loop {
+        crate::heartbeat!("dhcp_server_task");
+        let received =
+            crate::tasks::beating("dhcp_server_task", None, socket.recv_from(message)).await;
+        let length = match received {
+            Ok((length, _)) => length,
+            Err(error) => {
+                crate::log!(warn: "Warning: dropped a DHCP message: {:?}", error);
+                continue;
+            }
+        };
+        let Some(reply_length) = server.handle(&message[..length], Instant::now(), reply) else {
+            continue;
+        };
+        if let Err(error) = socket.send_to(&reply[..reply_length], broadcast).await {
+            crate::log!(warn: "Warning: failed to send a DHCP reply: {:?}", error);
+        }
+    }
+}
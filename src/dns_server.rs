@@ -0,0 +1,251 @@
+//! DNS server of the provisioning access point
+//!
+//! Every `A` query is answered with [`ACCESS_POINT_ADDRESS`], whatever the
+//! name, so the connectivity checks of [`crate::captive`] reach the device
+//! and phones open the provisioning form by themselves. Other types, like
+//! `AAAA`, get an empty answer rather than `NXDOMAIN`, which would tell the
+//! client the name does not exist at all.
+//!
+//! The server only runs in provisioning mode: on an access point kept
+//! alongside the station, clients use the device by its address.
+//!
+//! Only the first question of a query is answered. Its name is copied to the
+//! response without compression, since the pointers of a query may point
+//! anywhere in it, and responses never exceed [`MAX_MESSAGE_SIZE`].
+
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{Ipv4Address, Stack};
+use heapless::Vec;
+
+use crate::wifi::ACCESS_POINT_ADDRESS;
+
+/// Port the server listens on
+pub const SERVER_PORT: u16 = 53;
+
+/// Largest message handled, the limit of DNS over UDP without EDNS
+pub const MAX_MESSAGE_SIZE: usize = 512;
+
+/// Time clients may cache the answers in seconds
+///
+/// Short, so names resolve normally soon after the device is provisioned.
+pub const TTL: u32 = 10;
+
+/// Size of the header of a message
+pub(crate) const HEADER_SIZE: usize = 12;
+
+/// Largest name, in wire format
+pub(crate) const MAX_NAME_SIZE: usize = 255;
+
+/// Record types
+pub mod record_type {
+    pub const A: u16 = 1;
+    pub const AAAA: u16 = 28;
+}
+
+/// Class of internet records
+const CLASS_IN: u16 = 1;
+
+/// Class matching every class
+const CLASS_ANY: u16 = 255;
+
+/// Response codes
+pub mod response_code {
+    pub const NO_ERROR: u8 = 0;
+    pub const FORMAT_ERROR: u8 = 1;
+    pub const NOT_IMPLEMENTED: u8 = 4;
+}
+
+/// Flag of responses
+const FLAG_RESPONSE: u8 = 0x80;
+
+/// Mask of the opcode in the first flag byte
+const OPCODE_MASK: u8 = 0x78;
+
+/// Flag of answers of the server owning the name
+const FLAG_AUTHORITATIVE: u8 = 0x04;
+
+/// Flag of queries asking for recursion, echoed back
+const FLAG_RECURSION_DESIRED: u8 = 0x01;
+
+/// Pointer to the name of the question, always right after the header
+const QUESTION_NAME_POINTER: [u8; 2] = [0xc0, HEADER_SIZE as u8];
+
+/// The question of a query
+struct Question {
+    /// The name in wire format, without compression
+    name: Vec<u8, MAX_NAME_SIZE>,
+    record_type: u16,
+    class: u16,
+}
+
+/// Read a name at `offset`, following compression pointers
+///
+/// Return the name in wire format and the offset after it in the message.
+/// Each pointer must point before the start of the name or of the previous
+/// pointer target, so a malicious query cannot make the server loop.
+pub(crate) fn read_name(
+    message: &[u8],
+    mut offset: usize,
+) -> Option<(Vec<u8, MAX_NAME_SIZE>, usize)> {
+    let mut name = Vec::new();
+    let mut end = None;
+    let mut limit = offset;
+    loop {
+        let length = *message.get(offset)?;
+        match length & 0xc0 {
+            0xc0 => {
+                let target =
+                    (usize::from(length & 0x3f) << 8) | usize::from(*message.get(offset + 1)?);
+                if target >= limit {
+                    return None;
+                }
+                end.get_or_insert(offset + 2);
+                offset = target;
+                limit = target;
+            }
+            // Extended and binary labels are obsolete
+            0x40 | 0x80 => return None,
+            _ if length == 0 => {
+                name.push(0).ok()?;
+                return Some((name, end.unwrap_or(offset + 1)));
+            }
+            _ => {
+                let label = message.get(offset..offset + 1 + usize::from(length))?;
+                name.extend_from_slice(label).ok()?;
+                offset += label.len();
+            }
+        }
+    }
+}
+
+impl Question {
+    /// Parse the first question of a query
+    fn parse(query: &[u8]) -> Option<Self> {
+        let (name, offset) = read_name(query, HEADER_SIZE)?;
+        let fields = query.get(offset..offset + 4)?;
+        Some(Self {
+            name,
+            record_type: u16::from_be_bytes([fields[0], fields[1]]),
+            class: u16::from_be_bytes([fields[2], fields[3]]),
+        })
+    }
+}
+
+/// Answer a query with `address` for every name, and write the response
+///
+/// Return the length of the response, `None` if the message is ignored:
+/// responses, and messages too short to be answered.
+pub fn answer(
+    query: &[u8],
+    address: Ipv4Address,
+    response: &mut [u8; MAX_MESSAGE_SIZE],
+) -> Option<usize> {
+    let header = query.get(..HEADER_SIZE)?;
+    if header[2] & FLAG_RESPONSE != 0 {
+        return None;
+    }
+
+    response[..2].copy_from_slice(&header[..2]);
+    response[2] =
+        FLAG_RESPONSE | FLAG_AUTHORITATIVE | (header[2] & (OPCODE_MASK | FLAG_RECURSION_DESIRED));
+    response[4..HEADER_SIZE].fill(0);
+
+    let question_count = u16::from_be_bytes([header[4], header[5]]);
+    if header[2] & OPCODE_MASK != 0 {
+        response[3] = response_code::NOT_IMPLEMENTED;
+        return Some(HEADER_SIZE);
+    }
+    let question = match Question::parse(query) {
+        Some(question) if question_count > 0 => question,
+        _ => {
+            response[3] = response_code::FORMAT_ERROR;
+            return Some(HEADER_SIZE);
+        }
+    };
+
+    response[3] = response_code::NO_ERROR;
+    response[5] = 1;
+    let mut length = HEADER_SIZE;
+    let mut push = |bytes: &[u8]| {
+        response[length..length + bytes.len()].copy_from_slice(bytes);
+        length += bytes.len();
+    };
+    // Cannot overflow, the name is at most 255 bytes
+    push(&question.name);
+    push(&question.record_type.to_be_bytes());
+    push(&question.class.to_be_bytes());
+    if question.record_type == record_type::A && matches!(question.class, CLASS_IN | CLASS_ANY) {
+        push(&QUESTION_NAME_POINTER);
+        push(&record_type::A.to_be_bytes());
+        push(&CLASS_IN.to_be_bytes());
+        push(&TTL.to_be_bytes());
+        push(&4u16.to_be_bytes());
+        push(&address.octets());
+        response[7] = 1;
+    }
+    Some(length)
+}
+
+/// Buffers of the server task
+///
+/// They are statically allocated rather than living in the task future, so
+/// they do not grow the executor arena.
+struct Buffers {
+    rx_meta: [PacketMetadata; 2],
+    rx: [u8; MAX_MESSAGE_SIZE * 2],
+    tx_meta: [PacketMetadata; 2],
+    tx: [u8; MAX_MESSAGE_SIZE * 2],
+    query: [u8; MAX_MESSAGE_SIZE],
+    response: [u8; MAX_MESSAGE_SIZE],
+}
+
+/// Answer the DNS queries of the clients of the provisioning access point on
+/// `stack`
+#[embassy_executor::task]
+pub async fn dns_server_task(stack: Stack<'static>) {
+    let Buffers {
+        rx_meta,
+        rx,
+        tx_meta,
+        tx,
+        query,
+        response,
+    } = crate::mk_static!(
+        Buffers,
+        Buffers {
+            rx_meta: [PacketMetadata::EMPTY; 2],
+            rx: [0; MAX_MESSAGE_SIZE * 2],
+            tx_meta: [PacketMetadata::EMPTY; 2],
+            tx: [0; MAX_MESSAGE_SIZE * 2],
+            query: [0; MAX_MESSAGE_SIZE],
+            response: [0; MAX_MESSAGE_SIZE],
+        }
+    );
+    let mut socket = UdpSocket::new(stack, rx_meta, rx, tx_meta, tx);
+    if let Err(error) = socket.bind(SERVER_PORT) {
+        crate::log!(error: "Failed to bind the DNS server: {:?}", error);
+        return;
+    }
+
+    loop {
+        crate::heartbeat!("dns_server_task");
+        let received =
+            crate::tasks::beating("dns_server_task", None, socket.recv_from(query)).await;
+        let (length, metadata) = match received {
+            Ok(received) => received,
+            Err(error) => {
+                crate::log!(warn: "Warning: dropped a DNS query: {:?}", error);
+                continue;
+            }
+        };
+        let Some(response_length) = answer(&query[..length], ACCESS_POINT_ADDRESS, response) else {
+            continue;
+        };
+        if let Err(error) = socket
+            .send_to(&response[..response_length], metadata.endpoint)
+            .await
+        {
+            crate::log!(warn: "Warning: failed to send a DNS response: {:?}", error);
+        }
+    }
+}
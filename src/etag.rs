@@ -0,0 +1,136 @@
+//! Conditional requests using weak ETags
+//!
+//! Responses wrapped in [`Conditional`] carry an `ETag` header, and are
+//! replaced by an empty 304 response when the `If-None-Match` request header
+//! matches.
+
+use heapless::String;
+use picoserve::{
+    io::Read,
+    response::{Connection, IntoResponse, Response, ResponseWriter, StatusCode},
+    ResponseSent,
+};
+
+/// Maximal length of a kept `If-None-Match` header value
+pub const IF_NONE_MATCH_SIZE: usize = 128;
+
+/// The ETag of the `/version` endpoint
+pub const VERSION_ETAG: &str = concat!("W/\"", env!("CARGO_PKG_VERSION"), "\"");
+
+/// The ETag of the JSON representation of the `/version` endpoint
+pub const VERSION_JSON_ETAG: &str = concat!("W/\"", env!("CARGO_PKG_VERSION"), "-json\"");
+
+/// An extractor for the `If-None-Match` request header
+///
+/// Values longer than [`IF_NONE_MATCH_SIZE`] are dropped, which only costs a
+/// full response.
+#[derive(Default)]
+pub struct IfNoneMatch(pub Option<String<IF_NONE_MATCH_SIZE>>);
+
+impl IfNoneMatch {
+    /// Check whether the header matches an ETag
+    pub fn matches(&self, etag: &str) -> bool {
+        self.0.as_deref().is_some_and(|header| etag_matches(header, etag))
+    }
+}
+
+impl<'r, State> picoserve::extract::FromRequestParts<'r, State> for IfNoneMatch {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        _state: &'r State,
+        request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        let value = request_parts
+            .headers()
+            .get("If-None-Match")
+            .and_then(|value| value.as_str().ok())
+            .and_then(|value| String::try_from(value).ok());
+        Ok(Self(value))
+    }
+}
+
+/// Check whether an `If-None-Match` header value matches an ETag
+///
+/// The header is a comma-separated list of ETags or `*`. Tags are compared
+/// with the weak comparison function, ignoring the `W/` prefix.
+pub fn etag_matches(header: &str, etag: &str) -> bool {
+    let header = header.trim();
+    if header == "*" {
+        return true;
+    }
+
+    let etag = opaque_tag(etag);
+    header
+        .split(',')
+        .map(|candidate| opaque_tag(candidate.trim()))
+        .any(|candidate| !candidate.is_empty() && candidate == etag)
+}
+
+/// Strip the weak indicator from an ETag
+fn opaque_tag(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+/// A response writer appending a header to the response
+pub(crate) struct AppendHeaderWriter<V, W> {
+    pub(crate) name: &'static str,
+    pub(crate) value: V,
+    pub(crate) response_writer: W,
+}
+
+impl<V: core::fmt::Display, W: ResponseWriter> ResponseWriter for AppendHeaderWriter<V, W> {
+    type Error = W::Error;
+
+    async fn write_response<
+        R: Read<Error = Self::Error>,
+        H: picoserve::response::HeadersIter,
+        B: picoserve::response::Body,
+    >(
+        self,
+        connection: Connection<'_, R>,
+        response: Response<H, B>,
+    ) -> Result<ResponseSent, Self::Error> {
+        let response = response.with_header(self.name, self.value);
+        self.response_writer.write_response(connection, response).await
+    }
+}
+
+/// A response sent only if the client does not already have it
+pub struct Conditional<T> {
+    etag: &'static str,
+    response: Option<T>,
+}
+
+impl<T> Conditional<T> {
+    /// Wrap a response tagged with an ETag
+    pub fn new(etag: &'static str, if_none_match: &IfNoneMatch, response: T) -> Self {
+        Self {
+            etag,
+            response: (!if_none_match.matches(etag)).then_some(response),
+        }
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Conditional<T> {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        match self.response {
+            Some(response) => {
+                let response_writer = AppendHeaderWriter {
+                    name: "ETag",
+                    value: self.etag,
+                    response_writer,
+                };
+                response.write_to(connection, response_writer).await
+            }
+            None => {
+                let response = Response::new(StatusCode::NOT_MODIFIED, "").with_header("ETag", self.etag);
+                response_writer.write_response(connection, response).await
+            }
+        }
+    }
+}
@@ -0,0 +1,426 @@
+//! Files uploaded over HTTP into a dedicated flash region
+//!
+//! The region is split into [`MAX_FILES`] slots of [`SLOT_SIZE`] bytes. A
+//! slot starts with a header holding the name, the length and the CRC-32 of
+//! the file, followed by its content.
+//!
+//! An upload erases the slot and writes the name first, then the content as
+//! it is received, and commits the length and CRC-32 last, once the CRC-32
+//! sent by the client matched. A slot whose upload was interrupted, by an
+//! error or a reset, keeps an uncommitted header: the file is torn and is
+//! not served until it is uploaded again.
+//!
+//! The region is outside of the partition table, at the end of a 4 MiB
+//! flash, and must not overlap the application.
+
+use core::ops::Range;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use esp_storage::FlashStorage;
+
+/// Flash range of the files
+const FILES_RANGE: Range<u32> = 0x3C_0000..0x40_0000;
+
+/// Number of file slots
+pub const MAX_FILES: usize = 4;
+
+/// Size of a slot, header included
+pub const SLOT_SIZE: u32 = (FILES_RANGE.end - FILES_RANGE.start) / MAX_FILES as u32;
+
+/// Maximal length of a file name
+pub const MAX_NAME_SIZE: usize = 16;
+
+/// Size of the header of a slot
+const HEADER_SIZE: u32 = 32;
+
+/// Maximal size of a file
+pub const MAX_FILE_SIZE: u32 = SLOT_SIZE - HEADER_SIZE;
+
+/// Marker of a slot holding a file, written first
+const MAGIC: [u8; 4] = *b"fil1";
+
+/// Value of the state word of a committed file, the word is erased before
+const COMMITTED: u32 = 0;
+
+/// Offsets of the header fields
+const NAME_OFFSET: u32 = 4;
+const LENGTH_OFFSET: u32 = NAME_OFFSET + MAX_NAME_SIZE as u32;
+const STATE_OFFSET: u32 = LENGTH_OFFSET + 8;
+
+/// Size of the buffer content is written from, a multiple of the flash
+/// write size
+const WRITE_BUFFER_SIZE: usize = 256;
+
+/// An error of the file store
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The name is empty, too long or contains other characters than ASCII
+    /// letters, digits, `.`, `-` and `_`
+    InvalidName,
+
+    /// The file is larger than [`MAX_FILE_SIZE`]
+    TooLarge,
+
+    /// Every slot holds another file
+    NoSpace,
+
+    /// No file has this name
+    NotFound,
+
+    /// The last upload of the file did not complete
+    Torn,
+
+    /// More or less content was written than announced
+    LengthMismatch,
+
+    /// The CRC-32 of the content does not match the one sent by the client
+    CrcMismatch,
+
+    /// The flash could not be read or written
+    Flash,
+}
+
+/// A committed file
+#[derive(Clone, Copy, Debug)]
+pub struct File {
+    slot: u32,
+    length: u32,
+    crc: u32,
+}
+
+impl File {
+    /// Return the length of the file in bytes
+    pub fn len(&self) -> u32 {
+        self.length
+    }
+
+    /// Check whether the file is empty
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+}
+
+/// The header of a slot
+struct Header {
+    name: [u8; MAX_NAME_SIZE],
+    length: u32,
+    crc: u32,
+    committed: bool,
+}
+
+/// The files stored in flash
+pub struct FileStore {
+    flash: Mutex<CriticalSectionRawMutex, FlashStorage>,
+}
+
+impl FileStore {
+    /// Open the store
+    pub fn new(flash: FlashStorage) -> Self {
+        Self {
+            flash: Mutex::new(flash),
+        }
+    }
+
+    /// Find a committed file
+    pub async fn open(&self, name: &str) -> Result<File, Error> {
+        let name = encode_name(name)?;
+        let mut flash = self.flash.lock().await;
+        let slot = find_slot(&mut flash, &name)?.ok_or(Error::NotFound)?;
+        let header = read_header(&mut flash, slot)?.ok_or(Error::NotFound)?;
+        if !header.committed {
+            return Err(Error::Torn);
+        }
+        Ok(File {
+            slot,
+            length: header.length,
+            crc: header.crc,
+        })
+    }
+
+    /// Read part of a file into a buffer and return the number of bytes read
+    ///
+    /// Fails with [`Error::Torn`] if the file was replaced since it was
+    /// opened.
+    pub async fn read(&self, file: &File, offset: u32, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut flash = self.flash.lock().await;
+        let unchanged = read_header(&mut flash, file.slot)?.is_some_and(|header| {
+            header.committed && header.crc == file.crc && header.length == file.length
+        });
+        if !unchanged {
+            return Err(Error::Torn);
+        }
+        let length = buffer
+            .len()
+            .min(file.length.saturating_sub(offset) as usize);
+        flash
+            .read(file.slot + HEADER_SIZE + offset, &mut buffer[..length])
+            .map_err(|_| Error::Flash)?;
+        Ok(length)
+    }
+
+    /// Start replacing or creating a file of `length` bytes
+    ///
+    /// The previous content is erased right away. Other flash accesses to
+    /// the files wait until the upload is dropped.
+    pub async fn begin(&self, name: &str, length: u32) -> Result<Upload<'_>, Error> {
+        let upload = self.start(name, length, true).await?;
+        crate::log!("Uploading file {} ({} bytes)", name, length);
+        Ok(upload)
+    }
+
+    /// Start replacing or creating a file whose length is not known yet
+    ///
+    /// The upload is committed with [`Upload::finish_unsized`], once the
+    /// content ended.
+    pub async fn begin_unsized(&self, name: &str) -> Result<Upload<'_>, Error> {
+        let upload = self.start(name, MAX_FILE_SIZE, false).await?;
+        crate::log!("Uploading file {} (length unknown)", name);
+        Ok(upload)
+    }
+
+    /// Erase the slot of a file and write its name
+    async fn start(&self, name: &str, length: u32, sized: bool) -> Result<Upload<'_>, Error> {
+        let encoded = encode_name(name)?;
+        if length > MAX_FILE_SIZE {
+            return Err(Error::TooLarge);
+        }
+        let mut flash = self.flash.lock().await;
+        let slot = match find_slot(&mut flash, &encoded)? {
+            Some(slot) => slot,
+            None => find_free_slot(&mut flash)?.ok_or(Error::NoSpace)?,
+        };
+
+        flash
+            .erase(slot, slot + SLOT_SIZE)
+            .map_err(|_| Error::Flash)?;
+        let mut start = [0; LENGTH_OFFSET as usize];
+        start[..4].copy_from_slice(&MAGIC);
+        start[NAME_OFFSET as usize..].copy_from_slice(&encoded);
+        flash.write(slot, &start).map_err(|_| Error::Flash)?;
+
+        Ok(Upload {
+            flash,
+            slot,
+            length,
+            sized,
+            written: 0,
+            flushed: 0,
+            crc: Crc32::new(),
+            buffer: [0; WRITE_BUFFER_SIZE],
+            buffered: 0,
+        })
+    }
+}
+
+/// A file being uploaded
+///
+/// Dropping the upload before [`Upload::finish`] leaves the file torn.
+pub struct Upload<'a> {
+    flash: MutexGuard<'a, CriticalSectionRawMutex, FlashStorage>,
+    slot: u32,
+    length: u32,
+    sized: bool,
+    written: u32,
+    flushed: u32,
+    crc: Crc32,
+    buffer: [u8; WRITE_BUFFER_SIZE],
+    buffered: usize,
+}
+
+impl Upload<'_> {
+    /// Append content to the file
+    pub fn write(&mut self, mut bytes: &[u8]) -> Result<(), Error> {
+        if bytes.len() > (self.length - self.written) as usize {
+            return Err(if self.sized {
+                Error::LengthMismatch
+            } else {
+                Error::TooLarge
+            });
+        }
+        self.crc.update(bytes);
+        self.written += bytes.len() as u32;
+        while !bytes.is_empty() {
+            let count = bytes.len().min(WRITE_BUFFER_SIZE - self.buffered);
+            self.buffer[self.buffered..self.buffered + count].copy_from_slice(&bytes[..count]);
+            self.buffered += count;
+            bytes = &bytes[count..];
+            if self.buffered == WRITE_BUFFER_SIZE {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the content against the CRC-32 sent by the client and commit
+    /// the file
+    pub fn finish(self, expected_crc: u32) -> Result<File, Error> {
+        if self.written != self.length {
+            return Err(Error::LengthMismatch);
+        }
+        let crc = self.crc.finish();
+        if crc != expected_crc {
+            return Err(Error::CrcMismatch);
+        }
+        self.commit(crc)
+    }
+
+    /// Commit the file with the content written so far
+    ///
+    /// There is no CRC-32 from the client to check the content against, the
+    /// transport must be trusted to deliver it intact.
+    pub fn finish_unsized(mut self) -> Result<File, Error> {
+        self.length = self.written;
+        let crc = self.crc.finish();
+        self.commit(crc)
+    }
+
+    /// Write the remaining content, then the length and the CRC-32
+    fn commit(mut self, crc: u32) -> Result<File, Error> {
+        self.flush()?;
+
+        let mut length_and_crc = [0; 8];
+        length_and_crc[..4].copy_from_slice(&self.length.to_le_bytes());
+        length_and_crc[4..].copy_from_slice(&crc.to_le_bytes());
+        self.flash
+            .write(self.slot + LENGTH_OFFSET, &length_and_crc)
+            .map_err(|_| Error::Flash)?;
+        // The state is written last, so a reset in between leaves the file
+        // torn rather than committed with a wrong length
+        self.flash
+            .write(self.slot + STATE_OFFSET, &COMMITTED.to_le_bytes())
+            .map_err(|_| Error::Flash)?;
+
+        Ok(File {
+            slot: self.slot,
+            length: self.length,
+            crc,
+        })
+    }
+
+    /// Write the buffered content, padded to the flash write size
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.buffered == 0 {
+            return Ok(());
+        }
+        let padded = self.buffered.next_multiple_of(FlashStorage::WRITE_SIZE);
+        self.buffer[self.buffered..padded].fill(0xFF);
+        self.flash
+            .write(
+                self.slot + HEADER_SIZE + self.flushed,
+                &self.buffer[..padded],
+            )
+            .map_err(|_| Error::Flash)?;
+        self.flushed += self.buffered as u32;
+        self.buffered = 0;
+        Ok(())
+    }
+}
+
+/// Return the offset of the slot holding a file, committed or not
+fn find_slot(flash: &mut FlashStorage, name: &[u8; MAX_NAME_SIZE]) -> Result<Option<u32>, Error> {
+    for slot in slots() {
+        if read_header(flash, slot)?.is_some_and(|header| header.name == *name) {
+            return Ok(Some(slot));
+        }
+    }
+    Ok(None)
+}
+
+/// Return the offset of a slot holding no file
+fn find_free_slot(flash: &mut FlashStorage) -> Result<Option<u32>, Error> {
+    for slot in slots() {
+        if read_header(flash, slot)?.is_none() {
+            return Ok(Some(slot));
+        }
+    }
+    Ok(None)
+}
+
+/// Iterate over the offsets of the slots
+fn slots() -> impl Iterator<Item = u32> {
+    FILES_RANGE.step_by(SLOT_SIZE as usize)
+}
+
+/// Read the header of a slot, `None` if the slot holds no file
+fn read_header(flash: &mut FlashStorage, slot: u32) -> Result<Option<Header>, Error> {
+    let mut bytes = [0; HEADER_SIZE as usize];
+    flash.read(slot, &mut bytes).map_err(|_| Error::Flash)?;
+    if bytes[..4] != MAGIC {
+        return Ok(None);
+    }
+    let word = |offset: u32| {
+        let offset = offset as usize;
+        u32::from_le_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ])
+    };
+    let mut name = [0; MAX_NAME_SIZE];
+    name.copy_from_slice(&bytes[NAME_OFFSET as usize..LENGTH_OFFSET as usize]);
+    Ok(Some(Header {
+        name,
+        length: word(LENGTH_OFFSET),
+        crc: word(LENGTH_OFFSET + 4),
+        committed: word(STATE_OFFSET) == COMMITTED,
+    }))
+}
+
+/// Check whether a file name is valid
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_SIZE
+        && !name.starts_with('.')
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'-' | b'_'))
+}
+
+/// Encode a file name as a zero-padded byte array
+fn encode_name(name: &str) -> Result<[u8; MAX_NAME_SIZE], Error> {
+    if !is_valid_name(name) {
+        return Err(Error::InvalidName);
+    }
+    let mut encoded = [0; MAX_NAME_SIZE];
+    encoded[..name.len()].copy_from_slice(name.as_bytes());
+    Ok(encoded)
+}
+
+/// An incremental CRC-32, as computed by zlib and `crc32(1)`
+///
+/// The bitwise algorithm needs no table, and is fast enough for files
+/// arriving over WiFi.
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    /// Start a checksum
+    pub const fn new() -> Self {
+        Self(0xFFFF_FFFF)
+    }
+
+    /// Add bytes to the checksum
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u32::from(byte);
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+    }
+
+    /// Return the checksum of the bytes added so far
+    pub const fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
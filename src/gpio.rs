@@ -0,0 +1,107 @@
+//! Output pins controlled over HTTP
+
+use core::cell::RefCell;
+use core::str::FromStr;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use esp_hal::gpio::{Level, Output};
+use heapless::Vec;
+
+/// Maximal number of pins that can be controlled
+pub const MAX_GPIO_OUTPUTS: usize = 4;
+
+/// The whitelist of output pins and their drivers
+pub struct GpioOutputs {
+    pins: Mutex<CriticalSectionRawMutex, RefCell<Vec<(u8, Output<'static>), MAX_GPIO_OUTPUTS>>>,
+}
+
+impl GpioOutputs {
+    /// Create an empty whitelist
+    pub fn new() -> Self {
+        Self {
+            pins: Mutex::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Add a pin to the whitelist
+    ///
+    /// Panics if more than [`MAX_GPIO_OUTPUTS`] pins are added.
+    pub fn with_pin(self, number: u8, output: Output<'static>) -> Self {
+        self.pins.lock(|pins| {
+            if pins.borrow_mut().push((number, output)).is_err() {
+                panic!("Too many GPIO outputs");
+            }
+        });
+        self
+    }
+
+    /// Return whether a pin is set high, or `None` if it is not whitelisted
+    pub fn is_set_high(&self, number: u8) -> Option<bool> {
+        self.pins.lock(|pins| {
+            pins.borrow()
+                .iter()
+                .find(|(pin, _)| *pin == number)
+                .map(|(_, output)| output.is_set_high())
+        })
+    }
+
+    /// Return every whitelisted pin and whether it is set high
+    pub fn levels(&self) -> Vec<(u8, bool), MAX_GPIO_OUTPUTS> {
+        self.pins.lock(|pins| {
+            pins.borrow()
+                .iter()
+                .map(|(pin, output)| (*pin, output.is_set_high()))
+                .collect()
+        })
+    }
+
+    /// Apply an action to a pin and return whether it is now set high, or
+    /// `None` if it is not whitelisted
+    pub fn apply(&self, number: u8, action: PinAction) -> Option<bool> {
+        let high = self.pins.lock(|pins| {
+            let mut pins = pins.borrow_mut();
+            let (_, output) = pins.iter_mut().find(|(pin, _)| *pin == number)?;
+            match action {
+                PinAction::On => output.set_level(Level::High),
+                PinAction::Off => output.set_level(Level::Low),
+                PinAction::Toggle => output.toggle(),
+            }
+            Some(output.is_set_high())
+        })?;
+        crate::changes::notify();
+        Some(high)
+    }
+}
+
+impl Default for GpioOutputs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An action on an output pin
+#[derive(Clone, Copy, Debug)]
+pub enum PinAction {
+    /// Set the pin high
+    On,
+
+    /// Set the pin low
+    Off,
+
+    /// Invert the pin level
+    Toggle,
+}
+
+impl FromStr for PinAction {
+    type Err = ();
+
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        match action {
+            "on" => Ok(Self::On),
+            "off" => Ok(Self::Off),
+            "toggle" => Ok(Self::Toggle),
+            _ => Err(()),
+        }
+    }
+}
@@ -0,0 +1,436 @@
+//! On-the-fly gzip compression of dynamic responses
+//!
+//! Large JSON bodies like `/stats`, `/api` or `/logs` compress to a fraction
+//! of their size, which matters over a weak Wi-Fi link. Responses are
+//! compressed while they are written, one chunk of the chunked encoding at a
+//! time, so the whole body is never held in memory.
+//!
+//! The encoder is deliberately small: LZ77 over a [`WINDOW_SIZE`] window with
+//! short hash chains, and a single deflate block of fixed Huffman codes, so
+//! there are no code tables to build or send. JSON still shrinks to a
+//! quarter or less of its size.
+//!
+//! Each compressed response allocates [`ENCODER_HEAP_SIZE`] bytes of heap,
+//! about 11 KiB, for as long as it is written. When less than that plus
+//! [`HEAP_RESERVE`] is free, responses are sent uncompressed instead. Build
+//! without the `gzip` feature to remove the encoder altogether.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use picoserve::io::{ErrorType, Write};
+
+use crate::files::Crc32;
+
+/// Longest distance of a match
+pub const WINDOW_SIZE: usize = 2048;
+
+/// Size of the input buffer, the window and the data not encoded yet
+const BUFFER_SIZE: usize = 2 * WINDOW_SIZE;
+
+/// Number of bits of the hash of three bytes
+const HASH_BITS: u32 = 9;
+
+/// Number of hash chain heads
+const HASH_SIZE: usize = 1 << HASH_BITS;
+
+/// Number of earlier occurrences compared before giving up on a longer match
+const MAX_CHAIN: usize = 8;
+
+/// Shortest match worth encoding
+const MIN_MATCH: usize = 3;
+
+/// Longest match deflate can encode
+const MAX_MATCH: usize = 258;
+
+/// Bytes kept after the next byte to encode until the input ends, enough to
+/// find the longest match and hash the bytes it covers
+const LOOKAHEAD: usize = MAX_MATCH + MIN_MATCH;
+
+/// Size of the buffer of compressed bytes, written out when full
+pub const OUTPUT_SIZE: usize = 512;
+
+/// Room left in the output buffer before encoding a symbol, the longest
+/// symbol is 31 bits
+const SYMBOL_ROOM: usize = 8;
+
+/// Room left in the output buffer before the end of the stream, the end of
+/// the block and the trailer
+const TRAILER_ROOM: usize = 16;
+
+/// Bytes of heap allocated by an [`Encoder`]
+pub const ENCODER_HEAP_SIZE: usize = BUFFER_SIZE
+    + HASH_SIZE * core::mem::size_of::<u32>()
+    + WINDOW_SIZE * core::mem::size_of::<u16>()
+    + OUTPUT_SIZE;
+
+/// Heap left free for the rest of the firmware when deciding to compress
+pub const HEAP_RESERVE: usize = 16 * 1024;
+
+/// Header of a gzip member: deflate, no flags, no time, unknown OS
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+
+/// Base lengths of the length symbols 257 to 285
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+/// Extra bits of the length symbols 257 to 285
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances of the distance symbols
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+/// Extra bits of the distance symbols
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Allocate a zeroed buffer, or `None` if the heap is exhausted
+fn zeroed<T: Clone + Default>(length: usize) -> Option<Vec<T>> {
+    let mut buffer = Vec::new();
+    buffer.try_reserve_exact(length).ok()?;
+    buffer.resize(length, T::default());
+    Some(buffer)
+}
+
+/// Return the index of the symbol whose base is the largest not above a value
+fn symbol(bases: &[u16], value: usize) -> usize {
+    bases
+        .iter()
+        .rposition(|&base| usize::from(base) <= value)
+        .unwrap_or(0)
+}
+
+/// A streaming gzip encoder
+///
+/// Input is added with [`Encoder::feed`] and encoded with
+/// [`Encoder::compress`] into an output buffer, which the caller empties
+/// with [`Encoder::take_output`] whenever the encoder reports it full.
+pub struct Encoder {
+    /// The window followed by the bytes not encoded yet
+    buffer: Vec<u8>,
+
+    /// Absolute position of the first byte of the buffer
+    base: u32,
+
+    /// Index in the buffer of the next byte to encode
+    current: usize,
+
+    /// Number of bytes in the buffer
+    end: usize,
+
+    /// Absolute position plus one of the latest occurrence of each hash,
+    /// zero when none
+    head: Vec<u32>,
+
+    /// Distance to the previous occurrence of the hash at each position of
+    /// the window, zero when none
+    previous: Vec<u16>,
+
+    /// Compressed bytes not written out yet
+    output: Vec<u8>,
+
+    /// Bits not forming a full byte yet
+    bits: u32,
+
+    /// Number of bits in `bits`
+    bit_count: u32,
+
+    /// Checksum of the input
+    crc: Crc32,
+
+    /// Number of input bytes, modulo 2^32
+    length: u32,
+}
+
+impl Encoder {
+    /// Allocate an encoder, or return `None` if the heap is exhausted
+    pub fn try_new() -> Option<Self> {
+        let mut output = Vec::new();
+        output.try_reserve_exact(OUTPUT_SIZE).ok()?;
+        let mut encoder = Self {
+            buffer: zeroed(BUFFER_SIZE)?,
+            base: 0,
+            current: 0,
+            end: 0,
+            head: zeroed(HASH_SIZE)?,
+            previous: zeroed(WINDOW_SIZE)?,
+            output,
+            bits: 0,
+            bit_count: 0,
+            crc: Crc32::new(),
+            length: 0,
+        };
+        encoder.output.extend_from_slice(&GZIP_HEADER);
+        // A single final block of fixed Huffman codes
+        encoder.write_bits(1, 1);
+        encoder.write_bits(1, 2);
+        Some(encoder)
+    }
+
+    /// Add input, and return how much of it fit in the buffer
+    ///
+    /// Call [`Encoder::compress`] to make room for the rest.
+    pub fn feed(&mut self, input: &[u8]) -> usize {
+        if self.end == BUFFER_SIZE {
+            // Keep the window before the next byte to encode
+            let shift = self.current.saturating_sub(WINDOW_SIZE);
+            self.buffer.copy_within(shift..self.end, 0);
+            self.base += shift as u32;
+            self.current -= shift;
+            self.end -= shift;
+        }
+        let count = input.len().min(BUFFER_SIZE - self.end);
+        self.buffer[self.end..self.end + count].copy_from_slice(&input[..count]);
+        self.end += count;
+        self.crc.update(&input[..count]);
+        self.length = self.length.wrapping_add(count as u32);
+        count
+    }
+
+    /// Encode the buffered input, and return whether the output buffer
+    /// must be emptied to go on
+    ///
+    /// Unless `finishing`, the last `LOOKAHEAD` bytes are kept for the
+    /// input that follows them, so the output does not depend on how the
+    /// input was split.
+    pub fn compress(&mut self, finishing: bool) -> bool {
+        loop {
+            let available = self.end - self.current;
+            if available == 0 || (!finishing && available < LOOKAHEAD) {
+                return false;
+            }
+            if OUTPUT_SIZE - self.output.len() < SYMBOL_ROOM {
+                return true;
+            }
+
+            let (length, distance) = self.longest_match(available);
+            if length >= MIN_MATCH {
+                self.write_match(length, distance);
+                for _ in 0..length {
+                    self.insert();
+                    self.current += 1;
+                }
+            } else {
+                self.write_literal(self.buffer[self.current]);
+                self.insert();
+                self.current += 1;
+            }
+        }
+    }
+
+    /// Encode the rest of the input and end the stream, and return whether
+    /// the output buffer must be emptied to go on
+    ///
+    /// Once this returns `false`, the output holds the end of the stream.
+    pub fn finish(&mut self) -> bool {
+        if self.compress(true) || OUTPUT_SIZE - self.output.len() < TRAILER_ROOM {
+            return true;
+        }
+        // End of block, then padding to a byte boundary
+        self.write_bits(0, 7);
+        if self.bit_count > 0 {
+            self.output.push(self.bits as u8);
+            self.bits = 0;
+            self.bit_count = 0;
+        }
+        let crc = self.crc.finish();
+        self.output.extend_from_slice(&crc.to_le_bytes());
+        self.output.extend_from_slice(&self.length.to_le_bytes());
+        false
+    }
+
+    /// Return the compressed bytes not written out yet
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Forget the compressed bytes, once written out
+    pub fn take_output(&mut self) {
+        self.output.clear();
+    }
+
+    /// Return the hash of the three bytes at an index of the buffer
+    fn hash(&self, index: usize) -> usize {
+        let bytes = [
+            self.buffer[index],
+            self.buffer[index + 1],
+            self.buffer[index + 2],
+            0,
+        ];
+        (u32::from_le_bytes(bytes).wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    /// Record the position of the next byte to encode in its hash chain
+    fn insert(&mut self) {
+        if self.end - self.current < MIN_MATCH {
+            return;
+        }
+        let hash = self.hash(self.current);
+        let position = self.base + self.current as u32;
+        let latest = self.head[hash];
+        let distance = if latest == 0 {
+            0
+        } else {
+            position + 1 - latest
+        };
+        self.previous[position as usize % WINDOW_SIZE] = if distance < WINDOW_SIZE as u32 {
+            distance as u16
+        } else {
+            0
+        };
+        self.head[hash] = position + 1;
+    }
+
+    /// Return the length and distance of the longest earlier match of the
+    /// next bytes, the length is 0 when there is none
+    fn longest_match(&self, available: usize) -> (usize, usize) {
+        if available < MIN_MATCH {
+            return (0, 0);
+        }
+        let limit = available.min(MAX_MATCH);
+        let position = self.base + self.current as u32;
+        let latest = self.head[self.hash(self.current)];
+        if latest == 0 {
+            return (0, 0);
+        }
+        let mut candidate = latest - 1;
+        let mut best = (0, 0);
+        for _ in 0..MAX_CHAIN {
+            let distance = (position - candidate) as usize;
+            if distance == 0 || distance >= WINDOW_SIZE {
+                break;
+            }
+            let start = self.current - distance;
+            let length = self.buffer[start..start + limit]
+                .iter()
+                .zip(&self.buffer[self.current..self.current + limit])
+                .take_while(|(earlier, next)| earlier == next)
+                .count();
+            if length > best.0 {
+                best = (length, distance);
+                if length == limit {
+                    break;
+                }
+            }
+            let step = self.previous[candidate as usize % WINDOW_SIZE];
+            if step == 0 {
+                break;
+            }
+            candidate -= u32::from(step);
+        }
+        best
+    }
+
+    /// Write a literal byte
+    fn write_literal(&mut self, byte: u8) {
+        if byte < 144 {
+            self.write_code(0x30 + u32::from(byte), 8);
+        } else {
+            self.write_code(0x190 + u32::from(byte) - 144, 9);
+        }
+    }
+
+    /// Write a match of a length and a distance
+    fn write_match(&mut self, length: usize, distance: usize) {
+        let index = symbol(&LENGTH_BASES, length);
+        let code = 257 + index as u32;
+        if code < 280 {
+            self.write_code(code - 256, 7);
+        } else {
+            self.write_code(0xC0 + code - 280, 8);
+        }
+        self.write_bits(
+            (length - usize::from(LENGTH_BASES[index])) as u32,
+            u32::from(LENGTH_EXTRA_BITS[index]),
+        );
+
+        let index = symbol(&DISTANCE_BASES, distance);
+        self.write_code(index as u32, 5);
+        self.write_bits(
+            (distance - usize::from(DISTANCE_BASES[index])) as u32,
+            u32::from(DISTANCE_EXTRA_BITS[index]),
+        );
+    }
+
+    /// Write a Huffman code, which is packed starting from its most
+    /// significant bit
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    /// Write bits, packed starting from the least significant bit
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bits |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.output.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+}
+
+/// A writer compressing what is written through it into another writer
+///
+/// [`GzipWriter::finish`] must be called to end the stream.
+pub struct GzipWriter<W> {
+    writer: W,
+    encoder: Encoder,
+}
+
+impl<W: Write> GzipWriter<W> {
+    /// Compress into a writer with an encoder
+    pub fn new(writer: W, encoder: Encoder) -> Self {
+        Self { writer, encoder }
+    }
+
+    /// Write out the compressed bytes
+    async fn flush_output(&mut self) -> Result<(), W::Error> {
+        if !self.encoder.output().is_empty() {
+            self.writer.write_all(self.encoder.output()).await?;
+            self.encoder.take_output();
+        }
+        Ok(())
+    }
+
+    /// End the stream and write out the rest of it
+    pub async fn finish(mut self) -> Result<(), W::Error> {
+        while self.encoder.finish() {
+            self.flush_output().await?;
+        }
+        self.flush_output().await
+    }
+}
+
+impl<W: Write> ErrorType for GzipWriter<W> {
+    type Error = W::Error;
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    async fn write(&mut self, buffer: &[u8]) -> Result<usize, Self::Error> {
+        let mut input = buffer;
+        while !input.is_empty() {
+            let count = self.encoder.feed(input);
+            input = &input[count..];
+            while self.encoder.compress(false) {
+                self.flush_output().await?;
+            }
+        }
+        Ok(buffer.len())
+    }
+}
+
+/// Return whether the heap leaves room for an encoder
+pub fn heap_allows_encoder() -> bool {
+    esp_alloc::HEAP.free() >= ENCODER_HEAP_SIZE + HEAP_RESERVE
+}
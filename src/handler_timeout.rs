@@ -0,0 +1,236 @@
+//! Deadlines of the request handlers
+//!
+//! Picoserve only times out socket reads and writes, so a handler awaiting a
+//! stuck sensor or a dead upstream would hold its web task forever.
+//! [`HandlerTimeoutLayer`] arms a deadline for the web task when it calls the
+//! handler, and disarms it when the handler starts writing its response.
+//!
+//! A layer cannot answer once it called the handler, since picoserve hands
+//! the connection over with the request. The web task races the connection
+//! against [`expired`] instead: when the deadline passes, the connection
+//! future is dropped, releasing whatever the handler held, and the web task
+//! answers `504 Gateway Timeout` on the socket it lent to picoserve through a
+//! [`ReclaimableSocket`], then closes it.
+
+use core::fmt::Write as _;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::String;
+use picoserve::io::{Read, Socket, Write};
+use picoserve::response::{Connection, Response, ResponseWriter};
+use picoserve::ResponseSent;
+
+use crate::request_log::{truncated, PATH_SIZE};
+use crate::web::{path_has_prefix, AppState, SERVER_TASK_COUNT};
+
+/// Time a handler gets unless its route group sets another
+pub const DEFAULT_HANDLER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time allowed to write the `504` response
+const RESPONSE_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Body of the `504` response, an [`ApiError`](crate::web::ApiError)
+const RESPONSE_BODY: &str =
+    r#"{"code":"handler_timeout","message":"The request took too long to handle"}"#;
+
+/// A deadline armed for the handler of a request
+struct Armed {
+    /// Time the handler must have started its response by
+    at: Instant,
+
+    /// Path of the request, for the log
+    path: String<PATH_SIZE>,
+}
+
+/// Deadline of the handler running on each web task, `None` when disarmed
+static DEADLINES: [Signal<CriticalSectionRawMutex, Option<Armed>>; SERVER_TASK_COUNT] =
+    [const { Signal::new() }; SERVER_TASK_COUNT];
+
+/// Disarm the deadline of a web task, e.g. left over from a previous
+/// connection
+pub fn disarm(task: usize) {
+    DEADLINES[task].signal(None);
+}
+
+/// Wait until the deadline of a handler of a web task passes, and return
+/// the path of its request
+pub async fn expired(task: usize) -> String<PATH_SIZE> {
+    let deadline = &DEADLINES[task];
+    let mut armed = None;
+    loop {
+        armed = match armed {
+            None => deadline.wait().await,
+            Some(Armed { at, path }) => match select(Timer::at(at), deadline.wait()).await {
+                Either::First(()) => return path,
+                Either::Second(armed) => armed,
+            },
+        };
+    }
+}
+
+/// Answer a request whose handler timed out, on the socket of its connection
+///
+/// The response closes the connection, and is given up on if the client
+/// does not take it within a second.
+pub async fn write_timeout_response<S: Socket>(socket: &mut S) {
+    let mut head = String::<160>::new();
+    // Cannot fail, the head is shorter
+    let _ = write!(
+        head,
+        "HTTP/1.1 504 Gateway Timeout\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        RESPONSE_BODY.len()
+    );
+    let (_, mut writer) = socket.split();
+    let write = async {
+        writer.write_all(head.as_bytes()).await?;
+        writer.write_all(RESPONSE_BODY.as_bytes()).await?;
+        writer.flush().await
+    };
+    if embassy_time::with_timeout(RESPONSE_WRITE_TIMEOUT, write)
+        .await
+        .is_err()
+    {
+        crate::log!(warn: "Warning: client did not take the timeout response");
+    }
+}
+
+/// A socket lent to picoserve, which the web task gets back if the
+/// connection future is dropped before shutting it down
+pub struct ReclaimableSocket<'s, S>(pub &'s mut Option<S>);
+
+impl<S: Socket> Socket for ReclaimableSocket<'_, S> {
+    type Error = S::Error;
+    type ReadHalf<'a>
+        = S::ReadHalf<'a>
+    where
+        Self: 'a;
+    type WriteHalf<'a>
+        = S::WriteHalf<'a>
+    where
+        Self: 'a;
+
+    fn split(&mut self) -> (Self::ReadHalf<'_>, Self::WriteHalf<'_>) {
+        self.0
+            .as_mut()
+            .expect("The socket is only taken by the shutdown")
+            .split()
+    }
+
+    async fn shutdown<Timer: picoserve::Timer>(
+        self,
+        timeouts: &picoserve::Timeouts<Timer::Duration>,
+        timer: &mut Timer,
+    ) -> Result<(), picoserve::Error<Self::Error>> {
+        match self.0.take() {
+            Some(socket) => socket.shutdown(timeouts, timer).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Disarms the deadline of a web task when dropped, whichever way the
+/// handler ends
+struct ArmedGuard {
+    task: usize,
+}
+
+impl Drop for ArmedGuard {
+    fn drop(&mut self) {
+        disarm(self.task);
+    }
+}
+
+/// A response writer disarming the deadline once the response starts
+struct DisarmingWriter<W> {
+    task: usize,
+    response_writer: W,
+}
+
+impl<W: ResponseWriter> ResponseWriter for DisarmingWriter<W> {
+    type Error = W::Error;
+
+    async fn write_response<
+        R: Read<Error = Self::Error>,
+        H: picoserve::response::HeadersIter,
+        B: picoserve::response::Body,
+    >(
+        self,
+        connection: Connection<'_, R>,
+        response: Response<H, B>,
+    ) -> Result<ResponseSent, Self::Error> {
+        // Streams like server-sent events run for as long as the client
+        // listens, the write timeout of the socket covers them
+        disarm(self.task);
+        self.response_writer
+            .write_response(connection, response)
+            .await
+    }
+}
+
+/// A layer giving the handlers a deadline to start their response
+///
+/// Route groups are given as a path prefix and their timeout, `None` for
+/// handlers that wait on purpose, like long polls. Other routes get the
+/// default timeout.
+pub struct HandlerTimeoutLayer {
+    default: Duration,
+    routes: &'static [(&'static str, Option<Duration>)],
+}
+
+impl HandlerTimeoutLayer {
+    /// Time out the handlers after `default`, or after the timeout of their
+    /// route group in `routes`
+    pub const fn new(
+        default: Duration,
+        routes: &'static [(&'static str, Option<Duration>)],
+    ) -> Self {
+        Self { default, routes }
+    }
+
+    /// Return the timeout of the handlers of a path, if any
+    pub fn timeout(&self, path: &str) -> Option<Duration> {
+        self.routes
+            .iter()
+            .find(|(prefix, _)| path_has_prefix(path, prefix))
+            .map_or(Some(self.default), |&(_, timeout)| timeout)
+    }
+}
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for HandlerTimeoutLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let Some(timeout) = self.timeout(request_parts.path().encoded()) else {
+            return next.run(state, path_parameters, response_writer).await;
+        };
+
+        let task = state.web_task;
+        DEADLINES[task].signal(Some(Armed {
+            at: Instant::now() + timeout,
+            path: truncated(request_parts.path()),
+        }));
+        let _armed = ArmedGuard { task };
+        let response_writer = DisarmingWriter {
+            task,
+            response_writer,
+        };
+        next.run(state, path_parameters, response_writer).await
+    }
+}
@@ -0,0 +1,65 @@
+//! Allocator statistics reported at `/heap`
+//!
+//! The instantaneous usage hides short spikes such as TLS handshakes, so a
+//! task samples the heap periodically and keeps the highest usage seen.
+
+use embassy_time::{Duration, Ticker};
+use portable_atomic::{AtomicUsize, Ordering};
+use serde::Serialize;
+
+/// Period between two samples of the heap usage
+const SAMPLING_PERIOD: Duration = Duration::from_secs(2);
+
+/// Highest number of used heap bytes sampled since boot
+static HIGH_WATER_MARK: AtomicUsize = AtomicUsize::new(0);
+
+/// Statistics of the heap allocator
+#[derive(Serialize)]
+pub struct HeapStats {
+    /// Number of allocated bytes
+    pub used: usize,
+
+    /// Number of free bytes
+    pub free: usize,
+
+    /// Size of the heap
+    pub total: usize,
+
+    /// Highest number of allocated bytes seen since boot
+    pub high_water_mark: usize,
+}
+
+impl HeapStats {
+    /// Read the current statistics
+    ///
+    /// esp-alloc does not report the largest free block, so fragmentation is
+    /// not visible here.
+    pub fn current() -> Self {
+        let used = sample();
+        let free = esp_alloc::HEAP.free();
+        Self {
+            used,
+            free,
+            total: used + free,
+            high_water_mark: HIGH_WATER_MARK.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Read the heap usage and update the high-water mark
+fn sample() -> usize {
+    let used = esp_alloc::HEAP.used();
+    HIGH_WATER_MARK.fetch_max(used, Ordering::Relaxed);
+    used
+}
+
+/// Sample the heap usage periodically
+#[embassy_executor::task]
+pub async fn heap_monitor_task() {
+    let mut ticker = Ticker::every(SAMPLING_PERIOD);
+    loop {
+        crate::heartbeat!("heap_monitor_task");
+        sample();
+        ticker.next().await;
+    }
+}
@@ -0,0 +1,278 @@
+//! HTML pages built into a bounded buffer
+//!
+//! [`Page`] writes the markup itself and escapes every piece of text it is
+//! given, so a hostname or an error message cannot inject markup. Only the
+//! stylesheet and the scripts are written as is, and must be trusted.
+
+use core::fmt::{self, Display, Write};
+use core::ops::RangeInclusive;
+
+use heapless::String;
+
+/// Stylesheet shared by the pages
+pub const DEFAULT_STYLE: &str = "body{font-family:sans-serif;margin:2em auto;max-width:40em;padding:0 1em}\
+    th{text-align:left;padding-right:1em}\
+    label{display:block;margin:1em 0 .2em}.error{color:#b00}";
+
+/// An HTML page rendered into a bounded buffer
+pub struct Html<const N: usize>(pub String<N>);
+
+impl<const N: usize> picoserve::response::Content for Html<N> {
+    fn content_type(&self) -> &'static str {
+        "text/html; charset=utf-8"
+    }
+
+    fn content_length(&self) -> usize {
+        self.0.len()
+    }
+
+    async fn write_content<W: picoserve::io::Write>(self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(self.0.as_bytes()).await
+    }
+}
+
+/// Text escaped for HTML content and attribute values
+///
+/// `<`, `>`, `&`, `"` and `'` are replaced by character references.
+pub struct Escaped<T>(pub T);
+
+impl<T: Display> Display for Escaped<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// A writer escaping what is written through it
+        struct Escaper<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+        impl Write for Escaper<'_, '_> {
+            fn write_str(&mut self, text: &str) -> fmt::Result {
+                for character in text.chars() {
+                    match character {
+                        '<' => self.0.write_str("&lt;")?,
+                        '>' => self.0.write_str("&gt;")?,
+                        '&' => self.0.write_str("&amp;")?,
+                        '"' => self.0.write_str("&quot;")?,
+                        '\'' => self.0.write_str("&#39;")?,
+                        _ => self.0.write_char(character)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        write!(Escaper(f), "{}", self.0)
+    }
+}
+
+/// A builder of an HTML page
+///
+/// The head is written by [`Page::new`] and closed by the first element of
+/// the body, which starts with the title as heading. Content that does not
+/// fit the buffer is dropped and logged by [`Page::render`].
+pub struct Page<'a, const N: usize> {
+    html: String<N>,
+    title: &'a str,
+    in_body: bool,
+    overflowed: bool,
+}
+
+impl<'a, const N: usize> Page<'a, N> {
+    /// Start a page with the shared stylesheet
+    pub fn new(title: &'a str) -> Self {
+        let mut page = Self {
+            html: String::new(),
+            title,
+            in_body: false,
+            overflowed: false,
+        };
+        page.write(format_args!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+             <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+             <title>{}</title><style>{}</style>",
+            Escaped(title),
+            DEFAULT_STYLE
+        ));
+        page
+    }
+
+    /// Reload the page periodically
+    ///
+    /// This must be called before any element of the body.
+    pub fn refresh(mut self, seconds: u32) -> Self {
+        debug_assert!(!self.in_body, "The refresh must be set in the head");
+        self.write(format_args!("<meta http-equiv=\"refresh\" content=\"{}\">", seconds));
+        self
+    }
+
+    /// Add a paragraph
+    pub fn para(mut self, text: impl Display) -> Self {
+        self.body();
+        self.write(format_args!("<p>{}</p>", Escaped(text)));
+        self
+    }
+
+    /// Add a paragraph styled as an error
+    pub fn error(mut self, text: impl Display) -> Self {
+        self.body();
+        self.write(format_args!("<p class=\"error\">{}</p>", Escaped(text)));
+        self
+    }
+
+    /// Add a table with a header cell and a value cell per row
+    pub fn table<V: Display>(mut self, rows: impl IntoIterator<Item = (&'a str, V)>) -> Self {
+        self.body();
+        self.write(format_args!("<table>"));
+        for (header, value) in rows {
+            self.write(format_args!(
+                "<tr><th>{}</th><td>{}</td></tr>",
+                Escaped(header),
+                Escaped(value)
+            ));
+        }
+        self.write(format_args!("</table>"));
+        self
+    }
+
+    /// Add a table with a header row, identified by `id` for scripts
+    ///
+    /// Every row is given as a class, e.g. `error`, and its cells.
+    pub fn grid<C>(
+        mut self,
+        id: &str,
+        headers: &[&str],
+        rows: impl IntoIterator<Item = (Option<&'static str>, C)>,
+    ) -> Self
+    where
+        C: IntoIterator,
+        C::Item: Display,
+    {
+        self.body();
+        self.write(format_args!("<table id=\"{}\"><tr>", Escaped(id)));
+        for header in headers {
+            self.write(format_args!("<th>{}</th>", Escaped(header)));
+        }
+        self.write(format_args!("</tr>"));
+        for (class, cells) in rows {
+            match class {
+                Some(class) => self.write(format_args!("<tr class=\"{}\">", Escaped(class))),
+                None => self.write(format_args!("<tr>")),
+            }
+            for cell in cells {
+                self.write(format_args!("<td>{}</td>", Escaped(cell)));
+            }
+            self.write(format_args!("</tr>"));
+        }
+        self.write(format_args!("</table>"));
+        self
+    }
+
+    /// Add an inline script
+    ///
+    /// The script is written as is and must be trusted, and allowed by the
+    /// `Content-Security-Policy` of the page.
+    pub fn script(mut self, script: &str) -> Self {
+        self.body();
+        self.write(format_args!("<script>{}</script>", script));
+        self
+    }
+
+    /// Start a form posting to `action`, closed by [`Page::submit`]
+    pub fn form(mut self, action: &str) -> Self {
+        self.body();
+        self.write(format_args!("<form method=\"post\" action=\"{}\">", Escaped(action)));
+        self
+    }
+
+    /// Add a labelled number field to the form
+    pub fn number_input(mut self, name: &str, label: &str, range: RangeInclusive<i64>, value: i64) -> Self {
+        self.label(name, label);
+        self.write(format_args!(
+            "<input id=\"{0}\" name=\"{0}\" type=\"number\" min=\"{1}\" max=\"{2}\" value=\"{3}\">",
+            Escaped(name),
+            range.start(),
+            range.end(),
+            value
+        ));
+        self
+    }
+
+    /// Add a labelled text field to the form
+    pub fn text_input(mut self, name: &str, label: &str, max_length: usize, value: &str) -> Self {
+        self.label(name, label);
+        self.write(format_args!(
+            "<input id=\"{0}\" name=\"{0}\" maxlength=\"{1}\" value=\"{2}\">",
+            Escaped(name),
+            max_length,
+            Escaped(value)
+        ));
+        self
+    }
+
+    /// Add a labelled drop-down list to the form, the first option selected
+    pub fn select<V: Display>(mut self, name: &str, label: &str, options: impl IntoIterator<Item = V>) -> Self {
+        self.label(name, label);
+        self.write(format_args!("<select id=\"{0}\" name=\"{0}\">", Escaped(name)));
+        for option in options {
+            self.write(format_args!("<option>{}</option>", Escaped(option)));
+        }
+        self.write(format_args!("</select>"));
+        self
+    }
+
+    /// Add a labelled password field to the form
+    pub fn password_input(mut self, name: &str, label: &str) -> Self {
+        self.label(name, label);
+        self.write(format_args!(
+            "<input id=\"{0}\" name=\"{0}\" type=\"password\" autocomplete=\"current-password\">",
+            Escaped(name)
+        ));
+        self
+    }
+
+    /// Add a hidden field to the form
+    pub fn hidden_input(mut self, name: &str, value: impl Display) -> Self {
+        self.write(format_args!(
+            "<input name=\"{}\" type=\"hidden\" value=\"{}\">",
+            Escaped(name),
+            Escaped(value)
+        ));
+        self
+    }
+
+    /// Add a submit button and close the form
+    pub fn submit(mut self, label: &str) -> Self {
+        self.write(format_args!(
+            "<p><button type=\"submit\">{}</button></p></form>",
+            Escaped(label)
+        ));
+        self
+    }
+
+    /// Close the page
+    pub fn render(mut self) -> Html<N> {
+        self.body();
+        self.write(format_args!("</body></html>"));
+        if self.overflowed {
+            crate::log!("Page \"{}\" truncated to {} bytes", self.title, N);
+        }
+        Html(self.html)
+    }
+
+    /// Close the head and start the body, once
+    fn body(&mut self) {
+        if !self.in_body {
+            self.in_body = true;
+            let title = self.title;
+            self.write(format_args!("</head><body><h1>{}</h1>", Escaped(title)));
+        }
+    }
+
+    /// Write the label of a form field
+    fn label(&mut self, name: &str, label: &str) {
+        self.write(format_args!("<label for=\"{}\">{}</label>", Escaped(name), Escaped(label)));
+    }
+
+    /// Append markup, remembering whether it did not fit
+    fn write(&mut self, arguments: fmt::Arguments<'_>) {
+        if self.html.write_fmt(arguments).is_err() {
+            self.overflowed = true;
+        }
+    }
+}
@@ -8,6 +8,7 @@
 
 //! HTTP client
 
+use core::net::IpAddr;
 use core::str::from_utf8;
 use core::num::ParseIntError;
 
@@ -18,17 +19,21 @@ use embassy_net::tcp::client::TcpClientState;
 use embassy_net::tcp::ConnectError as TcpConnectError;
 use embassy_net::tcp::Error as TcpError;
 use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+
+use embedded_nal_async::{AddrType, Dns};
 
 use reqwless::client::HttpClient;
 use reqwless::client::TlsConfig;
 use reqwless::client::TlsVerify;
+use reqwless::headers::ContentType;
 use reqwless::request::Method;
 use reqwless::Error as ReqlessError;
 
 use heapless::Vec;
 
 use rand_core::RngCore as _;
-use rtt_target::rprintln;
 use time::error::Parse;
 use time::OffsetDateTime;
 
@@ -46,6 +51,9 @@ pub trait ClientTrait {
     async fn send_request(&mut self, url: &str) -> Result<Vec<u8, RESPONSE_SIZE>, Error>;
 }
 
+/// An HTTP client shared by tasks, one request at a time
+pub type SharedClient = Mutex<CriticalSectionRawMutex, Client>;
+
 /// HTTP client
 pub struct Client {
     /// Wifi stack
@@ -67,7 +75,7 @@ pub struct Client {
 impl Client {
     /// Create a new client
     pub fn new(stack: Stack<'static>, rng: RngWrapper) -> Self {
-        rprintln!("Create TCP client state");
+        crate::log!(debug: "Create TCP client state");
         let tcp_client_state = TcpClientState::<2, 4096, 4096>::new();
 
         Self {
@@ -98,17 +106,52 @@ impl Client {
         };
         let utc_result = OffsetDateTime::from_unix_timestamp(timestamp);
         let utc = utc_result.unwrap(); // We assume the timestamp is valid
-        rprintln!("Current UTC time: {}", utc);
+        crate::log!("Current UTC time: {}", utc);
         Ok(utc)
     }
+
+    /// Send a JSON body in a POST request
+    ///
+    /// Responses with a status code other than 2xx are errors.
+    pub async fn post_json(&mut self, url: &str, body: &[u8]) -> Result<(), Error> {
+        crate::log!(debug: "Send HTTP POST request to {}", url);
+
+        let resolver = Resolver::new(self.stack);
+
+        let seed = self.rng.next_u64();
+        let tls_config = TlsConfig::new(
+            seed,
+            &mut self.read_record_buffer,
+            &mut self.write_record_buffer,
+            TlsVerify::None,
+        );
+
+        let tcp_client = TcpClient::new(self.stack, &self.tcp_client_state);
+        let mut client = HttpClient::new_with_tls(&tcp_client, &resolver, tls_config);
+
+        let mut buffer = [0_u8; 1024];
+        let mut request = client
+            .request(Method::POST, url)
+            .await?
+            .body(body)
+            .content_type(ContentType::ApplicationJson);
+        let response = request.send(&mut buffer).await?;
+
+        crate::log!(debug: "Response status: {:?}", response.status);
+        if response.status.is_successful() {
+            Ok(())
+        } else {
+            Err(Error::Status)
+        }
+    }
 }
 
 impl ClientTrait for Client {
     async fn send_request(&mut self, url: &str) -> Result<Vec<u8, RESPONSE_SIZE>, Error> {
-        rprintln!("Send HTTPs request to {}", url);
+        crate::log!(debug: "Send HTTPs request to {}", url);
 
-        rprintln!("Create DNS socket");
-        let dns_socket = DnsSocket::new(self.stack);
+        crate::log!(debug: "Create DNS resolver");
+        let resolver = Resolver::new(self.stack);
 
         let seed = self.rng.next_u64();
         let tls_config = TlsConfig::new(
@@ -118,24 +161,24 @@ impl ClientTrait for Client {
             TlsVerify::None,
         );
 
-        rprintln!("Create TCP client");
+        crate::log!(debug: "Create TCP client");
         let tcp_client = TcpClient::new(self.stack, &self.tcp_client_state);
 
-        rprintln!("Create HTTP client");
-        let mut client = HttpClient::new_with_tls(&tcp_client, &dns_socket, tls_config);
+        crate::log!(debug: "Create HTTP client");
+        let mut client = HttpClient::new_with_tls(&tcp_client, &resolver, tls_config);
 
-        rprintln!("Create HTTP request");
+        crate::log!(debug: "Create HTTP request");
         let mut buffer = [0_u8; 4096];
         let mut request = client.request(Method::GET, url).await?;
 
-        rprintln!("Send HTTP request");
+        crate::log!(debug: "Send HTTP request");
         let response = request.send(&mut buffer).await?;
 
-        rprintln!("Response status: {:?}", response.status);
+        crate::log!(debug: "Response status: {:?}", response.status);
 
         let buffer = response.body().read_to_end().await?;
 
-        rprintln!("Read {} bytes", buffer.len());
+        crate::log!(debug: "Read {} bytes", buffer.len());
 
         let output =
             Vec::<u8, RESPONSE_SIZE>::from_slice(buffer).map_err(|()| Error::ResponseTooLarge)?;
@@ -144,12 +187,62 @@ impl ClientTrait for Client {
     }
 }
 
+/// A DNS resolver preferring the address family the station has
+///
+/// Names are resolved to IPv4 addresses when the station has one, falling
+/// back to IPv6 addresses for names without any, and to IPv6 addresses
+/// otherwise.
+struct Resolver<'a> {
+    socket: DnsSocket<'a>,
+    stack: Stack<'a>,
+}
+
+impl<'a> Resolver<'a> {
+    /// Create a resolver querying the DNS servers of a stack
+    fn new(stack: Stack<'a>) -> Self {
+        Self {
+            socket: DnsSocket::new(stack),
+            stack,
+        }
+    }
+}
+
+impl Dns for Resolver<'_> {
+    type Error = DnsError;
+
+    async fn get_host_by_name(&self, host: &str, addr_type: AddrType) -> Result<IpAddr, DnsError> {
+        if !matches!(addr_type, AddrType::Either) {
+            return self.socket.get_host_by_name(host, addr_type).await;
+        }
+        if self.stack.config_v4().is_none() {
+            return self.socket.get_host_by_name(host, AddrType::IPv6).await;
+        }
+        match self.socket.get_host_by_name(host, AddrType::IPv4).await {
+            Err(error) if crate::slaac::global_config(self.stack).is_some() => {
+                crate::log!(debug: "No IPv4 address for {}, trying IPv6", host);
+                self.socket
+                    .get_host_by_name(host, AddrType::IPv6)
+                    .await
+                    .map_err(|_| error)
+            }
+            result => result,
+        }
+    }
+
+    async fn get_host_by_address(&self, addr: IpAddr, result: &mut [u8]) -> Result<usize, DnsError> {
+        self.socket.get_host_by_address(addr, result).await
+    }
+}
+
 /// An error within an HTTP request
 #[derive(Debug)]
 pub enum Error {
     /// Response was too large
     ResponseTooLarge,
 
+    /// Server answered with a status code other than 2xx
+    Status,
+
     /// Error within TCP streams
     Tcp(TcpError),
 
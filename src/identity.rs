@@ -0,0 +1,156 @@
+//! Identity of the device reported at `/id`
+//!
+//! The station MAC address is read when Wi-Fi starts, and the device name is
+//! derived from it, so it stays the same across reboots and firmware updates.
+//! Anything advertising the device on the network should use
+//! [`device_name`], so every name of a device matches.
+
+use core::fmt::Write as _;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::once_lock::OnceLock;
+use heapless::String;
+use serde::Serialize;
+
+use crate::settings::{DEFAULT_HOSTNAME, MAX_HOSTNAME_SIZE};
+
+/// Prefix of the device names
+pub const DEVICE_NAME_PREFIX: &str = "picoserve-";
+
+/// Length of a device name, the prefix and three bytes in hexadecimal
+pub const DEVICE_NAME_SIZE: usize = DEVICE_NAME_PREFIX.len() + 6;
+
+/// Length of a MAC address formatted as `aa:bb:cc:dd:ee:ff`
+pub const MAC_ADDRESS_SIZE: usize = 17;
+
+/// Station MAC address, set once when Wi-Fi starts
+static MAC_ADDRESS: OnceLock<[u8; 6]> = OnceLock::new();
+
+/// Remember the station MAC address
+///
+/// Only the first address is kept.
+pub fn set_mac_address(mac: [u8; 6]) {
+    let _ = MAC_ADDRESS.init(mac);
+}
+
+/// Return the station MAC address, if Wi-Fi was started
+pub fn mac_address() -> Option<[u8; 6]> {
+    MAC_ADDRESS.try_get().copied()
+}
+
+/// Return the name of a device, e.g. `picoserve-a1b2c3`
+///
+/// The name uses the last three bytes of the MAC address, which are specific
+/// to the device while the first three identify the manufacturer.
+pub fn device_name(mac: &[u8; 6]) -> String<DEVICE_NAME_SIZE> {
+    let mut name = String::new();
+    // Cannot fail, the name has a fixed length
+    let _ = write!(
+        name,
+        "{DEVICE_NAME_PREFIX}{:02x}{:02x}{:02x}",
+        mac[3], mac[4], mac[5]
+    );
+    name
+}
+
+/// Return the hostname a device uses on the network, for DHCP and mDNS
+///
+/// This is the `configured` hostname, unless it was left to
+/// [`DEFAULT_HOSTNAME`]: then it is the name of the device, like
+/// `picoserve-a1b2c3`, so that several devices do not claim the same name.
+pub fn hostname(configured: String<MAX_HOSTNAME_SIZE>, mac: &[u8; 6]) -> String<MAX_HOSTNAME_SIZE> {
+    if configured != DEFAULT_HOSTNAME {
+        return configured;
+    }
+    let mut name = String::new();
+    // Cannot fail, device names are shorter than hostnames
+    let _ = name.push_str(&device_name(mac));
+    name
+}
+
+/// Length of the password of the provisioning access point
+pub const ACCESS_POINT_PASSWORD_SIZE: usize = 16;
+
+/// Password of the provisioning access point, set once before Wi-Fi starts
+static ACCESS_POINT_PASSWORD: OnceLock<String<ACCESS_POINT_PASSWORD_SIZE>> = OnceLock::new();
+
+/// Format random bytes as a WPA2 password for the provisioning access point
+///
+/// The password is generated on first boot and kept in the key-value store,
+/// see [`crate::provisioning::load_access_point_password`]. Anything derived
+/// from the MAC address could be computed from the beacons of the access
+/// point, random bytes cannot.
+pub fn access_point_password_from(
+    random: &[u8; ACCESS_POINT_PASSWORD_SIZE / 2],
+) -> String<ACCESS_POINT_PASSWORD_SIZE> {
+    let mut password = String::new();
+    for byte in random {
+        // Cannot fail, the password has a fixed length
+        let _ = write!(password, "{byte:02x}");
+    }
+    password
+}
+
+/// Remember the password of the provisioning access point
+///
+/// Only the first password is kept.
+pub fn set_access_point_password(password: String<ACCESS_POINT_PASSWORD_SIZE>) {
+    let _ = ACCESS_POINT_PASSWORD.init(password);
+}
+
+/// Return the password of the provisioning access point, if it was loaded
+pub fn access_point_password() -> Option<&'static str> {
+    ACCESS_POINT_PASSWORD.try_get().map(String::as_str)
+}
+
+/// Format a MAC address as `aa:bb:cc:dd:ee:ff`
+pub fn format_mac_address(mac: &[u8; 6]) -> String<MAC_ADDRESS_SIZE> {
+    let mut text = String::new();
+    for (index, byte) in mac.iter().enumerate() {
+        let separator = if index == 0 { "" } else { ":" };
+        // Cannot fail, the address has a fixed length
+        let _ = write!(text, "{separator}{byte:02x}");
+    }
+    text
+}
+
+/// JSON body returned by `/id`
+#[derive(Debug, Serialize)]
+pub struct DeviceIdentity {
+    /// Station MAC address
+    pub mac: String<MAC_ADDRESS_SIZE>,
+
+    /// Name derived from the MAC address
+    pub device_id: String<DEVICE_NAME_SIZE>,
+
+    /// Firmware version
+    pub version: &'static str,
+
+    /// Chip model, e.g. `esp32c3`
+    pub chip: &'static str,
+
+    /// Chip revision, e.g. `v0.4`
+    pub revision: String<8>,
+}
+
+impl DeviceIdentity {
+    /// Describe the device, once Wi-Fi was started
+    pub fn current() -> Option<Self> {
+        let mac = mac_address()?;
+        let mut revision = String::new();
+        // Cannot fail, versions are single bytes
+        let _ = write!(
+            revision,
+            "v{}.{}",
+            esp_hal::efuse::Efuse::major_chip_version(),
+            esp_hal::efuse::Efuse::minor_chip_version()
+        );
+        Some(Self {
+            mac: format_mac_address(&mac),
+            device_id: device_name(&mac),
+            version: env!("CARGO_PKG_VERSION"),
+            chip: esp_hal::chip!(),
+            revision,
+        })
+    }
+}
@@ -0,0 +1,103 @@
+//! Limit of the requests served on a kept-alive connection
+//!
+//! Picoserve drops a kept-alive connection once it stays idle past the
+//! persistent read timeout of [`picoserve::Config`], but not a client sending
+//! requests back to back, which would hold its web task forever.
+//! [`RequestLimitLayer`] counts the requests of the connection on each web
+//! task, and signals [`reached`] once the last allowed response is written.
+//! Like with [`crate::handler_timeout::expired`], the web task races the
+//! connection against it, then flushes and closes the socket.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Duration;
+use picoserve::io::{Read, Socket, Write};
+use picoserve::response::ResponseWriter;
+use picoserve::ResponseSent;
+use portable_atomic::{AtomicUsize, Ordering};
+
+use crate::web::{AppState, SERVER_TASK_COUNT};
+
+/// Requests served on a connection before it is closed by default
+pub const DEFAULT_MAX_REQUESTS_PER_CONNECTION: usize = 100;
+
+/// Time allowed to send the last response before closing
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Requests served on the connection of each web task
+static COUNTS: [AtomicUsize; SERVER_TASK_COUNT] =
+    [const { AtomicUsize::new(0) }; SERVER_TASK_COUNT];
+
+/// Signaled once the connection of each web task served its last request
+static REACHED: [Signal<CriticalSectionRawMutex, ()>; SERVER_TASK_COUNT] =
+    [const { Signal::new() }; SERVER_TASK_COUNT];
+
+/// Start counting the requests of a new connection on a web task
+pub fn reset(task: usize) {
+    COUNTS[task].store(0, Ordering::Relaxed);
+    REACHED[task].reset();
+}
+
+/// Count a request served on the connection of a web task, and signal
+/// [`reached`] once it is the `max`th
+pub fn served(task: usize, max: usize) {
+    let count = COUNTS[task].fetch_add(1, Ordering::Relaxed) + 1;
+    if count >= max {
+        REACHED[task].signal(());
+    }
+}
+
+/// Wait until the connection of a web task served its last request, and
+/// return the number of requests
+pub async fn reached(task: usize) -> usize {
+    REACHED[task].wait().await;
+    COUNTS[task].load(Ordering::Relaxed)
+}
+
+/// Send what is left of the last response of a connection, before the web
+/// task drops its socket
+///
+/// The response is given up on if the client does not take it within a
+/// second.
+pub async fn close<S: Socket>(socket: &mut S) {
+    let (_, mut writer) = socket.split();
+    if embassy_time::with_timeout(FLUSH_TIMEOUT, writer.flush())
+        .await
+        .is_err()
+    {
+        crate::log!(warn: "Warning: client did not take the last response");
+    }
+}
+
+/// A layer closing connections after
+/// [`AppState::max_requests_per_connection`] requests, see [`reached`]
+///
+/// Without a limit, requests are passed through untouched.
+pub struct RequestLimitLayer;
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for RequestLimitLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        _request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let Some(max) = state.max_requests_per_connection else {
+            return next.run(state, path_parameters, response_writer).await;
+        };
+
+        let sent = next.run(state, path_parameters, response_writer).await?;
+        served(state.web_task, max);
+        Ok(sent)
+    }
+}
@@ -0,0 +1,149 @@
+//! Status LED controlled over HTTP
+//!
+//! The LED is either steady or blinking. Blinking is driven by
+//! [`led_task`], which idles until the mode changes when the LED is steady.
+
+use core::cell::RefCell;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Ticker};
+use esp_hal::gpio::{Level, Output};
+use serde::Serialize;
+
+/// Valid range for the blink period, in milliseconds
+pub const BLINK_PERIOD_RANGE: core::ops::RangeInclusive<u32> = 50..=10_000;
+
+/// The mode of the LED
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LedMode {
+    /// Steady off
+    Off,
+
+    /// Steady on
+    On,
+
+    /// Toggled every half period
+    Blink {
+        /// Duration of a full on/off cycle in milliseconds
+        period_ms: u32,
+    },
+}
+
+/// The state of the LED reported over HTTP
+#[derive(Serialize)]
+pub struct LedState {
+    /// Whether the LED is currently lit
+    pub lit: bool,
+
+    /// The current mode: `off`, `on` or `blink`
+    pub mode: &'static str,
+
+    /// The blink period in milliseconds, when blinking
+    pub period_ms: Option<u32>,
+}
+
+impl LedState {
+    /// Describe an LED in a mode
+    fn new(output: &Output<'static>, mode: LedMode) -> Self {
+        let (mode, period_ms) = match mode {
+            LedMode::Off => ("off", None),
+            LedMode::On => ("on", None),
+            LedMode::Blink { period_ms } => ("blink", Some(period_ms)),
+        };
+        Self {
+            lit: output.is_set_high(),
+            mode,
+            period_ms,
+        }
+    }
+}
+
+/// An LED and its mode
+pub struct Led {
+    inner: Mutex<CriticalSectionRawMutex, RefCell<Option<(Output<'static>, LedMode)>>>,
+    changes: Signal<CriticalSectionRawMutex, LedMode>,
+}
+
+impl Led {
+    /// Wrap an output pin driving the LED, initially off
+    pub fn new(mut output: Output<'static>) -> Self {
+        output.set_level(Level::Low);
+        Self {
+            inner: Mutex::new(RefCell::new(Some((output, LedMode::Off)))),
+            changes: Signal::new(),
+        }
+    }
+
+    /// Create a placeholder for boards without an LED
+    pub const fn absent() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(None)),
+            changes: Signal::new(),
+        }
+    }
+
+    /// Return the state of the LED, or `None` if there is none
+    pub fn state(&self) -> Option<LedState> {
+        self.inner.lock(|inner| {
+            inner
+                .borrow()
+                .as_ref()
+                .map(|(output, mode)| LedState::new(output, *mode))
+        })
+    }
+
+    /// Change the mode and return the new state, or `None` if there is no LED
+    ///
+    /// Steady modes take effect immediately, blinking starts from the lit
+    /// state.
+    pub fn set_mode(&self, mode: LedMode) -> Option<LedState> {
+        let state = self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            let (output, current) = inner.as_mut()?;
+            *current = mode;
+            let level = if matches!(mode, LedMode::Off) { Level::Low } else { Level::High };
+            output.set_level(level);
+            Some(LedState::new(output, mode))
+        })?;
+        self.changes.signal(mode);
+        Some(state)
+    }
+
+    /// Toggle the LED if it is still blinking
+    fn blink(&self) {
+        self.inner.lock(|inner| {
+            if let Some((output, LedMode::Blink { .. })) = inner.borrow_mut().as_mut() {
+                output.toggle();
+            }
+        });
+    }
+}
+
+/// Blink the LED while it is in [`LedMode::Blink`]
+#[embassy_executor::task]
+pub async fn led_task(led: &'static Led) {
+    let mut mode = LedMode::Off;
+    loop {
+        crate::heartbeat!("led_task");
+        mode = match mode {
+            LedMode::Blink { period_ms } => {
+                let mut ticker = Ticker::every(Duration::from_millis(u64::from(period_ms / 2)));
+                loop {
+                    match select(led.changes.wait(), ticker.next()).await {
+                        Either::First(mode) => break mode,
+                        Either::Second(()) => {
+                            crate::heartbeat!("led_task");
+                            led.blink();
+                        }
+                    }
+                }
+            }
+            LedMode::Off | LedMode::On => {
+                crate::tasks::beating("led_task", None, led.changes.wait()).await
+            }
+        };
+    }
+}
@@ -2,10 +2,63 @@
 #![feature(impl_trait_in_assoc_type)]
 
 pub mod web;
+pub mod adc;
+pub mod api;
+pub mod boot;
+pub mod cache;
+pub mod cache_control;
+pub mod captive;
+pub mod changes;
+pub mod cidr;
 pub mod wifi;
 pub mod clock;
+pub mod compression;
+pub mod config;
+pub mod dhcp_server;
+pub mod dns_server;
+pub mod etag;
+pub mod files;
+pub mod gpio;
+pub mod handler_timeout;
+pub mod heap;
+pub mod html;
+pub mod identity;
+pub mod keep_alive;
+pub mod led;
+pub mod log_sink;
 pub mod http;
+pub mod mdns;
+pub mod metrics;
+pub mod multipart;
+pub mod negotiation;
+pub mod netstats;
+pub mod provisioning;
 pub mod random;
+pub mod range;
+pub mod request_id;
+pub mod request_log;
+pub mod restart;
+pub mod routes;
+pub mod scheduler;
+pub mod security_headers;
+pub mod session;
+pub mod settings;
+pub mod slaac;
+pub mod sse;
+pub mod static_assets;
+pub mod stats;
+pub mod storage;
+pub mod tasks;
+pub mod time_sync;
+pub mod timers;
+pub mod webhooks;
+pub mod well_known;
+#[cfg(feature = "gzip")]
+pub mod gzip;
+#[cfg(feature = "temperature")]
+pub mod temperature;
+#[cfg(feature = "tls")]
+pub mod tls;
 
 #[macro_export]
 macro_rules! mk_static {
@@ -0,0 +1,209 @@
+//! Log lines kept in RAM for the `/ws/logs` route
+//!
+//! The [`log!`](crate::log) macro prints a line over RTT and appends it to a
+//! ring buffer. Every line gets a sequence number, so readers can replay the
+//! buffer, wait for new lines, and tell how many lines they missed when they
+//! fell behind.
+//!
+//! Lines below the [`level`] set at runtime, at `/loglevel`, are neither
+//! printed nor buffered.
+
+use core::cell::RefCell;
+use core::fmt::{self, Write as _};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::watch::{Receiver, Watch};
+use embassy_time::Instant;
+use heapless::{Deque, String};
+use portable_atomic::{AtomicU8, Ordering};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Clock;
+use crate::request_log::truncated;
+use crate::web::WEB_TASK_POOL_SIZE;
+
+/// Number of lines kept in the buffer
+pub const LOG_SIZE: usize = 32;
+
+/// Maximal length of a line, longer lines are truncated
+pub const LINE_SIZE: usize = 128;
+
+/// Print a line over RTT and append it to the log buffer, if its level is
+/// enabled
+///
+/// The level is written before the format string, e.g.
+/// `log!(debug: "Read {} bytes", length)`, and is `info` when omitted.
+#[macro_export]
+macro_rules! log {
+    (error: $($arg:tt)*) => { $crate::log!(@ $crate::log_sink::LogLevel::Error, $($arg)*) };
+    (warn: $($arg:tt)*) => { $crate::log!(@ $crate::log_sink::LogLevel::Warn, $($arg)*) };
+    (info: $($arg:tt)*) => { $crate::log!(@ $crate::log_sink::LogLevel::Info, $($arg)*) };
+    (debug: $($arg:tt)*) => { $crate::log!(@ $crate::log_sink::LogLevel::Debug, $($arg)*) };
+    (trace: $($arg:tt)*) => { $crate::log!(@ $crate::log_sink::LogLevel::Trace, $($arg)*) };
+    (@ $level:expr, $($arg:tt)*) => {{
+        if $crate::log_sink::enabled($level) {
+            rtt_target::rprintln!($($arg)*);
+            $crate::log_sink::push(format_args!($($arg)*));
+        }
+    }};
+    ($($arg:tt)*) => { $crate::log!(info: $($arg)*) };
+}
+
+/// Severity of a log line, and the threshold of the lines kept
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum LogLevel {
+    /// No line at all, only used as a threshold
+    Off,
+
+    /// Failures needing attention
+    Error,
+
+    /// Unexpected but handled conditions
+    Warn,
+
+    /// Events of the device, the default threshold
+    Info,
+
+    /// Every request and the steps of the HTTP client
+    Debug,
+
+    /// Everything
+    Trace,
+}
+
+impl LogLevel {
+    /// Every level, from the quietest
+    pub const ALL: [Self; 6] = [Self::Off, Self::Error, Self::Warn, Self::Info, Self::Debug, Self::Trace];
+
+    /// Return the name of the level, as accepted by `PUT /loglevel`
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
+
+impl core::str::FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|level| level.as_str().eq_ignore_ascii_case(name))
+            .ok_or(())
+    }
+}
+
+/// Level of the quietest lines kept
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Return the level of the quietest lines kept
+pub fn level() -> LogLevel {
+    LogLevel::ALL[usize::from(LEVEL.load(Ordering::Relaxed))]
+}
+
+/// Keep the lines of a level and of more severe ones only
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Check whether lines of a level are kept
+pub fn enabled(level: LogLevel) -> bool {
+    level != LogLevel::Off && level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// A log line with its sequence number
+pub type Line = (u32, String<LINE_SIZE>);
+
+/// The buffered lines and the sequence number of the next one
+struct LogBuffer {
+    lines: Deque<Line, LOG_SIZE>,
+    next_sequence: u32,
+}
+
+/// The most recent lines, oldest first
+static LOG: Mutex<CriticalSectionRawMutex, RefCell<LogBuffer>> = Mutex::new(RefCell::new(LogBuffer {
+    lines: Deque::new(),
+    next_sequence: 0,
+}));
+
+/// The clock used for timestamps, once it is known
+static CLOCK: Mutex<CriticalSectionRawMutex, RefCell<Option<Clock>>> = Mutex::new(RefCell::new(None));
+
+/// Notifies readers of the sequence number of the next line
+static NEXT_SEQUENCE: Watch<CriticalSectionRawMutex, u32, WEB_TASK_POOL_SIZE> = Watch::new();
+
+/// Timestamp lines with wall-clock time from now on
+pub fn set_clock(clock: Clock) {
+    CLOCK.lock(|current| *current.borrow_mut() = Some(clock));
+}
+
+/// Append a line, dropping the oldest one when full
+///
+/// Lines are prefixed with the wall-clock time when the clock is
+/// synchronized, and with the uptime otherwise.
+pub fn push(arguments: fmt::Arguments<'_>) {
+    let mut line = String::<LINE_SIZE>::new();
+    let time = CLOCK.lock(|clock| {
+        clock
+            .borrow()
+            .as_ref()
+            .filter(|clock| clock.is_synchronized())
+            .and_then(|clock| clock.now().ok())
+    });
+    let _ = match time {
+        Some(time) => crate::web::write_iso8601(&mut line, &time).and_then(|()| line.write_char(' ')),
+        None => write!(line, "+{}ms ", Instant::now().as_millis()),
+    };
+    let text: String<LINE_SIZE> = truncated(arguments);
+    let _ = line.push_str(&text[..text.len().min(LINE_SIZE - line.len())]);
+
+    let next_sequence = LOG.lock(|log| {
+        let mut log = log.borrow_mut();
+        if log.lines.is_full() {
+            log.lines.pop_front();
+        }
+        let sequence = log.next_sequence;
+        // Cannot fail, there is room for at least one line
+        let _ = log.lines.push_back((sequence, line));
+        log.next_sequence = sequence.wrapping_add(1);
+        log.next_sequence
+    });
+    NEXT_SEQUENCE.sender().send(next_sequence);
+}
+
+/// Return the sequence number of the oldest buffered line
+pub fn oldest_sequence() -> u32 {
+    LOG.lock(|log| {
+        let log = log.borrow();
+        log.lines.front().map_or(log.next_sequence, |(sequence, _)| *sequence)
+    })
+}
+
+/// Return the first buffered line with a sequence number of at least
+/// `sequence`, and how many lines before it were dropped from the buffer
+pub fn line_from(sequence: u32) -> Option<(u32, Line)> {
+    LOG.lock(|log| {
+        let log = log.borrow();
+        let (oldest, _) = log.lines.front()?;
+        let dropped = oldest.saturating_sub(sequence);
+        let line = log
+            .lines
+            .iter()
+            .find(|(line_sequence, _)| *line_sequence >= sequence)?;
+        Some((dropped, line.clone()))
+    })
+}
+
+/// Subscribe to new lines, or `None` if every subscription is taken
+pub fn subscribe() -> Option<Receiver<'static, CriticalSectionRawMutex, u32, WEB_TASK_POOL_SIZE>> {
+    NEXT_SEQUENCE.receiver()
+}
@@ -0,0 +1,588 @@
+//! mDNS responder, so the device is reachable as `<hostname>.local`
+//!
+//! The responder runs on the station interface, joined to
+//! [`MULTICAST_ADDRESS`]. It answers `A` queries for the hostname, and
+//! advertises the web server as an `_http._tcp` service for DNS-SD browsers,
+//! with its port in the `SRV` record.
+//!
+//! Questions with the unicast-response (QU) bit set are answered to the
+//! sender, others (QM) to the group. Queries from a port other than
+//! [`PORT`] come from plain resolvers, and get a conventional unicast DNS
+//! response echoing their identifier and question.
+//!
+//! The records are announced when the task starts, and withdrawn with a
+//! goodbye, the same records with a zero TTL, before a reset requested with
+//! [`crate::restart::request_restart`]. The responder does not probe for
+//! conflicts: the default hostname is derived from the MAC address, see
+//! [`hostname`].
+
+use embassy_futures::select::{select, Either};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Timer};
+use heapless::String;
+
+use crate::dns_server::{read_name, HEADER_SIZE};
+use crate::settings::{Settings, MAX_HOSTNAME_SIZE};
+
+/// Port of mDNS
+pub const PORT: u16 = 5353;
+
+/// Group the responder joins and multicasts to
+pub const MULTICAST_ADDRESS: Ipv4Address = Ipv4Address::new(224, 0, 0, 251);
+
+/// Largest message handled
+pub const MAX_MESSAGE_SIZE: usize = 512;
+
+/// Time the records of the hostname may be cached in seconds
+pub const HOST_TTL: u32 = 120;
+
+/// Time the other records may be cached in seconds
+pub const SERVICE_TTL: u32 = 4500;
+
+/// Largest TTL of the responses to plain resolvers in seconds
+pub const LEGACY_TTL: u32 = 10;
+
+/// Labels of the advertised service type
+pub const SERVICE_TYPE: [&str; 3] = ["_http", "_tcp", "local"];
+
+/// Labels of the name DNS-SD browsers enumerate service types with
+pub const SERVICE_TYPE_ENUMERATION: [&str; 4] = ["_services", "_dns-sd", "_udp", "local"];
+
+/// Text of the `TXT` record of the service, the path of the web app
+pub const SERVICE_TEXT: &str = "path=/";
+
+/// Number of announcements sent when the task starts
+const ANNOUNCEMENT_COUNT: usize = 2;
+
+/// Delay between the announcements
+const ANNOUNCEMENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time a reset waits for the goodbye to be sent
+const GOODBYE_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Record types
+pub mod record_type {
+    pub const A: u16 = 1;
+    pub const PTR: u16 = 12;
+    pub const TXT: u16 = 16;
+    pub const SRV: u16 = 33;
+    pub const ANY: u16 = 255;
+}
+
+/// Class of internet records
+const CLASS_IN: u16 = 1;
+
+/// Class matching every class
+const CLASS_ANY: u16 = 255;
+
+/// Bit of the class of questions asking for a unicast response
+pub const UNICAST_RESPONSE: u16 = 0x8000;
+
+/// Bit of the class of unique records, telling caches to flush older ones
+pub const CACHE_FLUSH: u16 = 0x8000;
+
+/// Flags of responses: response, authoritative
+const RESPONSE_FLAGS: [u8; 2] = [0x84, 0x00];
+
+/// Flag of responses
+const FLAG_RESPONSE: u8 = 0x80;
+
+/// Mask of the opcode in the first flag byte
+const OPCODE_MASK: u8 = 0x78;
+
+/// Records the responder owns
+///
+/// They are a bit set, written in the order of the bits.
+mod records {
+    /// `A` record of the hostname
+    pub const ADDRESS: u8 = 0x01;
+    /// `PTR` record from the service type enumeration to the service type
+    pub const SERVICE_TYPE: u8 = 0x02;
+    /// `PTR` record from the service type to the service instance
+    pub const SERVICE: u8 = 0x04;
+    /// `SRV` record of the service instance
+    pub const LOCATION: u8 = 0x08;
+    /// `TXT` record of the service instance
+    pub const TEXT: u8 = 0x10;
+    pub const ALL: u8 = ADDRESS | SERVICE_TYPE | SERVICE | LOCATION | TEXT;
+}
+
+/// Return the hostname the device answers to, without `.local`
+///
+/// This is the hostname of the settings, the one sent to the DHCP server as
+/// well, see [`crate::identity::hostname`].
+pub fn hostname(settings: &Settings, mac: &[u8; 6]) -> String<MAX_HOSTNAME_SIZE> {
+    crate::identity::hostname(settings.hostname(), mac)
+}
+
+/// Return whether `name`, in wire format, is made of `labels`
+///
+/// Names compare without regard to case. Length bytes are below 64, so they
+/// are not changed by the conversion.
+fn is_name(name: &[u8], labels: &[&str]) -> bool {
+    let mut rest = name;
+    for label in labels {
+        match rest.split_first() {
+            Some((&length, tail))
+                if usize::from(length) == label.len()
+                    && tail.len() > label.len()
+                    && tail[..label.len()].eq_ignore_ascii_case(label.as_bytes()) =>
+            {
+                rest = &tail[label.len()..];
+            }
+            _ => return false,
+        }
+    }
+    rest == [0]
+}
+
+/// A response being written
+struct Writer<'a> {
+    buffer: &'a mut [u8; MAX_MESSAGE_SIZE],
+    length: usize,
+}
+
+impl Writer<'_> {
+    fn push(&mut self, bytes: &[u8]) -> Option<()> {
+        let end = self.length + bytes.len();
+        self.buffer
+            .get_mut(self.length..end)?
+            .copy_from_slice(bytes);
+        self.length = end;
+        Some(())
+    }
+
+    fn name(&mut self, labels: &[&str]) -> Option<()> {
+        for label in labels {
+            self.push(&[u8::try_from(label.len()).ok()?])?;
+            self.push(label.as_bytes())?;
+        }
+        self.push(&[0])
+    }
+
+    /// Write a record, with data written by `data`
+    fn record(
+        &mut self,
+        name: &[&str],
+        record_type: u16,
+        class: u16,
+        ttl: u32,
+        data: impl FnOnce(&mut Self) -> Option<()>,
+    ) -> Option<()> {
+        self.name(name)?;
+        self.push(&record_type.to_be_bytes())?;
+        self.push(&class.to_be_bytes())?;
+        self.push(&ttl.to_be_bytes())?;
+        let data_start = self.length + 2;
+        self.push(&[0, 0])?;
+        data(self)?;
+        let data_length = u16::try_from(self.length - data_start).ok()?;
+        self.buffer[data_start - 2..data_start].copy_from_slice(&data_length.to_be_bytes());
+        Some(())
+    }
+}
+
+/// How a response is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    /// To the group, for questions without the unicast-response bit
+    Multicast,
+    /// To the sender of the query
+    Unicast,
+}
+
+/// A response to a query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Response {
+    /// Length of the response in the buffer
+    pub length: usize,
+    pub destination: Destination,
+}
+
+/// The records of the device
+pub struct Responder<'a> {
+    /// Hostname, without `.local`
+    pub hostname: &'a str,
+    /// Address of the station, the `A` record is left out without one
+    pub address: Option<Ipv4Address>,
+    /// Port of the web server
+    pub port: u16,
+}
+
+impl Responder<'_> {
+    /// Answer a query, and write the response
+    ///
+    /// `legacy` tells whether the query comes from a plain resolver, see the
+    /// module documentation. Return `None` if no question is about the
+    /// records of the device, and for messages that are not queries.
+    pub fn answer(
+        &self,
+        query: &[u8],
+        legacy: bool,
+        response: &mut [u8; MAX_MESSAGE_SIZE],
+    ) -> Option<Response> {
+        let header = query.get(..HEADER_SIZE)?;
+        if header[2] & (FLAG_RESPONSE | OPCODE_MASK) != 0 {
+            return None;
+        }
+
+        let question_count = u16::from_be_bytes([header[4], header[5]]);
+        let mut answers = 0;
+        let mut unicast = true;
+        let mut first_question = None;
+        let mut offset = HEADER_SIZE;
+        for _ in 0..question_count {
+            let (name, end) = read_name(query, offset)?;
+            let fields = query.get(end..end + 4)?;
+            let record_type = u16::from_be_bytes([fields[0], fields[1]]);
+            let class = u16::from_be_bytes([fields[2], fields[3]]);
+            offset = end + 4;
+
+            if !matches!(class & !UNICAST_RESPONSE, CLASS_IN | CLASS_ANY) {
+                continue;
+            }
+            let matching = self.records_for(&name, record_type);
+            if matching == 0 {
+                continue;
+            }
+            answers |= matching;
+            unicast &= class & UNICAST_RESPONSE != 0;
+            first_question.get_or_insert((name, record_type, class));
+        }
+        let (name, record_type, class) = first_question?;
+
+        let mut additionals = 0;
+        if answers & records::SERVICE != 0 {
+            additionals |= records::LOCATION | records::TEXT | records::ADDRESS;
+        }
+        if answers & records::LOCATION != 0 {
+            additionals |= records::ADDRESS;
+        }
+        additionals &= !answers & self.available();
+
+        let mut writer = Writer {
+            buffer: response,
+            length: 0,
+        };
+        if legacy {
+            writer.push(&header[..2])?;
+        } else {
+            writer.push(&[0, 0])?;
+        }
+        writer.push(&RESPONSE_FLAGS)?;
+        writer.push(&u16::from(legacy).to_be_bytes())?;
+        writer.push(&(answers.count_ones() as u16).to_be_bytes())?;
+        writer.push(&[0, 0])?;
+        writer.push(&(additionals.count_ones() as u16).to_be_bytes())?;
+        if legacy {
+            writer.push(&name)?;
+            writer.push(&record_type.to_be_bytes())?;
+            writer.push(&(class & !UNICAST_RESPONSE).to_be_bytes())?;
+        }
+        self.write_records(&mut writer, answers | additionals, None, legacy)?;
+
+        Some(Response {
+            length: writer.length,
+            destination: if legacy || unicast {
+                Destination::Unicast
+            } else {
+                Destination::Multicast
+            },
+        })
+    }
+
+    /// Write an unsolicited response with every record, and return its length
+    ///
+    /// A `goodbye` withdraws the records, which then have a zero TTL.
+    pub fn announcement(
+        &self,
+        goodbye: bool,
+        response: &mut [u8; MAX_MESSAGE_SIZE],
+    ) -> Option<usize> {
+        let records = self.available();
+        let mut writer = Writer {
+            buffer: response,
+            length: 0,
+        };
+        writer.push(&[0, 0])?;
+        writer.push(&RESPONSE_FLAGS)?;
+        writer.push(&[0, 0])?;
+        writer.push(&(records.count_ones() as u16).to_be_bytes())?;
+        writer.push(&[0, 0, 0, 0])?;
+        self.write_records(&mut writer, records, goodbye.then_some(0), false)?;
+        Some(writer.length)
+    }
+
+    /// Return the records the device has
+    fn available(&self) -> u8 {
+        if self.address.is_some() {
+            records::ALL
+        } else {
+            records::ALL & !records::ADDRESS
+        }
+    }
+
+    /// Return the records answering a question
+    fn records_for(&self, name: &[u8], record_type: u16) -> u8 {
+        use record_type::*;
+
+        let records = if is_name(name, &[self.hostname, "local"]) {
+            match record_type {
+                A | ANY => records::ADDRESS,
+                _ => 0,
+            }
+        } else if is_name(name, &SERVICE_TYPE) {
+            match record_type {
+                PTR | ANY => records::SERVICE,
+                _ => 0,
+            }
+        } else if is_name(name, &SERVICE_TYPE_ENUMERATION) {
+            match record_type {
+                PTR | ANY => records::SERVICE_TYPE,
+                _ => 0,
+            }
+        } else if is_name(name, &self.instance()) {
+            match record_type {
+                SRV => records::LOCATION,
+                TXT => records::TEXT,
+                ANY => records::LOCATION | records::TEXT,
+                _ => 0,
+            }
+        } else {
+            0
+        };
+        records & self.available()
+    }
+
+    /// Return the labels of the service instance, named after the hostname
+    fn instance(&self) -> [&str; 4] {
+        [
+            self.hostname,
+            SERVICE_TYPE[0],
+            SERVICE_TYPE[1],
+            SERVICE_TYPE[2],
+        ]
+    }
+
+    /// Write `records`, with their usual TTL unless `ttl` is set
+    ///
+    /// Responses to plain resolvers have short TTLs, and no cache-flush bit.
+    fn write_records(
+        &self,
+        writer: &mut Writer,
+        records: u8,
+        ttl: Option<u32>,
+        legacy: bool,
+    ) -> Option<()> {
+        let host = [self.hostname, "local"];
+        let instance = self.instance();
+        let ttl = |usual: u32| {
+            let ttl = ttl.unwrap_or(usual);
+            if legacy {
+                ttl.min(LEGACY_TTL)
+            } else {
+                ttl
+            }
+        };
+        let unique = if legacy {
+            CLASS_IN
+        } else {
+            CLASS_IN | CACHE_FLUSH
+        };
+
+        if let Some(address) = self.address.filter(|_| records & records::ADDRESS != 0) {
+            writer.record(&host, record_type::A, unique, ttl(HOST_TTL), |writer| {
+                writer.push(&address.octets())
+            })?;
+        }
+        if records & records::SERVICE_TYPE != 0 {
+            writer.record(
+                &SERVICE_TYPE_ENUMERATION,
+                record_type::PTR,
+                CLASS_IN,
+                ttl(SERVICE_TTL),
+                |writer| writer.name(&SERVICE_TYPE),
+            )?;
+        }
+        if records & records::SERVICE != 0 {
+            writer.record(
+                &SERVICE_TYPE,
+                record_type::PTR,
+                CLASS_IN,
+                ttl(SERVICE_TTL),
+                |writer| writer.name(&instance),
+            )?;
+        }
+        if records & records::LOCATION != 0 {
+            writer.record(
+                &instance,
+                record_type::SRV,
+                unique,
+                ttl(HOST_TTL),
+                |writer| {
+                    // Priority and weight
+                    writer.push(&[0, 0, 0, 0])?;
+                    writer.push(&self.port.to_be_bytes())?;
+                    writer.name(&host)
+                },
+            )?;
+        }
+        if records & records::TEXT != 0 {
+            writer.record(
+                &instance,
+                record_type::TXT,
+                unique,
+                ttl(SERVICE_TTL),
+                |writer| {
+                    writer.push(&[SERVICE_TEXT.len() as u8])?;
+                    writer.push(SERVICE_TEXT.as_bytes())
+                },
+            )?;
+        }
+        Some(())
+    }
+}
+
+/// Pending goodbye request
+static GOODBYE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Set once the goodbye was sent
+static GOODBYE_SENT: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Withdraw the records before a reset
+///
+/// Return once the goodbye was sent, or after [`GOODBYE_TIMEOUT`], for
+/// instance when the responder does not run.
+pub async fn say_goodbye() {
+    GOODBYE.signal(());
+    let _ = with_timeout(GOODBYE_TIMEOUT, GOODBYE_SENT.wait()).await;
+}
+
+/// Buffers of the responder task
+///
+/// They are statically allocated rather than living in the task future, so
+/// they do not grow the executor arena.
+struct Buffers {
+    rx_meta: [PacketMetadata; 4],
+    rx: [u8; MAX_MESSAGE_SIZE * 2],
+    tx_meta: [PacketMetadata; 2],
+    tx: [u8; MAX_MESSAGE_SIZE * 2],
+    query: [u8; MAX_MESSAGE_SIZE],
+    response: [u8; MAX_MESSAGE_SIZE],
+}
+
+/// Endpoint of the group
+const MULTICAST_ENDPOINT: IpEndpoint = IpEndpoint::new(IpAddress::Ipv4(MULTICAST_ADDRESS), PORT);
+
+/// Answer the mDNS queries on the station `stack`, advertising the web
+/// server on `port`
+///
+/// The hostname and the address are read for every query, so changes to the
+/// settings apply right away.
+#[embassy_executor::task]
+pub async fn mdns_task(stack: Stack<'static>, settings: &'static Settings, port: u16) {
+    let Some(mac) = crate::identity::mac_address() else {
+        crate::log!(error: "mDNS responder started before the MAC address is known");
+        return;
+    };
+    let Buffers {
+        rx_meta,
+        rx,
+        tx_meta,
+        tx,
+        query,
+        response,
+    } = crate::mk_static!(
+        Buffers,
+        Buffers {
+            rx_meta: [PacketMetadata::EMPTY; 4],
+            rx: [0; MAX_MESSAGE_SIZE * 2],
+            tx_meta: [PacketMetadata::EMPTY; 2],
+            tx: [0; MAX_MESSAGE_SIZE * 2],
+            query: [0; MAX_MESSAGE_SIZE],
+            response: [0; MAX_MESSAGE_SIZE],
+        }
+    );
+    if let Err(error) = stack.join_multicast_group(MULTICAST_ADDRESS) {
+        crate::log!(error: "Failed to join the mDNS group: {:?}", error);
+        return;
+    }
+    let mut socket = UdpSocket::new(stack, rx_meta, rx, tx_meta, tx);
+    if let Err(error) = socket.bind(PORT) {
+        crate::log!(error: "Failed to bind the mDNS responder: {:?}", error);
+        return;
+    }
+
+    for announcement in 0..ANNOUNCEMENT_COUNT {
+        if announcement > 0 {
+            Timer::after(ANNOUNCEMENT_INTERVAL).await;
+        }
+        let hostname = hostname(settings, &mac);
+        let responder = Responder {
+            hostname: &hostname,
+            address: stack.config_v4().map(|config| config.address.address()),
+            port,
+        };
+        if let Some(length) = responder.announcement(false, response) {
+            if let Err(error) = socket
+                .send_to(&response[..length], MULTICAST_ENDPOINT)
+                .await
+            {
+                crate::log!(warn: "Warning: failed to send an mDNS announcement: {:?}", error);
+            }
+        }
+    }
+
+    loop {
+        crate::heartbeat!("mdns_task");
+        let received = crate::tasks::beating(
+            "mdns_task",
+            None,
+            select(socket.recv_from(query), GOODBYE.wait()),
+        )
+        .await;
+        let hostname = hostname(settings, &mac);
+        let responder = Responder {
+            hostname: &hostname,
+            address: stack.config_v4().map(|config| config.address.address()),
+            port,
+        };
+        let (length, metadata) = match received {
+            Either::First(Ok(received)) => received,
+            Either::First(Err(error)) => {
+                crate::log!(warn: "Warning: dropped an mDNS query: {:?}", error);
+                continue;
+            }
+            Either::Second(()) => {
+                if let Some(length) = responder.announcement(true, response) {
+                    if let Err(error) = socket
+                        .send_to(&response[..length], MULTICAST_ENDPOINT)
+                        .await
+                    {
+                        crate::log!(warn: "Warning: failed to send the mDNS goodbye: {:?}", error);
+                    }
+                    socket.flush().await;
+                }
+                GOODBYE_SENT.signal(());
+                continue;
+            }
+        };
+        let legacy = metadata.endpoint.port != PORT;
+        let Some(answer) = responder.answer(&query[..length], legacy, response) else {
+            continue;
+        };
+        let destination = match answer.destination {
+            Destination::Unicast => metadata.endpoint,
+            Destination::Multicast => MULTICAST_ENDPOINT,
+        };
+        if let Err(error) = socket
+            .send_to(&response[..answer.length], destination)
+            .await
+        {
+            crate::log!(warn: "Warning: failed to send an mDNS response: {:?}", error);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! Counters exposed in Prometheus text format at `/metrics`
+//!
+//! Metric names are part of the interface with dashboards, they must not
+//! change across firmware versions.
+
+use core::fmt::Write as _;
+
+use embassy_time::{Duration, Instant};
+use heapless::String;
+use picoserve::io::{ErrorType, Socket, Write};
+use picoserve::response::chunked::{ChunkWriter, Chunks, ChunksWritten};
+use picoserve::response::StatusCode;
+use portable_atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::stats::{LatencyHistogram, Stats, LATENCY_BOUNDS_MS};
+use crate::web::{SERVER_TASK_COUNT, WEB_TASK_POOL_SIZE};
+use crate::wifi;
+
+/// Duration after which all web tasks being busy is logged as a warning
+const SATURATION_WARNING: Duration = Duration::from_secs(5);
+
+/// Labels of the status classes, indexed by the first digit minus one
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+/// Counters of the web server
+pub struct Metrics {
+    /// Number of responses per status class
+    requests: [AtomicU32; STATUS_CLASSES.len()],
+
+    /// Number of bytes written to sockets, headers included
+    bytes_written: AtomicU32,
+
+    /// Number of bytes written to the socket of each web task, wrapping
+    task_bytes_written: [AtomicU32; SERVER_TASK_COUNT],
+
+    /// Number of responses over the thresholds of the timing layer
+    slow_requests: AtomicU32,
+
+    /// Number of connections being served
+    active_connections: AtomicU32,
+
+    /// Time in ticks when every web task became busy
+    saturated_since: AtomicU64,
+}
+
+impl Metrics {
+    /// Create zeroed counters
+    pub const fn new() -> Self {
+        Self {
+            requests: [const { AtomicU32::new(0) }; STATUS_CLASSES.len()],
+            bytes_written: AtomicU32::new(0),
+            task_bytes_written: [const { AtomicU32::new(0) }; SERVER_TASK_COUNT],
+            slow_requests: AtomicU32::new(0),
+            active_connections: AtomicU32::new(0),
+            saturated_since: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a connection until the returned guard is dropped
+    pub fn open_connection(&'static self) -> ConnectionGuard {
+        let active = self.active_connections.fetch_add(1, Ordering::Relaxed) + 1;
+        if active as usize == WEB_TASK_POOL_SIZE {
+            self.saturated_since
+                .store(Instant::now().as_ticks(), Ordering::Relaxed);
+        }
+        ConnectionGuard { metrics: self }
+    }
+
+    /// Return the number of connections being served
+    pub fn active_connections(&self) -> u32 {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    /// Count a response
+    pub fn record_response(&self, status_code: StatusCode) {
+        let class = usize::from(status_code.as_u16() / 100);
+        if let Some(counter) = class.checked_sub(1).and_then(|index| self.requests.get(index)) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count bytes written to the socket of a web task
+    pub fn record_bytes_written(&self, task: usize, count: usize) {
+        #[expect(clippy::cast_possible_truncation, reason = "Writes are smaller than 4 GiB")]
+        let count = count as u32;
+        self.bytes_written.fetch_add(count, Ordering::Relaxed);
+        if let Some(counter) = self.task_bytes_written.get(task) {
+            counter.fetch_add(count, Ordering::Relaxed);
+        }
+    }
+
+    /// Return the number of bytes written to the socket of a web task so far
+    ///
+    /// The counter wraps, the bytes of a response are the wrapping
+    /// difference of the counter before and after it.
+    pub fn task_bytes_written(&self, task: usize) -> u32 {
+        self.task_bytes_written
+            .get(task)
+            .map_or(0, |counter| counter.load(Ordering::Relaxed))
+    }
+
+    /// Count a response over the thresholds of the timing layer
+    pub fn record_slow_request(&self) {
+        self.slow_requests.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A connection counted in [`Metrics::active_connections`]
+///
+/// New clients cannot queue while every web task is busy, since no socket is
+/// listening: they are refused by the network stack. A warning is logged
+/// when this lasts longer than [`SATURATION_WARNING`].
+pub struct ConnectionGuard {
+    metrics: &'static Metrics,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let previous = self.metrics.active_connections.fetch_sub(1, Ordering::Relaxed);
+        if previous as usize == WEB_TASK_POOL_SIZE {
+            let since = Instant::from_ticks(self.metrics.saturated_since.load(Ordering::Relaxed));
+            let saturated = since.elapsed();
+            if saturated > SATURATION_WARNING {
+                crate::log!(
+                    warn: "Warning: all {} web tasks were busy for {} s",
+                    WEB_TASK_POOL_SIZE,
+                    saturated.as_secs()
+                );
+            }
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A socket counting the bytes written to it
+pub struct CountingSocket<S> {
+    socket: S,
+    metrics: &'static Metrics,
+    task: usize,
+}
+
+impl<S> CountingSocket<S> {
+    /// Wrap a socket of a web task
+    pub fn new(socket: S, metrics: &'static Metrics, task: usize) -> Self {
+        Self {
+            socket,
+            metrics,
+            task,
+        }
+    }
+}
+
+impl<S: Socket> Socket for CountingSocket<S> {
+    type Error = S::Error;
+    type ReadHalf<'a>
+        = S::ReadHalf<'a>
+    where
+        S: 'a;
+    type WriteHalf<'a>
+        = CountingWriter<S::WriteHalf<'a>>
+    where
+        S: 'a;
+
+    fn split(&mut self) -> (Self::ReadHalf<'_>, Self::WriteHalf<'_>) {
+        let (reader, writer) = self.socket.split();
+        let writer = CountingWriter {
+            writer,
+            metrics: self.metrics,
+            task: self.task,
+        };
+        (reader, writer)
+    }
+
+    async fn shutdown<Timer: picoserve::Timer>(
+        self,
+        timeouts: &picoserve::Timeouts<Timer::Duration>,
+        timer: &mut Timer,
+    ) -> Result<(), picoserve::Error<Self::Error>> {
+        self.socket.shutdown(timeouts, timer).await
+    }
+}
+
+/// A writer counting the bytes written through it
+pub struct CountingWriter<W> {
+    writer: W,
+    metrics: &'static Metrics,
+    task: usize,
+}
+
+impl<W: Write> ErrorType for CountingWriter<W> {
+    type Error = W::Error;
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let count = self.writer.write(buf).await?;
+        self.metrics.record_bytes_written(self.task, count);
+        Ok(count)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.writer.flush().await
+    }
+}
+
+/// The metrics in Prometheus text exposition format
+pub struct MetricsExposition {
+    pub metrics: &'static Metrics,
+    pub stats: &'static Stats,
+}
+
+impl Chunks for MetricsExposition {
+    fn content_type(&self) -> &'static str {
+        "text/plain; version=0.0.4"
+    }
+
+    async fn write_chunks<W: Write>(
+        self,
+        mut chunk_writer: ChunkWriter<W>,
+    ) -> Result<ChunksWritten, W::Error> {
+        let mut buffer = String::<512>::new();
+
+        let _ = writeln!(buffer, "# TYPE picoserve_http_requests_total counter");
+        chunk_writer.write_chunk(buffer.as_bytes()).await?;
+        for (class, counter) in STATUS_CLASSES.iter().zip(&self.metrics.requests) {
+            buffer.clear();
+            let _ = writeln!(
+                buffer,
+                "picoserve_http_requests_total{{code=\"{}\"}} {}",
+                class,
+                counter.load(Ordering::Relaxed)
+            );
+            chunk_writer.write_chunk(buffer.as_bytes()).await?;
+        }
+
+        buffer.clear();
+        let _ = writeln!(buffer, "# TYPE picoserve_http_response_bytes_total counter");
+        let _ = writeln!(
+            buffer,
+            "picoserve_http_response_bytes_total {}",
+            self.metrics.bytes_written.load(Ordering::Relaxed)
+        );
+        chunk_writer.write_chunk(buffer.as_bytes()).await?;
+
+        buffer.clear();
+        let _ = writeln!(buffer, "# TYPE picoserve_slow_requests_total counter");
+        let _ = writeln!(
+            buffer,
+            "picoserve_slow_requests_total {}",
+            self.metrics.slow_requests.load(Ordering::Relaxed)
+        );
+        chunk_writer.write_chunk(buffer.as_bytes()).await?;
+
+        buffer.clear();
+        let _ = writeln!(buffer, "# TYPE picoserve_active_connections gauge");
+        let _ = writeln!(
+            buffer,
+            "picoserve_active_connections {}",
+            self.metrics.active_connections()
+        );
+        chunk_writer.write_chunk(buffer.as_bytes()).await?;
+
+        buffer.clear();
+        let _ = writeln!(buffer, "# TYPE picoserve_heap_free_bytes gauge");
+        let _ = writeln!(buffer, "picoserve_heap_free_bytes {}", esp_alloc::HEAP.free());
+        let _ = writeln!(buffer, "# TYPE picoserve_uptime_seconds counter");
+        let _ = writeln!(buffer, "picoserve_uptime_seconds {}", Instant::now().as_secs());
+        chunk_writer.write_chunk(buffer.as_bytes()).await?;
+
+        if let Some(rssi) = wifi::rssi() {
+            buffer.clear();
+            let _ = writeln!(buffer, "# TYPE picoserve_wifi_rssi_dbm gauge");
+            let _ = writeln!(buffer, "picoserve_wifi_rssi_dbm {}", rssi);
+            chunk_writer.write_chunk(buffer.as_bytes()).await?;
+        }
+
+        // Routes without a response yet are left out to keep the output short
+        buffer.clear();
+        let _ = writeln!(buffer, "# TYPE picoserve_http_request_duration_seconds histogram");
+        chunk_writer.write_chunk(buffer.as_bytes()).await?;
+        for (route, histogram) in self.stats.latencies() {
+            let buckets = histogram.buckets();
+            let count: u64 = buckets.iter().sum();
+            if count == 0 {
+                continue;
+            }
+
+            let mut cumulative = 0;
+            for (bound_ms, bucket) in LATENCY_BOUNDS_MS.iter().zip(buckets) {
+                cumulative += bucket;
+                buffer.clear();
+                let _ = writeln!(
+                    buffer,
+                    "picoserve_http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}.{:03}\"}} {}",
+                    route,
+                    bound_ms / 1000,
+                    bound_ms % 1000,
+                    cumulative
+                );
+                chunk_writer.write_chunk(buffer.as_bytes()).await?;
+            }
+
+            let sum_ms = histogram.sum_ms();
+            buffer.clear();
+            let _ = writeln!(
+                buffer,
+                "picoserve_http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                route, count
+            );
+            let _ = writeln!(
+                buffer,
+                "picoserve_http_request_duration_seconds_sum{{route=\"{}\"}} {}.{:03}",
+                route,
+                sum_ms / 1000,
+                sum_ms % 1000
+            );
+            let _ = writeln!(
+                buffer,
+                "picoserve_http_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, count
+            );
+            chunk_writer.write_chunk(buffer.as_bytes()).await?;
+        }
+
+        // Gauges, so alerts see the one slow request the histogram smooths out
+        let maxima: [(&str, fn(&LatencyHistogram) -> u64); 2] = [
+            ("picoserve_http_request_duration_max_seconds", LatencyHistogram::max_ms),
+            (
+                "picoserve_http_request_duration_max_since_boot_seconds",
+                LatencyHistogram::max_since_boot_ms,
+            ),
+        ];
+        for (name, max_ms) in maxima {
+            buffer.clear();
+            let _ = writeln!(buffer, "# TYPE {} gauge", name);
+            chunk_writer.write_chunk(buffer.as_bytes()).await?;
+            for (route, histogram) in self.stats.latencies() {
+                if histogram.max_since_boot_ms() == 0 && histogram.buckets().iter().sum::<u64>() == 0 {
+                    continue;
+                }
+                let max_ms = max_ms(histogram);
+                buffer.clear();
+                let _ = writeln!(
+                    buffer,
+                    "{}{{route=\"{}\"}} {}.{:03}",
+                    name,
+                    route,
+                    max_ms / 1000,
+                    max_ms % 1000
+                );
+                chunk_writer.write_chunk(buffer.as_bytes()).await?;
+            }
+        }
+
+        #[cfg(feature = "temperature")]
+        if let Some(readings) = crate::temperature::readings() {
+            buffer.clear();
+            let _ = writeln!(buffer, "# TYPE picoserve_temperature_celsius gauge");
+            let _ = writeln!(buffer, "picoserve_temperature_celsius {}", readings.celsius);
+            chunk_writer.write_chunk(buffer.as_bytes()).await?;
+        }
+
+        chunk_writer.finalize().await
+    }
+}
@@ -0,0 +1,337 @@
+//! Streaming parser of `multipart/form-data` request bodies
+//!
+//! Browsers send the files chosen with `<input type="file">` as parts of a
+//! multipart body (RFC 7578). [`Multipart`] reads the body through a buffer
+//! of [`BUFFER_SIZE`] bytes: the headers of a part must fit in it, but its
+//! content is handed out in chunks as it arrives, so files much larger than
+//! the buffer are never held in memory.
+//!
+//! The body reader reports the end of the body once `Content-Length` bytes
+//! were read, so a body missing its closing delimiter is an error rather than
+//! a wait.
+
+use embassy_time::{with_timeout, Duration};
+use heapless::{String, Vec};
+use picoserve::io::Read;
+
+use crate::request_log::truncated;
+
+/// Maximal length of a boundary, from RFC 2046
+pub const MAX_BOUNDARY_SIZE: usize = 70;
+
+/// Length of the longest delimiter, a CRLF, `--` and the boundary
+const MAX_DELIMITER_SIZE: usize = MAX_BOUNDARY_SIZE + 4;
+
+/// Size of the read buffer, and maximal size of the headers of a part
+pub const BUFFER_SIZE: usize = 512;
+
+/// Maximal length of the name, file name and content type of a part, longer
+/// values are truncated
+pub const MAX_FIELD_SIZE: usize = 64;
+
+/// An error of the multipart parser
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The body could not be read
+    Io(E),
+
+    /// The body did not arrive within the read timeout
+    Timeout,
+
+    /// The body ended before the closing delimiter
+    Truncated,
+
+    /// A delimiter or the headers of a part are malformed
+    Malformed,
+
+    /// The headers of a part do not fit in the buffer
+    HeaderTooLong,
+}
+
+/// Return the boundary of a `multipart/form-data` content type
+///
+/// The boundary may be quoted, and must be 1 to [`MAX_BOUNDARY_SIZE`]
+/// printable ASCII characters not ending with a space.
+pub fn boundary(content_type: &str) -> Option<&str> {
+    let (media_type, parameters) = content_type.split_once(';')?;
+    if !media_type
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    let boundary = parameter(parameters, "boundary")?;
+    let valid = (1..=MAX_BOUNDARY_SIZE).contains(&boundary.len())
+        && !boundary.ends_with(' ')
+        && boundary
+            .bytes()
+            .all(|byte| byte == b' ' || byte.is_ascii_graphic());
+    valid.then_some(boundary)
+}
+
+/// Return the value of a parameter in a list like `; name="file"; size=3`
+///
+/// Names are matched ignoring case, and quotes are removed from values.
+/// Escapes in quoted values are kept as is, browsers percent-encode quotes
+/// in file names instead.
+pub fn parameter<'a>(parameters: &'a str, key: &str) -> Option<&'a str> {
+    let mut rest = parameters;
+    loop {
+        rest = rest.trim_start_matches([';', ' ', '\t']);
+        if rest.is_empty() {
+            return None;
+        }
+        let name_end = rest.find(['=', ';']).unwrap_or(rest.len());
+        let name = rest[..name_end].trim();
+        rest = &rest[name_end..];
+        let Some(after_equals) = rest.strip_prefix('=') else {
+            // A parameter without value
+            continue;
+        };
+
+        let after_equals = after_equals.trim_start();
+        let value = if let Some(quoted) = after_equals.strip_prefix('"') {
+            let length = closing_quote(quoted)?;
+            rest = &quoted[length + 1..];
+            &quoted[..length]
+        } else {
+            let length = after_equals.find(';').unwrap_or(after_equals.len());
+            rest = &after_equals[length..];
+            after_equals[..length].trim_end()
+        };
+
+        if name.eq_ignore_ascii_case(key) {
+            return Some(value);
+        }
+    }
+}
+
+/// Return the offset of the quote closing a quoted string, skipping
+/// escaped quotes
+fn closing_quote(quoted: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (index, byte) in quoted.bytes().enumerate() {
+        match byte {
+            b'"' if !escaped => return Some(index),
+            b'\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Return the offset of the first occurrence of a needle in a haystack
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// The headers of a part
+#[derive(Debug)]
+pub struct Part {
+    /// Name of the form field
+    pub name: String<MAX_FIELD_SIZE>,
+
+    /// Name of the file, for file fields
+    pub filename: Option<String<MAX_FIELD_SIZE>>,
+
+    /// Content type of the part, if sent
+    pub content_type: Option<String<MAX_FIELD_SIZE>>,
+}
+
+impl Part {
+    /// Parse the headers of a part
+    ///
+    /// Headers other than `Content-Disposition` and `Content-Type` are
+    /// ignored.
+    fn parse<'a>(lines: impl Iterator<Item = &'a [u8]>) -> Option<Self> {
+        let mut name = None;
+        let mut filename = None;
+        let mut content_type = None;
+        for line in lines {
+            let line = core::str::from_utf8(line).ok()?;
+            let (header, value) = line.split_once(':')?;
+            let value = value.trim();
+            if header.trim().eq_ignore_ascii_case("Content-Disposition") {
+                let (disposition, parameters) = value.split_once(';')?;
+                if !disposition.trim().eq_ignore_ascii_case("form-data") {
+                    return None;
+                }
+                name = parameter(parameters, "name").map(truncated);
+                filename = parameter(parameters, "filename").map(truncated);
+            } else if header.trim().eq_ignore_ascii_case("Content-Type") {
+                content_type = Some(truncated(value));
+            }
+        }
+        Some(Self {
+            name: name?,
+            filename,
+            content_type,
+        })
+    }
+}
+
+/// Position of the parser in the body
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// In the content of a part, or in the preamble before the first one
+    Content,
+
+    /// Right after a delimiter
+    Delimiter,
+
+    /// After the closing delimiter
+    Done,
+}
+
+/// A multipart body being read
+///
+/// Parts are iterated with [`Multipart::next_part`], and the content of the
+/// current part is read with [`Multipart::next_chunk`]. Content left unread
+/// is skipped when moving to the next part.
+pub struct Multipart<R> {
+    reader: R,
+    timeout: Option<Duration>,
+    delimiter: Vec<u8, MAX_DELIMITER_SIZE>,
+    buffer: [u8; BUFFER_SIZE],
+    start: usize,
+    end: usize,
+    state: State,
+}
+
+impl<R: Read> Multipart<R> {
+    /// Read a body split by a boundary, waiting at most `timeout` for each
+    /// read
+    ///
+    /// The boundary must have been checked with [`boundary`].
+    pub fn new(reader: R, boundary: &str, timeout: Option<Duration>) -> Self {
+        let mut delimiter = Vec::new();
+        // Cannot fail, the boundary was checked
+        let _ = delimiter.extend_from_slice(b"\r\n--");
+        let _ = delimiter.extend_from_slice(boundary.as_bytes());
+
+        // The first delimiter has no leading CRLF, one is added so the
+        // preamble is read like the content of a part
+        let mut buffer = [0; BUFFER_SIZE];
+        buffer[..2].copy_from_slice(b"\r\n");
+
+        Self {
+            reader,
+            timeout,
+            delimiter,
+            buffer,
+            start: 0,
+            end: 2,
+            state: State::Content,
+        }
+    }
+
+    /// Move to the next part and return its headers, or `None` after the
+    /// last one
+    pub async fn next_part(&mut self) -> Result<Option<Part>, Error<R::Error>> {
+        while self.next_chunk().await?.is_some() {}
+        if self.state == State::Done {
+            return Ok(None);
+        }
+
+        // Transport padding may follow a delimiter
+        loop {
+            self.ensure(1).await?;
+            if !matches!(self.buffer[self.start], b' ' | b'\t') {
+                break;
+            }
+            self.start += 1;
+        }
+        self.ensure(2).await?;
+        match &self.buffer[self.start..self.start + 2] {
+            b"--" => {
+                self.state = State::Done;
+                return Ok(None);
+            }
+            b"\r\n" => self.start += 2,
+            _ => return Err(Error::Malformed),
+        }
+
+        // Headers end with an empty line, they must all fit in the buffer
+        let headers_end = loop {
+            let headers = &self.buffer[self.start..self.end];
+            if headers.starts_with(b"\r\n") {
+                break 0;
+            }
+            if let Some(index) = find(headers, b"\r\n\r\n") {
+                break index + 2;
+            }
+            self.fill().await?;
+        };
+        let headers = &self.buffer[self.start..self.start + headers_end];
+        let lines = headers
+            .split(|&byte| byte == b'\n')
+            .filter_map(|line| line.strip_suffix(b"\r"));
+        let part = Part::parse(lines).ok_or(Error::Malformed)?;
+        self.start += headers_end + 2;
+        self.state = State::Content;
+        Ok(Some(part))
+    }
+
+    /// Return the next chunk of content of the current part, or `None` at
+    /// its end
+    pub async fn next_chunk(&mut self) -> Result<Option<&[u8]>, Error<R::Error>> {
+        if self.state != State::Content {
+            return Ok(None);
+        }
+        loop {
+            let available = &self.buffer[self.start..self.end];
+            let content_end = match find(available, &self.delimiter) {
+                Some(0) => {
+                    self.start += self.delimiter.len();
+                    self.state = State::Delimiter;
+                    return Ok(None);
+                }
+                Some(index) => index,
+                // The end of the buffer may be the start of a delimiter
+                None => available.len().saturating_sub(self.delimiter.len() - 1),
+            };
+            if content_end > 0 {
+                let chunk = self.start..self.start + content_end;
+                self.start += content_end;
+                return Ok(Some(&self.buffer[chunk]));
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Read until at least `count` bytes are buffered
+    async fn ensure(&mut self, count: usize) -> Result<(), Error<R::Error>> {
+        while self.end - self.start < count {
+            self.fill().await?;
+        }
+        Ok(())
+    }
+
+    /// Read more of the body into the buffer
+    async fn fill(&mut self) -> Result<(), Error<R::Error>> {
+        if self.start > 0 {
+            self.buffer.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        if self.end == BUFFER_SIZE {
+            return Err(Error::HeaderTooLong);
+        }
+
+        let read = self.reader.read(&mut self.buffer[self.end..]);
+        let read = match self.timeout {
+            Some(timeout) => with_timeout(timeout, read)
+                .await
+                .map_err(|_| Error::Timeout)?,
+            None => read.await,
+        }
+        .map_err(Error::Io)?;
+        if read == 0 {
+            return Err(Error::Truncated);
+        }
+        self.end += read;
+        Ok(())
+    }
+}
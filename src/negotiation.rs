@@ -0,0 +1,101 @@
+//! Content negotiation between JSON and plain text
+//!
+//! Routes returning [`Negotiated`] serve JSON to clients asking for
+//! `application/json` and plain text to the others, so scripts and terminals
+//! can share a route.
+
+use picoserve::io::Read;
+use picoserve::response::{Connection, IntoResponse, Json, ResponseWriter};
+use picoserve::ResponseSent;
+use serde::Serialize;
+
+/// The representation requested in an `Accept` header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preference {
+    /// `application/json` is listed
+    Json,
+
+    /// `text/plain` or `text/*` is listed, but not JSON
+    Text,
+
+    /// No header, or only wildcards and unsupported types
+    Any,
+}
+
+impl Preference {
+    /// Parse an `Accept` header value
+    ///
+    /// Media ranges with `q=0` are ignored, other weights are not compared.
+    pub fn from_header(header: &str) -> Self {
+        let mut preference = Self::Any;
+        for range in header.split(',') {
+            let mut parts = range.split(';').map(str::trim);
+            let media_type = parts.next().unwrap_or("");
+            let rejected = parts
+                .filter_map(|parameter| parameter.strip_prefix("q="))
+                .any(|weight| weight.parse::<f32>().ok() == Some(0.0));
+            if rejected {
+                continue;
+            }
+
+            if media_type.eq_ignore_ascii_case("application/json") {
+                return Self::Json;
+            }
+            if media_type.eq_ignore_ascii_case("text/plain") || media_type.eq_ignore_ascii_case("text/*") {
+                preference = Self::Text;
+            }
+        }
+        preference
+    }
+
+    /// Return whether JSON should be served, falling back to a default when
+    /// the client has no preference
+    pub fn wants_json(self, default_json: bool) -> bool {
+        match self {
+            Self::Json => true,
+            Self::Text => false,
+            Self::Any => default_json,
+        }
+    }
+}
+
+/// An extractor for the representation requested in the `Accept` header
+pub struct Accept(pub Preference);
+
+impl<'r, State> picoserve::extract::FromRequestParts<'r, State> for Accept {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        _state: &'r State,
+        request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        let preference = request_parts
+            .headers()
+            .get("Accept")
+            .and_then(|value| value.as_str().ok())
+            .map_or(Preference::Any, Preference::from_header);
+        Ok(Self(preference))
+    }
+}
+
+/// A response served as JSON or as plain text
+pub enum Negotiated<J, T> {
+    /// Serialized as `application/json`
+    Json(J),
+
+    /// Served as `text/plain`
+    Text(T),
+}
+
+impl<J: Serialize, T: IntoResponse> IntoResponse for Negotiated<J, T> {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        match self {
+            Self::Json(value) => Json(value).write_to(connection, response_writer).await,
+            Self::Text(text) => text.write_to(connection, response_writer).await,
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Traffic counters of the web server, served at `/netstats`
+//!
+//! The bytes are counted by [`CountingSocket`](crate::metrics::CountingSocket)
+//! as they are written, headers included, and attributed to the status class
+//! of the response by the timing layer. Unlike the Prometheus counters of
+//! `/metrics`, these are reset with `POST /netstats/reset`, e.g. before a
+//! measurement.
+//!
+//! `embassy-net` keeps no packet or error counters of its interface, so
+//! only the HTTP traffic is reported.
+
+use embassy_time::Instant;
+use portable_atomic::{AtomicU64, Ordering};
+use serde::Serialize;
+
+/// Number of status classes, 1xx to 5xx
+const STATUS_CLASS_COUNT: usize = 5;
+
+/// Byte counters of the responses
+pub struct NetStats {
+    /// Number of bytes written per status class, indexed by the first digit
+    /// minus one
+    bytes: [AtomicU64; STATUS_CLASS_COUNT],
+
+    /// Number of responses per status class
+    responses: [AtomicU64; STATUS_CLASS_COUNT],
+
+    /// Time in ticks of the last reset
+    reset_at: AtomicU64,
+}
+
+impl NetStats {
+    /// Create zeroed counters
+    pub const fn new() -> Self {
+        Self {
+            bytes: [const { AtomicU64::new(0) }; STATUS_CLASS_COUNT],
+            responses: [const { AtomicU64::new(0) }; STATUS_CLASS_COUNT],
+            reset_at: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a response and the bytes written for it
+    pub fn record(&self, status: u16, bytes: u32) {
+        let Some(class) = usize::from(status / 100).checked_sub(1) else {
+            return;
+        };
+        if let (Some(counter), Some(responses)) = (self.bytes.get(class), self.responses.get(class))
+        {
+            counter.fetch_add(u64::from(bytes), Ordering::Relaxed);
+            responses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Zero every counter
+    pub fn reset(&self) {
+        for counter in self.bytes.iter().chain(&self.responses) {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.reset_at
+            .store(Instant::now().as_ticks(), Ordering::Relaxed);
+    }
+
+    /// Read all counters
+    pub fn snapshot(&self) -> NetStatsSnapshot {
+        let class = |index: usize| ClassTraffic {
+            responses: self.responses[index].load(Ordering::Relaxed),
+            bytes: self.bytes[index].load(Ordering::Relaxed),
+        };
+        let reset_at = Instant::from_ticks(self.reset_at.load(Ordering::Relaxed));
+        let by_status_class = TrafficByClass {
+            informational: class(0),
+            success: class(1),
+            redirection: class(2),
+            client_error: class(3),
+            server_error: class(4),
+        };
+        NetStatsSnapshot {
+            bytes_written: self
+                .bytes
+                .iter()
+                .map(|counter| counter.load(Ordering::Relaxed))
+                .sum(),
+            by_status_class,
+            seconds_since_reset: reset_at.elapsed().as_secs(),
+        }
+    }
+}
+
+impl Default for NetStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// JSON body returned by the `/netstats` route
+#[derive(Serialize)]
+pub struct NetStatsSnapshot {
+    /// Number of bytes written to clients, headers included
+    pub bytes_written: u64,
+
+    /// Traffic per status class
+    pub by_status_class: TrafficByClass,
+
+    /// Seconds since the last reset, or since boot
+    pub seconds_since_reset: u64,
+}
+
+/// Traffic per status class, keyed `1xx` to `5xx`
+#[derive(Serialize)]
+pub struct TrafficByClass {
+    #[serde(rename = "1xx")]
+    pub informational: ClassTraffic,
+
+    #[serde(rename = "2xx")]
+    pub success: ClassTraffic,
+
+    #[serde(rename = "3xx")]
+    pub redirection: ClassTraffic,
+
+    #[serde(rename = "4xx")]
+    pub client_error: ClassTraffic,
+
+    #[serde(rename = "5xx")]
+    pub server_error: ClassTraffic,
+}
+
+/// Traffic of the responses of a status class
+#[derive(Serialize)]
+pub struct ClassTraffic {
+    /// Number of responses
+    pub responses: u64,
+
+    /// Number of bytes written, headers included
+    pub bytes: u64,
+}
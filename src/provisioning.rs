@@ -0,0 +1,444 @@
+//! Provisioning of the Wi-Fi credentials from a browser
+//!
+//! Without stored credentials the device cannot join a network, so it starts
+//! an access point named after [`crate::identity::device_name`] instead,
+//! protected by a random password, see [`load_access_point_password`], and
+//! serves a
+//! form at [`PROVISIONING_PATH`]. The form lists the networks
+//! found by a scan before the access point started. The credentials posted
+//! are saved in the key-value store, and the device restarts to join the
+//! network.
+//!
+//! Up to [`MAX_STORED_NETWORKS`] networks are stored, in priority order, see
+//! [`StoredNetworks`]. A network provisioned from the form goes first, the
+//! others are managed at `/wifi/networks` once connected.
+//!
+//! Passwords are never written to a page or a log line, [`WifiCredentials`]
+//! does not even print them in debug output.
+
+use core::cell::RefCell;
+use core::fmt::{self, Write as _};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use heapless::{String, Vec};
+use portable_atomic::{AtomicBool, Ordering};
+use rand_core::RngCore;
+
+use crate::identity::{self, ACCESS_POINT_PASSWORD_SIZE};
+use crate::storage::{self, Storage};
+
+/// Path of the provisioning form
+pub const PROVISIONING_PATH: &str = "/provision";
+
+/// Key of the first stored network in the key-value store, the others have
+/// their position appended, e.g. `wifi1`
+///
+/// The first network has the key credentials were saved under before more
+/// could be stored, so they are kept by an update.
+pub const STORAGE_KEY: &str = "wifi";
+
+/// Key of the SSID of the network joined last in the key-value store
+pub const LAST_NETWORK_KEY: &str = "wifi_last";
+
+/// Key of the password of the provisioning access point in the key-value
+/// store
+pub const ACCESS_POINT_PASSWORD_KEY: &str = "ap_password";
+
+/// Maximal number of stored networks
+pub const MAX_STORED_NETWORKS: usize = 4;
+
+/// Maximal length of an SSID
+pub const MAX_SSID_SIZE: usize = 32;
+
+/// Maximal length of a WPA2 password, 63 characters or 64 hexadecimal digits
+pub const MAX_PASSWORD_SIZE: usize = 64;
+
+/// Minimal length of a WPA2 password, open networks have none
+pub const MIN_PASSWORD_SIZE: usize = 8;
+
+/// Maximal number of networks listed on the form
+pub const MAX_NETWORKS: usize = 16;
+
+/// Whether the device runs as a provisioning access point
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Networks found before the access point started, strongest first
+static NETWORKS: Mutex<CriticalSectionRawMutex, RefCell<Vec<String<MAX_SSID_SIZE>, MAX_NETWORKS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Return whether the device runs as a provisioning access point
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Remember that the device runs as a provisioning access point
+pub fn set_active() {
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// Remember the networks found by a scan, strongest first
+///
+/// Hidden networks and repeated SSIDs are skipped.
+pub fn set_networks<'a>(ssids: impl IntoIterator<Item = &'a str>) {
+    let mut networks = Vec::new();
+    for ssid in ssids {
+        if ssid.is_empty()
+            || networks
+                .iter()
+                .any(|network: &String<MAX_SSID_SIZE>| network == ssid)
+        {
+            continue;
+        }
+        let Ok(ssid) = String::try_from(ssid) else {
+            continue;
+        };
+        if networks.push(ssid).is_err() {
+            break;
+        }
+    }
+    NETWORKS.lock(|cell| *cell.borrow_mut() = networks);
+}
+
+/// Return the networks found before the access point started
+pub fn networks() -> Vec<String<MAX_SSID_SIZE>, MAX_NETWORKS> {
+    NETWORKS.lock(|networks| networks.borrow().clone())
+}
+
+/// The credentials of a network
+#[derive(Clone, PartialEq, Eq)]
+pub struct WifiCredentials {
+    /// SSID of the network
+    pub ssid: String<MAX_SSID_SIZE>,
+
+    /// Password of the network, empty for an open network
+    pub password: String<MAX_PASSWORD_SIZE>,
+}
+
+impl fmt::Debug for WifiCredentials {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("WifiCredentials")
+            .field("ssid", &self.ssid)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// An error of the credentials posted to the form
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CredentialsError {
+    /// The SSID is empty or longer than [`MAX_SSID_SIZE`]
+    InvalidSsid,
+
+    /// The password is neither empty nor [`MIN_PASSWORD_SIZE`] to
+    /// [`MAX_PASSWORD_SIZE`] long
+    InvalidPassword,
+}
+
+impl CredentialsError {
+    /// Return the message shown above the form
+    pub fn message(self) -> &'static str {
+        match self {
+            Self::InvalidSsid => "Choose a network",
+            Self::InvalidPassword => {
+                "The password must be 8 to 64 characters, or empty for an open network"
+            }
+        }
+    }
+}
+
+impl WifiCredentials {
+    /// Check the fields posted to the form
+    pub fn new(ssid: &str, password: &str) -> Result<Self, CredentialsError> {
+        if ssid.is_empty() {
+            return Err(CredentialsError::InvalidSsid);
+        }
+        let ssid = String::try_from(ssid).map_err(|()| CredentialsError::InvalidSsid)?;
+        if !password.is_empty() && password.len() < MIN_PASSWORD_SIZE {
+            return Err(CredentialsError::InvalidPassword);
+        }
+        let password =
+            String::try_from(password).map_err(|()| CredentialsError::InvalidPassword)?;
+        Ok(Self { ssid, password })
+    }
+
+    /// Encode the credentials as a value of the key-value store
+    ///
+    /// The value is the length of the SSID, the SSID and the password.
+    pub fn encode(&self) -> Vec<u8, { storage::MAX_VALUE_SIZE }> {
+        let mut value = Vec::new();
+        // Cannot fail, both fields fit in a value
+        let _ = value.push(self.ssid.len() as u8);
+        let _ = value.extend_from_slice(self.ssid.as_bytes());
+        let _ = value.extend_from_slice(self.password.as_bytes());
+        value
+    }
+
+    /// Decode credentials encoded with [`WifiCredentials::encode`]
+    pub fn decode(value: &[u8]) -> Option<Self> {
+        let (&ssid_length, rest) = value.split_first()?;
+        let ssid_length = usize::from(ssid_length);
+        if rest.len() < ssid_length {
+            return None;
+        }
+        let (ssid, password) = rest.split_at(ssid_length);
+        Self::new(
+            core::str::from_utf8(ssid).ok()?,
+            core::str::from_utf8(password).ok()?,
+        )
+        .ok()
+    }
+}
+
+/// An error of the stored networks
+#[derive(Debug)]
+pub enum NetworksError {
+    /// [`MAX_STORED_NETWORKS`] networks are stored
+    Full,
+
+    /// No stored network is at the position
+    NotFound,
+
+    /// The SSIDs of a new order are not the stored ones
+    InvalidOrder,
+
+    /// The only stored network cannot be removed, the device would have none
+    /// to join
+    LastNetwork,
+
+    /// The networks could not be saved
+    Storage(storage::Error),
+}
+
+/// The networks the station may join, in priority order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoredNetworks {
+    networks: Vec<WifiCredentials, MAX_STORED_NETWORKS>,
+}
+
+impl StoredNetworks {
+    /// Create an empty list
+    pub const fn new() -> Self {
+        Self {
+            networks: Vec::new(),
+        }
+    }
+
+    /// Create a list of a single network
+    pub fn single(credentials: WifiCredentials) -> Self {
+        let mut networks = Self::new();
+        // Cannot fail, the list is empty
+        let _ = networks.networks.push(credentials);
+        networks
+    }
+
+    /// Return the networks, highest priority first
+    pub fn as_slice(&self) -> &[WifiCredentials] {
+        &self.networks
+    }
+
+    pub fn len(&self) -> usize {
+        self.networks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    /// Return the position of the network with an SSID
+    pub fn position(&self, ssid: &str) -> Option<usize> {
+        self.networks
+            .iter()
+            .position(|network| network.ssid == ssid)
+    }
+
+    /// Add a network with the lowest priority and return its position
+    ///
+    /// A network with the same SSID is updated in place instead, keeping its
+    /// priority.
+    pub fn add(&mut self, credentials: WifiCredentials) -> Result<usize, NetworksError> {
+        if let Some(index) = self.position(&credentials.ssid) {
+            self.networks[index] = credentials;
+            return Ok(index);
+        }
+        self.networks
+            .push(credentials)
+            .map_err(|_| NetworksError::Full)?;
+        Ok(self.networks.len() - 1)
+    }
+
+    /// Add a network with the highest priority
+    ///
+    /// A network with the same SSID moves first. When the list is full, the
+    /// network with the lowest priority is dropped.
+    pub fn add_first(&mut self, credentials: WifiCredentials) {
+        let index = self
+            .position(&credentials.ssid)
+            .unwrap_or(self.networks.len().min(MAX_STORED_NETWORKS - 1));
+        if index < self.networks.len() {
+            self.networks.remove(index);
+        }
+        // Cannot fail, there is room left
+        let _ = self.networks.insert(0, credentials);
+    }
+
+    /// Remove the network at a position
+    pub fn remove(&mut self, index: usize) -> Result<WifiCredentials, NetworksError> {
+        if index >= self.networks.len() {
+            return Err(NetworksError::NotFound);
+        }
+        if self.networks.len() == 1 {
+            return Err(NetworksError::LastNetwork);
+        }
+        Ok(self.networks.remove(index))
+    }
+
+    /// Change the priorities, given every stored SSID once in the new order
+    pub fn reorder<'a>(
+        &mut self,
+        ssids: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), NetworksError> {
+        let mut networks = Vec::new();
+        for ssid in ssids {
+            let network = self.position(ssid).ok_or(NetworksError::InvalidOrder)?;
+            let network = &self.networks[network];
+            if networks.contains(network) {
+                return Err(NetworksError::InvalidOrder);
+            }
+            networks
+                .push(network.clone())
+                .map_err(|_| NetworksError::InvalidOrder)?;
+        }
+        if networks.len() != self.networks.len() {
+            return Err(NetworksError::InvalidOrder);
+        }
+        self.networks = networks;
+        Ok(())
+    }
+}
+
+/// Return the key of the network at a position in the key-value store
+fn storage_key(index: usize) -> String<{ storage::MAX_KEY_SIZE }> {
+    let mut key = String::new();
+    // Cannot fail, the prefix and a digit fit in a key
+    let _ = key.push_str(STORAGE_KEY);
+    if index > 0 {
+        let _ = write!(key, "{index}");
+    }
+    key
+}
+
+/// Load the stored networks from the key-value store
+///
+/// Invalid values are skipped, the others keep their order.
+pub async fn load_networks(storage: &Storage) -> StoredNetworks {
+    let mut networks = StoredNetworks::new();
+    let mut value = [0; storage::MAX_VALUE_SIZE];
+    for index in 0..MAX_STORED_NETWORKS {
+        match storage.get(&storage_key(index), &mut value).await {
+            Ok(Some(length)) => match WifiCredentials::decode(&value[..length]) {
+                // Cannot fail, at most one network per key
+                Some(credentials) => {
+                    let _ = networks.networks.push(credentials);
+                }
+                None => {
+                    crate::log!(warn: "Warning: ignoring invalid stored Wi-Fi network {}", index)
+                }
+            },
+            Ok(None) => {}
+            Err(error) => {
+                crate::log!(error: "Failed to load Wi-Fi network {}: {:?}", index, error);
+            }
+        }
+    }
+    networks
+}
+
+/// Save the stored networks in the key-value store
+///
+/// Every position is written, and the keys past the last network deleted.
+pub async fn save_networks(
+    storage: &Storage,
+    networks: &StoredNetworks,
+) -> Result<(), storage::Error> {
+    for index in 0..MAX_STORED_NETWORKS {
+        let key = storage_key(index);
+        match networks.networks.get(index) {
+            Some(credentials) => storage.set(&key, &credentials.encode()).await?,
+            None => storage.delete(&key).await?,
+        }
+    }
+    Ok(())
+}
+
+/// Save credentials posted to the provisioning form, with the highest
+/// priority, see [`StoredNetworks::add_first`]
+///
+/// The network is also saved as the one joined last, so it is tried first
+/// after the restart.
+pub async fn save_credentials(
+    storage: &Storage,
+    credentials: &WifiCredentials,
+) -> Result<(), storage::Error> {
+    let mut networks = load_networks(storage).await;
+    networks.add_first(credentials.clone());
+    save_networks(storage, &networks).await?;
+    save_last_network(storage, &credentials.ssid).await
+}
+
+/// Load the SSID of the network joined last, if any
+pub async fn load_last_network(storage: &Storage) -> Option<String<MAX_SSID_SIZE>> {
+    let mut value = [0; storage::MAX_VALUE_SIZE];
+    match storage.get(LAST_NETWORK_KEY, &mut value).await {
+        Ok(Some(length)) => core::str::from_utf8(&value[..length])
+            .ok()
+            .and_then(|ssid| String::try_from(ssid).ok()),
+        Ok(None) => None,
+        Err(error) => {
+            crate::log!(error: "Failed to load the last Wi-Fi network: {:?}", error);
+            None
+        }
+    }
+}
+
+/// Save the SSID of the network joined last
+pub async fn save_last_network(storage: &Storage, ssid: &str) -> Result<(), storage::Error> {
+    storage.set(LAST_NETWORK_KEY, ssid.as_bytes()).await
+}
+
+/// Load the password of the provisioning access point, generating it with
+/// `rng` on first boot, and remember it, see
+/// [`identity::access_point_password`]
+///
+/// A password that cannot be saved is only used until the next restart.
+pub async fn load_access_point_password(storage: &Storage, rng: &mut impl RngCore) {
+    let mut value = [0; storage::MAX_VALUE_SIZE];
+    let stored = match storage.get(ACCESS_POINT_PASSWORD_KEY, &mut value).await {
+        Ok(Some(length)) => core::str::from_utf8(&value[..length])
+            .ok()
+            .filter(|password| password.len() == ACCESS_POINT_PASSWORD_SIZE)
+            .and_then(|password| String::try_from(password).ok()),
+        Ok(None) => None,
+        Err(error) => {
+            crate::log!(error: "Failed to load the access point password: {:?}", error);
+            None
+        }
+    };
+    let password = match stored {
+        Some(password) => password,
+        None => {
+            let mut random = [0; ACCESS_POINT_PASSWORD_SIZE / 2];
+            rng.fill_bytes(&mut random);
+            let password = identity::access_point_password_from(&random);
+            if let Err(error) = storage
+                .set(ACCESS_POINT_PASSWORD_KEY, password.as_bytes())
+                .await
+            {
+                crate::log!(error: "Failed to save the access point password: {:?}", error);
+            }
+            password
+        }
+    };
+    identity::set_access_point_password(password);
+}
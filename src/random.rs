@@ -8,11 +8,40 @@
 
 //! Random numbers generator
 
+use core::cell::RefCell;
+use core::fmt;
+use core::fmt::Write as _;
+
 use rand_core::CryptoRng;
 use rand_core::RngCore;
 
+use base64::Engine as _;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
 use esp_hal::rng::Rng;
 
+use heapless::String;
+
+use serde::Deserialize;
+
+
+/// Size of API tokens in bytes
+pub const TOKEN_SIZE: usize = 32;
+
+/// Maximal number of bytes generated by `/random`
+pub const MAX_RANDOM_BYTES: usize = 64;
+
+/// Number of bytes generated by `/random` by default
+pub const DEFAULT_RANDOM_BYTES: usize = 16;
+
+/// Maximal length of encoded random bytes, as hexadecimal
+pub const MAX_RANDOM_TEXT_SIZE: usize = 2 * MAX_RANDOM_BYTES;
+
+/// A random number generator shared by request handlers
+pub type SharedRng = Mutex<CriticalSectionRawMutex, RefCell<RngWrapper>>;
+
 /// A wrapper for ESP random number generator that implement traits form
 /// `rand_core`
 #[derive(Clone)]
@@ -53,4 +82,79 @@ fn u32_pair_to_u64(first: u32, second: u32) -> u64 {
     let [a, b, c, d] = first.to_ne_bytes();
     let [e, f, g, h] = second.to_ne_bytes();
     u64::from_ne_bytes([a, b, c, d, e, f, g, h])
-}
\ No newline at end of file
+}
+
+/// A random API token
+///
+/// Tokens are exchanged as lowercase hexadecimal strings.
+#[derive(Clone, Copy)]
+pub struct Token([u8; TOKEN_SIZE]);
+
+impl Token {
+    /// Generate a new random token
+    pub fn generate(rng: &mut RngWrapper) -> Self {
+        let mut bytes = [0_u8; TOKEN_SIZE];
+        rng.fill_bytes(&mut bytes);
+        Self(bytes)
+    }
+
+    /// Return the raw bytes of the token
+    pub fn as_bytes(&self) -> &[u8; TOKEN_SIZE] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(formatter, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Generate a new API token and print it over RTT
+///
+/// The token is kept out of the log sink, whose lines are served over HTTP.
+pub fn generate_api_token(rng: &mut RngWrapper) -> Token {
+    let token = Token::generate(rng);
+    rtt_target::rprintln!("API token: {}", token);
+    token
+}
+
+/// Encoding of random bytes
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RandomFormat {
+    /// Lowercase hexadecimal
+    #[default]
+    Hex,
+
+    /// Standard base64 with padding
+    Base64,
+}
+
+/// Encode at most [`MAX_RANDOM_BYTES`] bytes as text
+///
+/// Return `None` if there are more bytes.
+pub fn encode_random(bytes: &[u8], format: RandomFormat) -> Option<String<MAX_RANDOM_TEXT_SIZE>> {
+    if bytes.len() > MAX_RANDOM_BYTES {
+        return None;
+    }
+    let mut text = String::new();
+    match format {
+        RandomFormat::Hex => {
+            for byte in bytes {
+                write!(text, "{byte:02x}").ok()?;
+            }
+        }
+        RandomFormat::Base64 => {
+            let mut buffer = [0; MAX_RANDOM_TEXT_SIZE];
+            let length = base64::engine::general_purpose::STANDARD
+                .encode_slice(bytes, &mut buffer)
+                .ok()?;
+            text.push_str(core::str::from_utf8(&buffer[..length]).ok()?).ok()?;
+        }
+    }
+    Some(text)
+}
@@ -0,0 +1,121 @@
+//! Range requests, for resuming downloads of flash files
+//!
+//! Only a single range of bytes is served, with `206 Partial Content`.
+//! Requests for several ranges, or with a `Range` header that does not
+//! parse, get the whole content with `200 OK`, as if the header was absent,
+//! which RFC 9110 allows.
+
+use core::fmt;
+use core::ops::Range;
+
+use heapless::String;
+
+/// Maximal length of a kept `Range` header value
+pub const RANGE_SIZE: usize = 64;
+
+/// An extractor for the `Range` request header
+///
+/// Values longer than [`RANGE_SIZE`] are dropped, which only costs a full
+/// response.
+#[derive(Default)]
+pub struct RangeHeader(pub Option<String<RANGE_SIZE>>);
+
+impl RangeHeader {
+    /// Resolve the header against the length of the content
+    pub fn resolve(&self, length: u32) -> ByteRange {
+        self.0
+            .as_deref()
+            .map_or(ByteRange::Full, |header| parse_range(header, length))
+    }
+}
+
+impl<'r, State> picoserve::extract::FromRequestParts<'r, State> for RangeHeader {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        _state: &'r State,
+        request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        let value = request_parts
+            .headers()
+            .get("Range")
+            .and_then(|value| value.as_str().ok())
+            .and_then(|value| String::try_from(value).ok());
+        Ok(Self(value))
+    }
+}
+
+/// The part of a content to serve
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// The whole content, with `200 OK`
+    Full,
+
+    /// A range of bytes, with `206 Partial Content`
+    Partial(Range<u32>),
+
+    /// No byte of the content, with `416 Range Not Satisfiable`
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header value against the length of the content
+///
+/// The forms `bytes=first-last`, `bytes=first-` and `bytes=-suffix` are
+/// supported. A last position past the end is clamped, as is a suffix longer
+/// than the content.
+pub fn parse_range(header: &str, length: u32) -> ByteRange {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    if first.is_empty() {
+        // A suffix of the content
+        let Ok(suffix) = last.parse::<u32>() else {
+            return ByteRange::Full;
+        };
+        if suffix == 0 || length == 0 {
+            return ByteRange::Unsatisfiable;
+        }
+        return ByteRange::Partial(length.saturating_sub(suffix)..length);
+    }
+
+    let Ok(first) = first.parse::<u32>() else {
+        return ByteRange::Full;
+    };
+    let end = if last.is_empty() {
+        length
+    } else {
+        match last.parse::<u32>() {
+            Ok(last) if last >= first => last.saturating_add(1).min(length),
+            _ => return ByteRange::Full,
+        }
+    };
+    if first >= length {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(first..end)
+}
+
+/// The value of a `Content-Range` response header
+pub struct ContentRange {
+    /// The range served, or `None` for a 416 response
+    pub range: Option<Range<u32>>,
+
+    /// The length of the whole content
+    pub length: u32,
+}
+
+impl fmt::Display for ContentRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.range {
+            Some(range) => write!(f, "bytes {}-{}/{}", range.start, range.end - 1, self.length),
+            None => write!(f, "bytes */{}", self.length),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Identifiers correlating the log lines of a request
+//!
+//! Every request gets an ID, echoed in the `X-Request-Id` response header.
+//! IDs supplied by the client in the request header of the same name are
+//! kept, others are taken from an incrementing counter.
+//!
+//! A web task serves one request at a time, so the ID of its request is kept
+//! in a slot of the task, see [`RequestId::current`], rather than in a copy
+//! of the shared state.
+
+use core::cell::RefCell;
+use core::fmt;
+use core::fmt::Write as _;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use heapless::String;
+use portable_atomic::{AtomicU32, Ordering};
+
+use crate::web::SERVER_TASK_COUNT;
+
+/// Name of the request and response header carrying the ID
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Maximal length of an ID supplied by a client
+pub const REQUEST_ID_SIZE: usize = 32;
+
+/// Counter of the generated IDs
+static NEXT_REQUEST_ID: AtomicU32 = AtomicU32::new(1);
+
+/// The ID of a request
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequestId(String<REQUEST_ID_SIZE>);
+
+/// ID of the request served by each web task
+static CURRENT: [Mutex<CriticalSectionRawMutex, RefCell<RequestId>>; SERVER_TASK_COUNT] =
+    [const { Mutex::new(RefCell::new(RequestId(String::new()))) }; SERVER_TASK_COUNT];
+
+impl RequestId {
+    /// Generate a new ID, written as 8 hexadecimal digits
+    pub fn next() -> Self {
+        let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        let mut text = String::new();
+        // Cannot fail, 8 digits fit
+        let _ = write!(text, "{:08x}", id);
+        Self(text)
+    }
+
+    /// Keep an ID supplied by a client
+    ///
+    /// Returns `None` for IDs that are empty, longer than
+    /// [`REQUEST_ID_SIZE`] or contain anything but visible ASCII, which could
+    /// not be echoed safely in a header.
+    pub fn from_header(value: &str) -> Option<Self> {
+        if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_graphic()) {
+            return None;
+        }
+        String::try_from(value).ok().map(Self)
+    }
+
+    /// Make this the ID of the request served by a web task
+    pub fn set_current(self, task: usize) {
+        CURRENT[task].lock(|current| *current.borrow_mut() = self);
+    }
+
+    /// Return the ID of the request served by a web task, empty before its
+    /// first request
+    pub fn current(task: usize) -> Self {
+        CURRENT[task].lock(|current| current.borrow().clone())
+    }
+
+    /// Return the ID as a string, empty outside of a request
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -0,0 +1,299 @@
+//! Ring buffer of the most recent HTTP requests
+//!
+//! Records are kept in RAM so they are available after the probe has been
+//! unplugged, and exposed by the `/logs` route.
+//!
+//! Every record carries a sequence number, increasing by one per request, so
+//! clients poll incrementally with `/logs?after=<sequence>`. The response
+//! carries the latest sequence number in [`SEQUENCE_HEADER`] to resume from.
+
+use core::cell::RefCell;
+use core::fmt;
+use core::fmt::Write as _;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use heapless::{Deque, String};
+use picoserve::io::{Read, Write};
+use picoserve::response::chunked::ChunkedResponse;
+use picoserve::response::{Connection, IntoResponse, ResponseWriter};
+use picoserve::ResponseSent;
+use serde::{Deserialize, Serialize};
+
+use crate::compression::{Body, Compression, EncodedChunks, Encoding};
+
+/// Number of requests kept in the log
+pub const REQUEST_LOG_SIZE: usize = 64;
+
+/// Maximal length of a logged path, longer paths are truncated
+pub const PATH_SIZE: usize = 48;
+
+/// Maximal length of a logged method
+pub const METHOD_SIZE: usize = 8;
+
+/// Header of the `/logs` responses carrying the latest sequence number
+pub const SEQUENCE_HEADER: &str = "X-Log-Sequence";
+
+/// The most recent requests, oldest first, with consecutive sequence numbers
+struct RequestLog {
+    records: Deque<RequestRecord, REQUEST_LOG_SIZE>,
+    next_sequence: u64,
+}
+
+/// The log of the requests
+static REQUEST_LOG: Mutex<CriticalSectionRawMutex, RefCell<RequestLog>> =
+    Mutex::new(RefCell::new(RequestLog {
+        records: Deque::new(),
+        next_sequence: 1,
+    }));
+
+/// A logged request
+#[derive(Clone, Serialize)]
+pub struct RequestRecord {
+    /// Sequence number, assigned by [`record`] starting from 1
+    pub sequence: u64,
+
+    /// Time of the request as Unix epoch
+    ///
+    /// This counts from boot when the clock is not synchronized.
+    pub timestamp: u64,
+
+    /// Time of the request in milliseconds since boot
+    pub uptime_ms: u64,
+
+    /// Request method
+    pub method: String<METHOD_SIZE>,
+
+    /// Requested path, possibly truncated
+    pub path: String<PATH_SIZE>,
+
+    /// Response status code
+    pub status: u16,
+
+    /// Time taken to write the response in milliseconds
+    pub duration_ms: u64,
+}
+
+/// Append a request to the log with the next sequence number, dropping the
+/// oldest one when full
+pub fn record(mut record: RequestRecord) {
+    REQUEST_LOG.lock(|log| {
+        let mut log = log.borrow_mut();
+        record.sequence = log.next_sequence;
+        log.next_sequence += 1;
+        if log.records.is_full() {
+            log.records.pop_front();
+        }
+        // Cannot fail, there is room for at least one record
+        let _ = log.records.push_back(record);
+    });
+}
+
+/// Return the sequence number of the most recent request, 0 before the
+/// first one
+pub fn latest_sequence() -> u64 {
+    REQUEST_LOG.lock(|log| log.borrow().next_sequence - 1)
+}
+
+/// Return the request with a sequence number, if it is still in the log
+pub fn by_sequence(sequence: u64) -> Option<RequestRecord> {
+    REQUEST_LOG.lock(|log| {
+        let log = log.borrow();
+        let oldest = log.records.front()?.sequence;
+        let position = usize::try_from(sequence.checked_sub(oldest)?).ok()?;
+        log.records.iter().nth(position).cloned()
+    })
+}
+
+/// A class of response status codes, e.g. `4xx`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum StatusClass {
+    /// `2xx`
+    #[serde(rename = "2xx")]
+    Success,
+
+    /// `3xx`
+    #[serde(rename = "3xx")]
+    Redirection,
+
+    /// `4xx`
+    #[serde(rename = "4xx")]
+    ClientError,
+
+    /// `5xx`
+    #[serde(rename = "5xx")]
+    ServerError,
+}
+
+impl StatusClass {
+    /// Check whether a status code belongs to the class
+    pub fn matches(self, status: u16) -> bool {
+        let hundreds = match self {
+            Self::Success => 2,
+            Self::Redirection => 3,
+            Self::ClientError => 4,
+            Self::ServerError => 5,
+        };
+        status / 100 == hundreds
+    }
+}
+
+/// Filters of the records written by [`RequestLogLines`]
+#[derive(Clone, Default)]
+pub struct RecordFilter {
+    /// Only records with a greater sequence number
+    pub after: u64,
+
+    /// Only responses of a status class
+    pub status: Option<StatusClass>,
+
+    /// Only paths starting with a prefix
+    pub path_prefix: Option<String<PATH_SIZE>>,
+}
+
+impl RecordFilter {
+    /// Check whether a record passes the filters
+    pub fn matches(&self, record: &RequestRecord) -> bool {
+        record.sequence > self.after
+            && self.status.is_none_or(|status| status.matches(record.status))
+            && self
+                .path_prefix
+                .as_ref()
+                .is_none_or(|prefix| record.path.starts_with(prefix.as_str()))
+    }
+}
+
+/// Format a value into a fixed-size string, truncating it if needed
+pub fn truncated<const N: usize>(value: impl fmt::Display) -> String<N> {
+    /// A writer dropping everything past its capacity
+    struct Truncating<const N: usize>(String<N>);
+
+    impl<const N: usize> fmt::Write for Truncating<N> {
+        fn write_str(&mut self, text: &str) -> fmt::Result {
+            for character in text.chars() {
+                if self.0.push(character).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    let mut writer = Truncating(String::new());
+    let _ = write!(writer, "{value}");
+    writer.0
+}
+
+/// Maximal size of a record written as a JSON line
+const LINE_SIZE: usize = 208;
+
+/// The request log as JSON lines, newest first
+///
+/// Records are filtered as they are written, and only those present when
+/// the response started are written, so the latest sequence number sent in
+/// [`SEQUENCE_HEADER`] covers all of them.
+pub struct RequestLogLines {
+    /// Maximal number of records to write
+    pub limit: usize,
+
+    /// Records to write
+    pub filter: RecordFilter,
+
+    /// Sequence number of the most recent record to write
+    latest: u64,
+
+    /// Coding allowed for the response
+    compression: Compression,
+}
+
+impl RequestLogLines {
+    /// Write at most `limit` records passing a filter
+    pub fn new(limit: usize, filter: RecordFilter) -> Self {
+        Self {
+            limit,
+            filter,
+            latest: latest_sequence(),
+            compression: Compression::new(None, false),
+        }
+    }
+
+    /// Compress the lines if allowed
+    pub fn compressed(self, compression: Compression) -> Self {
+        Self {
+            compression,
+            ..self
+        }
+    }
+}
+
+impl Body for RequestLogLines {
+    fn content_type(&self) -> &'static str {
+        "application/x-ndjson"
+    }
+
+    fn content_length(&self) -> Option<usize> {
+        None
+    }
+
+    fn size_hint(&self) -> usize {
+        let candidates = self.latest.saturating_sub(self.filter.after);
+        let records = usize::try_from(candidates).unwrap_or(usize::MAX);
+        self.limit.min(records).saturating_mul(LINE_SIZE)
+    }
+
+    async fn write_body<W: Write>(self, mut writer: W) -> Result<(), W::Error> {
+        // Records are copied out one at a time so the log is never locked
+        // while writing to the socket
+        let mut written = 0;
+        let mut sequence = self.latest;
+        while written < self.limit && sequence > self.filter.after {
+            // Older records were dropped from the log
+            let Some(record) = by_sequence(sequence) else {
+                break;
+            };
+            sequence -= 1;
+            if !self.filter.matches(&record) {
+                continue;
+            }
+            let mut line: String<LINE_SIZE> =
+                serde_json_core::to_string(&record).unwrap_or_default();
+            let _ = line.push('\n');
+            writer.write_all(line.as_bytes()).await?;
+            written += 1;
+        }
+        Ok(())
+    }
+}
+
+impl IntoResponse for RequestLogLines {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let latest = self.latest;
+        let encoding = self.compression.encoding(self.size_hint());
+        let chunks = EncodedChunks {
+            body: self,
+            encoding,
+        };
+        match chunks.encoding {
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip(_) => {
+                let response = ChunkedResponse::new(chunks)
+                    .into_response()
+                    .with_header(SEQUENCE_HEADER, latest)
+                    .with_header("Content-Encoding", "gzip")
+                    .with_header("Vary", "Accept-Encoding");
+                response_writer.write_response(connection, response).await
+            }
+            Encoding::Identity => {
+                let response = ChunkedResponse::new(chunks)
+                    .into_response()
+                    .with_header(SEQUENCE_HEADER, latest)
+                    .with_header("Vary", "Accept-Encoding");
+                response_writer.write_response(connection, response).await
+            }
+        }
+    }
+}
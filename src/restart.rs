@@ -0,0 +1,46 @@
+//! Deferred software reset requested over HTTP
+//!
+//! The reset cannot happen in the handler, or the response would never reach
+//! the client. Handlers signal [`restart_task`] instead, which waits for the
+//! response to be flushed before resetting.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+
+use crate::clock::Clock;
+
+/// Minimal delay before a reset, leaving time to flush the response
+pub const MIN_RESTART_DELAY: Duration = Duration::from_millis(500);
+
+/// Maximal delay before a reset
+pub const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// Pending reset request, carrying the delay before the reset
+static RESTART: Signal<CriticalSectionRawMutex, Duration> = Signal::new();
+
+/// Schedule a reset, clamping the delay to the allowed range
+///
+/// Return the actual delay. A later request replaces a pending one.
+pub fn request_restart(delay: Duration) -> Duration {
+    let delay = delay.clamp(MIN_RESTART_DELAY, MAX_RESTART_DELAY);
+    RESTART.signal(delay);
+    delay
+}
+
+/// Reset the device when requested
+///
+/// A synchronized clock is saved to RTC memory first, so the time survives
+/// the reset, and the mDNS records are withdrawn.
+#[embassy_executor::task]
+pub async fn restart_task(clock: Clock) {
+    let delay = crate::tasks::beating("restart_task", None, RESTART.wait()).await;
+    crate::log!("Restarting in {} ms", delay.as_millis());
+    Timer::after(delay).await;
+    crate::mdns::say_goodbye().await;
+
+    if clock.is_synchronized() {
+        clock.save_to_rtc_memory(Duration::from_secs(0));
+    }
+    esp_hal::system::software_reset()
+}
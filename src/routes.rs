@@ -0,0 +1,174 @@
+//! Description of the routes, served at `/api` and `/help`
+//!
+//! The routes are listed once, with [`route_table!`], next to the routers of
+//! [`crate::web`] and [`crate::api`]. The same tables answer `OPTIONS`, fill
+//! the `Allow` header of 405 responses, index the counters of `/stats` and
+//! document the device, so a route missing from them is visible everywhere.
+//!
+//! The authentication of a route is not written in the tables but derived
+//! from the prefixes the authentication layers are built from, so the
+//! documentation cannot claim a route is open when it is not.
+
+use picoserve::io::Write;
+use picoserve::response::chunked::{ChunkWriter, Chunks, ChunksWritten};
+use serde::{Serialize, Serializer};
+
+use crate::api::API_V1_PREFIX;
+use crate::html::{Escaped, DEFAULT_STYLE};
+use crate::web::{path_has_prefix, routes, BASIC_AUTH_PREFIXES, SESSION_PREFIX};
+
+/// Build a table of [`RouteInfo`] from `path => methods, description;`
+/// lines
+macro_rules! route_table {
+    ($($path:expr => $methods:literal, $description:literal;)*) => {
+        &[$($crate::routes::RouteInfo {
+            path: $path,
+            methods: $methods,
+            description: $description,
+        }),*]
+    };
+}
+
+pub(crate) use route_table;
+
+/// A route, with the methods it allows and what it does
+#[derive(Clone, Copy, Debug)]
+pub struct RouteInfo {
+    /// Route pattern, e.g. `/gpio/{pin}`
+    pub path: &'static str,
+
+    /// Methods allowed on the route, e.g. `GET, POST`, without `OPTIONS`
+    pub methods: &'static str,
+
+    /// What the route does, in a few words
+    pub description: &'static str,
+}
+
+impl RouteInfo {
+    /// Return how clients of the route authenticate
+    pub fn auth(&self) -> RouteAuth {
+        auth(self.path)
+    }
+}
+
+/// How clients of a route authenticate
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteAuth {
+    /// Open to every client
+    None,
+
+    /// HTTP Basic authentication, see [`crate::web::BasicAuthLayer`]
+    Basic,
+
+    /// A session cookie from `/login`, see [`crate::web::SessionLayer`]
+    Session,
+
+    /// An API token, see [`crate::web::ApiTokenLayer`]
+    Token,
+}
+
+impl RouteAuth {
+    /// Return the name of the authentication, as serialized
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Basic => "basic",
+            Self::Session => "session",
+            Self::Token => "token",
+        }
+    }
+}
+
+/// Return how clients of the route matching a pattern authenticate
+pub fn auth(path: &str) -> RouteAuth {
+    if path_has_prefix(path, API_V1_PREFIX) {
+        RouteAuth::Token
+    } else if path_has_prefix(path, SESSION_PREFIX) {
+        RouteAuth::Session
+    } else if BASIC_AUTH_PREFIXES
+        .iter()
+        .any(|prefix| path_has_prefix(path, prefix))
+    {
+        RouteAuth::Basic
+    } else {
+        RouteAuth::None
+    }
+}
+
+impl Serialize for RouteInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut route = serializer.serialize_struct("RouteInfo", 4)?;
+        route.serialize_field("path", self.path)?;
+        route.serialize_field("methods", self.methods)?;
+        route.serialize_field("auth", &self.auth())?;
+        route.serialize_field("description", self.description)?;
+        route.end()
+    }
+}
+
+/// JSON body returned by `/api`, every route in the order of the tables
+///
+/// The routes are serialized straight from the tables, never copied.
+pub struct RouteListing;
+
+impl Serialize for RouteListing {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(routes())
+    }
+}
+
+/// HTML page returned by `/help`, a table of every route
+///
+/// The page is written one row at a time, so its size does not grow with the
+/// number of routes.
+pub struct RouteHelp;
+
+impl Chunks for RouteHelp {
+    fn content_type(&self) -> &'static str {
+        "text/html; charset=utf-8"
+    }
+
+    async fn write_chunks<W: Write>(
+        self,
+        mut chunk_writer: ChunkWriter<W>,
+    ) -> Result<ChunksWritten, W::Error> {
+        chunk_writer
+            .write_chunk(b"<!DOCTYPE html><html><head><meta charset=\"utf-8\">")
+            .await?;
+        chunk_writer
+            .write_chunk(
+                b"<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">",
+            )
+            .await?;
+        chunk_writer
+            .write_chunk(b"<title>Routes</title><style>")
+            .await?;
+        chunk_writer.write_chunk(DEFAULT_STYLE.as_bytes()).await?;
+        chunk_writer
+            .write_chunk(b"</style></head><body><h1>Routes</h1><table>")
+            .await?;
+        chunk_writer
+            .write_chunk(b"<tr><th>Path</th><th>Methods</th><th>Auth</th><th>Description</th></tr>")
+            .await?;
+        for route in routes() {
+            let mut row = heapless::String::<256>::new();
+            // Cannot fail, the longest row is far shorter
+            let _ = core::fmt::write(
+                &mut row,
+                format_args!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    Escaped(route.path),
+                    Escaped(route.methods),
+                    route.auth().as_str(),
+                    Escaped(route.description)
+                ),
+            );
+            chunk_writer.write_chunk(row.as_bytes()).await?;
+        }
+        chunk_writer.write_chunk(b"</table></body></html>").await?;
+        chunk_writer.finalize().await
+    }
+}
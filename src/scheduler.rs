@@ -0,0 +1,424 @@
+//! Actions run at a time of day, like a small crontab
+//!
+//! [`scheduler_task`] wakes at the start of every minute and runs the
+//! entries matching the local time of the [`Clock`]. Entries are managed at
+//! `/schedule` and saved in the key-value store under [`STORAGE_KEY`], so
+//! they survive a reset.
+//!
+//! Nothing runs while the clock is unsynchronized, since it then counts from
+//! the epoch at boot. The minutes elapsed since the last run are caught up
+//! when the task was late, but a jump of more than [`MAX_CATCH_UP`] minutes,
+//! e.g. when the clock is synchronized, skips the minutes in between rather
+//! than running a day's worth of entries at once. A jump backwards, e.g.
+//! when the UTC offset is lowered, does not run the repeated minutes twice.
+
+use core::cell::RefCell;
+use core::fmt::Write as _;
+use core::ops::Range;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Timer};
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::clock::Clock;
+use crate::gpio::{GpioOutputs, PinAction};
+use crate::restart;
+use crate::storage::{self, Storage};
+
+/// Maximal number of entries
+pub const MAX_ENTRIES: usize = 8;
+
+/// Key of the entries in the key-value store
+pub const STORAGE_KEY: &str = "schedule";
+
+/// Maximal number of missed minutes caught up after a late wakeup
+pub const MAX_CATCH_UP: i64 = 5;
+
+/// Days mask matching every day of the week
+pub const EVERY_DAY: u8 = 0x7F;
+
+/// Size of an encoded entry in the key-value store
+const ENCODED_ENTRY_SIZE: usize = 6;
+
+/// Minutes in a day
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// An action run by an entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    /// Set a GPIO output high
+    GpioOn,
+
+    /// Set a GPIO output low
+    GpioOff,
+
+    /// Invert a GPIO output
+    GpioToggle,
+
+    /// Reset the device
+    Reboot,
+}
+
+impl ActionKind {
+    /// Return whether the action needs a pin
+    pub fn needs_pin(self) -> bool {
+        !matches!(self, Self::Reboot)
+    }
+
+    /// Return the action on a pin, if any
+    fn pin_action(self) -> Option<PinAction> {
+        match self {
+            Self::GpioOn => Some(PinAction::On),
+            Self::GpioOff => Some(PinAction::Off),
+            Self::GpioToggle => Some(PinAction::Toggle),
+            Self::Reboot => None,
+        }
+    }
+
+    /// Encode the action as a byte
+    fn encode(self) -> u8 {
+        match self {
+            Self::GpioOn => 0,
+            Self::GpioOff => 1,
+            Self::GpioToggle => 2,
+            Self::Reboot => 3,
+        }
+    }
+
+    /// Decode an action encoded by [`ActionKind::encode`]
+    fn decode(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::GpioOn),
+            1 => Some(Self::GpioOff),
+            2 => Some(Self::GpioToggle),
+            3 => Some(Self::Reboot),
+            _ => None,
+        }
+    }
+}
+
+/// An entry of the schedule
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Identifier of the entry, unique in the schedule
+    pub id: u8,
+
+    /// Hour of the local time, 0 to 23
+    pub hour: u8,
+
+    /// Minute of the local time, 0 to 59
+    pub minute: u8,
+
+    /// Days of the week, bit 0 for Monday to bit 6 for Sunday
+    pub days: u8,
+
+    /// Action to run
+    pub action: ActionKind,
+
+    /// Pin of the GPIO actions
+    pub pin: Option<u8>,
+}
+
+impl Entry {
+    /// Check whether the entry runs at a local minute, counted from the
+    /// epoch
+    pub fn matches(&self, local_minute: i64) -> bool {
+        let day = local_minute.div_euclid(MINUTES_PER_DAY);
+        let minute_of_day = local_minute.rem_euclid(MINUTES_PER_DAY);
+        // The epoch was a Thursday
+        let weekday = (day + 3).rem_euclid(7);
+        self.days & (1 << weekday) != 0
+            && i64::from(self.hour) * 60 + i64::from(self.minute) == minute_of_day
+    }
+
+    /// Encode the entry for the key-value store
+    fn encode(&self) -> [u8; ENCODED_ENTRY_SIZE] {
+        [
+            self.id,
+            self.hour,
+            self.minute,
+            self.days,
+            self.action.encode(),
+            self.pin.unwrap_or(u8::MAX),
+        ]
+    }
+
+    /// Decode an entry encoded by [`Entry::encode`]
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let &[id, hour, minute, days, action, pin] = bytes else {
+            return None;
+        };
+        Some(Self {
+            id,
+            hour,
+            minute,
+            days,
+            action: ActionKind::decode(action)?,
+            pin: (pin != u8::MAX).then_some(pin),
+        })
+    }
+
+    /// Run the action of the entry
+    fn run(&self, gpio: &GpioOutputs) {
+        let Some(action) = self.action.pin_action() else {
+            crate::log!("Schedule entry {}: rebooting", self.id);
+            restart::request_restart(Duration::from_secs(0));
+            return;
+        };
+        match self
+            .pin
+            .and_then(|pin| gpio.apply(pin, action).map(|high| (pin, high)))
+        {
+            Some((pin, high)) => {
+                crate::log!("Schedule entry {}: GPIO {} set to {}", self.id, pin, high)
+            }
+            None => crate::log!("Schedule entry {}: unknown GPIO {:?}", self.id, self.pin),
+        }
+    }
+}
+
+/// An error of the schedule
+#[derive(Debug)]
+pub enum Error {
+    /// The schedule has [`MAX_ENTRIES`] entries
+    Full,
+
+    /// No entry has the identifier
+    NotFound,
+
+    /// The entries could not be saved
+    Storage(storage::Error),
+}
+
+/// The entries of the schedule
+pub struct Schedule {
+    entries: Mutex<CriticalSectionRawMutex, RefCell<Vec<Entry, MAX_ENTRIES>>>,
+    storage: Option<&'static Storage>,
+}
+
+impl Schedule {
+    /// Create an empty schedule, saved in `storage` if any
+    pub fn new(storage: Option<&'static Storage>) -> Self {
+        Self {
+            entries: Mutex::new(RefCell::new(Vec::new())),
+            storage,
+        }
+    }
+
+    /// Return every entry
+    pub fn entries(&self) -> Vec<Entry, MAX_ENTRIES> {
+        self.entries.lock(|entries| entries.borrow().clone())
+    }
+
+    /// Add an entry and return it with its identifier
+    pub async fn add(&self, mut entry: Entry) -> Result<Entry, Error> {
+        self.entries.lock(|entries| {
+            let mut entries = entries.borrow_mut();
+            // Cannot fail, there are fewer entries than identifiers
+            entry.id = (0..=u8::MAX)
+                .find(|id| entries.iter().all(|entry| entry.id != *id))
+                .unwrap_or_default();
+            entries.push(entry).map_err(|_| Error::Full)
+        })?;
+        self.save().await?;
+        Ok(entry)
+    }
+
+    /// Remove an entry
+    pub async fn remove(&self, id: u8) -> Result<(), Error> {
+        self.entries.lock(|entries| {
+            let mut entries = entries.borrow_mut();
+            let index = entries
+                .iter()
+                .position(|entry| entry.id == id)
+                .ok_or(Error::NotFound)?;
+            entries.remove(index);
+            Ok(())
+        })?;
+        self.save().await
+    }
+
+    /// Remove every entry
+    pub async fn clear(&self) -> Result<(), Error> {
+        self.entries.lock(|entries| entries.borrow_mut().clear());
+        self.save().await
+    }
+
+    /// Load the entries from the key-value store
+    ///
+    /// Entries that do not decode are dropped.
+    pub async fn load(&self) {
+        let Some(storage) = self.storage else {
+            return;
+        };
+        let mut value = [0; storage::MAX_VALUE_SIZE];
+        match storage.get(STORAGE_KEY, &mut value).await {
+            Ok(Some(length)) => {
+                let loaded: Vec<Entry, MAX_ENTRIES> = value[..length]
+                    .chunks_exact(ENCODED_ENTRY_SIZE)
+                    .filter_map(Entry::decode)
+                    .take(MAX_ENTRIES)
+                    .collect();
+                crate::log!("Loaded {} schedule entries", loaded.len());
+                self.entries.lock(|entries| *entries.borrow_mut() = loaded);
+            }
+            Ok(None) => {}
+            Err(error) => crate::log!("Failed to load the schedule: {:?}", error),
+        }
+    }
+
+    /// Save the entries to the key-value store
+    async fn save(&self) -> Result<(), Error> {
+        let Some(storage) = self.storage else {
+            return Ok(());
+        };
+        let mut value: Vec<u8, { MAX_ENTRIES * ENCODED_ENTRY_SIZE }> = Vec::new();
+        for entry in self.entries() {
+            // Cannot fail, the buffer fits every entry
+            let _ = value.extend_from_slice(&entry.encode());
+        }
+        storage
+            .set(STORAGE_KEY, &value)
+            .await
+            .map_err(Error::Storage)
+    }
+}
+
+/// Return the local time as minutes since the epoch
+pub fn local_minute(time: OffsetDateTime) -> i64 {
+    (time.unix_timestamp() + i64::from(time.offset().whole_seconds())).div_euclid(60)
+}
+
+/// Return the minutes to run after `last` was run, up to `current`
+///
+/// Without a last minute, only the current one runs. Minutes already run,
+/// after a jump backwards, are not run again. A jump forward of more than
+/// [`MAX_CATCH_UP`] minutes only runs the current minute.
+pub fn minutes_to_run(last: Option<i64>, current: i64) -> Range<i64> {
+    match last {
+        None => current..current + 1,
+        Some(last) if current <= last => current..current,
+        Some(last) if current - last > MAX_CATCH_UP => {
+            crate::log!(
+                warn: "Warning: the clock jumped {} minutes forward, skipping the schedule in between",
+                current - last
+            );
+            current..current + 1
+        }
+        Some(last) => last + 1..current + 1,
+    }
+}
+
+/// Format the time of an entry as `HH:MM`
+pub fn format_time(hour: u8, minute: u8) -> String<5> {
+    let mut time = String::new();
+    // Cannot fail, the buffer fits two 2-digit numbers
+    let _ = write!(time, "{:02}:{:02}", hour, minute);
+    time
+}
+
+/// Parse a time formatted as `HH:MM`
+pub fn parse_time(time: &str) -> Option<(u8, u8)> {
+    let (hour, minute) = time.split_once(':')?;
+    if hour.len() != 2 || minute.len() != 2 {
+        return None;
+    }
+    let hour: u8 = hour.parse().ok().filter(|hour| *hour < 24)?;
+    let minute: u8 = minute.parse().ok().filter(|minute| *minute < 60)?;
+    Some((hour, minute))
+}
+
+/// Run the entries of the schedule at their time
+#[embassy_executor::task]
+pub async fn scheduler_task(schedule: &'static Schedule, clock: Clock, gpio: &'static GpioOutputs) {
+    schedule.load().await;
+
+    // The minute the device booted in has already been run, maybe by the
+    // entry that rebooted it
+    let mut last_minute = clock
+        .is_synchronized()
+        .then(|| clock.now().ok().map(local_minute))
+        .flatten();
+    let mut warned = false;
+
+    loop {
+        crate::heartbeat!("scheduler_task");
+        let wait = Timer::after(clock.duration_to_next_rounded_wakeup(Duration::from_secs(60)));
+        crate::tasks::beating("scheduler_task", None, wait).await;
+
+        if !clock.is_synchronized() {
+            if !warned {
+                crate::log!(warn: "Warning: the clock is unsynchronized, the schedule does not run");
+                warned = true;
+            }
+            continue;
+        }
+        let Ok(now) = clock.now() else {
+            continue;
+        };
+
+        let current = local_minute(now);
+        for minute in minutes_to_run(last_minute, current) {
+            for entry in schedule
+                .entries()
+                .iter()
+                .filter(|entry| entry.matches(minute))
+            {
+                entry.run(gpio);
+            }
+        }
+        last_minute = Some(last_minute.map_or(current, |last| last.max(current)));
+    }
+}
+
+/// JSON body of `POST /schedule`
+#[derive(Deserialize)]
+pub struct EntryRequest {
+    /// Local time formatted as `HH:MM`
+    pub time: String<5>,
+
+    /// Days of the week, bit 0 for Monday to bit 6 for Sunday, every day if
+    /// absent
+    pub days: Option<u8>,
+
+    /// Action to run
+    pub action: ActionKind,
+
+    /// Pin of the GPIO actions
+    pub pin: Option<u8>,
+}
+
+/// An entry returned by the `/schedule` routes
+#[derive(Serialize)]
+pub struct EntryResponse {
+    /// Identifier of the entry, for `DELETE /schedule/{id}`
+    pub id: u8,
+
+    /// Local time formatted as `HH:MM`
+    pub time: String<5>,
+
+    /// Days of the week, bit 0 for Monday to bit 6 for Sunday
+    pub days: u8,
+
+    /// Action to run
+    pub action: ActionKind,
+
+    /// Pin of the GPIO actions
+    pub pin: Option<u8>,
+}
+
+impl From<&Entry> for EntryResponse {
+    fn from(entry: &Entry) -> Self {
+        Self {
+            id: entry.id,
+            time: format_time(entry.hour, entry.minute),
+            days: entry.days,
+            action: entry.action,
+            pin: entry.pin,
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Security headers added to every response
+//!
+//! A [`SecurityHeadersLayer`] adds one [`SecurityHeaders`] profile to the
+//! responses of the router it is applied to. Nested routers with a profile
+//! of their own are excluded from the outer layer with
+//! [`SecurityHeadersLayer::except`], so no response gets a header twice.
+
+use picoserve::io::Read;
+use picoserve::response::{Connection, Response, ResponseWriter};
+use picoserve::ResponseSent;
+
+use crate::cache_control::CachePolicy;
+use crate::etag::AppendHeaderWriter;
+use crate::web::path_has_prefix;
+
+/// A set of security headers
+#[derive(Clone, Copy, Debug)]
+pub struct SecurityHeaders {
+    /// Value of the `Content-Security-Policy` header
+    pub content_security_policy: &'static str,
+
+    /// Value of the `X-Frame-Options` header
+    pub frame_options: &'static str,
+
+    /// Policy of the `Cache-Control` header, if any
+    ///
+    /// Leave this unset for routes setting their own, like static assets.
+    pub cache_control: Option<CachePolicy>,
+}
+
+impl SecurityHeaders {
+    /// Headers for the HTML pages
+    ///
+    /// Inline styles are allowed for the dashboard and the settings page.
+    /// The only inline script allowed is
+    /// [`LOG_VIEW_SCRIPT`](crate::web::LOG_VIEW_SCRIPT), by its SHA-256 hash.
+    pub const HTML: Self = Self {
+        content_security_policy: "default-src 'self'; style-src 'self' 'unsafe-inline'; \
+            script-src 'self' 'sha256-ih09OduU3yso4+YYsnSDm0I735xDCb+RmSuAtUVDGUs='; \
+            img-src 'self' data:; connect-src 'self'; frame-ancestors 'none'",
+        frame_options: "DENY",
+        cache_control: None,
+    };
+
+    /// Headers for the JSON API, which is never cached
+    pub const API: Self = Self {
+        content_security_policy: "default-src 'none'; frame-ancestors 'none'",
+        frame_options: "DENY",
+        cache_control: Some(CachePolicy::NO_STORE),
+    };
+
+    /// Replace the `Content-Security-Policy`, e.g. to allow inline scripts
+    pub const fn with_content_security_policy(self, policy: &'static str) -> Self {
+        Self {
+            content_security_policy: policy,
+            ..self
+        }
+    }
+
+    /// Replace the `Cache-Control` header
+    pub const fn with_cache_control(self, cache_control: Option<CachePolicy>) -> Self {
+        Self { cache_control, ..self }
+    }
+
+    /// Iterate over the header names and values
+    pub fn headers(&self) -> impl Iterator<Item = (&'static str, &'static str)> {
+        [
+            ("X-Content-Type-Options", Some("nosniff")),
+            ("X-Frame-Options", Some(self.frame_options)),
+            ("Content-Security-Policy", Some(self.content_security_policy)),
+            ("Cache-Control", self.cache_control.map(CachePolicy::value)),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+    }
+}
+
+/// A response writer adding security headers to the response
+struct SecurityHeadersWriter<W> {
+    headers: SecurityHeaders,
+    response_writer: W,
+}
+
+impl<W: ResponseWriter> ResponseWriter for SecurityHeadersWriter<W> {
+    type Error = W::Error;
+
+    async fn write_response<
+        R: Read<Error = Self::Error>,
+        H: picoserve::response::HeadersIter,
+        B: picoserve::response::Body,
+    >(
+        self,
+        connection: Connection<'_, R>,
+        response: Response<H, B>,
+    ) -> Result<ResponseSent, Self::Error> {
+        let response = response
+            .with_header("X-Content-Type-Options", "nosniff")
+            .with_header("X-Frame-Options", self.headers.frame_options)
+            .with_header("Content-Security-Policy", self.headers.content_security_policy);
+        match self.headers.cache_control {
+            Some(cache_control) => {
+                let response_writer = AppendHeaderWriter {
+                    name: "Cache-Control",
+                    value: cache_control,
+                    response_writer: self.response_writer,
+                };
+                response_writer.write_response(connection, response).await
+            }
+            None => self.response_writer.write_response(connection, response).await,
+        }
+    }
+}
+
+/// A layer adding a [`SecurityHeaders`] profile to the responses
+///
+/// The headers are added by a response writer wrapper, so responses built by
+/// other layers, like 401 or 405 responses, get them as well when this layer
+/// is applied after them.
+pub struct SecurityHeadersLayer {
+    headers: SecurityHeaders,
+    excluded_prefix: Option<&'static str>,
+}
+
+impl SecurityHeadersLayer {
+    /// Add `headers` to every response
+    pub const fn new(headers: SecurityHeaders) -> Self {
+        Self {
+            headers,
+            excluded_prefix: None,
+        }
+    }
+
+    /// Leave the paths equal to or below `prefix` alone, for a nested router
+    /// with its own layer
+    pub const fn except(self, prefix: &'static str) -> Self {
+        Self {
+            excluded_prefix: Some(prefix),
+            ..self
+        }
+    }
+
+    /// Check whether the headers are added to the response to a path
+    pub fn applies_to(&self, path: &str) -> bool {
+        self.excluded_prefix
+            .is_none_or(|prefix| !path_has_prefix(path, prefix))
+    }
+}
+
+impl<State, PathParameters> picoserve::routing::Layer<State, PathParameters> for SecurityHeadersLayer {
+    type NextState = State;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &State,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        if self.applies_to(request_parts.path().encoded()) {
+            let response_writer = SecurityHeadersWriter {
+                headers: self.headers,
+                response_writer,
+            };
+            next.run(state, path_parameters, response_writer).await
+        } else {
+            next.run(state, path_parameters, response_writer).await
+        }
+    }
+}
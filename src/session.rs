@@ -0,0 +1,258 @@
+//! Cookie sessions of the HTML admin pages
+//!
+//! `POST /login` checks the admin password and stores a random session
+//! identifier, sent back in the [`COOKIE_NAME`] cookie. The browser then
+//! presents the cookie on every request, so phones do not prompt for Basic
+//! credentials on each page. Sessions unused for the idle timeout, by
+//! default [`IDLE_TIMEOUT`], expire, and the least recently used one is
+//! dropped when the table is full.
+//!
+//! The browser also presents the cookie on form posts from other sites, so
+//! each session has a random [`CsrfToken`] as well. The HTML forms embed it
+//! in the [`CSRF_FIELD`] field, which other sites cannot read.
+
+use core::cell::RefCell;
+use core::fmt;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use portable_atomic::{AtomicU64, Ordering};
+use rand_core::RngCore;
+
+use crate::random::RngWrapper;
+use crate::web::{constant_time_eq, decode_hex};
+
+/// Maximal number of concurrent sessions
+pub const MAX_SESSIONS: usize = 4;
+
+/// Size of session identifiers in bytes
+pub const SESSION_ID_SIZE: usize = 16;
+
+/// Time after which an unused session expires, until another one is set
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Name of the session cookie
+pub const COOKIE_NAME: &str = "sid";
+
+/// Size of CSRF tokens in bytes
+pub const CSRF_TOKEN_SIZE: usize = 16;
+
+/// Name of the form field holding the CSRF token
+pub const CSRF_FIELD: &str = "csrf_token";
+
+/// A random session identifier
+///
+/// Identifiers are exchanged as lowercase hexadecimal strings.
+#[derive(Clone, Copy)]
+pub struct SessionId([u8; SESSION_ID_SIZE]);
+
+impl SessionId {
+    /// Parse a hexadecimal identifier
+    pub fn parse(hex: &str) -> Option<Self> {
+        let mut bytes = [0; SESSION_ID_SIZE];
+        decode_hex(hex, &mut bytes).then_some(Self(bytes))
+    }
+
+    /// Compare two identifiers in constant time
+    fn matches(&self, other: &Self) -> bool {
+        constant_time_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(formatter, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The CSRF token of a session
+///
+/// Tokens are exchanged as lowercase hexadecimal strings.
+#[derive(Clone, Copy)]
+pub struct CsrfToken([u8; CSRF_TOKEN_SIZE]);
+
+impl CsrfToken {
+    /// Check a hexadecimal token against this one in constant time
+    fn matches(&self, hex: &str) -> bool {
+        let mut candidate = [0; CSRF_TOKEN_SIZE];
+        decode_hex(hex, &mut candidate) && constant_time_eq(&candidate, &self.0)
+    }
+}
+
+impl fmt::Display for CsrfToken {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(formatter, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A logged-in session
+struct Session {
+    id: SessionId,
+    csrf_token: CsrfToken,
+    last_used: Instant,
+}
+
+impl Session {
+    /// Check whether the session is past an idle timeout
+    fn is_expired(&self, now: Instant, idle_timeout: Duration) -> bool {
+        now.saturating_duration_since(self.last_used) > idle_timeout
+    }
+}
+
+/// The table of the logged-in sessions
+pub struct SessionStore {
+    sessions: Mutex<CriticalSectionRawMutex, RefCell<Vec<Session, MAX_SESSIONS>>>,
+    rng: Mutex<CriticalSectionRawMutex, RefCell<RngWrapper>>,
+
+    /// Idle timeout in ticks
+    idle_timeout: AtomicU64,
+}
+
+impl SessionStore {
+    /// Create an empty table
+    pub fn new(rng: RngWrapper) -> Self {
+        Self {
+            sessions: Mutex::new(RefCell::new(Vec::new())),
+            rng: Mutex::new(RefCell::new(rng)),
+            idle_timeout: AtomicU64::new(IDLE_TIMEOUT.as_ticks()),
+        }
+    }
+
+    /// Return the time after which an unused session expires
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_ticks(self.idle_timeout.load(Ordering::Relaxed))
+    }
+
+    /// Change the time after which an unused session expires
+    ///
+    /// Live sessions are held to the new timeout from their last use.
+    pub fn set_idle_timeout(&self, idle_timeout: Duration) {
+        self.idle_timeout
+            .store(idle_timeout.as_ticks(), Ordering::Relaxed);
+    }
+
+    /// Start a session and return its identifier
+    pub fn create(&self) -> SessionId {
+        let mut bytes = [0; SESSION_ID_SIZE];
+        let mut csrf_bytes = [0; CSRF_TOKEN_SIZE];
+        self.rng.lock(|rng| {
+            let mut rng = rng.borrow_mut();
+            rng.fill_bytes(&mut bytes);
+            rng.fill_bytes(&mut csrf_bytes);
+        });
+        let id = SessionId(bytes);
+        let csrf_token = CsrfToken(csrf_bytes);
+
+        let now = Instant::now();
+        let idle_timeout = self.idle_timeout();
+        self.sessions.lock(|sessions| {
+            let mut sessions = sessions.borrow_mut();
+            sessions.retain(|session| !session.is_expired(now, idle_timeout));
+            if sessions.is_full() {
+                let oldest = sessions
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, session)| session.last_used)
+                    .map(|(index, _)| index);
+                if let Some(index) = oldest {
+                    sessions.swap_remove(index);
+                    crate::log!("Dropped the least recently used session");
+                }
+            }
+            // Cannot fail, a slot was freed above
+            let _ = sessions.push(Session {
+                id,
+                csrf_token,
+                last_used: now,
+            });
+        });
+        id
+    }
+
+    /// Check whether a session exists and has not expired, and mark it as
+    /// used
+    pub fn touch(&self, id: &SessionId) -> bool {
+        self.csrf_token(id).is_some()
+    }
+
+    /// Return the CSRF token of a session that exists and has not expired,
+    /// and mark it as used
+    pub fn csrf_token(&self, id: &SessionId) -> Option<CsrfToken> {
+        let now = Instant::now();
+        let idle_timeout = self.idle_timeout();
+        self.sessions.lock(|sessions| {
+            let mut sessions = sessions.borrow_mut();
+            sessions.retain(|session| !session.is_expired(now, idle_timeout));
+            let session = sessions.iter_mut().find(|session| session.id.matches(id))?;
+            session.last_used = now;
+            Some(session.csrf_token)
+        })
+    }
+
+    /// Check a hexadecimal CSRF token against the one of a live session
+    pub fn check_csrf_token(&self, id: &SessionId, hex: &str) -> bool {
+        self.csrf_token(id).is_some_and(|token| token.matches(hex))
+    }
+
+    /// End a session
+    pub fn remove(&self, id: &SessionId) {
+        self.sessions.lock(|sessions| {
+            sessions
+                .borrow_mut()
+                .retain(|session| !session.id.matches(id));
+        });
+    }
+}
+
+/// Find the value of a cookie in a `Cookie` header
+///
+/// The header holds `name=value` pairs separated by `;`. Values may be
+/// enclosed in double quotes, which are removed. The first cookie with the
+/// name wins.
+pub fn find_cookie<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header.split(';').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        if key.trim() != name {
+            return None;
+        }
+        let value = value.trim();
+        Some(
+            value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value),
+        )
+    })
+}
+
+/// The value of a `Set-Cookie` header starting or ending a session
+pub enum SetCookie {
+    /// Store the identifier of a new session
+    Start(SessionId),
+
+    /// Remove the cookie from the browser
+    Clear,
+}
+
+impl fmt::Display for SetCookie {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Start(id) => write!(
+                formatter,
+                "{COOKIE_NAME}={id}; Path=/; HttpOnly; SameSite=Strict"
+            ),
+            Self::Clear => write!(
+                formatter,
+                "{COOKIE_NAME}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0"
+            ),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Settings editable from the `/settings` page
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use heapless::String;
+use portable_atomic::{AtomicU32, Ordering};
+
+/// Maximal length of a hostname
+pub const MAX_HOSTNAME_SIZE: usize = 32;
+
+/// Hostname used until another one is set
+pub const DEFAULT_HOSTNAME: &str = "esp32c3";
+
+/// Blink period used until another one is set, in milliseconds
+pub const DEFAULT_BLINK_PERIOD_MS: u32 = 500;
+
+/// Settings shared by the routes
+pub struct Settings {
+    /// Name of the device on the network
+    hostname: Mutex<CriticalSectionRawMutex, RefCell<String<MAX_HOSTNAME_SIZE>>>,
+
+    /// Blink period of the LED in milliseconds
+    blink_period_ms: AtomicU32,
+}
+
+impl Settings {
+    /// Create the default settings
+    pub fn new() -> Self {
+        let mut hostname = String::new();
+        // Cannot fail, the default hostname is short enough
+        let _ = hostname.push_str(DEFAULT_HOSTNAME);
+        Self {
+            hostname: Mutex::new(RefCell::new(hostname)),
+            blink_period_ms: AtomicU32::new(DEFAULT_BLINK_PERIOD_MS),
+        }
+    }
+
+    /// Return the hostname
+    pub fn hostname(&self) -> String<MAX_HOSTNAME_SIZE> {
+        self.hostname.lock(|hostname| hostname.borrow().clone())
+    }
+
+    /// Change the hostname, which must have been checked with
+    /// [`is_valid_hostname`]
+    pub fn set_hostname(&self, hostname: String<MAX_HOSTNAME_SIZE>) {
+        self.hostname.lock(|current| *current.borrow_mut() = hostname);
+        crate::changes::notify();
+    }
+
+    /// Return the blink period of the LED in milliseconds
+    pub fn blink_period_ms(&self) -> u32 {
+        self.blink_period_ms.load(Ordering::Relaxed)
+    }
+
+    /// Change the blink period of the LED in milliseconds
+    pub fn set_blink_period_ms(&self, period_ms: u32) {
+        self.blink_period_ms.store(period_ms, Ordering::Relaxed);
+        crate::changes::notify();
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Check whether a hostname is a valid DNS label
+///
+/// Only ASCII letters, digits and inner hyphens are allowed.
+pub fn is_valid_hostname(hostname: &str) -> bool {
+    !hostname.is_empty()
+        && hostname.len() <= MAX_HOSTNAME_SIZE
+        && !hostname.starts_with('-')
+        && !hostname.ends_with('-')
+        && hostname
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+}
@@ -0,0 +1,407 @@
+//! IPv6 addresses of the station, from the router advertisements
+//!
+//! The station starts with the link-local address derived from its MAC
+//! address, and solicits the routers of the network. When one advertises a
+//! `/64` prefix for autonomous configuration, the address made of the prefix
+//! and the same interface identifier replaces it, with the router as
+//! gateway and the DNS servers it advertises. The address is dropped again
+//! when its lifetime ends without a new advertisement, or the link goes
+//! down.
+//!
+//! The network stack holds a single IPv6 address, so the link-local address
+//! is only reachable until a global one is configured. Duplicate address
+//! detection is not done, the interface identifier is unique with the MAC
+//! address.
+
+use embassy_futures::select::{select, Either};
+use embassy_net::raw::{IpProtocol, IpVersion, PacketMetadata, RawSocket};
+use embassy_net::{ConfigV6, Ipv6Address, Ipv6Cidr, Stack, StaticConfigV6};
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+
+/// Length of the prefixes used for autonomous configuration
+pub const PREFIX_LENGTH: u8 = 64;
+
+/// Maximal number of DNS servers kept from an advertisement
+pub const MAX_DNS_SERVERS: usize = 3;
+
+/// Size of the router solicitations sent
+pub const SOLICITATION_SIZE: usize = IPV6_HEADER_SIZE + 16;
+
+/// Largest packet handled, the minimal MTU of IPv6
+pub const MAX_PACKET_SIZE: usize = 1280;
+
+/// Router solicitations sent after the link comes up, see RFC 4861
+const MAX_SOLICITATIONS: u32 = 3;
+
+/// Time between two router solicitations
+const SOLICITATION_INTERVAL: Duration = Duration::from_secs(4);
+
+/// Multicast address of the routers of the link
+const ALL_ROUTERS: Ipv6Address = Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+/// Size of the header of an IPv6 packet
+const IPV6_HEADER_SIZE: usize = 40;
+
+/// Size of a router advertisement before its options
+const ADVERTISEMENT_HEADER_SIZE: usize = 16;
+
+/// Next header value of ICMPv6
+const NEXT_HEADER_ICMPV6: u8 = 58;
+
+/// Hop limit of neighbor discovery messages, which must not be routed
+const HOP_LIMIT: u8 = 255;
+
+/// Lifetime meaning forever
+const INFINITE_LIFETIME: u32 = u32::MAX;
+
+/// ICMPv6 message types
+mod message_type {
+    pub const ROUTER_SOLICITATION: u8 = 133;
+    pub const ROUTER_ADVERTISEMENT: u8 = 134;
+}
+
+/// Options of the neighbor discovery messages
+mod option {
+    pub const SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+    pub const PREFIX_INFORMATION: u8 = 3;
+    pub const RECURSIVE_DNS_SERVER: u8 = 25;
+}
+
+/// Flag of the prefixes to configure an address from
+const FLAG_AUTONOMOUS: u8 = 0x40;
+
+/// Return the interface identifier of a MAC address, in modified EUI-64
+/// format
+pub fn interface_identifier(mac: &[u8; 6]) -> [u8; 8] {
+    [
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ]
+}
+
+/// Return the address of a prefix with the interface identifier of a MAC
+/// address
+pub fn address_in_prefix(prefix: Ipv6Address, mac: &[u8; 6]) -> Ipv6Address {
+    let mut octets = prefix.octets();
+    octets[8..].copy_from_slice(&interface_identifier(mac));
+    Ipv6Address::from(octets)
+}
+
+/// Return the link-local address of a MAC address
+pub fn link_local_address(mac: &[u8; 6]) -> Ipv6Address {
+    address_in_prefix(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), mac)
+}
+
+/// Return the configuration with only the link-local address of a MAC
+/// address
+pub fn link_local_config(mac: &[u8; 6]) -> StaticConfigV6 {
+    StaticConfigV6 {
+        address: Ipv6Cidr::new(link_local_address(mac), PREFIX_LENGTH),
+        gateway: None,
+        dns_servers: Vec::new(),
+    }
+}
+
+/// Return the IPv6 configuration of a stack if it has a global address
+pub fn global_config(stack: Stack<'_>) -> Option<StaticConfigV6> {
+    stack
+        .config_v6()
+        .filter(|config| !config.address.address().is_unicast_link_local())
+}
+
+/// A prefix advertised by a router for autonomous configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Prefix {
+    /// The prefix, with the interface identifier bits zero
+    pub prefix: Ipv6Address,
+
+    /// Seconds the addresses in the prefix are valid, 0 when withdrawn
+    pub valid_lifetime: u32,
+}
+
+impl Prefix {
+    /// Return the time an address configured now stops being valid, `None`
+    /// if it never does
+    pub fn expires(&self, now: Instant) -> Option<Instant> {
+        (self.valid_lifetime != INFINITE_LIFETIME)
+            .then(|| now + Duration::from_secs(u64::from(self.valid_lifetime)))
+    }
+}
+
+/// The parts of a router advertisement used to configure the station
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouterAdvertisement {
+    /// Link-local address of the router
+    pub router: Ipv6Address,
+
+    /// Seconds the router is a default router, 0 if it is not one
+    pub router_lifetime: u16,
+
+    /// The first prefix for autonomous configuration, if any
+    pub prefix: Option<Prefix>,
+
+    /// The advertised DNS servers
+    pub dns_servers: Vec<Ipv6Address, MAX_DNS_SERVERS>,
+}
+
+impl RouterAdvertisement {
+    /// Parse an IPv6 packet, `None` for anything but a valid router
+    /// advertisement
+    ///
+    /// Advertisements must come from a link-local address with the hop
+    /// limit of neighbor discovery, so they were not routed, and have a
+    /// valid checksum.
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        let header = packet.get(..IPV6_HEADER_SIZE)?;
+        let payload_length = usize::from(u16::from_be_bytes([header[4], header[5]]));
+        let message = packet.get(IPV6_HEADER_SIZE..IPV6_HEADER_SIZE + payload_length)?;
+        let source = address_at(header, 8);
+        let destination = address_at(header, 24);
+        if header[0] >> 4 != 6
+            || header[6] != NEXT_HEADER_ICMPV6
+            || header[7] != HOP_LIMIT
+            || !source.is_unicast_link_local()
+            || message.len() < ADVERTISEMENT_HEADER_SIZE
+            || message[0] != message_type::ROUTER_ADVERTISEMENT
+            || message[1] != 0
+            || checksum(source, destination, message) != 0
+        {
+            return None;
+        }
+
+        let mut advertisement = Self {
+            router: source,
+            router_lifetime: u16::from_be_bytes([message[6], message[7]]),
+            prefix: None,
+            dns_servers: Vec::new(),
+        };
+        let mut options = &message[ADVERTISEMENT_HEADER_SIZE..];
+        while options.len() >= 2 {
+            let length = usize::from(options[1]) * 8;
+            // A zero length is invalid and would never end
+            let value = options.get(..length).filter(|_| length > 0)?;
+            match value[0] {
+                option::PREFIX_INFORMATION if length == 32 => advertisement.add_prefix(value),
+                option::RECURSIVE_DNS_SERVER if length >= 24 => {
+                    for address in value[8..].chunks_exact(16) {
+                        let address = address_at(address, 0);
+                        // Ignore the servers past the maximal number
+                        let _ = advertisement.dns_servers.push(address);
+                    }
+                }
+                _ => {}
+            }
+            options = &options[length..];
+        }
+        Some(advertisement)
+    }
+
+    /// Keep a prefix information option if it is the first usable one
+    fn add_prefix(&mut self, value: &[u8]) {
+        let prefix = address_at(value, 16);
+        let usable = value[2] == PREFIX_LENGTH
+            && value[3] & FLAG_AUTONOMOUS != 0
+            && !prefix.is_unicast_link_local();
+        if self.prefix.is_none() && usable {
+            self.prefix = Some(Prefix {
+                prefix,
+                valid_lifetime: u32::from_be_bytes([value[4], value[5], value[6], value[7]]),
+            });
+        }
+    }
+
+    /// Return the configuration of the station in the advertised prefix
+    pub fn config(&self, prefix: &Prefix, mac: &[u8; 6]) -> StaticConfigV6 {
+        StaticConfigV6 {
+            address: Ipv6Cidr::new(address_in_prefix(prefix.prefix, mac), PREFIX_LENGTH),
+            gateway: (self.router_lifetime > 0).then_some(self.router),
+            dns_servers: self.dns_servers.clone(),
+        }
+    }
+}
+
+/// Return the address at an offset of a slice at least 16 bytes longer
+fn address_at(bytes: &[u8], offset: usize) -> Ipv6Address {
+    let mut octets = [0; 16];
+    octets.copy_from_slice(&bytes[offset..offset + 16]);
+    Ipv6Address::from(octets)
+}
+
+/// Compute the checksum of an ICMPv6 message
+///
+/// The checksum of a message including its checksum is zero when valid.
+pub fn checksum(source: Ipv6Address, destination: Ipv6Address, message: &[u8]) -> u16 {
+    let length = message.len() as u32;
+    let pseudo_header = source
+        .octets()
+        .into_iter()
+        .chain(destination.octets())
+        .chain(length.to_be_bytes())
+        .chain([0, 0, 0, NEXT_HEADER_ICMPV6]);
+    let mut bytes = pseudo_header.chain(message.iter().copied());
+    let mut sum = 0u32;
+    while let Some(high) = bytes.next() {
+        let low = bytes.next().unwrap_or(0);
+        sum += u32::from(u16::from_be_bytes([high, low]));
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Write a router solicitation from a link-local address to the routers of
+/// the link
+pub fn router_solicitation(
+    source: Ipv6Address,
+    mac: &[u8; 6],
+    packet: &mut [u8; SOLICITATION_SIZE],
+) {
+    let (header, message) = packet.split_at_mut(IPV6_HEADER_SIZE);
+    header.fill(0);
+    header[0] = 0x60;
+    header[4..6].copy_from_slice(&(message.len() as u16).to_be_bytes());
+    header[6] = NEXT_HEADER_ICMPV6;
+    header[7] = HOP_LIMIT;
+    header[8..24].copy_from_slice(&source.octets());
+    header[24..40].copy_from_slice(&ALL_ROUTERS.octets());
+
+    message.fill(0);
+    message[0] = message_type::ROUTER_SOLICITATION;
+    message[8] = option::SOURCE_LINK_LAYER_ADDRESS;
+    message[9] = 1;
+    message[10..16].copy_from_slice(mac);
+    let checksum = checksum(source, ALL_ROUTERS, message);
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// Buffers of the task
+///
+/// They are statically allocated rather than living in the task future, so
+/// they do not grow the executor arena.
+struct Buffers {
+    rx_meta: [PacketMetadata; 2],
+    rx: [u8; MAX_PACKET_SIZE * 2],
+    tx_meta: [PacketMetadata; 1],
+    tx: [u8; SOLICITATION_SIZE],
+    packet: [u8; MAX_PACKET_SIZE],
+}
+
+/// Configure the IPv6 address of the station on `stack` from the router
+/// advertisements
+#[embassy_executor::task]
+pub async fn slaac_task(stack: Stack<'static>, mac: [u8; 6]) {
+    let Buffers {
+        rx_meta,
+        rx,
+        tx_meta,
+        tx,
+        packet,
+    } = crate::mk_static!(
+        Buffers,
+        Buffers {
+            rx_meta: [PacketMetadata::EMPTY; 2],
+            rx: [0; MAX_PACKET_SIZE * 2],
+            tx_meta: [PacketMetadata::EMPTY; 1],
+            tx: [0; SOLICITATION_SIZE],
+            packet: [0; MAX_PACKET_SIZE],
+        }
+    );
+    let socket = RawSocket::new(
+        stack,
+        IpVersion::Ipv6,
+        IpProtocol::Icmpv6,
+        rx_meta,
+        rx,
+        tx_meta,
+        tx,
+    );
+
+    loop {
+        crate::heartbeat!("slaac_task");
+        crate::tasks::beating("slaac_task", None, stack.wait_link_up()).await;
+        match select(
+            stack.wait_link_down(),
+            follow_routers(stack, &socket, &mac, packet),
+        )
+        .await
+        {
+            Either::First(()) => {
+                stack.set_config_v6(ConfigV6::Static(link_local_config(&mac)));
+            }
+            Either::Second(never) => match never {},
+        }
+    }
+}
+
+/// Solicit the routers, and follow their advertisements
+async fn follow_routers(
+    stack: Stack<'static>,
+    socket: &RawSocket<'_>,
+    mac: &[u8; 6],
+    packet: &mut [u8; MAX_PACKET_SIZE],
+) -> core::convert::Infallible {
+    let mut solicitation = [0; SOLICITATION_SIZE];
+    router_solicitation(link_local_address(mac), mac, &mut solicitation);
+    let mut solicitations = 0;
+    let mut expires = None;
+    loop {
+        crate::heartbeat!("slaac_task");
+        let soliciting = expires.is_none() && solicitations < MAX_SOLICITATIONS;
+        if soliciting {
+            socket.send(&solicitation).await;
+            solicitations += 1;
+        }
+        let deadline = match expires {
+            Some(expires) => expires,
+            None if soliciting => Instant::now() + SOLICITATION_INTERVAL,
+            None => Instant::MAX,
+        };
+
+        let received = select(socket.recv(packet), Timer::at(deadline));
+        let length = match crate::tasks::beating("slaac_task", None, received).await {
+            Either::First(Ok(length)) => length,
+            Either::First(Err(error)) => {
+                crate::log!(warn: "Warning: dropped an ICMPv6 packet: {:?}", error);
+                continue;
+            }
+            Either::Second(()) => {
+                if expires.is_some() {
+                    crate::log!("IPv6 address expired, soliciting the routers again");
+                    stack.set_config_v6(ConfigV6::Static(link_local_config(mac)));
+                    expires = None;
+                    solicitations = 0;
+                }
+                continue;
+            }
+        };
+
+        let Some(advertisement) = RouterAdvertisement::parse(&packet[..length]) else {
+            continue;
+        };
+        match advertisement.prefix {
+            Some(prefix) if prefix.valid_lifetime > 0 => {
+                let config = advertisement.config(&prefix, mac);
+                if stack.config_v6().as_ref() != Some(&config) {
+                    crate::log!("Got IPv6 address {}", config.address);
+                    stack.set_config_v6(ConfigV6::Static(config));
+                }
+                expires = Some(prefix.expires(Instant::now()).unwrap_or(Instant::MAX));
+            }
+            Some(_) => {
+                crate::log!("IPv6 prefix withdrawn by {}", advertisement.router);
+                stack.set_config_v6(ConfigV6::Static(link_local_config(mac)));
+                expires = None;
+            }
+            // Other advertisements only refresh the router
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Server-sent events streamed over a chunked response
+//!
+//! Every event is written as its own chunk and flushed immediately. Writes
+//! go through the socket writer of picoserve, so the `write` timeout of
+//! [`picoserve::Config`] applies and a stalled client gets dropped.
+
+use core::fmt::Write as _;
+
+use heapless::String;
+use picoserve::io::{Read, Write};
+use picoserve::response::chunked::{ChunkWriter, ChunkedResponse, Chunks, ChunksWritten};
+use picoserve::response::{Connection, IntoResponse, ResponseWriter};
+use picoserve::ResponseSent;
+
+/// Maximal size of a single encoded event
+const EVENT_SIZE: usize = 512;
+
+/// A writer of server-sent events
+pub struct EventWriter<W: Write> {
+    /// The underlying chunk writer
+    chunk_writer: ChunkWriter<W>,
+
+    /// Identifier of the next event
+    next_id: u32,
+}
+
+impl<W: Write> EventWriter<W> {
+    /// Write an event with an `event:` type, an incrementing `id:` and data
+    ///
+    /// Data must fit a single line; events too large to be encoded are
+    /// skipped.
+    pub async fn write_event(&mut self, event: &str, data: &str) -> Result<(), W::Error> {
+        let mut frame = String::<EVENT_SIZE>::new();
+        if write!(frame, "id: {}\nevent: {}\ndata: {}\n\n", self.next_id, event, data).is_err() {
+            return Ok(());
+        }
+        self.next_id = self.next_id.wrapping_add(1);
+
+        self.chunk_writer.write_chunk(frame.as_bytes()).await?;
+        self.chunk_writer.flush().await
+    }
+
+    /// Write a comment line, keeping idle connections alive
+    pub async fn write_keepalive(&mut self) -> Result<(), W::Error> {
+        self.chunk_writer.write_chunk(b":\n\n").await?;
+        self.chunk_writer.flush().await
+    }
+}
+
+/// A source of server-sent events
+pub trait EventSource {
+    /// Write events until the stream ends or a write fails
+    async fn write_events<W: Write>(self, writer: &mut EventWriter<W>) -> Result<(), W::Error>;
+}
+
+/// A `text/event-stream` response
+pub struct EventStream<S> {
+    /// The source of events
+    source: S,
+
+    /// Identifier of the first event
+    first_id: u32,
+}
+
+impl<S: EventSource> EventStream<S> {
+    /// Create a new stream
+    ///
+    /// The last event identifier seen by the client, from the
+    /// `Last-Event-ID` header, is used to continue numbering.
+    pub fn new(source: S, last_event_id: Option<u32>) -> Self {
+        let first_id = last_event_id.map_or(0, |id| id.wrapping_add(1));
+        Self { source, first_id }
+    }
+}
+
+impl<S: EventSource> Chunks for EventStream<S> {
+    fn content_type(&self) -> &'static str {
+        "text/event-stream"
+    }
+
+    async fn write_chunks<W: Write>(
+        self,
+        chunk_writer: ChunkWriter<W>,
+    ) -> Result<ChunksWritten, W::Error> {
+        let mut writer = EventWriter {
+            chunk_writer,
+            next_id: self.first_id,
+        };
+        self.source.write_events(&mut writer).await?;
+        writer.chunk_writer.finalize().await
+    }
+}
+
+impl<S: EventSource> IntoResponse for EventStream<S> {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let response = ChunkedResponse::new(self)
+            .into_response()
+            .with_header("Cache-Control", "no-cache");
+        response_writer.write_response(connection, response).await
+    }
+}
+
+/// An extractor for the `Last-Event-ID` header
+pub struct LastEventId(pub Option<u32>);
+
+impl<'r, State> picoserve::extract::FromRequestParts<'r, State> for LastEventId {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        _state: &'r State,
+        request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        let id = request_parts
+            .headers()
+            .get("Last-Event-ID")
+            .and_then(|value| value.as_str().ok())
+            .and_then(|value| value.trim().parse().ok());
+        Ok(Self(id))
+    }
+}
@@ -0,0 +1,237 @@
+//! Static assets embedded in the firmware
+//!
+//! Assets are listed in [`ASSETS`]; adding a file to the web server only
+//! requires adding one line there.
+//!
+//! Every asset is embedded twice: as is, and gzip-compressed by the build
+//! script. The compressed variant is served with `Content-Encoding: gzip`
+//! when the request carries `Accept-Encoding: gzip`, the identity variant
+//! otherwise, so clients without gzip support keep working.
+//!
+//! The build script also computes a weak ETag from the crate version and a
+//! hash of the content, so unchanged assets are answered with 304.
+//!
+//! Assets are cached by browsers for a day, unless their line in [`ASSETS`]
+//! sets another [`CachePolicy`].
+
+use core::str::FromStr;
+
+use picoserve::io::{Read, Write};
+use picoserve::response::{Connection, Content, IntoResponse, Response, ResponseWriter, StatusCode};
+use picoserve::ResponseSent;
+
+use crate::cache_control::{CachePolicy, Cached};
+use crate::etag::{Conditional, IfNoneMatch};
+
+/// Size of the chunks assets are written in
+///
+/// This must fit the TCP transmit buffer of the web tasks.
+const WRITE_CHUNK_SIZE: usize = 512;
+
+/// Declare an asset embedded from the `assets/static` directory, cached
+/// with [`CachePolicy::IMMUTABLE`] unless given another policy
+macro_rules! asset {
+    ($name:literal) => {
+        asset!($name, CachePolicy::IMMUTABLE)
+    };
+    ($name:literal, $cache_control:expr) => {
+        Asset {
+            name: $name,
+            bytes: include_bytes!(concat!("../assets/static/", $name)),
+            gzip_bytes: include_bytes!(concat!(env!("OUT_DIR"), "/static/", $name, ".gz")),
+            content_type: content_type($name),
+            etag: include_str!(concat!(env!("OUT_DIR"), "/static/", $name, ".etag")),
+            cache_control: $cache_control,
+        }
+    };
+}
+
+/// The embedded assets
+pub const ASSETS: &[Asset] = &[
+    asset!("index.html", CachePolicy::NO_CACHE),
+    asset!("app.js"),
+    asset!("style.css"),
+    asset!("favicon.ico"),
+    asset!("ws-time.js"),
+];
+
+/// Return the content type of a file from its extension
+pub(crate) const fn content_type(name: &str) -> &'static str {
+    if ends_with(name, ".html") {
+        "text/html; charset=utf-8"
+    } else if ends_with(name, ".js") {
+        "text/javascript; charset=utf-8"
+    } else if ends_with(name, ".css") {
+        "text/css; charset=utf-8"
+    } else if ends_with(name, ".ico") {
+        "image/x-icon"
+    } else if ends_with(name, ".json") {
+        "application/json"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// Check whether a string ends with a suffix in a `const` context
+const fn ends_with(name: &str, suffix: &str) -> bool {
+    let name = name.as_bytes();
+    let suffix = suffix.as_bytes();
+    if suffix.len() > name.len() {
+        return false;
+    }
+    let start = name.len() - suffix.len();
+    let mut index = 0;
+    while index < suffix.len() {
+        if name[start + index] != suffix[index] {
+            return false;
+        }
+        index += 1;
+    }
+    true
+}
+
+/// An embedded asset
+#[derive(Clone, Copy)]
+pub struct Asset {
+    /// The file name
+    pub name: &'static str,
+
+    /// The file content
+    pub bytes: &'static [u8],
+
+    /// The gzip-compressed file content
+    pub gzip_bytes: &'static [u8],
+
+    /// The content type
+    pub content_type: &'static str,
+
+    /// The weak ETag
+    pub etag: &'static str,
+
+    /// The `Cache-Control` header value
+    pub cache_control: CachePolicy,
+}
+
+impl Asset {
+    /// Look up an asset by name
+    pub fn find(name: &str) -> Option<Self> {
+        ASSETS.iter().find(|asset| asset.name == name).copied()
+    }
+
+    /// The asset served at `/`
+    pub fn index() -> Self {
+        Self::find("index.html").unwrap()
+    }
+
+    /// Select the variant to serve, or a 304 if the client has it cached
+    pub fn encoded(self, accepts_gzip: bool, if_none_match: &IfNoneMatch) -> Cached<Conditional<EncodedAsset>> {
+        let encoded = EncodedAsset {
+            asset: self,
+            gzip: accepts_gzip,
+        };
+        Cached::new(self.cache_control, Conditional::new(self.etag, if_none_match, encoded))
+    }
+}
+
+/// Parse an asset name from a path segment, rejecting unknown assets
+impl FromStr for Asset {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::find(name).ok_or(())
+    }
+}
+
+/// Asset body written in chunks that fit the TCP transmit buffer
+struct AssetContent {
+    bytes: &'static [u8],
+    content_type: &'static str,
+}
+
+impl Content for AssetContent {
+    fn content_type(&self) -> &'static str {
+        self.content_type
+    }
+
+    fn content_length(&self) -> usize {
+        self.bytes.len()
+    }
+
+    async fn write_content<W: Write>(self, mut writer: W) -> Result<(), W::Error> {
+        for chunk in self.bytes.chunks(WRITE_CHUNK_SIZE) {
+            writer.write_all(chunk).await?;
+        }
+        Ok(())
+    }
+}
+
+/// An asset with the variant to serve selected
+pub struct EncodedAsset {
+    asset: Asset,
+    gzip: bool,
+}
+
+impl IntoResponse for EncodedAsset {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let content_type = self.asset.content_type;
+        if self.gzip {
+            let content = AssetContent {
+                bytes: self.asset.gzip_bytes,
+                content_type,
+            };
+            let response = Response::new(StatusCode::OK, content)
+                .with_header("Vary", "Accept-Encoding")
+                .with_header("Content-Encoding", "gzip");
+            response_writer.write_response(connection, response).await
+        } else {
+            let content = AssetContent {
+                bytes: self.asset.bytes,
+                content_type,
+            };
+            let response = Response::new(StatusCode::OK, content)
+                .with_header("Vary", "Accept-Encoding");
+            response_writer.write_response(connection, response).await
+        }
+    }
+}
+
+/// An extractor checking whether the client accepts gzip encoding
+pub struct AcceptsGzip(pub bool);
+
+impl<'r, State> picoserve::extract::FromRequestParts<'r, State> for AcceptsGzip {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        _state: &'r State,
+        request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        let accepts_gzip = request_parts
+            .headers()
+            .get("Accept-Encoding")
+            .and_then(|value| value.as_str().ok())
+            .is_some_and(accepts_gzip);
+        Ok(Self(accepts_gzip))
+    }
+}
+
+/// Check whether an `Accept-Encoding` header value allows gzip
+///
+/// An explicit `q=0` rejects the encoding.
+pub fn accepts_gzip(header: &str) -> bool {
+    header.split(',').any(|coding| {
+        let mut parameters = coding.split(';');
+        let name = parameters.next().unwrap_or_default().trim();
+        let rejected = parameters.any(|parameter| {
+            parameter
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|quality| quality.parse::<f32>().ok())
+                == Some(0.0)
+        });
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !rejected
+    })
+}
@@ -0,0 +1,368 @@
+//! Request statistics exposed at `/stats`
+//!
+//! Counters are atomics so they can be updated from every web task through a
+//! shared reference in the app state. They are 64 bits wide and cannot wrap
+//! in practice.
+//!
+//! Routes are identified by their index in [`routes`], which is
+//! fixed at build time. Paths matching no route share a single set of
+//! counters, so memory does not grow with the paths clients request.
+//!
+//! Counters are zeroed by `POST /stats/reset`, except the slowest response
+//! time of each route since boot.
+
+use portable_atomic::{AtomicU64, Ordering};
+use serde::Serialize;
+
+use crate::api;
+use crate::web::{self, routes};
+use crate::webhooks;
+
+/// Number of routes with a hit counter
+pub const ROUTE_COUNT: usize = web::ROUTES.len() + api::ROUTES.len();
+
+/// Upper bounds of the response time buckets in milliseconds, exclusive
+///
+/// Slower responses go to an extra last bucket.
+pub const LATENCY_BOUNDS_MS: [u64; 4] = [10, 50, 200, 1000];
+
+/// Number of response time buckets
+pub const LATENCY_BUCKET_COUNT: usize = LATENCY_BOUNDS_MS.len() + 1;
+
+/// Route label of the requests matching no route
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Histogram of response times
+pub struct LatencyHistogram {
+    /// Number of responses per bucket, see [`LATENCY_BOUNDS_MS`]
+    buckets: [AtomicU64; LATENCY_BUCKET_COUNT],
+
+    /// Sum of all response times in milliseconds
+    sum_ms: AtomicU64,
+
+    /// Slowest response time in milliseconds since the last reset
+    max_ms: AtomicU64,
+
+    /// Slowest response time in milliseconds since boot
+    max_since_boot_ms: AtomicU64,
+}
+
+impl LatencyHistogram {
+    /// Create an empty histogram
+    pub const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKET_COUNT],
+            sum_ms: AtomicU64::new(0),
+            max_ms: AtomicU64::new(0),
+            max_since_boot_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a response time
+    pub fn record(&self, duration_ms: u64) {
+        self.buckets[latency_bucket(duration_ms)].fetch_add(1, Ordering::Relaxed);
+        self.sum_ms.fetch_add(duration_ms, Ordering::Relaxed);
+        self.max_ms.fetch_max(duration_ms, Ordering::Relaxed);
+        self.max_since_boot_ms.fetch_max(duration_ms, Ordering::Relaxed);
+    }
+
+    /// Zero the histogram, but keep the slowest response time since boot
+    pub fn reset(&self) {
+        for counter in self.buckets.iter().chain([&self.sum_ms, &self.max_ms]) {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Return the number of responses per bucket
+    pub fn buckets(&self) -> [u64; LATENCY_BUCKET_COUNT] {
+        core::array::from_fn(|index| self.buckets[index].load(Ordering::Relaxed))
+    }
+
+    /// Return the sum of all response times in milliseconds
+    pub fn sum_ms(&self) -> u64 {
+        self.sum_ms.load(Ordering::Relaxed)
+    }
+
+    /// Return the slowest response time in milliseconds since the last reset
+    pub fn max_ms(&self) -> u64 {
+        self.max_ms.load(Ordering::Relaxed)
+    }
+
+    /// Return the slowest response time in milliseconds since boot
+    pub fn max_since_boot_ms(&self) -> u64 {
+        self.max_since_boot_ms.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Return the index of the bucket of a response time
+pub fn latency_bucket(duration_ms: u64) -> usize {
+    LATENCY_BOUNDS_MS
+        .iter()
+        .position(|&bound| duration_ms < bound)
+        .unwrap_or(LATENCY_BOUNDS_MS.len())
+}
+
+/// Request counters of the web server
+pub struct Stats {
+    /// Number of requests
+    total: AtomicU64,
+
+    /// Number of responses with a 4xx or 5xx status code
+    errors: AtomicU64,
+
+    /// Number of requests per route, indexed like [`routes`]
+    routes: [AtomicU64; ROUTE_COUNT],
+
+    /// Response times per route, indexed like [`routes`], followed
+    /// by the response times of unmatched paths
+    latencies: [LatencyHistogram; ROUTE_COUNT + 1],
+
+    /// Number of requests to cached routes answered from the cache
+    cache_hits: AtomicU64,
+
+    /// Number of requests to cached routes passed to the handler
+    cache_misses: AtomicU64,
+
+    /// Number of events delivered to a webhook
+    webhook_deliveries: AtomicU64,
+
+    /// Number of events not delivered to a webhook after the retry
+    webhook_failures: AtomicU64,
+
+    /// Number of requests rejected by a [`crate::web::SemaphoreLayer`]
+    busy_rejections: AtomicU64,
+}
+
+impl Stats {
+    /// Create zeroed counters
+    pub const fn new() -> Self {
+        Self {
+            total: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            routes: [const { AtomicU64::new(0) }; ROUTE_COUNT],
+            latencies: [const { LatencyHistogram::new() }; ROUTE_COUNT + 1],
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            webhook_deliveries: AtomicU64::new(0),
+            webhook_failures: AtomicU64::new(0),
+            busy_rejections: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a request, its response status code and its response time
+    ///
+    /// Paths not matching a known route are only counted in the total and
+    /// in the shared histogram of unmatched paths.
+    pub fn record(&self, path: &str, status: u16, duration_ms: u64) {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        if status >= 400 {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let index = route_index(path);
+        if let Some(index) = index {
+            self.routes[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.latencies[index.unwrap_or(ROUTE_COUNT)].record(duration_ms);
+    }
+
+    /// Count a `GET` request to a cached route
+    pub fn record_cache(&self, hit: bool) {
+        let counter = if hit { &self.cache_hits } else { &self.cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count the delivery of an event to a webhook
+    pub fn record_webhook(&self, delivered: bool) {
+        let counter = if delivered { &self.webhook_deliveries } else { &self.webhook_failures };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a request rejected because the routes it belongs to were busy
+    pub fn record_busy_rejection(&self) {
+        self.busy_rejections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Zero the counters, but keep the slowest response times since boot
+    pub fn reset(&self) {
+        let counters = [
+            &self.total,
+            &self.errors,
+            &self.cache_hits,
+            &self.cache_misses,
+            &self.webhook_deliveries,
+            &self.webhook_failures,
+            &self.busy_rejections,
+        ];
+        for counter in counters.into_iter().chain(&self.routes) {
+            counter.store(0, Ordering::Relaxed);
+        }
+        for histogram in &self.latencies {
+            histogram.reset();
+        }
+    }
+
+    /// Iterate over the response time histograms with their route pattern,
+    /// unmatched paths last with [`UNMATCHED_ROUTE`]
+    pub fn latencies(&self) -> impl Iterator<Item = (&'static str, &LatencyHistogram)> {
+        routes()
+            .map(|route| route.path)
+            .chain([UNMATCHED_ROUTE])
+            .zip(&self.latencies)
+    }
+
+    /// Read all counters
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            total_requests: self.total.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            routes: routes()
+                .zip(&self.routes)
+                .zip(&self.latencies)
+                .map(|((route, hits), latency)| RouteHits {
+                    route: route.path,
+                    hits: hits.load(Ordering::Relaxed),
+                    latency: LatencySummary::from(latency),
+                })
+                .collect(),
+            unmatched_latency: LatencySummary::from(&self.latencies[ROUTE_COUNT]),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            webhooks: WebhookCounters {
+                delivered: self.webhook_deliveries.load(Ordering::Relaxed),
+                failed: self.webhook_failures.load(Ordering::Relaxed),
+                dropped: webhooks::dropped_events(),
+            },
+            busy_rejections: self.busy_rejections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// JSON body returned by the `/stats` route
+#[derive(Serialize)]
+pub struct StatsSnapshot {
+    /// Number of requests
+    pub total_requests: u64,
+
+    /// Number of responses with a 4xx or 5xx status code
+    pub errors: u64,
+
+    /// Number of requests per route
+    pub routes: heapless::Vec<RouteHits, ROUTE_COUNT>,
+
+    /// Response times of the paths matching no route
+    pub unmatched_latency: LatencySummary,
+
+    /// Number of requests answered from the response cache
+    pub cache_hits: u64,
+
+    /// Number of requests to cached routes passed to the handler
+    pub cache_misses: u64,
+
+    /// Outcomes of the webhook notifications
+    pub webhooks: WebhookCounters,
+
+    /// Number of requests rejected because their routes were busy
+    pub busy_rejections: u64,
+}
+
+/// Outcomes of the webhook notifications
+#[derive(Serialize)]
+pub struct WebhookCounters {
+    /// Number of events delivered, counted once per URL
+    pub delivered: u64,
+
+    /// Number of events not delivered after the retry, counted once per URL
+    pub failed: u64,
+
+    /// Number of events dropped because the queue was full
+    pub dropped: u64,
+}
+
+/// Number of requests to a route
+#[derive(Serialize)]
+pub struct RouteHits {
+    /// Route pattern, e.g. `/gpio/{pin}`
+    pub route: &'static str,
+
+    /// Number of requests
+    pub hits: u64,
+
+    /// Response times
+    pub latency: LatencySummary,
+}
+
+/// Number of responses per response time bucket
+#[derive(Serialize)]
+pub struct LatencySummary {
+    /// Responses faster than 10 ms
+    #[serde(rename = "<10ms")]
+    pub under_10ms: u64,
+
+    /// Responses taking 10 ms to 50 ms
+    #[serde(rename = "<50ms")]
+    pub under_50ms: u64,
+
+    /// Responses taking 50 ms to 200 ms
+    #[serde(rename = "<200ms")]
+    pub under_200ms: u64,
+
+    /// Responses taking 200 ms to 1 s
+    #[serde(rename = "<1s")]
+    pub under_1s: u64,
+
+    /// Responses taking 1 s or more
+    #[serde(rename = ">=1s")]
+    pub over_1s: u64,
+
+    /// Slowest response time in milliseconds since the last reset
+    pub max_ms: u64,
+
+    /// Slowest response time in milliseconds since boot
+    pub max_since_boot_ms: u64,
+}
+
+impl From<&LatencyHistogram> for LatencySummary {
+    fn from(histogram: &LatencyHistogram) -> Self {
+        let [under_10ms, under_50ms, under_200ms, under_1s, over_1s] = histogram.buckets();
+        Self {
+            under_10ms,
+            under_50ms,
+            under_200ms,
+            under_1s,
+            over_1s,
+            max_ms: histogram.max_ms(),
+            max_since_boot_ms: histogram.max_since_boot_ms(),
+        }
+    }
+}
+
+/// Return the index in [`routes`] of the route matching a path
+pub fn route_index(path: &str) -> Option<usize> {
+    routes().position(|route| route_matches(route.path, path))
+}
+
+/// Check whether a path matches a route pattern
+///
+/// Segments written as `{name}` in the pattern match any single segment.
+pub fn route_matches(pattern: &str, path: &str) -> bool {
+    let mut pattern_segments = pattern.split('/');
+    let mut path_segments = path.split('/');
+    loop {
+        match (pattern_segments.next(), path_segments.next()) {
+            (None, None) => return true,
+            (Some(expected), Some(segment)) => {
+                let wildcard = expected.starts_with('{') && expected.ends_with('}') && !segment.is_empty();
+                if !wildcard && expected != segment {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
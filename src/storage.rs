@@ -0,0 +1,146 @@
+//! Persistent key-value store in flash
+//!
+//! Values are appended to a journal in the NVS partition by
+//! `sequential-storage`, which spreads the writes over all sectors and
+//! compacts them when the partition fills up. The store can be shared by
+//! handlers and other tasks through a `&'static Storage`.
+//!
+//! Flash operations are blocking and stall the executor for a few
+//! milliseconds, or longer when a sector is erased.
+
+use core::ops::Range;
+
+use embassy_embedded_hal::adapter::BlockingAsync;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use esp_storage::FlashStorage;
+use sequential_storage::cache::NoCache;
+use sequential_storage::map;
+
+/// Flash range of the store, the NVS partition of the default partition table
+const STORAGE_RANGE: Range<u32> = 0x9000..0xF000;
+
+/// Maximal length of a key
+pub const MAX_KEY_SIZE: usize = 16;
+
+/// Maximal size of a value
+pub const MAX_VALUE_SIZE: usize = 128;
+
+/// Size of the buffer items are serialized into, with room for the header
+const ITEM_BUFFER_SIZE: usize = MAX_KEY_SIZE + MAX_VALUE_SIZE + 16;
+
+/// A key, stored as a zero-padded byte array
+pub type Key = [u8; MAX_KEY_SIZE];
+
+/// An error of the store
+#[derive(Debug)]
+pub enum Error {
+    /// The key is empty, too long or contains a NUL byte
+    InvalidKey,
+
+    /// The value is larger than [`MAX_VALUE_SIZE`]
+    ValueTooLarge,
+
+    /// The flash could not be read or written
+    Flash,
+}
+
+/// The key-value store
+pub struct Storage {
+    flash: Mutex<CriticalSectionRawMutex, BlockingAsync<FlashStorage>>,
+}
+
+impl Storage {
+    /// Open the store
+    ///
+    /// A corrupted store is erased, so the device boots with an empty store
+    /// rather than not at all.
+    pub async fn open(flash: FlashStorage) -> Self {
+        let mut flash = BlockingAsync::new(flash);
+        let mut buffer = [0; ITEM_BUFFER_SIZE];
+        let probe = map::fetch_item::<Key, &[u8], _>(
+            &mut flash,
+            STORAGE_RANGE,
+            &mut NoCache::new(),
+            &mut buffer,
+            &[0; MAX_KEY_SIZE],
+        )
+        .await;
+        if let Err(error) = probe {
+            crate::log!("Key-value store is unreadable ({:?}), erasing it", error);
+            if let Err(error) = sequential_storage::erase_all(&mut flash, STORAGE_RANGE).await {
+                crate::log!("Failed to erase key-value store: {:?}", error);
+            }
+        }
+        Self {
+            flash: Mutex::new(flash),
+        }
+    }
+
+    /// Read a value into a buffer and return its length, or `None` if the
+    /// key is not set
+    pub async fn get(&self, key: &str, value: &mut [u8; MAX_VALUE_SIZE]) -> Result<Option<usize>, Error> {
+        let key = encode_key(key)?;
+        let mut buffer = [0; ITEM_BUFFER_SIZE];
+        let mut flash = self.flash.lock().await;
+        let item = map::fetch_item::<Key, &[u8], _>(
+            &mut *flash,
+            STORAGE_RANGE,
+            &mut NoCache::new(),
+            &mut buffer,
+            &key,
+        )
+        .await
+        .map_err(|_| Error::Flash)?;
+        Ok(item.map(|item| {
+            value[..item.len()].copy_from_slice(item);
+            item.len()
+        }))
+    }
+
+    /// Set a value
+    pub async fn set(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+        let key = encode_key(key)?;
+        if value.len() > MAX_VALUE_SIZE {
+            return Err(Error::ValueTooLarge);
+        }
+        let mut buffer = [0; ITEM_BUFFER_SIZE];
+        let mut flash = self.flash.lock().await;
+        map::store_item(
+            &mut *flash,
+            STORAGE_RANGE,
+            &mut NoCache::new(),
+            &mut buffer,
+            &key,
+            &value,
+        )
+        .await
+        .map_err(|_| Error::Flash)
+    }
+
+    /// Delete a value, deleting a missing key is not an error
+    pub async fn delete(&self, key: &str) -> Result<(), Error> {
+        let key = encode_key(key)?;
+        let mut buffer = [0; ITEM_BUFFER_SIZE];
+        let mut flash = self.flash.lock().await;
+        map::remove_item::<Key, _>(
+            &mut *flash,
+            STORAGE_RANGE,
+            &mut NoCache::new(),
+            &mut buffer,
+            &key,
+        )
+        .await
+        .map_err(|_| Error::Flash)
+    }
+}
+
+/// Encode a key as a zero-padded byte array
+fn encode_key(key: &str) -> Result<Key, Error> {
+    if key.is_empty() || key.len() > MAX_KEY_SIZE || key.bytes().any(|byte| byte == 0) {
+        return Err(Error::InvalidKey);
+    }
+    let mut encoded = [0; MAX_KEY_SIZE];
+    encoded[..key.len()].copy_from_slice(key.as_bytes());
+    Ok(encoded)
+}
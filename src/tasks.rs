@@ -0,0 +1,128 @@
+//! Heartbeats of the long-running tasks, served at `/tasks`
+//!
+//! Tasks call [`heartbeat!`](crate::heartbeat) at the top of their loop. A
+//! task whose last heartbeat is older than [`STALE_AFTER`] is reported as
+//! stale: it is stuck in an await, or blocking the executor.
+//!
+//! Tasks legitimately waiting for something that may take long, like a
+//! client connection, wrap the wait in [`beating`] so they keep beating
+//! meanwhile. Web tasks do not beat while serving a connection, so a web task
+//! stuck in a handler, or streaming to a client for a long time, is stale.
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::pin;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Ticker};
+use heapless::Vec;
+use serde::Serialize;
+
+/// Maximal number of registered tasks, later ones are not tracked
+pub const MAX_TASKS: usize = 26;
+
+/// Period of the heartbeats sent by [`beating`]
+pub const HEARTBEAT_PERIOD: Duration = Duration::from_secs(10);
+
+/// Age of the last heartbeat after which a task is stale
+pub const STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// Register the calling task on first use and record a heartbeat
+///
+/// Instances of a task pool are told apart by an instance number:
+/// `heartbeat!("web_task", id)`.
+#[macro_export]
+macro_rules! heartbeat {
+    ($name:literal) => {
+        $crate::tasks::heartbeat($name, None)
+    };
+    ($name:literal, $instance:expr) => {
+        $crate::tasks::heartbeat($name, Some($instance))
+    };
+}
+
+/// A registered task
+struct Task {
+    name: &'static str,
+    instance: Option<usize>,
+    last_heartbeat: Instant,
+}
+
+/// The registered tasks, in registration order
+static TASKS: Mutex<CriticalSectionRawMutex, RefCell<Vec<Task, MAX_TASKS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Record a heartbeat of a task, registering it if needed
+pub fn heartbeat(name: &'static str, instance: Option<usize>) {
+    let now = Instant::now();
+    TASKS.lock(|tasks| {
+        let mut tasks = tasks.borrow_mut();
+        match tasks
+            .iter_mut()
+            .find(|task| task.name == name && task.instance == instance)
+        {
+            Some(task) => task.last_heartbeat = now,
+            None => {
+                let _ = tasks.push(Task {
+                    name,
+                    instance,
+                    last_heartbeat: now,
+                });
+            }
+        }
+    });
+}
+
+/// Await a future, recording heartbeats of a task meanwhile
+pub async fn beating<F: Future>(
+    name: &'static str,
+    instance: Option<usize>,
+    future: F,
+) -> F::Output {
+    let mut future = pin!(future);
+    let mut ticker = Ticker::every(HEARTBEAT_PERIOD);
+    loop {
+        heartbeat(name, instance);
+        match select(future.as_mut(), ticker.next()).await {
+            Either::First(output) => return output,
+            Either::Second(()) => {}
+        }
+    }
+}
+
+/// Status of a task returned by `/tasks`
+#[derive(Serialize)]
+pub struct TaskStatus {
+    /// Name of the task
+    pub name: &'static str,
+
+    /// Instance number within a task pool
+    pub instance: Option<usize>,
+
+    /// Seconds since the last heartbeat
+    pub seconds_since_heartbeat: u64,
+
+    /// Whether the last heartbeat is older than [`STALE_AFTER`]
+    pub stale: bool,
+}
+
+/// Return the status of every registered task
+pub fn statuses() -> Vec<TaskStatus, MAX_TASKS> {
+    TASKS.lock(|tasks| {
+        tasks
+            .borrow()
+            .iter()
+            .map(|task| {
+                let age = task.last_heartbeat.elapsed();
+                TaskStatus {
+                    name: task.name,
+                    instance: task.instance,
+                    seconds_since_heartbeat: age.as_secs(),
+                    stale: age > STALE_AFTER,
+                }
+            })
+            .collect()
+    })
+}
@@ -0,0 +1,84 @@
+//! Internal temperature sensor served at `/api/v1/temperature`
+//!
+//! The sensor measures the die temperature, which is several degrees above
+//! the ambient temperature. Readings are corrected by a constant offset set
+//! at build time with the `TEMPERATURE_OFFSET` environment variable, in
+//! degrees Celsius.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Ticker, Timer};
+use esp_hal::tsens::TemperatureSensor;
+use serde::Serialize;
+
+/// Period between two readings
+const SAMPLING_PERIOD: Duration = Duration::from_secs(10);
+
+/// Time for the sensor to settle after it is powered up
+const SETTLING_TIME: Duration = Duration::from_millis(500);
+
+/// Latest, lowest and highest reading since boot
+static READINGS: Mutex<CriticalSectionRawMutex, Cell<Option<TemperatureReadings>>> =
+    Mutex::new(Cell::new(None));
+
+/// Readings of the temperature sensor in degrees Celsius
+#[derive(Clone, Copy, Serialize)]
+pub struct TemperatureReadings {
+    /// Latest reading
+    pub celsius: f32,
+
+    /// Lowest reading since boot
+    pub min_celsius: f32,
+
+    /// Highest reading since boot
+    pub max_celsius: f32,
+}
+
+/// Return the readings, or `None` before the first one
+pub fn readings() -> Option<TemperatureReadings> {
+    READINGS.lock(Cell::get)
+}
+
+/// Return the correction added to raw readings
+fn offset() -> f32 {
+    option_env!("TEMPERATURE_OFFSET")
+        .and_then(|offset| offset.parse().ok())
+        .unwrap_or(0.0)
+}
+
+/// Record a corrected reading
+fn record(celsius: f32) {
+    READINGS.lock(|readings| {
+        let updated = match readings.get() {
+            Some(previous) => TemperatureReadings {
+                celsius,
+                min_celsius: previous.min_celsius.min(celsius),
+                max_celsius: previous.max_celsius.max(celsius),
+            },
+            None => TemperatureReadings {
+                celsius,
+                min_celsius: celsius,
+                max_celsius: celsius,
+            },
+        };
+        readings.set(Some(updated));
+    });
+}
+
+/// Read the temperature sensor periodically
+#[embassy_executor::task]
+pub async fn temperature_task(sensor: TemperatureSensor<'static>) {
+    let offset = offset();
+
+    // The first conversion after power-up reads low
+    Timer::after(SETTLING_TIME).await;
+
+    let mut ticker = Ticker::every(SAMPLING_PERIOD);
+    loop {
+        crate::heartbeat!("temperature_task");
+        record(sensor.get_temperature().to_celsius() + offset);
+        ticker.next().await;
+    }
+}
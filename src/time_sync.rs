@@ -0,0 +1,140 @@
+//! Synchronization of the clock on request, at `POST /time/sync`
+//!
+//! The TLS handshake with the time server takes seconds, so handlers only
+//! [`request`] a synchronization and answer `202 Accepted`; the work is done
+//! by [`time_sync_task`], and its outcome is polled at `/time/sync/status`.
+//! Requests made while a synchronization is running join it instead of
+//! starting another one, so clients retrying do not stampede the server.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Instant};
+use serde::Serialize;
+
+use crate::clock::Clock;
+use crate::http::SharedClient;
+use crate::wifi::{self, WifiStateReceiver};
+
+/// Time allowed for a synchronization, including waiting for the client
+pub const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wakes [`time_sync_task`], several requests coalesce into one
+static REQUESTS: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// State of the synchronizations
+static STATUS: Mutex<CriticalSectionRawMutex, RefCell<SyncStatus>> =
+    Mutex::new(RefCell::new(SyncStatus {
+        running: false,
+        last: None,
+    }));
+
+/// JSON body returned by the `/time/sync` routes
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct SyncStatus {
+    /// Whether a synchronization is in progress
+    pub running: bool,
+
+    /// Outcome of the last synchronization, if any
+    pub last: Option<SyncResult>,
+}
+
+/// Outcome of a synchronization
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct SyncResult {
+    /// Whether the clock was set
+    pub success: bool,
+
+    /// Reason of a failure, `timeout` or `request_failed`
+    pub error: Option<&'static str>,
+
+    /// Duration of the request to the time server in milliseconds
+    pub round_trip_ms: u64,
+
+    /// Seconds the clock was moved forward, negative if backwards
+    pub adjustment_s: Option<i64>,
+
+    /// Seconds since boot when the synchronization finished
+    pub finished_at: u64,
+}
+
+/// Return the state of the synchronizations
+pub fn status() -> SyncStatus {
+    STATUS.lock(|status| *status.borrow())
+}
+
+/// Start a synchronization, unless one is running, and return the state
+pub fn request() -> SyncStatus {
+    STATUS.lock(|status| {
+        let mut status = status.borrow_mut();
+        if !status.running {
+            status.running = true;
+            REQUESTS.signal(());
+        }
+        *status
+    })
+}
+
+/// Synchronize the clock with the time server when requested
+///
+/// A synchronization requested while the station has no address waits for
+/// it to get one back.
+#[embassy_executor::task]
+pub async fn time_sync_task(
+    client: &'static SharedClient,
+    clock: Clock,
+    mut link: WifiStateReceiver,
+) {
+    loop {
+        crate::heartbeat!("time_sync_task");
+        crate::tasks::beating("time_sync_task", None, REQUESTS.wait()).await;
+        crate::tasks::beating("time_sync_task", None, wifi::wait_for_connection(&mut link)).await;
+
+        crate::log!("Synchronizing the clock");
+        let started_at = Instant::now();
+        let sync = async { client.lock().await.fetch_current_time().await };
+        let outcome = with_timeout(SYNC_TIMEOUT, sync).await;
+        let round_trip_ms = started_at.elapsed().as_millis();
+
+        let (error, adjustment_s) = match outcome {
+            Ok(Ok(now)) => {
+                let before = clock.now_as_epoch();
+                #[expect(
+                    clippy::cast_sign_loss,
+                    reason = "Current timestamp will never be negative"
+                )]
+                let current_time = now.unix_timestamp() as u64;
+                clock.set_time(current_time);
+                clock.save_to_rtc_memory(Duration::from_secs(0));
+                #[expect(clippy::cast_possible_wrap, reason = "Timestamps fit an i64")]
+                let adjustment_s = clock.now_as_epoch() as i64 - before as i64;
+                crate::log!("Clock synchronized, moved by {} s", adjustment_s);
+                (None, Some(adjustment_s))
+            }
+            Ok(Err(error)) => {
+                crate::log!("Failed to synchronize the clock: {:?}", error);
+                (Some("request_failed"), None)
+            }
+            Err(_) => {
+                crate::log!("Clock synchronization timed out");
+                (Some("timeout"), None)
+            }
+        };
+
+        let result = SyncResult {
+            success: error.is_none(),
+            error,
+            round_trip_ms,
+            adjustment_s,
+            finished_at: Instant::now().as_secs(),
+        };
+        STATUS.lock(|status| {
+            *status.borrow_mut() = SyncStatus {
+                running: false,
+                last: Some(result),
+            };
+        });
+    }
+}
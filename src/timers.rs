@@ -0,0 +1,51 @@
+//! Raw counters of the timers, to diagnose clock drift
+//!
+//! The wall time of [`Clock`] is derived from [`Instant`], which counts the
+//! system timer, while the RTC counts the slow clock and survives deep sleep.
+//! Two snapshots taken apart show how far the sources drift from each other.
+
+use embassy_time::Instant;
+use esp_hal::rtc_cntl::Rtc;
+use esp_hal::timer::systimer::{SystemTimer, Unit};
+use serde::Serialize;
+
+use crate::clock::Clock;
+
+/// Counters of every time source, sampled together
+#[derive(Serialize)]
+pub struct TimerSnapshot {
+    /// Time of the RTC in microseconds, if the web app was given it
+    pub rtc_us: Option<u64>,
+
+    /// Ticks of the system timer
+    pub systimer_ticks: u64,
+
+    /// Frequency of the system timer
+    pub systimer_ticks_per_second: u64,
+
+    /// Time since boot of `embassy_time` in microseconds
+    pub instant_us: u64,
+
+    /// Wall time of the clock in milliseconds since the epoch
+    pub clock_epoch_ms: u64,
+
+    /// Whether the wall time was obtained from a trusted source
+    pub clock_synchronized: bool,
+}
+
+impl TimerSnapshot {
+    /// Sample every time source
+    ///
+    /// Interrupts are disabled while sampling, so the counters are read
+    /// within a few microseconds of each other.
+    pub fn sample(rtc: Option<&Rtc<'_>>, clock: &Clock) -> Self {
+        critical_section::with(|_| Self {
+            rtc_us: rtc.map(Rtc::current_time_us),
+            systimer_ticks: SystemTimer::unit_value(Unit::Unit0),
+            systimer_ticks_per_second: SystemTimer::ticks_per_second(),
+            instant_us: Instant::now().as_micros(),
+            clock_epoch_ms: clock.now_as_epoch_ms(),
+            clock_synchronized: clock.is_synchronized(),
+        })
+    }
+}
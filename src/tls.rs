@@ -0,0 +1,124 @@
+//! TLS for the web server, enabled with the `tls` feature
+//!
+//! The server certificate and private key are embedded at build time from
+//! `certs/cert.pem` and `certs/key.pem`, see the README for how to generate
+//! a self-signed pair.
+//!
+//! A TLS session needs about 40 KiB of heap for mbedtls, so only a single
+//! web task is spawned when this feature is enabled.
+
+use embassy_net::tcp::TcpSocket;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::once_lock::OnceLock;
+use esp_mbedtls::asynch::Session;
+use esp_mbedtls::{Certificates, Mode, Tls, TlsError, TlsVersion, X509};
+use picoserve::io::{ErrorType, Read, Socket, Write};
+
+/// The server certificate, NUL-terminated as required by mbedtls
+const CERTIFICATE: &str = concat!(include_str!("../certs/cert.pem"), "\0");
+
+/// The server private key, NUL-terminated as required by mbedtls
+const PRIVATE_KEY: &str = concat!(include_str!("../certs/key.pem"), "\0");
+
+/// The TLS context, initialized once at boot
+static TLS: OnceLock<Tls<'static>> = OnceLock::new();
+
+/// Initialize the TLS context with the SHA accelerator
+pub fn init(sha: esp_hal::peripherals::SHA<'static>) {
+    let tls = Tls::new(sha).expect("Failed to initialize TLS");
+    if TLS.init(tls).is_err() {
+        panic!("TLS initialized twice");
+    }
+}
+
+/// Perform the server side of a TLS handshake on an accepted socket
+pub async fn accept(socket: TcpSocket<'_>) -> Result<TlsSocket<'_>, TlsError> {
+    let tls = TLS.get().await;
+    let certificates = Certificates {
+        certificate: X509::pem(CERTIFICATE.as_bytes()).ok(),
+        private_key: X509::pem(PRIVATE_KEY.as_bytes()).ok(),
+        ..Default::default()
+    };
+    let mut session = Session::new(
+        socket,
+        Mode::Server,
+        TlsVersion::Tls1_2,
+        certificates,
+        tls.reference(),
+    )?;
+    session.connect().await?;
+
+    Ok(TlsSocket {
+        session: Mutex::new(session),
+    })
+}
+
+/// A TLS session usable as a picoserve socket
+///
+/// Reads and writes both need exclusive access to the session, so the two
+/// halves share it through a mutex, held for a whole read or write. A read
+/// waiting for the peer keeps the other half from writing, so the server
+/// cannot read and write at the same time: the WebSocket routes are turned
+/// off, see [`HalfDuplexLayer`](crate::web::HalfDuplexLayer).
+pub struct TlsSocket<'a> {
+    session: Mutex<NoopRawMutex, Session<'a, TcpSocket<'a>>>,
+}
+
+impl<'a> Socket for TlsSocket<'a> {
+    type Error = TlsError;
+    type ReadHalf<'b>
+        = TlsHalf<'b, 'a>
+    where
+        'a: 'b;
+    type WriteHalf<'b>
+        = TlsHalf<'b, 'a>
+    where
+        'a: 'b;
+
+    fn split(&mut self) -> (Self::ReadHalf<'_>, Self::WriteHalf<'_>) {
+        (
+            TlsHalf {
+                session: &self.session,
+            },
+            TlsHalf {
+                session: &self.session,
+            },
+        )
+    }
+
+    async fn shutdown<Timer: picoserve::Timer>(
+        self,
+        _timeouts: &picoserve::Timeouts<Timer::Duration>,
+        _timer: &mut Timer,
+    ) -> Result<(), picoserve::Error<Self::Error>> {
+        // The peer may already be gone, there is nothing to do about it
+        let _ = self.session.into_inner().close().await;
+        Ok(())
+    }
+}
+
+/// One half of a [`TlsSocket`]
+pub struct TlsHalf<'b, 'a> {
+    session: &'b Mutex<NoopRawMutex, Session<'a, TcpSocket<'a>>>,
+}
+
+impl ErrorType for TlsHalf<'_, '_> {
+    type Error = TlsError;
+}
+
+impl Read for TlsHalf<'_, '_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.session.lock().await.read(buf).await
+    }
+}
+
+impl Write for TlsHalf<'_, '_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.session.lock().await.write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.session.lock().await.flush().await
+    }
+}
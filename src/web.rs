@@ -1,20 +1,389 @@
-use embassy_net::Stack;
-use embassy_time::{Duration, Instant};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpAddress, IpEndpoint, Stack};
+use embassy_futures::select::{select, select3, Either, Either3};
+use embassy_time::{Duration, Instant, Ticker};
 use esp_alloc as _;
+use esp_hal::gpio::Output;
+use esp_hal::rtc_cntl::Rtc;
 use picoserve::{io::Read, request::Path, response::ResponseWriter, routing, AppRouter, Router, AppWithStateBuilder};
-use rtt_target::rprintln;
+use picoserve::extract::Query;
+use picoserve::response::chunked::ChunkedResponse;
+use picoserve::response::{ws, File, IntoResponse, StatusCode};
+use core::cell::{Cell, RefCell};
 use core::fmt::Write;
+use base64::Engine as _;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::semaphore::{GreedySemaphore, Semaphore as _};
+use embassy_sync::watch::Watch;
+use portable_atomic::{AtomicBool, Ordering};
+use rand_core::RngCore as _;
 use heapless::String;
-use time;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, UtcOffset};
 
+use crate::adc::AdcReadings;
+use crate::api::{self, GpioResponse, API_V1_PREFIX};
+use crate::boot;
+use crate::captive;
+use crate::cidr::{self, Cidr, LAN_RANGES};
+use crate::cache_control::{CachePolicy, Cached};
+use crate::cache::{CachedRoute, ResponseCache, CACHE_BODY_SIZE, DEFAULT_CACHED_ROUTES};
+use crate::changes::{self, MAX_POLL_WAIT};
 use crate::clock::Clock;
+use crate::compression::{Compressible, Compression, DEFAULT_COMPRESSION_THRESHOLD};
+use crate::config::{ConfigBody, ConfigError, ConfigHandle};
+use crate::files::{self, FileStore};
+use crate::handler_timeout::{self, HandlerTimeoutLayer, ReclaimableSocket, DEFAULT_HANDLER_TIMEOUT};
+use crate::keep_alive::{self, RequestLimitLayer, DEFAULT_MAX_REQUESTS_PER_CONNECTION};
+use crate::etag::{AppendHeaderWriter, Conditional, IfNoneMatch, VERSION_ETAG, VERSION_JSON_ETAG};
+use crate::heap::HeapStats;
+use crate::html::{Html, Page};
+use crate::identity::DeviceIdentity;
+use crate::gpio::{GpioOutputs, MAX_GPIO_OUTPUTS};
+use crate::led::{Led, LedMode, BLINK_PERIOD_RANGE};
+use crate::log_sink::{self, LogLevel};
+use crate::metrics::{CountingSocket, Metrics, MetricsExposition};
+use crate::multipart::{self, Multipart};
+use crate::request_log::{self, RecordFilter, RequestLogLines, RequestRecord, StatusClass};
+use crate::request_id::{RequestId, REQUEST_ID_HEADER};
+use crate::restart;
+use crate::routes::{route_table, RouteHelp, RouteInfo, RouteListing};
+use crate::scheduler::{self, EntryRequest, EntryResponse, Schedule};
+use crate::security_headers::{SecurityHeaders, SecurityHeadersLayer};
+use crate::session::{self, CsrfToken, SessionId, SessionStore, SetCookie};
+use crate::negotiation::{Accept, Negotiated};
+use crate::provisioning::{self, CredentialsError, NetworksError, StoredNetworks, WifiCredentials};
+use crate::netstats::NetStats;
+use crate::range::{ByteRange, ContentRange, RangeHeader};
+use crate::random::{self, generate_api_token, RandomFormat, RngWrapper, SharedRng, Token, DEFAULT_RANDOM_BYTES, MAX_RANDOM_BYTES, TOKEN_SIZE};
+use crate::timers::TimerSnapshot;
+use crate::tasks::{self, TaskStatus, MAX_TASKS};
+use crate::time_sync;
+use crate::webhooks::{self, WebhookRequest, WebhookResponse, Webhooks};
+use crate::well_known::{self, SecurityTxt};
+use crate::wifi::{self, WifiMode};
+use crate::stats::{self, Stats};
+use crate::settings::{is_valid_hostname, Settings, MAX_HOSTNAME_SIZE};
+use crate::storage::Storage;
+use crate::static_assets::{self, AcceptsGzip, Asset};
+use crate::sse::{EventSource, EventStream, EventWriter, LastEventId};
 
+/// Number of web tasks, i.e. of HTTP connections served concurrently
+#[cfg(not(feature = "tls"))]
+pub const WEB_TASK_POOL_SIZE: usize = 4;
+
+/// Number of web tasks, limited to one because of the RAM cost of TLS
+#[cfg(feature = "tls")]
 pub const WEB_TASK_POOL_SIZE: usize = 1;
 
+/// Number of admin web tasks, see [`AdminApplication`]
+///
+/// The admin surface is used by one person at a time, so a single task is
+/// enough, and it only costs one more [`TaskBuffers`] of static RAM, outside
+/// of the heap.
+pub const ADMIN_TASK_POOL_SIZE: usize = 1;
+
+/// Number of web tasks serving the access point kept alongside the station,
+/// see the `ap-sta` feature
+///
+/// Each task costs one more [`TaskBuffers`] of static RAM.
+pub const ACCESS_POINT_TASK_POOL_SIZE: usize = if cfg!(feature = "ap-sta") { 1 } else { 0 };
+
+/// Number of web tasks of every group
+pub const SERVER_TASK_COUNT: usize =
+    WEB_TASK_POOL_SIZE + ADMIN_TASK_POOL_SIZE + ACCESS_POINT_TASK_POOL_SIZE;
+
+/// Size of the TCP receive buffer of each web task
+const TCP_RX_BUFFER_SIZE: usize = 1024;
+
+/// Size of the TCP transmit buffer of each web task
+const TCP_TX_BUFFER_SIZE: usize = 1024;
+
+/// Size of the HTTP request buffer of each web task
+const HTTP_BUFFER_SIZE: usize = 2048;
+
+/// Period between two events on the `/events` stream
+const EVENTS_PERIOD: Duration = Duration::from_secs(5);
+
+/// Maximal size of a JSON request body
+pub const MAX_JSON_BODY_SIZE: usize = 512;
+
+/// Routes at the root, with the methods reported in the `Allow` header of
+/// 405 and `OPTIONS` responses and the description served at `/api`
+///
+/// Every route must be listed here, `OPTIONS` answers 404 for the others.
+/// The routes of the API group are listed in [`api::ROUTES`].
+pub const ROUTES: &[RouteInfo] = route_table![
+    "/" => "GET", "Home page";
+    "/api" => "GET", "This list of routes, as JSON";
+    "/help" => "GET", "This list of routes, as a page";
+    "/static/{asset}" => "GET", "Static assets of the pages";
+    "/favicon.ico" => "GET", "Favicon";
+    "/version" => "GET", "Firmware version";
+    "/old-time" => "GET", "Moved to /time";
+    "/time" => "GET", "Current time";
+    "/time/epoch" => "GET", "Current time in seconds since the epoch";
+    "/time/epoch_ms" => "GET", "Current time in milliseconds since the epoch";
+    "/time/sync" => "POST", "Start a clock synchronization";
+    "/time/sync/status" => "GET", "Outcome of the last clock synchronization";
+    "/timezone" => "GET, POST", "Read or set the UTC offset";
+    "/ws/time" => "GET", "Current time over a WebSocket";
+    "/ws/time/demo" => "GET", "Page showing the WebSocket time";
+    "/ws/logs" => "GET", "Log lines over a WebSocket";
+    "/events" => "GET", "Server-sent status events";
+    "/events/rssi" => "GET", "Server-sent RSSI samples";
+    "/time-since-boot" => "GET", "Time since boot";
+    "/time-since-rtc-update" => "GET", "Time since the clock was set";
+    "/api/status" => "GET", "Moved to /api/v1/status";
+    "/logs" => "GET", "Recent requests as JSON lines";
+    "/logs/view" => "GET", "Recent requests, refreshed live";
+    "/loglevel" => "GET, PUT", "Read or set the log level";
+    "/health" => "GET", "Health check";
+    "/metrics" => "GET", "Prometheus metrics";
+    "/stream" => "GET", "Chunked streaming demo";
+    "/uptime" => "GET", "Time since boot in seconds";
+    "/heap" => "GET", "Heap usage";
+    "/tasks" => "GET", "Task heartbeats";
+    "/debug/timers" => "GET", "Raw counters of the timers";
+    "/wifi" => "GET", "Wi-Fi association";
+    "/wifi/scan" => "GET", "Nearby Wi-Fi networks";
+    "/wifi/networks" => "GET, POST, PUT", "List, add or reorder the stored Wi-Fi networks";
+    "/wifi/networks/{index}" => "DELETE", "Remove a stored Wi-Fi network";
+    "/wifi/disconnect" => "POST", "Leave the Wi-Fi network and stay disconnected";
+    "/wifi/reconnect" => "POST", "Connect to the Wi-Fi network again";
+    "/poll" => "GET", "Long poll for state changes";
+    "/stats" => "GET", "Request statistics";
+    "/stats/reset" => "POST", "Zero the request statistics";
+    "/netstats" => "GET", "Network statistics";
+    "/whoami" => "GET", "Address and user agent of the client";
+    "/id" => "GET", "Identity of the device";
+    "/random" => "GET", "Random bytes";
+    "/netstats/reset" => "POST", "Zero the network statistics";
+    "/schedule" => "GET, POST, DELETE", "List, add or clear scheduled GPIO actions";
+    "/schedule/{id}" => "DELETE", "Remove a scheduled GPIO action";
+    "/webhooks" => "GET, POST", "List or register webhooks";
+    "/webhooks/{id}" => "DELETE", "Unregister a webhook";
+    "/settings" => "GET, POST", "Settings page";
+    "/config" => "GET, PUT", "Read or replace the configuration";
+    "/login" => "GET, POST", "Login page";
+    "/logout" => "POST", "End the session";
+    "/admin" => "GET", "Check the admin credentials";
+    "/admin/token/rotate" => "POST", "Rotate the API token";
+    "/restart" => "POST", "Restart the device";
+    "/files/{name}" => "GET, PUT", "Download or upload a file";
+    "/upload" => "POST", "Upload a file from a form";
+    "/echo" => "POST", "Report the size and CRC-32 of the body, or echo it";
+    provisioning::PROVISIONING_PATH => "GET, POST", "Wi-Fi setup, in provisioning mode";
+    well_known::ROBOTS_PATH => "GET", "Robots exclusion file";
+    well_known::SECURITY_TXT_PATH => "GET", "Security contact";
+    captive::ANDROID_PROBE => "GET", "Connectivity check of Android";
+    captive::APPLE_PROBE => "GET", "Connectivity check of Apple devices";
+    captive::WINDOWS_PROBE => "GET", "Connectivity check of Windows";
+    captive::WINDOWS_NCSI_PROBE => "GET", "Connectivity check of older Windows";
+];
+
+/// Iterate over every route, root routes first
+pub fn routes() -> impl Iterator<Item = &'static RouteInfo> {
+    ROUTES.iter().chain(api::ROUTES)
+}
+
+/// Return the methods allowed on the route matching a path, if any
+pub fn allowed_methods_on(path: &str) -> Option<&'static str> {
+    routes()
+        .find(|route| stats::route_matches(route.path, path))
+        .map(|route| route.methods)
+}
+
+/// Value of the `Allow` header, the methods of a route and `OPTIONS`
+pub struct AllowHeader(pub &'static str);
+
+impl core::fmt::Display for AllowHeader {
+    fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.0.is_empty() {
+            formatter.write_str("OPTIONS")
+        } else {
+            write!(formatter, "{}, OPTIONS", self.0)
+        }
+    }
+}
+
+/// Security headers of the routes at the root
+///
+/// The API group adds its own, see [`api::SECURITY_HEADERS`].
+pub const SECURITY_HEADERS: SecurityHeadersLayer =
+    SecurityHeadersLayer::new(SecurityHeaders::HTML).except(API_V1_PREFIX);
+
+/// Username for the routes protected by [`BasicAuthLayer`]
+///
+/// The build fails without it, see `build.rs`.
+const ADMIN_USERNAME: &str = env!("ADMIN_USERNAME");
+
+/// Password for the routes protected by [`BasicAuthLayer`]
+///
+/// The build fails without it, see `build.rs`.
+const ADMIN_PASSWORD: &str = env!("ADMIN_PASSWORD");
+
+/// Valid range for UTC offsets set via `POST /timezone`, in minutes
+pub(crate) const OFFSET_MINUTES_RANGE: core::ops::RangeInclusive<i16> = -720..=840;
+
 /// The state used by the web app, containing the clock
+///
+/// The state is shared by all web tasks, so every field must be usable
+/// through a shared reference. Counters are atomics, other mutable data goes
+/// behind an `embassy_sync` mutex with a `CriticalSectionRawMutex`, like
+/// [`GpioOutputs`]. A `RefCell` or `static mut` is not enough, because
+/// handlers of different connections run concurrently.
+///
+/// [`RequestIdLayer`] passes a copy with the [`RequestId`] of the request to
+/// the inner layers and handlers, the shared state has an empty one.
+#[derive(Clone)]
 pub struct AppState {
     pub clock: Clock,
     pub stack: Stack<'static>,
+    pub credentials: Credentials,
+    pub api_token: &'static ApiTokenStore,
+    pub rng: &'static SharedRng,
+    pub sessions: &'static SessionStore,
+    pub metrics: &'static Metrics,
+    pub stats: &'static Stats,
+    pub netstats: &'static NetStats,
+    pub gpio: &'static GpioOutputs,
+    pub led: &'static Led,
+    pub adc: &'static AdcReadings,
+    pub settings: &'static Settings,
+    pub max_request_body_size: usize,
+    pub normalize_paths: bool,
+    pub captive_portal: bool,
+    pub admin_port: Option<u16>,
+    pub cache: &'static ResponseCache,
+    pub storage: Option<&'static Storage>,
+    pub schedule: &'static Schedule,
+    pub webhooks: &'static Webhooks,
+    pub files: Option<&'static FileStore>,
+    pub rtc: Option<&'static Rtc<'static>>,
+    pub read_request_timeout: Option<Duration>,
+    pub control: &'static ServerControl,
+    pub remote_endpoint: Option<IpEndpoint>,
+    pub web_task: usize,
+    pub admin_allowlist: &'static [Cidr],
+    pub compression_threshold: Option<usize>,
+    pub max_requests_per_connection: Option<usize>,
+}
+
+/// A handle to pause and resume the web server
+///
+/// While paused, web tasks finish the connections in flight and then stop
+/// listening, so new clients get connection refused instead of hanging.
+pub struct ServerControl {
+    /// Whether the server is paused
+    paused: AtomicBool,
+
+    /// Notifies web tasks when the paused state changes
+    changes: Watch<CriticalSectionRawMutex, bool, SERVER_TASK_COUNT>,
+}
+
+impl ServerControl {
+    /// Create a handle for a running server
+    pub const fn new() -> Self {
+        Self {
+            paused: AtomicBool::new(false),
+            changes: Watch::new_with(false),
+        }
+    }
+
+    /// Stop accepting new connections
+    pub fn pause(&self) {
+        crate::log!("Pausing web server");
+        self.paused.store(true, Ordering::Relaxed);
+        self.changes.sender().send(true);
+    }
+
+    /// Accept new connections again
+    pub fn resume(&self) {
+        crate::log!("Resuming web server");
+        self.paused.store(false, Ordering::Relaxed);
+        self.changes.sender().send(false);
+    }
+
+    /// Return whether the server is paused
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for ServerControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The API token accepted by [`ApiTokenLayer`], which can be rotated at runtime
+pub struct ApiTokenStore {
+    token: Mutex<CriticalSectionRawMutex, Cell<Token>>,
+    rng: Mutex<CriticalSectionRawMutex, RefCell<RngWrapper>>,
+}
+
+impl ApiTokenStore {
+    /// Create a store with a freshly generated token
+    pub fn new(mut rng: RngWrapper) -> Self {
+        let token = generate_api_token(&mut rng);
+        Self {
+            token: Mutex::new(Cell::new(token)),
+            rng: Mutex::new(RefCell::new(rng)),
+        }
+    }
+
+    /// Replace the token with a freshly generated one and return it
+    pub fn rotate(&self) -> Token {
+        let token = self
+            .rng
+            .lock(|rng| generate_api_token(&mut rng.borrow_mut()));
+        self.token.lock(|current| current.set(token));
+        token
+    }
+
+    /// Check a hexadecimal token against the current one
+    pub fn matches(&self, hex: &str) -> bool {
+        let mut candidate = [0_u8; TOKEN_SIZE];
+        if !decode_hex(hex, &mut candidate) {
+            return false;
+        }
+        let token = self.token.lock(Cell::get);
+        constant_time_eq(&candidate, token.as_bytes())
+    }
+}
+
+/// Credentials for HTTP Basic authentication
+#[derive(Clone)]
+pub struct Credentials {
+    pub username: &'static str,
+    pub password: &'static str,
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self {
+            username: ADMIN_USERNAME,
+            password: ADMIN_PASSWORD,
+        }
+    }
+}
+
+impl AppState {
+    /// Return the parts of the device the configuration document is read
+    /// from and applied to
+    pub fn config(&self) -> ConfigHandle {
+        ConfigHandle {
+            clock: self.clock.clone(),
+            settings: self.settings,
+            led: self.led,
+            sessions: self.sessions,
+            webhooks: self.webhooks,
+            storage: self.storage,
+        }
+    }
 }
 
 /// An extractor for getting the clock from the app state
@@ -31,190 +400,4861 @@ impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for ClockExtractor {
     }
 }
 
-pub struct Application;
+/// JSON body returned by the `/time` route
+#[derive(Serialize)]
+pub struct TimeResponse {
+    /// Seconds since the Unix epoch
+    pub unix: i64,
 
-impl AppWithStateBuilder for Application {
-    type State = AppState;
-    type PathRouter = impl routing::PathRouter<AppState>;
+    /// Local time formatted as ISO 8601
+    pub iso8601: String<32>,
 
-    fn build_app(self) -> picoserve::Router<Self::PathRouter, AppState> {
-        picoserve::Router::new()
-            .route("/", routing::get(|| async move { "Hello World" }))
-            .route("/version", routing::get(|| async move {
-                let mut version_string = String::<64>::new();
-                write!(version_string, "Version: {}", env!("CARGO_PKG_VERSION")).unwrap();
-                version_string
-            }))
-            .route("/time", routing::get(|ClockExtractor(clock)| async move {
-                match clock.now() {
-                    Ok(time) => {
-                        let mut time_string = String::<128>::new();
-                        write!(time_string, "{}", time).unwrap();
-                        time_string
-                    }
-                    Err(_) => {
-                        let mut error_string = String::<128>::new();
-                        write!(error_string, "Error getting current time").unwrap();
-                        error_string
-                    }
-                }
-            }))
-            .route("/time-since-boot", routing::get(|ClockExtractor(clock)| async move {
-                let seconds = clock.time_since_boot();
-                let mut response = String::<128>::new();
-                write!(response, "Time since boot: {} seconds", seconds).unwrap();
-                response
-            }))
-            .route("/time-since-rtc-update", routing::get(|ClockExtractor(clock)| async move {
-                match clock.time_since_rtc_update() {
-                    Some(seconds) => {
-                        let mut response = String::<128>::new();
-                        write!(response, "Time since RTC update: {} seconds", seconds).unwrap();
-                        response
-                    }
-                    None => {
-                        let mut response = String::<128>::new();
-                        write!(response, "No RTC update has been performed").unwrap();
-                        response
-                    }
-                }
-            }))
-            .layer(TimeLayer)
+    /// UTC offset formatted as `+HH:MM`
+    pub offset: String<8>,
+}
+
+impl From<OffsetDateTime> for TimeResponse {
+    fn from(time: OffsetDateTime) -> Self {
+        let mut iso8601 = String::new();
+        write_iso8601(&mut iso8601, &time).unwrap();
+        let mut offset = String::new();
+        write_offset(&mut offset, time.offset()).unwrap();
+
+        Self {
+            unix: time.unix_timestamp(),
+            iso8601,
+            offset,
+        }
     }
 }
 
-pub struct WebApp {
-    pub router: &'static Router<<Application as AppWithStateBuilder>::PathRouter, AppState>,
-    pub config: &'static picoserve::Config<Duration>,
-    pub state: &'static AppState,
+/// JSON body accepted by `POST /timezone`
+#[derive(Deserialize)]
+pub struct TimezoneRequest {
+    /// Offset from UTC in minutes
+    pub offset_minutes: i16,
 }
 
-// impl Default for WebApp {
-//     fn default() -> Self {
-//         // Create a default clock for the default implementation
-//         let default_clock = Clock::new(0, time::UtcOffset::UTC);
-//         Self::new_with_clock(default_clock, Stack::default())
-//     }
-// }
+/// A representation of the time selected with `/time?fmt=`
+#[derive(Clone, Copy)]
+pub enum TimeFormat {
+    /// A [`TimeResponse`] JSON object
+    Json,
 
-impl WebApp {
-    pub fn new_with_clock(clock: Clock, stack: Stack<'static>) -> Self {
-        let router = picoserve::make_static!(AppRouter<Application>, Application.build_app());
+    /// Seconds since the Unix epoch
+    Unix,
 
-        let config = picoserve::make_static!(
-            picoserve::Config<Duration>,
-            picoserve::Config::new(picoserve::Timeouts {
-                start_read_request: Some(Duration::from_secs(5)),
-                persistent_start_read_request: Some(Duration::from_secs(1)),
-                read_request: Some(Duration::from_secs(1)),
-                write: Some(Duration::from_secs(1)),
-            })
-            .keep_connection_alive()
-        );
+    /// ISO 8601
+    Iso,
 
-        let state = picoserve::make_static!(
-            AppState,
-            AppState { clock, stack}
-        );
+    /// Human readable, e.g. `Tuesday, 28 May 2024 16:05:45 +00:00`
+    Human,
+}
 
-        Self { router, config, state }
+impl core::str::FromStr for TimeFormat {
+    type Err = ();
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(Self::Json),
+            "unix" => Ok(Self::Unix),
+            "iso" => Ok(Self::Iso),
+            "human" => Ok(Self::Human),
+            _ => Err(()),
+        }
     }
 }
 
+impl TimeFormat {
+    /// Render a time in this format
+    pub fn render(self, time: OffsetDateTime) -> TimeOutput {
+        let mut text = String::new();
+        match self {
+            Self::Json => return TimeOutput::Json(TimeResponse::from(time)),
+            Self::Unix => write!(text, "{}", time.unix_timestamp()).unwrap(),
+            Self::Iso => write_iso8601(&mut text, &time).unwrap(),
+            Self::Human => {
+                write!(
+                    text,
+                    "{}, {} {} {} {:02}:{:02}:{:02} ",
+                    time.weekday(),
+                    time.day(),
+                    time.month(),
+                    time.year(),
+                    time.hour(),
+                    time.minute(),
+                    time.second(),
+                )
+                .unwrap();
+                write_offset(&mut text, time.offset()).unwrap();
+            }
+        }
+        TimeOutput::Text(text)
+    }
+}
 
-#[embassy_executor::task(pool_size = WEB_TASK_POOL_SIZE)]
-pub async fn web_task(
-    id: usize,
-    stack: Stack<'static>,
-    router: &'static AppRouter<Application>,
-    config: &'static picoserve::Config<Duration>,
-    state: &'static AppState,
-) -> ! {
-    let port = 80;
-    let mut tcp_rx_buffer = [0; 1024];
-    let mut tcp_tx_buffer = [0; 1024];
-    let mut http_buffer = [0; 2048];
-
-    picoserve::listen_and_serve_with_state(
-        id,
-        router,
-        config,
-        stack,
-        port,
-        &mut tcp_rx_buffer,
-        &mut tcp_tx_buffer,
-        &mut http_buffer,
-        state,
-    )
-    .await
+/// A time rendered as JSON or plain text
+pub type TimeOutput = Negotiated<TimeResponse, String<64>>;
+
+/// JSON body returned by `/version` when JSON is requested
+#[derive(Serialize)]
+pub struct VersionResponse {
+    /// Version of the firmware
+    pub version: &'static str,
 }
 
-struct TimedResponseWriter<'r, W> {
-    path: Path<'r>,
-    start_time: Instant,
-    response_writer: W,
+/// A redirect to another location
+///
+/// The location is sent in the `Location` header, which can be a
+/// `&'static str` or a bounded string built at runtime. The response has an
+/// empty body.
+pub struct Redirect<L = &'static str> {
+    status_code: StatusCode,
+    location: L,
 }
 
-impl<'r, W: ResponseWriter> ResponseWriter for TimedResponseWriter<'r, W> {
-    type Error = W::Error;
+impl<L: core::fmt::Display> Redirect<L> {
+    /// Redirect with `301 Moved Permanently`, for moved resources
+    pub fn permanent(location: L) -> Self {
+        Self {
+            status_code: StatusCode::MOVED_PERMANENTLY,
+            location,
+        }
+    }
 
-    async fn write_response<
-        R: Read<Error = Self::Error>,
-        H: picoserve::response::HeadersIter,
-        B: picoserve::response::Body,
-    >(
+    /// Redirect with `302 Found`, for temporarily moved resources
+    pub fn found(location: L) -> Self {
+        Self {
+            status_code: StatusCode::FOUND,
+            location,
+        }
+    }
+
+    /// Redirect with `303 See Other`, e.g. after a form submission
+    ///
+    /// The client follows the redirect with a `GET` request.
+    pub fn see_other(location: L) -> Self {
+        Self {
+            status_code: StatusCode::SEE_OTHER,
+            location,
+        }
+    }
+
+    /// Redirect with `307 Temporary Redirect`, keeping the request method
+    pub fn temporary(location: L) -> Self {
+        Self {
+            status_code: StatusCode::TEMPORARY_REDIRECT,
+            location,
+        }
+    }
+}
+
+impl<L: core::fmt::Display> IntoResponse for Redirect<L> {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
         self,
         connection: picoserve::response::Connection<'_, R>,
-        response: picoserve::response::Response<H, B>,
-    ) -> Result<picoserve::ResponseSent, Self::Error> {
-        let status_code = response.status_code();
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let response = picoserve::response::Response::new(self.status_code, "")
+            .with_header("Location", self.location);
+        response_writer.write_response(connection, response).await
+    }
+}
 
-        let result = self
-            .response_writer
-            .write_response(connection, response)
-            .await;
+/// Size of the buffer the dashboard is rendered into
+const DASHBOARD_SIZE: usize = 3072;
 
-        rprintln!(
-            "Path: {}; Status Code: {}; Response Time: {}ms",
-            self.path,
-            status_code,
-            self.start_time.elapsed().as_millis()
-        );
+/// Size of the buffer a [`Streamed`] body is produced into
+pub const STREAM_CHUNK_SIZE: usize = 512;
 
-        result
+/// Size of the body served by the `/stream` demonstration route
+const STREAM_DEMO_SIZE: usize = 16 * 1024;
+
+/// A response body produced in chunks, for bodies larger than the HTTP buffer
+///
+/// The closure fills the buffer it is given and returns the number of bytes
+/// written, with `0` marking the end of the body. Each chunk is a separate
+/// write to the socket, so the write timeout applies per chunk rather than to
+/// the whole body.
+///
+/// The body is sent with `Transfer-Encoding: chunked` unless its length is
+/// given with [`Streamed::with_length`].
+pub struct Streamed<F> {
+    content_type: &'static str,
+    length: Option<usize>,
+    fill: F,
+}
+
+impl<F: FnMut(&mut [u8]) -> usize> Streamed<F> {
+    /// Stream a body of unknown length
+    pub fn new(content_type: &'static str, fill: F) -> Self {
+        Self {
+            content_type,
+            length: None,
+            fill,
+        }
+    }
+
+    /// Declare the total length of the body, sent as `Content-Length`
+    ///
+    /// The closure must then produce exactly this many bytes.
+    pub fn with_length(self, length: usize) -> Self {
+        Self {
+            length: Some(length),
+            ..self
+        }
     }
 }
 
-struct TimeLayer;
+impl<F: FnMut(&mut [u8]) -> usize> picoserve::response::chunked::Chunks for Streamed<F> {
+    fn content_type(&self) -> &'static str {
+        self.content_type
+    }
 
-impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for TimeLayer {
-    type NextState = AppState;
-    type NextPathParameters = PathParameters;
+    async fn write_chunks<W: picoserve::io::Write>(
+        mut self,
+        mut chunk_writer: picoserve::response::chunked::ChunkWriter<W>,
+    ) -> Result<picoserve::response::chunked::ChunksWritten, W::Error> {
+        let mut buffer = [0; STREAM_CHUNK_SIZE];
+        loop {
+            let length = (self.fill)(&mut buffer);
+            if length == 0 {
+                break;
+            }
+            chunk_writer.write_chunk(&buffer[..length]).await?;
+        }
+        chunk_writer.finalize().await
+    }
+}
 
-    async fn call_layer<
-        'a,
-        R: Read + 'a,
-        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
-        W: ResponseWriter<Error = R::Error>,
-    >(
-        &self,
-        next: NextLayer,
-        state: &AppState,
-        path_parameters: PathParameters,
-        request_parts: picoserve::request::RequestParts<'_>,
+/// A [`Streamed`] body with a known length
+struct SizedStream<F>(Streamed<F>);
+
+impl<F: FnMut(&mut [u8]) -> usize> picoserve::response::Content for SizedStream<F> {
+    fn content_type(&self) -> &'static str {
+        self.0.content_type
+    }
+
+    fn content_length(&self) -> usize {
+        self.0.length.unwrap_or(0)
+    }
+
+    async fn write_content<W: picoserve::io::Write>(mut self, mut writer: W) -> Result<(), W::Error> {
+        let mut buffer = [0; STREAM_CHUNK_SIZE];
+        loop {
+            let length = (self.0.fill)(&mut buffer);
+            if length == 0 {
+                return Ok(());
+            }
+            writer.write_all(&buffer[..length]).await?;
+        }
+    }
+}
+
+impl<F: FnMut(&mut [u8]) -> usize> IntoResponse for Streamed<F> {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: picoserve::response::Connection<'_, R>,
         response_writer: W,
     ) -> Result<picoserve::ResponseSent, W::Error> {
-        let path = request_parts.path();
+        if self.length.is_some() {
+            picoserve::response::Response::new(StatusCode::OK, SizedStream(self))
+                .write_to(connection, response_writer)
+                .await
+        } else {
+            ChunkedResponse::new(self).write_to(connection, response_writer).await
+        }
+    }
+}
 
-        next.run(
-            state,
-            path_parameters,
-            TimedResponseWriter {
-                path,
-                start_time: Instant::now(),
-                response_writer,
-            },
+/// Generate the body of the `/stream` route: numbered 64-byte lines
+fn stream_demo() -> Streamed<impl FnMut(&mut [u8]) -> usize> {
+    const LINE_SIZE: usize = 64;
+
+    let mut line_number = 0;
+    Streamed::new("text/plain; charset=utf-8", move |buffer: &mut [u8]| {
+        let mut written = 0;
+        while line_number < STREAM_DEMO_SIZE / LINE_SIZE && written + LINE_SIZE <= buffer.len() {
+            let line = &mut buffer[written..written + LINE_SIZE];
+            line.fill(b'.');
+            let mut number = String::<8>::new();
+            write!(number, "{line_number:06}").unwrap();
+            line[..number.len()].copy_from_slice(number.as_bytes());
+            line[LINE_SIZE - 1] = b'\n';
+            written += LINE_SIZE;
+            line_number += 1;
+        }
+        written
+    })
+}
+
+/// Render the status dashboard served at `/`
+fn render_dashboard(clock: &Clock, stack: Stack<'static>) -> Html<DASHBOARD_SIZE> {
+    let mut time = String::<40>::new();
+    match clock.now() {
+        Ok(now) if clock.is_synchronized() => write_iso8601(&mut time, &now).unwrap(),
+        _ => time.push_str("unsynchronized").unwrap(),
+    }
+    let mut address = String::<24>::new();
+    match stack.config_v4() {
+        Some(config) => write!(address, "{}", config.address).unwrap(),
+        None => address.push_str("none").unwrap(),
+    }
+    let mut rssi = String::<16>::new();
+    match crate::wifi::rssi() {
+        Some(dbm) => write!(rssi, "{} dBm", dbm).unwrap(),
+        None => rssi.push_str("unknown").unwrap(),
+    }
+
+    Page::new("ESP32-C3 Picoserve")
+        .refresh(5)
+        .table([
+            ("Version", format_args!("{}", env!("CARGO_PKG_VERSION"))),
+            ("Time", format_args!("{}", time)),
+            ("Uptime", format_args!("{} s", clock.time_since_boot())),
+            ("IP address", format_args!("{}", address)),
+            ("RSSI", format_args!("{}", rssi)),
+            ("Free heap", format_args!("{} bytes", esp_alloc::HEAP.free())),
+        ])
+        .render()
+}
+
+/// Size of the buffer the settings page is rendered into
+const SETTINGS_PAGE_SIZE: usize = 2048;
+
+/// Render the settings form served at `/settings`
+///
+/// The form posts back to the same route and works without JavaScript. Both
+/// forms carry the CSRF token of the session, checked by [`CsrfForm`].
+fn render_settings(
+    clock: &Clock,
+    settings: &Settings,
+    csrf_token: &CsrfToken,
+    error: Option<&str>,
+) -> Html<SETTINGS_PAGE_SIZE> {
+    let page = Page::new("Settings");
+    let page = match error {
+        Some(error) => page.error(error),
+        None => page,
+    };
+    page.form("/settings")
+        .hidden_input(session::CSRF_FIELD, csrf_token)
+        .number_input(
+            "offset_minutes",
+            "UTC offset (minutes)",
+            i64::from(*OFFSET_MINUTES_RANGE.start())..=i64::from(*OFFSET_MINUTES_RANGE.end()),
+            i64::from(clock.offset().whole_minutes()),
         )
-        .await
+        .text_input("hostname", "Hostname", MAX_HOSTNAME_SIZE, &settings.hostname())
+        .number_input(
+            "blink_period_ms",
+            "LED blink period (ms)",
+            i64::from(*BLINK_PERIOD_RANGE.start())..=i64::from(*BLINK_PERIOD_RANGE.end()),
+            i64::from(settings.blink_period_ms()),
+        )
+        .submit("Save")
+        .form("/logout")
+        .hidden_input(session::CSRF_FIELD, csrf_token)
+        .submit("Log out")
+        .render()
+}
+
+/// Size of the buffer the log viewer is rendered into
+const LOG_VIEW_PAGE_SIZE: usize = 4096;
+
+/// Number of requests rendered with the log viewer, newer ones are fetched
+/// by its script
+const LOG_VIEW_ROWS: u64 = 20;
+
+/// Script of the log viewer, appending the requests newer than the last row
+/// every 2 seconds and keeping the latest 100
+///
+/// Its own polls are left out. The script is allowed by its hash in
+/// [`SecurityHeaders::HTML`](crate::security_headers::SecurityHeaders::HTML),
+/// which must be updated with it.
+pub const LOG_VIEW_SCRIPT: &str = r#"const log=document.getElementById("log");
+let after=0;
+for(const row of log.rows)after=Math.max(after,+row.cells[0].textContent||0);
+async function poll(){
+try{
+const lines=(await (await fetch("/logs?after="+after)).text()).split("\n").filter(Boolean).reverse();
+for(const line of lines){
+const record=JSON.parse(line);
+after=Math.max(after,record.sequence);
+if(record.path=="/logs")continue;
+const row=log.insertRow();
+if(record.status>=400)row.className="error";
+for(const value of[record.sequence,record.method,record.path,record.status,record.duration_ms])row.insertCell().textContent=value;
+}
+while(log.rows.length>101)log.deleteRow(1);
+}catch(error){}
+setTimeout(poll,2000);
+}
+setTimeout(poll,2000);"#;
+
+/// Render the log viewer served at `/logs/view`
+///
+/// The latest requests are rendered as a table, oldest first, with errors
+/// highlighted, and the script appends the newer ones.
+fn render_log_view() -> Html<LOG_VIEW_PAGE_SIZE> {
+    let latest = request_log::latest_sequence();
+    let rows = (latest.saturating_sub(LOG_VIEW_ROWS) + 1..=latest)
+        .filter_map(request_log::by_sequence)
+        .filter(|record| record.path != "/logs")
+        .map(|record| {
+            let class = (record.status >= 400).then_some("error");
+            let cells: [String<{ request_log::PATH_SIZE }>; 5] = [
+                request_log::truncated(record.sequence),
+                request_log::truncated(record.method.as_str()),
+                record.path,
+                request_log::truncated(record.status),
+                request_log::truncated(record.duration_ms),
+            ];
+            (class, cells)
+        });
+    Page::new("Request log")
+        .grid("log", &["#", "Method", "Path", "Status", "ms"], rows)
+        .script(LOG_VIEW_SCRIPT)
+        .render()
+}
+
+/// Size of the buffer the login page is rendered into
+const LOGIN_PAGE_SIZE: usize = 1024;
+
+/// Page shown after logging in
+const LOGIN_REDIRECT: &str = "/settings";
+
+/// Render the login form served at `/login`
+fn render_login(error: Option<&str>) -> Html<LOGIN_PAGE_SIZE> {
+    let page = Page::new("Login");
+    let page = match error {
+        Some(error) => page.error(error),
+        None => page,
+    };
+    page.form("/login")
+        .password_input("password", "Password")
+        .submit("Log in")
+        .render()
+}
+
+/// Size of the buffer the provisioning pages are rendered into
+const PROVISIONING_PAGE_SIZE: usize = 2048;
+
+/// Delay before restarting with provisioned credentials, leaving time to
+/// show the confirmation
+const PROVISIONING_RESTART_DELAY: Duration = Duration::from_secs(3);
+
+/// Render the provisioning form served at [`provisioning::PROVISIONING_PATH`]
+///
+/// The networks found by the scan are listed, or typed in when none was
+/// found. The password field is always left empty.
+fn render_provisioning(error: Option<&str>) -> Html<PROVISIONING_PAGE_SIZE> {
+    let page = Page::new("Wi-Fi setup");
+    let page = match error {
+        Some(error) => page.error(error),
+        None => page,
+    };
+    let networks = provisioning::networks();
+    let page = page.form(provisioning::PROVISIONING_PATH);
+    let page = if networks.is_empty() {
+        page.text_input("ssid", "Network", provisioning::MAX_SSID_SIZE, "")
+    } else {
+        page.select("ssid", "Network", &networks)
+    };
+    page.password_input("password", "Password")
+        .submit("Connect")
+        .render()
+}
+
+/// Render the confirmation of provisioned credentials, without the password
+fn render_provisioned(ssid: &str) -> Html<PROVISIONING_PAGE_SIZE> {
+    let mut message = String::<128>::new();
+    // Cannot fail, the SSID is at most 32 bytes
+    let _ = write!(message, "Credentials saved. The device restarts to join {}.", ssid);
+    Page::new("Wi-Fi setup")
+        .para(message)
+        .para("Reconnect to that network to reach the device.")
+        .render()
+}
+
+/// A `303 See Other` redirect starting or ending a session
+struct SessionRedirect {
+    location: &'static str,
+    cookie: SetCookie,
+}
+
+impl IntoResponse for SessionRedirect {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: picoserve::response::Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let response = picoserve::response::Response::new(StatusCode::SEE_OTHER, "")
+            .with_header("Location", self.location)
+            .with_header("Set-Cookie", self.cookie)
+            .with_header("Cache-Control", "no-store");
+        response_writer.write_response(connection, response).await
+    }
+}
+
+/// Validated changes submitted from the settings form
+#[derive(Debug)]
+pub struct SettingsUpdate {
+    /// UTC offset
+    pub offset: UtcOffset,
+
+    /// Name of the device on the network
+    pub hostname: String<MAX_HOSTNAME_SIZE>,
+
+    /// Blink period of the LED in milliseconds
+    pub blink_period_ms: u32,
+}
+
+impl SettingsUpdate {
+    /// Validate the fields of a submitted form
+    pub fn parse(form: &QueryParams) -> Result<Self, &'static str> {
+        let offset_minutes: i16 = form
+            .get("offset_minutes")
+            .and_then(|offset| offset.trim().parse().ok())
+            .filter(|offset| OFFSET_MINUTES_RANGE.contains(offset))
+            .ok_or("The UTC offset must be between -720 and 840 minutes")?;
+        let offset = UtcOffset::from_whole_seconds(i32::from(offset_minutes) * 60)
+            .map_err(|_| "The UTC offset is out of range")?;
+
+        let hostname = form
+            .get("hostname")
+            .map(str::trim)
+            .filter(|hostname| is_valid_hostname(hostname))
+            .and_then(|hostname| String::try_from(hostname).ok())
+            .ok_or("The hostname must be 1 to 32 letters, digits or inner hyphens")?;
+
+        let blink_period_ms = form
+            .get("blink_period_ms")
+            .and_then(|period| period.trim().parse().ok())
+            .filter(|period| BLINK_PERIOD_RANGE.contains(period))
+            .ok_or("The blink period must be between 50 and 10000 ms")?;
+
+        Ok(Self {
+            offset,
+            hostname,
+            blink_period_ms,
+        })
     }
-}
\ No newline at end of file
+
+    /// Apply the changes, restarting a blinking LED with the new period
+    pub(crate) fn apply(self, clock: &Clock, settings: &Settings, led: &Led) {
+        clock.set_offset(self.offset);
+        settings.set_hostname(self.hostname);
+        settings.set_blink_period_ms(self.blink_period_ms);
+        if led.state().is_some_and(|state| state.period_ms.is_some()) {
+            led.set_mode(LedMode::Blink { period_ms: self.blink_period_ms });
+        }
+    }
+}
+
+/// Return the clock if it is synchronized
+///
+/// The fallback clock counts from the epoch at boot, and devices using this
+/// one as their time source must not take its time for real.
+fn synchronized(clock: &Clock) -> Result<&Clock, ApiError> {
+    if clock.is_synchronized() {
+        Ok(clock)
+    } else {
+        Err(ApiError::service_unavailable("clock_unsynced").with_message("The clock is not synchronized"))
+    }
+}
+
+/// Format a timestamp as the plain-text body of `/time/epoch`, like the
+/// time service queried by [`crate::http`]
+fn epoch_text(epoch: u64) -> String<20> {
+    let mut text = String::new();
+    // Cannot fail, a u64 has at most 20 digits
+    let _ = write!(text, "{}", epoch);
+    text
+}
+
+/// Parse a UTC offset formatted as `+HHMM` or `+HH:MM`
+pub fn parse_offset(offset: &str) -> Option<UtcOffset> {
+    let (sign, digits) = match offset.as_bytes().first()? {
+        b'+' => (1, &offset[1..]),
+        b'-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    let digits = digits.strip_prefix(' ').unwrap_or(digits);
+    let (hours, minutes) = match digits.len() {
+        4 => (&digits[..2], &digits[2..]),
+        5 if digits.as_bytes()[2] == b':' => (&digits[..2], &digits[3..]),
+        _ => return None,
+    };
+    let hours: i8 = hours.parse().ok()?;
+    let minutes: i8 = minutes.parse().ok()?;
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+/// JSON body returned by the `/timezone` route
+#[derive(Serialize)]
+pub struct TimezoneResponse {
+    /// Offset from UTC in minutes
+    pub offset_minutes: i16,
+
+    /// Offset from UTC formatted as `+HH:MM`
+    pub offset: String<8>,
+}
+
+impl From<UtcOffset> for TimezoneResponse {
+    fn from(utc_offset: UtcOffset) -> Self {
+        let mut offset = String::new();
+        write_offset(&mut offset, utc_offset).unwrap();
+
+        #[expect(clippy::cast_possible_truncation, reason = "Offsets are within a day")]
+        let offset_minutes = (utc_offset.whole_seconds() / 60) as i16;
+
+        Self { offset_minutes, offset }
+    }
+}
+
+/// JSON body returned by `POST /admin/token/rotate`
+#[derive(Serialize)]
+pub struct TokenResponse {
+    /// The new API token
+    pub token: String<{ 2 * TOKEN_SIZE }>,
+}
+
+/// Query parameters of the `/logs` route
+#[derive(Deserialize)]
+pub struct LogsQuery {
+    /// Maximal number of records to return
+    pub limit: Option<usize>,
+
+    /// Only return records with a greater sequence number
+    pub after: Option<u64>,
+
+    /// Only return responses of a status class, e.g. `4xx`
+    pub status: Option<StatusClass>,
+
+    /// Only return paths starting with a prefix
+    pub path_prefix: Option<String<{ request_log::PATH_SIZE }>>,
+}
+
+/// Query parameters of the `/random` route
+#[derive(Deserialize)]
+pub struct RandomQuery {
+    /// Number of bytes to generate, at most [`MAX_RANDOM_BYTES`]
+    pub bytes: Option<usize>,
+
+    /// Encoding of the bytes
+    pub format: Option<RandomFormat>,
+}
+
+/// Query parameters of the `/restart` route
+#[derive(Deserialize)]
+pub struct RestartQuery {
+    /// Seconds to wait before the reset
+    pub delay: Option<u64>,
+}
+
+/// Query parameters of the `/poll` route
+#[derive(Deserialize)]
+pub struct PollQuery {
+    /// Last sequence number seen by the client, the current state is
+    /// returned immediately without it
+    pub since: Option<u32>,
+}
+
+/// JSON body returned by the `/poll` route
+#[derive(Serialize)]
+pub struct PollResponse {
+    /// Sequence number of this state, to pass as `since` to the next poll
+    pub sequence: u32,
+
+    /// The GPIO outputs
+    pub gpio: heapless::Vec<GpioResponse, MAX_GPIO_OUTPUTS>,
+
+    /// Name of the device on the network
+    pub hostname: String<MAX_HOSTNAME_SIZE>,
+
+    /// Blink period of the LED in milliseconds
+    pub blink_period_ms: u32,
+
+    /// SSID of the network, if associated
+    pub ssid: Option<String<{ provisioning::MAX_SSID_SIZE }>>,
+}
+
+impl PollResponse {
+    /// Read the current state
+    fn current(sequence: u32, gpio: &GpioOutputs, settings: &Settings) -> Self {
+        Self {
+            sequence,
+            gpio: gpio
+                .levels()
+                .into_iter()
+                .map(|(pin, high)| GpioResponse { pin, high })
+                .collect(),
+            hostname: settings.hostname(),
+            blink_period_ms: settings.blink_period_ms(),
+            ssid: wifi::status().map(|status| status.ssid),
+        }
+    }
+}
+
+/// JSON body returned by `POST /restart`
+#[derive(Serialize)]
+pub struct RestartResponse {
+    /// Milliseconds until the reset
+    pub restart_in_ms: u64,
+}
+
+/// JSON body returned by the `/loglevel` routes
+#[derive(Serialize)]
+pub struct LogLevelResponse {
+    /// Level of the quietest log lines kept
+    pub level: LogLevel,
+}
+
+/// JSON body returned by the `/uptime` route
+#[derive(Serialize)]
+pub struct UptimeResponse {
+    /// Seconds since boot
+    pub uptime: u64,
+
+    /// Number of boots since power-up
+    pub boot_count: u32,
+
+    /// Reason of the last reset, e.g. `ChipPowerOn` or `CoreRtcWdt`
+    pub reset_reason: Option<String<32>>,
+}
+
+/// Maximal number of DNS servers reported by `/wifi`
+const MAX_DNS_SERVERS: usize = 3;
+
+/// JSON body returned by the `/whoami` route
+#[derive(Serialize)]
+pub struct WhoamiResponse {
+    /// IP address of the client
+    pub ip: String<39>,
+
+    /// TCP port of the client
+    pub port: u16,
+
+    /// The `User-Agent` header of the request, if any
+    pub user_agent: Option<String<USER_AGENT_SIZE>>,
+}
+
+impl WhoamiResponse {
+    /// Describe the client of a request
+    fn new(endpoint: IpEndpoint, user_agent: Option<String<USER_AGENT_SIZE>>) -> Self {
+        let mut ip = String::new();
+        write!(ip, "{}", endpoint.addr).unwrap();
+        Self { ip, port: endpoint.port, user_agent }
+    }
+}
+
+/// JSON body returned by the `/tasks` route
+#[derive(Serialize)]
+pub struct TasksResponse {
+    /// The registered tasks, in registration order
+    pub tasks: heapless::Vec<TaskStatus, MAX_TASKS>,
+}
+
+/// JSON body returned by the `/wifi` route
+#[derive(Serialize)]
+pub struct WifiResponse {
+    /// SSID of the network, if associated
+    pub ssid: Option<String<{ provisioning::MAX_SSID_SIZE }>>,
+
+    /// BSSID of the access point as `aa:bb:cc:dd:ee:ff`
+    pub bssid: Option<String<17>>,
+
+    /// Channel of the access point
+    pub channel: Option<u8>,
+
+    /// Last sampled RSSI in dBm
+    pub rssi: Option<i32>,
+
+    /// RSSI in dBm sampled every 10 s while associated, oldest first
+    pub rssi_history: heapless::Vec<i32, { wifi::SIGNAL_HISTORY_SIZE }>,
+
+    /// Whether the signal is below the low threshold, until it recovers
+    pub signal_low: bool,
+
+    /// Leased IPv4 address
+    pub address: Option<String<15>>,
+
+    /// Netmask of the leased address
+    pub netmask: Option<String<15>>,
+
+    /// Default gateway
+    pub gateway: Option<String<15>>,
+
+    /// DNS servers
+    pub dns_servers: heapless::Vec<String<15>, MAX_DNS_SERVERS>,
+
+    /// IPv6 address with its prefix length, link-local until a router
+    /// advertises a prefix
+    pub ipv6_address: Option<String<43>>,
+
+    /// IPv6 default gateway, the link-local address of the router
+    pub ipv6_gateway: Option<String<39>>,
+
+    /// Seconds since the association
+    pub connected_for: Option<u64>,
+
+    /// Attempts to reconnect since boot
+    pub reconnect_attempts: u32,
+
+    /// Times the station lost its address since boot
+    pub link_losses: u32,
+
+    /// Attempts to reconnect in a row, reset by a stable association
+    pub backoff_attempts: u32,
+
+    /// Delay before the next attempt to reconnect in milliseconds, without
+    /// jitter
+    pub backoff_delay_ms: u64,
+
+    /// Milliseconds until the next attempt to reconnect, if waiting
+    pub next_attempt_in_ms: Option<u64>,
+
+    /// Power saving applied to the station
+    pub power_save: wifi::PowerSave,
+
+    /// Beacons between two wake-ups in maximum power saving
+    pub listen_interval: u16,
+
+    /// Longest delay added to the first packet after the station went idle,
+    /// in milliseconds
+    pub power_save_max_delay_ms: u64,
+}
+
+impl WifiResponse {
+    /// Collect the connection details of the station interface
+    fn current(stack: Stack<'static>) -> Self {
+        let status = wifi::status();
+        let config = stack.config_v4();
+        let config_v6 = stack.config_v6();
+        let backoff = wifi::backoff();
+        Self {
+            ssid: status.as_ref().map(|status| status.ssid.clone()),
+            bssid: status.as_ref().and_then(|status| status.bssid).map(|bssid| {
+                let mut text = String::new();
+                for (index, byte) in bssid.iter().enumerate() {
+                    let separator = if index == 0 { "" } else { ":" };
+                    write!(text, "{separator}{byte:02x}").unwrap();
+                }
+                text
+            }),
+            channel: status.as_ref().and_then(|status| status.channel),
+            rssi: wifi::rssi(),
+            rssi_history: wifi::SIGNAL.samples(),
+            signal_low: wifi::SIGNAL.is_low(),
+            address: config.as_ref().map(|config| request_log::truncated(config.address.address())),
+            netmask: config.as_ref().map(|config| request_log::truncated(config.address.netmask())),
+            gateway: config.as_ref().and_then(|config| config.gateway).map(request_log::truncated),
+            dns_servers: config
+                .iter()
+                .flat_map(|config| config.dns_servers.iter())
+                .map(|&server| request_log::truncated(server))
+                .take(MAX_DNS_SERVERS)
+                .collect(),
+            ipv6_address: config_v6.as_ref().map(|config| request_log::truncated(config.address)),
+            ipv6_gateway: config_v6.as_ref().and_then(|config| config.gateway).map(request_log::truncated),
+            connected_for: status.as_ref().map(|status| status.connected_since.elapsed().as_secs()),
+            reconnect_attempts: wifi::reconnect_attempts(),
+            link_losses: wifi::link_losses(),
+            backoff_attempts: backoff.attempts(),
+            backoff_delay_ms: backoff.delay().as_millis(),
+            next_attempt_in_ms: wifi::next_attempt_in().map(|delay| delay.as_millis()),
+            power_save: wifi::power_save(),
+            listen_interval: wifi::LISTEN_INTERVAL,
+            power_save_max_delay_ms: wifi::power_save().max_wake_delay().as_millis(),
+        }
+    }
+}
+
+/// Seconds a client is asked to wait before retrying a scan the station
+/// could not run while connecting
+const SCAN_RETRY_AFTER_S: u64 = 5;
+
+/// JSON body returned by the `/wifi/scan` route
+#[derive(Serialize)]
+pub struct WifiScanResponse {
+    /// Nearby networks, strongest first
+    pub networks: wifi::ScanResults,
+}
+
+impl WifiScanResponse {
+    /// Scan for networks, or reuse the results of a recent scan
+    async fn scan() -> Result<Self, ApiError> {
+        match wifi::scan().await {
+            Ok(networks) => Ok(Self { networks }),
+            Err(wifi::ScanError::Unavailable) => Err(ApiError::service_unavailable("scan_unavailable")
+                .with_message("No scan is possible while connecting")
+                .with_retry_after(SCAN_RETRY_AFTER_S)),
+            Err(wifi::ScanError::Failed(error)) => {
+                crate::log!(error: "Failed to scan for networks: {:?}", error);
+                Err(ApiError::internal("scan_failed"))
+            }
+        }
+    }
+}
+
+/// A stored network returned by the `/wifi/networks` routes, without its
+/// password
+#[derive(Serialize)]
+pub struct StoredNetworkResponse {
+    /// Position in the priority order, for `DELETE /wifi/networks/{index}`
+    pub index: u8,
+
+    /// SSID of the network
+    pub ssid: String<{ provisioning::MAX_SSID_SIZE }>,
+
+    /// Whether the network has a password
+    pub secured: bool,
+}
+
+/// JSON body returned by the `/wifi/networks` routes
+#[derive(Serialize)]
+pub struct StoredNetworksResponse {
+    /// Stored networks, highest priority first
+    pub networks: heapless::Vec<StoredNetworkResponse, { provisioning::MAX_STORED_NETWORKS }>,
+}
+
+impl StoredNetworksResponse {
+    fn new(networks: &StoredNetworks) -> Self {
+        Self {
+            networks: networks
+                .as_slice()
+                .iter()
+                .zip(0..)
+                .map(|(network, index)| StoredNetworkResponse {
+                    index,
+                    ssid: network.ssid.clone(),
+                    secured: !network.password.is_empty(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// JSON body of `POST /wifi/networks`
+#[derive(Deserialize)]
+pub struct NetworkRequest {
+    /// SSID of the network, a stored network with the same SSID is updated
+    pub ssid: String<{ provisioning::MAX_SSID_SIZE }>,
+
+    /// Password of the network, empty or missing for an open network
+    #[serde(default)]
+    pub password: String<{ provisioning::MAX_PASSWORD_SIZE }>,
+}
+
+/// JSON body of `PUT /wifi/networks`
+#[derive(Deserialize)]
+pub struct NetworkOrderRequest {
+    /// Every stored SSID once, highest priority first
+    pub ssids: heapless::Vec<String<{ provisioning::MAX_SSID_SIZE }>, { provisioning::MAX_STORED_NETWORKS }>,
+}
+
+/// JSON body returned by the `/health` route
+#[derive(Serialize)]
+pub struct HealthResponse {
+    /// Seconds since boot
+    pub uptime: u64,
+
+    /// Whether an IPv4 address is currently leased or a global IPv6
+    /// address configured
+    pub link_up: bool,
+
+    /// Leased IPv4 address
+    pub ipv4_address: Option<String<15>>,
+
+    /// IPv6 address, link-local until a router advertises a prefix
+    pub ipv6_address: Option<String<39>>,
+
+    /// Whether the device joined a network or runs the provisioning access
+    /// point
+    pub wifi_mode: WifiMode,
+
+    /// Whether the clock was synchronized from a server
+    pub clock_synchronized: bool,
+
+    /// Free heap in bytes
+    pub heap_free: usize,
+
+    /// Used heap in bytes
+    pub heap_used: usize,
+
+    /// Whether the web server is paused
+    pub paused: bool,
+
+    /// Number of connections being served
+    pub active_connections: u32,
+}
+
+impl HealthResponse {
+    /// Return whether the device is fully operational
+    pub fn is_healthy(&self) -> bool {
+        self.link_up && self.clock_synchronized && !self.paused
+    }
+}
+
+/// JSON body returned for unknown paths, an [`ApiError`] with the path
+#[derive(Serialize)]
+pub struct NotFoundResponse {
+    /// Always `"not_found"`
+    pub code: &'static str,
+
+    /// Always `"Not found"`
+    pub message: &'static str,
+
+    /// The requested path, possibly truncated
+    pub path: String<{ request_log::PATH_SIZE }>,
+}
+
+impl NotFoundResponse {
+    /// Report an unknown path
+    pub fn new(path: impl core::fmt::Display) -> Self {
+        Self {
+            code: "not_found",
+            message: "Not found",
+            path: request_log::truncated(path),
+        }
+    }
+}
+
+/// An error response with the JSON body `{"code": "...", "message": "..."}`
+///
+/// The code is a stable, machine-readable `snake_case` identifier; the
+/// message is for humans and may change. Every non-2xx response of the API
+/// has this body, including extractor rejections and the responses of the
+/// layers.
+#[derive(Clone, Copy, Serialize)]
+pub struct ApiError {
+    /// Status code of the response
+    #[serde(skip)]
+    pub status_code: StatusCode,
+
+    /// Machine-readable error code, e.g. `clock_unsynced`
+    pub code: &'static str,
+
+    /// Human readable description of the error
+    pub message: &'static str,
+
+    /// Seconds after which the request is expected to succeed, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_s: Option<u64>,
+}
+
+impl ApiError {
+    /// Create an error
+    ///
+    /// The shorthands below use the reason phrase of the status as message,
+    /// replace it with [`ApiError::with_message`].
+    pub const fn new(status_code: StatusCode, code: &'static str, message: &'static str) -> Self {
+        Self {
+            status_code,
+            code,
+            message,
+            retry_after_s: None,
+        }
+    }
+
+    /// Replace the message
+    pub const fn with_message(self, message: &'static str) -> Self {
+        Self { message, ..self }
+    }
+
+    /// Tell the client when to retry
+    pub const fn with_retry_after(self, seconds: u64) -> Self {
+        Self {
+            retry_after_s: Some(seconds),
+            ..self
+        }
+    }
+
+    /// `400 Bad Request`
+    pub const fn bad_request(code: &'static str) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, "Bad request")
+    }
+
+    /// `401 Unauthorized`
+    pub const fn unauthorized(code: &'static str) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, code, "Unauthorized")
+    }
+
+    /// `404 Not Found`
+    pub const fn not_found(code: &'static str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, code, "Not found")
+    }
+
+    /// `405 Method Not Allowed`
+    pub const fn method_not_allowed() -> Self {
+        Self::new(StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", "Method not allowed")
+    }
+
+    /// `413 Payload Too Large`
+    pub const fn payload_too_large(code: &'static str) -> Self {
+        Self::new(StatusCode::PAYLOAD_TOO_LARGE, code, "Payload too large")
+    }
+
+    /// `415 Unsupported Media Type`
+    pub const fn unsupported_media_type(code: &'static str) -> Self {
+        Self::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, code, "Unsupported media type")
+    }
+
+    /// `500 Internal Server Error`
+    pub const fn internal(code: &'static str) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, code, "Internal server error")
+    }
+
+    /// `503 Service Unavailable`
+    pub const fn service_unavailable(code: &'static str) -> Self {
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, code, "Service unavailable")
+    }
+}
+
+impl IntoResponse for ApiError {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: picoserve::response::Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        (self.status_code, Json(self))
+            .write_to(connection, response_writer)
+            .await
+    }
+}
+
+/// Write a time as ISO 8601, using `Z` for UTC
+///
+/// The output is also valid RFC 3339.
+pub(crate) fn write_iso8601(out: &mut impl Write, time: &OffsetDateTime) -> core::fmt::Result {
+    write!(
+        out,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+    )?;
+    if time.offset().is_utc() {
+        out.write_char('Z')
+    } else {
+        write_offset(out, time.offset())
+    }
+}
+
+/// Write a UTC offset as `+HH:MM`
+fn write_offset(out: &mut impl Write, offset: UtcOffset) -> core::fmt::Result {
+    let sign = if offset.is_negative() { '-' } else { '+' };
+    let (hours, minutes, _) = offset.as_hms();
+    write!(out, "{}{:02}:{:02}", sign, hours.unsigned_abs(), minutes.unsigned_abs())
+}
+
+/// An extractor for getting the API token store from the app state
+pub struct ApiTokenExtractor(pub &'static ApiTokenStore);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for ApiTokenExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.api_token))
+    }
+}
+
+/// An extractor for getting the network stack from the app state
+pub struct StackExtractor(pub Stack<'static>);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for StackExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.stack))
+    }
+}
+
+/// An extractor for getting the output pins from the app state
+pub struct GpioExtractor(pub &'static GpioOutputs);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for GpioExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.gpio))
+    }
+}
+
+/// An extractor for getting the LED from the app state
+pub struct LedExtractor(pub &'static Led);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for LedExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.led))
+    }
+}
+
+/// An extractor for getting the ADC readings from the app state
+pub struct AdcExtractor(pub &'static AdcReadings);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for AdcExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.adc))
+    }
+}
+
+/// An extractor for getting the settings from the app state
+pub struct SettingsExtractor(pub &'static Settings);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for SettingsExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.settings))
+    }
+}
+
+/// An extractor for getting the key-value store from the app state
+///
+/// Requests are rejected with 503 if there is no store.
+pub struct StorageExtractor(pub &'static Storage);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for StorageExtractor {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        state
+            .storage
+            .map(Self)
+            .ok_or(ApiError::service_unavailable("no_storage").with_message("No key-value store"))
+    }
+}
+
+/// An extractor for getting the flash file store from the app state
+///
+/// Requests are rejected with 503 if there is no store.
+pub struct FilesExtractor(pub &'static FileStore);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for FilesExtractor {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        state.files.map(Self).ok_or(NO_FILE_STORE)
+    }
+}
+
+/// The error returned when there is no flash file store
+const NO_FILE_STORE: ApiError = ApiError::service_unavailable("no_file_store").with_message("No file store");
+
+/// An extractor for getting the RTC from the app state, if any
+pub struct RtcExtractor(pub Option<&'static Rtc<'static>>);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for RtcExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.rtc))
+    }
+}
+
+/// An extractor for getting the admin credentials from the app state
+pub struct CredentialsExtractor(pub Credentials);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for CredentialsExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.credentials.clone()))
+    }
+}
+
+/// An extractor for getting the session table from the app state
+pub struct SessionsExtractor(pub &'static SessionStore);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for SessionsExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.sessions))
+    }
+}
+
+/// An extractor for the session identifier in the `Cookie` header
+///
+/// The identifier is `None` if there is no session cookie or it is
+/// malformed. Whether the session exists is not checked.
+pub struct SessionCookie(pub Option<SessionId>);
+
+impl SessionCookie {
+    /// Parse the session cookie of a request
+    fn parse(request_parts: &picoserve::request::RequestParts<'_>) -> Self {
+        let id = request_parts
+            .headers()
+            .get("Cookie")
+            .and_then(|value| value.as_str().ok())
+            .and_then(|header| session::find_cookie(header, session::COOKIE_NAME))
+            .and_then(SessionId::parse);
+        Self(id)
+    }
+}
+
+impl<'r, State> picoserve::extract::FromRequestParts<'r, State> for SessionCookie {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        _state: &'r State,
+        request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self::parse(request_parts))
+    }
+}
+
+/// An extractor for the CSRF token of the session of a request
+///
+/// Requests without a live session are redirected to `/login`.
+pub struct CsrfTokenExtractor(pub CsrfToken);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for CsrfTokenExtractor {
+    type Rejection = Redirect;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        SessionCookie::parse(request_parts)
+            .0
+            .and_then(|id| state.sessions.csrf_token(&id))
+            .map(Self)
+            .ok_or(Redirect::see_other("/login"))
+    }
+}
+
+/// An extractor for getting the server control handle from the app state
+pub struct ControlExtractor(pub &'static ServerControl);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for ControlExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.control))
+    }
+}
+
+/// An extractor for getting the metrics from the app state
+pub struct MetricsExtractor(pub &'static Metrics);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for MetricsExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.metrics))
+    }
+}
+
+/// An extractor for getting the request statistics from the app state
+pub struct StatsExtractor(pub &'static Stats);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for StatsExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.stats))
+    }
+}
+
+/// An extractor for getting the schedule from the app state
+pub struct ScheduleExtractor(pub &'static Schedule);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for ScheduleExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.schedule))
+    }
+}
+
+/// An extractor for getting the configuration handle from the app state
+pub struct ConfigExtractor(pub ConfigHandle);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for ConfigExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.config()))
+    }
+}
+
+/// An extractor for getting the shared random number generator from the app
+/// state
+pub struct RngExtractor(pub &'static SharedRng);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for RngExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.rng))
+    }
+}
+
+/// An extractor for getting the webhook registry from the app state
+pub struct WebhooksExtractor(pub &'static Webhooks);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for WebhooksExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.webhooks))
+    }
+}
+
+/// An extractor for getting the traffic counters from the app state
+pub struct NetStatsExtractor(pub &'static NetStats);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for NetStatsExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.netstats))
+    }
+}
+
+/// An extractor for getting the response cache from the app state
+pub struct CacheExtractor(pub &'static ResponseCache);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for CacheExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.cache))
+    }
+}
+
+/// An extractor for getting the ID of the request, for log lines
+pub struct RequestIdExtractor(pub RequestId);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for RequestIdExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(RequestId::current(state.web_task)))
+    }
+}
+
+/// An extractor for getting the address and port of the client
+///
+/// Every web task sets the endpoint of the connection it serves, so
+/// requests are only rejected with 500 if the socket lost it.
+pub struct ClientAddr(pub IpEndpoint);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for ClientAddr {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        state
+            .remote_endpoint
+            .map(Self)
+            .ok_or(ApiError::internal("no_client_address").with_message("Client address unknown"))
+    }
+}
+
+/// Maximal length of a kept `User-Agent` header value
+const USER_AGENT_SIZE: usize = 128;
+
+/// An extractor for the `User-Agent` request header
+///
+/// Longer values are cut at [`USER_AGENT_SIZE`] bytes.
+pub struct UserAgent(pub Option<String<USER_AGENT_SIZE>>);
+
+impl<'r, State> picoserve::extract::FromRequestParts<'r, State> for UserAgent {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        _state: &'r State,
+        request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        let user_agent = request_parts
+            .headers()
+            .get("User-Agent")
+            .and_then(|value| value.as_str().ok())
+            .map(|value| {
+                let mut end = value.len().min(USER_AGENT_SIZE);
+                while !value.is_char_boundary(end) {
+                    end -= 1;
+                }
+                String::try_from(&value[..end]).unwrap_or_default()
+            });
+        Ok(Self(user_agent))
+    }
+}
+
+/// An extractor for getting whether the device acts as a captive portal
+pub struct CaptivePortalExtractor(pub bool);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for CaptivePortalExtractor {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        _request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(state.captive_portal))
+    }
+}
+
+/// An extractor for getting the key-value store the provisioned Wi-Fi
+/// credentials are saved in
+///
+/// Outside of provisioning the provisioning form is an unknown path like
+/// any other, so requests are rejected with 404.
+pub struct ProvisioningExtractor(pub Option<&'static Storage>);
+
+impl<'r> picoserve::extract::FromRequestParts<'r, AppState> for ProvisioningExtractor {
+    type Rejection = (StatusCode, Json<NotFoundResponse>);
+
+    async fn from_request_parts(
+        state: &'r AppState,
+        request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        if provisioning::is_active() {
+            Ok(Self(state.storage))
+        } else {
+            Err((StatusCode::NOT_FOUND, Json(NotFoundResponse::new(request_parts.path()))))
+        }
+    }
+}
+
+/// Answer a connectivity check of `path`
+///
+/// Outside of provisioning the probes are unknown paths like any other.
+fn captive_probe(captive_portal: bool, path: &str) -> Result<Redirect, (StatusCode, Json<NotFoundResponse>)> {
+    if captive_portal {
+        Ok(Redirect::found(captive::PROVISIONING_PAGE))
+    } else {
+        Err((StatusCode::NOT_FOUND, Json(NotFoundResponse::new(path))))
+    }
+}
+
+pub struct Application;
+
+impl AppWithStateBuilder for Application {
+    type State = AppState;
+    type PathRouter = impl routing::PathRouter<AppState>;
+
+    fn build_app(self) -> picoserve::Router<Self::PathRouter, AppState> {
+        let router = picoserve::Router::from_service(NotFound)
+            .route("/", routing::get(|ClockExtractor(clock), StackExtractor(stack)| async move {
+                Cached::new(CachePolicy::NO_CACHE, render_dashboard(&clock, stack))
+            }))
+            .route(("/static", routing::parse_path_segment::<Asset>()), routing::get(|asset: Asset, AcceptsGzip(gzip), if_none_match: IfNoneMatch| async move {
+                asset.encoded(gzip, &if_none_match)
+            }))
+            .route("/favicon.ico", routing::get(|AcceptsGzip(gzip), if_none_match: IfNoneMatch| async move {
+                Asset::find("favicon.ico").unwrap().encoded(gzip, &if_none_match)
+            }))
+            .route("/version", routing::get(|Accept(preference), if_none_match: IfNoneMatch| async move {
+                if preference.wants_json(false) {
+                    let version = VersionResponse { version: env!("CARGO_PKG_VERSION") };
+                    Conditional::new(VERSION_JSON_ETAG, &if_none_match, Negotiated::Json(version))
+                } else {
+                    let mut version_string = String::<64>::new();
+                    write!(version_string, "Version: {}", env!("CARGO_PKG_VERSION")).unwrap();
+                    Conditional::new(VERSION_ETAG, &if_none_match, Negotiated::Text(version_string))
+                }
+            }))
+            .route("/old-time", routing::get(|| async { Redirect::permanent("/time") }))
+            .route("/time", routing::get(|ClockExtractor(clock), Accept(preference), query: QueryParams| async move {
+                let format = match query.get("fmt") {
+                    None if preference.wants_json(true) => TimeFormat::Json,
+                    None => TimeFormat::Iso,
+                    Some(format) => format.parse().map_err(|()| {
+                        ApiError::bad_request("invalid_format").with_message("fmt must be one of unix, iso or human")
+                    })?,
+                };
+                let offset = query
+                    .get("offset")
+                    .map(|offset| parse_offset(offset).ok_or(
+                        ApiError::bad_request("invalid_offset").with_message("offset must be formatted as +HHMM or +HH:MM"),
+                    ))
+                    .transpose()?;
+
+                let time = clock.now().map_err(|_| {
+                    ApiError::service_unavailable("clock_unsynced").with_message("The clock is not synchronized")
+                })?;
+                // The override only affects this response, not the clock
+                let time = match offset {
+                    Some(offset) => time.checked_to_offset(offset).ok_or(
+                        ApiError::bad_request("offset_out_of_range").with_message("Time is out of range in this offset"),
+                    )?,
+                    None => time,
+                };
+
+                Ok(format.render(time))
+            }))
+            .route("/id", routing::get(|| async move {
+                DeviceIdentity::current()
+                    .map(Json)
+                    .ok_or(ApiError::service_unavailable("wifi_not_started"))
+            }))
+            .route("/random", routing::get(|Query(query): Query<RandomQuery>, RngExtractor(rng)| async move {
+                let size = query.bytes.unwrap_or(DEFAULT_RANDOM_BYTES);
+                if size == 0 || size > MAX_RANDOM_BYTES {
+                    return Err(ApiError::bad_request("invalid_size"));
+                }
+                let mut bytes = [0_u8; MAX_RANDOM_BYTES];
+                rng.lock(|rng| rng.borrow_mut().fill_bytes(&mut bytes[..size]));
+                random::encode_random(&bytes[..size], query.format.unwrap_or_default())
+                    .ok_or(ApiError::internal("encoding_failed"))
+            }))
+            .route("/time/epoch", routing::get(|ClockExtractor(clock)| async move {
+                synchronized(&clock).map(|clock| epoch_text(clock.now_as_epoch()))
+            }))
+            .route("/time/epoch_ms", routing::get(|ClockExtractor(clock)| async move {
+                synchronized(&clock).map(|clock| epoch_text(clock.now_as_epoch_ms()))
+            }))
+            .route("/ws/time", routing::get(|ClockExtractor(clock), upgrade: ws::WebSocketUpgrade| async move {
+                upgrade.on_upgrade(TimeTicker { clock })
+            }))
+            .route("/ws/logs", routing::get(|upgrade: ws::WebSocketUpgrade| async move {
+                upgrade.on_upgrade(LogTail)
+            }))
+            .route("/ws/time/demo", routing::get_service(File::html(include_str!("../assets/ws-time.html"))))
+            .route("/events", routing::get(|ClockExtractor(clock), LastEventId(last_event_id)| async move {
+                EventStream::new(StatusEvents { clock }, last_event_id)
+            }))
+            .route("/events/rssi", routing::get(|LastEventId(last_event_id)| async move {
+                let receiver = wifi::rssi_receiver()
+                    .ok_or(ApiError::service_unavailable("too_many_streams").with_message("Too many RSSI streams"))?;
+                Ok::<_, ApiError>(EventStream::new(RssiEvents { receiver }, last_event_id))
+            }))
+            .route("/time-since-boot", routing::get(|ClockExtractor(clock)| async move {
+                let seconds = clock.time_since_boot();
+                let mut response = String::<128>::new();
+                write!(response, "Time since boot: {} seconds", seconds).unwrap();
+                response
+            }))
+            .route("/time-since-rtc-update", routing::get(|ClockExtractor(clock)| async move {
+                match clock.time_since_rtc_update() {
+                    Some(seconds) => {
+                        let mut response = String::<128>::new();
+                        write!(response, "Time since RTC update: {} seconds", seconds).unwrap();
+                        response
+                    }
+                    None => {
+                        let mut response = String::<128>::new();
+                        write!(response, "No RTC update has been performed").unwrap();
+                        response
+                    }
+                }
+            }))
+            .route("/api/status", routing::get(|| async { Redirect::permanent("/api/v1/status") }))
+            .nest(API_V1_PREFIX, api::api_v1())
+            .route("/logs", routing::get(|compression: Compression, Query(query): Query<LogsQuery>| async move {
+                let filter = RecordFilter {
+                    after: query.after.unwrap_or(0),
+                    status: query.status,
+                    path_prefix: query.path_prefix,
+                };
+                RequestLogLines::new(query.limit.unwrap_or(request_log::REQUEST_LOG_SIZE), filter)
+                    .compressed(compression)
+            }))
+            .route("/logs/view", routing::get(|| async move { render_log_view() }))
+            .route("/loglevel", routing::get(|| async move {
+                Json(LogLevelResponse { level: log_sink::level() })
+            }).put(|Bytes(body): Bytes<16>| async move {
+                let level = core::str::from_utf8(&body)
+                    .ok()
+                    .and_then(|name| name.trim().parse::<LogLevel>().ok())
+                    .ok_or(ApiError::bad_request("invalid_level").with_message("Expected off, error, warn, info, debug or trace"))?;
+                log_sink::set_level(level);
+                // A warning, so the change is recorded at most levels
+                crate::log!(warn: "Log level set to {}", level.as_str());
+                Ok::<_, ApiError>(Json(LogLevelResponse { level }))
+            }))
+            .route("/health", routing::get(|ClockExtractor(clock), StackExtractor(stack), ControlExtractor(control), MetricsExtractor(metrics)| async move {
+                let health = HealthResponse {
+                    uptime: Instant::now().as_secs(),
+                    link_up: wifi::has_address(stack),
+                    ipv4_address: stack.config_v4().map(|config| request_log::truncated(config.address.address())),
+                    ipv6_address: stack.config_v6().map(|config| request_log::truncated(config.address.address())),
+                    wifi_mode: wifi::mode(),
+                    clock_synchronized: clock.is_synchronized(),
+                    heap_free: esp_alloc::HEAP.free(),
+                    heap_used: esp_alloc::HEAP.used(),
+                    paused: control.is_paused(),
+                    active_connections: metrics.active_connections(),
+                };
+                let status_code = if health.is_healthy() {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                (status_code, Json(health))
+            }))
+            .route("/metrics", routing::get(|MetricsExtractor(metrics), StatsExtractor(stats)| async move {
+                ChunkedResponse::new(MetricsExposition { metrics, stats })
+            }))
+            .route("/stream", routing::get(|| async move { stream_demo() }))
+            .route("/uptime", routing::get(|| async move {
+                Json(UptimeResponse {
+                    uptime: Instant::now().as_secs(),
+                    boot_count: boot::boot_count(),
+                    reset_reason: boot::reset_reason()
+                        .map(|reason| request_log::truncated(format_args!("{:?}", reason))),
+                })
+            }))
+            .route("/heap", routing::get(|| async move { Json(HeapStats::current()) }))
+            .route("/tasks", routing::get(|| async move { Json(TasksResponse { tasks: tasks::statuses() }) }))
+            .route("/debug/timers", routing::get(|RtcExtractor(rtc), ClockExtractor(clock)| async move {
+                Json(TimerSnapshot::sample(rtc, &clock))
+            }))
+            .route("/wifi", routing::get(|StackExtractor(stack), CacheExtractor(cache)| async move {
+                let wifi = WifiResponse::current(stack);
+                cache.store("/wifi", &wifi);
+                Json(wifi)
+            }))
+            .route("/wifi/scan", routing::get(|| async move { WifiScanResponse::scan().await.map(Json) }))
+            .route("/poll", routing::get(|Query(query): Query<PollQuery>, GpioExtractor(gpio), SettingsExtractor(settings)| async move {
+                // The write timeout only starts once the response is written,
+                // so waiting here does not count against it
+                let sequence = match query.since {
+                    Some(since) => changes::wait_for_change(since, MAX_POLL_WAIT).await,
+                    None => Some(changes::sequence()),
+                };
+                match sequence {
+                    Some(sequence) => Ok(Json(PollResponse::current(sequence, gpio, settings))),
+                    None => Err((StatusCode::NO_CONTENT, "")),
+                }
+            }))
+            .route("/api", routing::get(|compression: Compression| async move {
+                Compressible::content(Json(RouteListing), compression)
+            }))
+            .route("/help", routing::get(|| async move { ChunkedResponse::new(RouteHelp) }))
+            .route("/stats", routing::get(|StatsExtractor(stats), compression: Compression| async move {
+                Compressible::content(Json(stats.snapshot()), compression)
+            }))
+            .route("/schedule", routing::get(|ScheduleExtractor(schedule)| async move {
+                let entries: heapless::Vec<EntryResponse, { scheduler::MAX_ENTRIES }> =
+                    schedule.entries().iter().map(EntryResponse::from).collect();
+                Json(entries)
+            }).post(|ScheduleExtractor(schedule), GpioExtractor(gpio), Json(request): Json<EntryRequest>| async move {
+                let Some((hour, minute)) = scheduler::parse_time(&request.time) else {
+                    return Err(ApiError::bad_request("invalid_time").with_message("time must be formatted as HH:MM"));
+                };
+                let days = request.days.unwrap_or(scheduler::EVERY_DAY);
+                if days == 0 || days > scheduler::EVERY_DAY {
+                    return Err(ApiError::bad_request("invalid_days").with_message("days must be a mask between 1 and 127"));
+                }
+                let pin = match (request.action.needs_pin(), request.pin) {
+                    (true, Some(pin)) if gpio.is_set_high(pin).is_some() => Some(pin),
+                    (true, _) => return Err(ApiError::bad_request("unknown_pin").with_message("GPIO actions need a known pin")),
+                    (false, _) => None,
+                };
+                let entry = scheduler::Entry { id: 0, hour, minute, days, action: request.action, pin };
+                let entry = schedule.add(entry).await.map_err(schedule_error)?;
+                Ok((StatusCode::CREATED, Json(EntryResponse::from(&entry))))
+            }).delete(|ScheduleExtractor(schedule)| async move {
+                schedule.clear().await.map_err(schedule_error)?;
+                Ok::<_, ApiError>(StatusCode::NO_CONTENT)
+            }))
+            .route(("/schedule", routing::parse_path_segment::<u8>()), routing::delete(|id: u8, ScheduleExtractor(schedule)| async move {
+                schedule.remove(id).await.map_err(schedule_error)?;
+                Ok::<_, ApiError>(StatusCode::NO_CONTENT)
+            }))
+            .route("/webhooks", routing::get(|WebhooksExtractor(webhooks)| async move {
+                let urls: heapless::Vec<WebhookResponse, { webhooks::MAX_WEBHOOKS }> = webhooks
+                    .urls()
+                    .into_iter()
+                    .map(|(id, url)| WebhookResponse { id, url })
+                    .collect();
+                Json(urls)
+            }).post(|WebhooksExtractor(webhooks), Json(request): Json<WebhookRequest>| async move {
+                if !webhooks::is_valid_url(&request.url) {
+                    return Err(ApiError::bad_request("invalid_url").with_message("url must start with http:// or https://"));
+                }
+                let id = webhooks.add(request.url.clone()).await.map_err(webhook_error)?;
+                Ok((StatusCode::CREATED, Json(WebhookResponse { id, url: request.url })))
+            }))
+            .route(("/webhooks", routing::parse_path_segment::<u8>()), routing::delete(|id: u8, WebhooksExtractor(webhooks)| async move {
+                webhooks.remove(id).await.map_err(webhook_error)?;
+                Ok::<_, ApiError>(StatusCode::NO_CONTENT)
+            }))
+            .route("/netstats", routing::get(|NetStatsExtractor(netstats)| async move { Json(netstats.snapshot()) }))
+            .route("/whoami", routing::get(|ClientAddr(endpoint), UserAgent(user_agent)| async move {
+                Json(WhoamiResponse::new(endpoint, user_agent))
+            }));
+
+        well_known_routes(admin_routes(router))
+            .route(
+                ("/files", routing::parse_path_segment::<String<{ files::MAX_NAME_SIZE }>>()),
+                routing::get(|name: String<{ files::MAX_NAME_SIZE }>, FilesExtractor(store), range: RangeHeader| async move {
+                    let file = store.open(&name).await.map_err(file_error)?;
+                    Ok::<_, ApiError>(FlashFile {
+                        store,
+                        file,
+                        content_type: static_assets::content_type(&name),
+                        range: range.resolve(file.len()),
+                    })
+                })
+                .put_service(FileUpload),
+            )
+            .route("/upload", routing::post_service(MultipartUpload))
+            .route("/echo", routing::post_service(Echo))
+            .route(provisioning::PROVISIONING_PATH, routing::get(|ProvisioningExtractor(_)| async move {
+                render_provisioning(None)
+            }).post(|ProvisioningExtractor(storage), Form(form): Form| async move {
+                let ssid = form.get("ssid").unwrap_or_default();
+                let password = form.get("password").unwrap_or_default();
+                let credentials = WifiCredentials::new(ssid, password)
+                    .map_err(|error| (StatusCode::BAD_REQUEST, render_provisioning(Some(error.message()))))?;
+                let Some(storage) = storage else {
+                    return Err((StatusCode::SERVICE_UNAVAILABLE, render_provisioning(Some("No storage to save the credentials in"))));
+                };
+                if let Err(error) = provisioning::save_credentials(storage, &credentials).await {
+                    crate::log!(error: "Failed to save the Wi-Fi credentials: {:?}", error);
+                    return Err((StatusCode::INTERNAL_SERVER_ERROR, render_provisioning(Some("Failed to save the credentials"))));
+                }
+                crate::log!("Wi-Fi credentials for {} saved, restarting", credentials.ssid);
+                restart::request_restart(PROVISIONING_RESTART_DELAY);
+                Ok(render_provisioned(&credentials.ssid))
+            }))
+            .route(captive::ANDROID_PROBE, routing::get(|CaptivePortalExtractor(captive_portal)| async move {
+                captive_probe(captive_portal, captive::ANDROID_PROBE)
+            }))
+            .route(captive::APPLE_PROBE, routing::get(|CaptivePortalExtractor(captive_portal)| async move {
+                captive_probe(captive_portal, captive::APPLE_PROBE)
+            }))
+            .route(captive::WINDOWS_PROBE, routing::get(|CaptivePortalExtractor(captive_portal)| async move {
+                captive_probe(captive_portal, captive::WINDOWS_PROBE)
+            }))
+            .route(captive::WINDOWS_NCSI_PROBE, routing::get(|CaptivePortalExtractor(captive_portal)| async move {
+                captive_probe(captive_portal, captive::WINDOWS_NCSI_PROBE)
+            }))
+            .layer(HandlerTimeoutLayer::new(DEFAULT_HANDLER_TIMEOUT, HANDLER_TIMEOUTS))
+            .layer(RequestLimitLayer)
+            .layer(RequiresNetworkLayer::routes(NETWORK_ROUTES))
+            .layer(HalfDuplexLayer)
+            .layer(SemaphoreLayer::uploads())
+            .layer(CacheLayer)
+            .layer(BasicAuthLayer::protecting(BASIC_AUTH_PREFIXES))
+            .layer(SessionLayer::protecting(SESSION_PREFIX))
+            .layer(OptionsLayer::all())
+            .layer(AdminSourceLayer)
+            .layer(AdminPortLayer)
+            .layer(BodyLimitLayer)
+            .layer(MethodNotAllowedLayer)
+            .layer(NormalizePathLayer)
+            .layer(SECURITY_HEADERS)
+            .layer(TimeLayer::new())
+            .layer(RequestIdLayer)
+    }
+}
+
+/// Paths of the admin surface, see [`AdminApplication`]
+pub const ADMIN_PATHS: &[&str] = &[
+    "/admin",
+    "/restart",
+    "/settings",
+    "/config",
+    "/login",
+    "/logout",
+    "/time/sync",
+    "/timezone",
+    "/stats/reset",
+    "/netstats/reset",
+    "/wifi/networks",
+    "/wifi/disconnect",
+    "/wifi/reconnect",
+];
+
+/// Add the routes of the admin surface, at every path of [`ADMIN_PATHS`]
+fn admin_routes(
+    router: picoserve::Router<impl routing::PathRouter<AppState>, AppState>,
+) -> picoserve::Router<impl routing::PathRouter<AppState>, AppState> {
+    router
+        .route("/settings", routing::get(|ClockExtractor(clock), SettingsExtractor(settings), CsrfTokenExtractor(csrf_token)| async move {
+            render_settings(&clock, settings, &csrf_token, None)
+        }).post(|ClockExtractor(clock), SettingsExtractor(settings), LedExtractor(led), ConfigExtractor(config), CsrfTokenExtractor(csrf_token), CsrfForm(form): CsrfForm| async move {
+            match SettingsUpdate::parse(&form) {
+                Ok(update) => {
+                    update.apply(&clock, settings, led);
+                    if config.save().await.is_err() {
+                        crate::log!("Failed to save the settings");
+                    }
+                    Ok(Redirect::see_other("/settings"))
+                }
+                Err(error) => Err((StatusCode::BAD_REQUEST, render_settings(&clock, settings, &csrf_token, Some(error)))),
+            }
+        }))
+        .route("/login", routing::get(|| async move { render_login(None) }).post(
+            |CredentialsExtractor(credentials), SessionsExtractor(sessions), Form(form): Form| async move {
+                let password = form.get("password").unwrap_or_default();
+                if !constant_time_eq(password.as_bytes(), credentials.password.as_bytes()) {
+                    crate::log!("Rejected login with a wrong password");
+                    return Err((StatusCode::UNAUTHORIZED, render_login(Some("Wrong password"))));
+                }
+                let id = sessions.create();
+                crate::log!("Session started");
+                Ok(SessionRedirect {
+                    location: LOGIN_REDIRECT,
+                    cookie: SetCookie::Start(id),
+                })
+            },
+        ))
+        .route("/logout", routing::post(|SessionsExtractor(sessions), SessionCookie(id), CsrfForm(_): CsrfForm| async move {
+            if let Some(id) = id {
+                sessions.remove(&id);
+                crate::log!("Session ended");
+            }
+            SessionRedirect {
+                location: "/login",
+                cookie: SetCookie::Clear,
+            }
+        }))
+        .route("/config", routing::get(|ConfigExtractor(config)| async move {
+            Json(config.current())
+        }).put(|ConfigExtractor(config), RequestIdExtractor(request_id), ConfigBody(document): ConfigBody| async move {
+            config.replace(document).await?;
+            crate::log!("[{}] Configuration replaced", request_id);
+            Ok::<_, ConfigError>(Json(config.current()))
+        }))
+        .route("/time/sync", routing::post(|| async move {
+            picoserve::response::Response::new(StatusCode::ACCEPTED, picoserve::response::Json(time_sync::request()))
+                .with_header("Location", "/time/sync/status")
+        }))
+        .route("/time/sync/status", routing::get(|| async move { Json(time_sync::status()) }))
+        .route("/timezone", routing::get(|ClockExtractor(clock)| async move {
+            Json(TimezoneResponse::from(clock.offset()))
+        }).post(|ClockExtractor(clock), ConfigExtractor(config), RequestIdExtractor(request_id), Json(request): Json<TimezoneRequest>| async move {
+            if !OFFSET_MINUTES_RANGE.contains(&request.offset_minutes) {
+                return Err(ApiError::bad_request("invalid_offset")
+                    .with_message("offset_minutes must be between -720 and 840"));
+            }
+            let offset = UtcOffset::from_whole_seconds(i32::from(request.offset_minutes) * 60)
+                .map_err(|_| ApiError::bad_request("invalid_offset").with_message("Invalid offset"))?;
+            clock.set_offset(offset);
+            crate::log!("[{}] UTC offset changed to {}", request_id, offset);
+            if config.save().await.is_err() {
+                crate::log!("[{}] Failed to save the UTC offset", request_id);
+            }
+            Ok(Json(TimezoneResponse::from(offset)))
+        }))
+        .route("/stats/reset", routing::post(|StatsExtractor(stats)| async move {
+            stats.reset();
+            (StatusCode::NO_CONTENT, "")
+        }))
+        .route("/netstats/reset", routing::post(|NetStatsExtractor(netstats)| async move {
+            netstats.reset();
+            (StatusCode::NO_CONTENT, "")
+        }))
+        .route("/admin", routing::get(|| async move { "Authenticated" }))
+        .route("/admin/token/rotate", routing::post(|ApiTokenExtractor(api_token)| async move {
+            let mut token = String::new();
+            write!(token, "{}", api_token.rotate()).unwrap();
+            Json(TokenResponse { token })
+        }))
+        .route("/restart", routing::post(|Query(query): Query<RestartQuery>| async move {
+            let delay = Duration::from_secs(query.delay.unwrap_or(0));
+            let delay = restart::request_restart(delay);
+            (StatusCode::ACCEPTED, Json(RestartResponse { restart_in_ms: delay.as_millis() }))
+        }))
+        .route("/wifi/networks", routing::get(|StorageExtractor(storage)| async move {
+            Json(StoredNetworksResponse::new(&provisioning::load_networks(storage).await))
+        }).post(|StorageExtractor(storage), Json(request): Json<NetworkRequest>| async move {
+            let credentials = WifiCredentials::new(&request.ssid, &request.password).map_err(credentials_error)?;
+            let networks = wifi::update_networks(storage, |networks| {
+                networks.add(credentials)?;
+                Ok(networks.clone())
+            }).await.map_err(networks_error)?;
+            Ok::<_, ApiError>((StatusCode::CREATED, Json(StoredNetworksResponse::new(&networks))))
+        }).put(|StorageExtractor(storage), Json(request): Json<NetworkOrderRequest>| async move {
+            let networks = wifi::update_networks(storage, |networks| {
+                networks.reorder(request.ssids.iter().map(String::as_str))?;
+                Ok(networks.clone())
+            }).await.map_err(networks_error)?;
+            Ok::<_, ApiError>(Json(StoredNetworksResponse::new(&networks)))
+        }))
+        .route(("/wifi/networks", routing::parse_path_segment::<u8>()), routing::delete(|index: u8, StorageExtractor(storage)| async move {
+            wifi::update_networks(storage, |networks| networks.remove(usize::from(index))).await.map_err(networks_error)?;
+            Ok::<_, ApiError>(StatusCode::NO_CONTENT)
+        }))
+        // Over the station, the response is lost with the association
+        .route("/wifi/disconnect", routing::post(|StackExtractor(stack), RequestIdExtractor(request_id)| async move {
+            crate::log!("[{}] Wi-Fi disconnect requested", request_id);
+            wifi::disconnect().await.map_err(command_error)?;
+            Ok::<_, ApiError>(Json(WifiResponse::current(stack)))
+        }))
+        .route("/wifi/reconnect", routing::post(|StackExtractor(stack), RequestIdExtractor(request_id)| async move {
+            crate::log!("[{}] Wi-Fi reconnect requested", request_id);
+            wifi::reconnect().await.map_err(command_error)?;
+            Ok::<_, ApiError>(Json(WifiResponse::current(stack)))
+        }))
+}
+
+/// Add `/robots.txt` and the routes under `/.well-known/`, see
+/// [`well_known`]
+///
+/// The paths are not protected by any layer, crawlers and researchers
+/// cannot authenticate.
+fn well_known_routes(
+    router: picoserve::Router<impl routing::PathRouter<AppState>, AppState>,
+) -> picoserve::Router<impl routing::PathRouter<AppState>, AppState> {
+    router
+        .route(well_known::ROBOTS_PATH, routing::get(|| async move { well_known::ROBOTS_TXT }))
+        .nest_service(well_known::WELL_KNOWN_PREFIX, WellKnownNotFound)
+        .route(well_known::SECURITY_TXT_PATH, routing::get(|| async move {
+            SecurityTxt::configured().ok_or((StatusCode::NOT_FOUND, ""))
+        }))
+}
+
+/// The admin surface alone, served on [`WebAppConfig::admin_port`]
+///
+/// When an admin port is set, [`Application`] hides the admin paths, so a
+/// firewall restricting the admin port restricts the whole admin surface.
+pub struct AdminApplication;
+
+impl AppWithStateBuilder for AdminApplication {
+    type State = AppState;
+    type PathRouter = impl routing::PathRouter<AppState>;
+
+    fn build_app(self) -> picoserve::Router<Self::PathRouter, AppState> {
+        admin_routes(picoserve::Router::from_service(NotFound))
+            .layer(HandlerTimeoutLayer::new(DEFAULT_HANDLER_TIMEOUT, HANDLER_TIMEOUTS))
+            .layer(RequestLimitLayer)
+            .layer(RequiresNetworkLayer::routes(NETWORK_ROUTES))
+            .layer(BasicAuthLayer::protecting(BASIC_AUTH_PREFIXES))
+            .layer(SessionLayer::protecting(SESSION_PREFIX))
+            .layer(OptionsLayer::under(ADMIN_PATHS))
+            .layer(AdminSourceLayer)
+            .layer(BodyLimitLayer)
+            .layer(MethodNotAllowedLayer)
+            .layer(NormalizePathLayer)
+            .layer(SECURITY_HEADERS)
+            .layer(TimeLayer::new())
+            .layer(RequestIdLayer)
+    }
+}
+
+/// Buffers used by a single web task
+///
+/// These are statically allocated once per task rather than living in the
+/// task future, so that raising [`WEB_TASK_POOL_SIZE`] neither grows the
+/// executor arena nor touches the heap.
+pub struct TaskBuffers {
+    tcp_rx: [u8; TCP_RX_BUFFER_SIZE],
+    tcp_tx: [u8; TCP_TX_BUFFER_SIZE],
+    http: [u8; HTTP_BUFFER_SIZE],
+}
+
+impl TaskBuffers {
+    const fn new() -> Self {
+        Self {
+            tcp_rx: [0; TCP_RX_BUFFER_SIZE],
+            tcp_tx: [0; TCP_TX_BUFFER_SIZE],
+            http: [0; HTTP_BUFFER_SIZE],
+        }
+    }
+}
+
+/// A websocket sending the current time once per second
+struct TimeTicker {
+    clock: Clock,
+}
+
+impl ws::WebSocketCallback for TimeTicker {
+    async fn run<R: Read, W: picoserve::io::Write<Error = R::Error>>(
+        self,
+        mut rx: ws::SocketRx<R>,
+        mut tx: ws::SocketTx<W>,
+    ) -> Result<(), W::Error> {
+        let mut buffer = [0; 128];
+        let mut ticker = Ticker::every(Duration::from_secs(1));
+
+        let close_reason = loop {
+            match select(rx.next_message(&mut buffer), ticker.next()).await {
+                Either::First(Ok(ws::Message::Ping(data))) => tx.send_pong(data).await?,
+                Either::First(Ok(ws::Message::Close(reason))) => {
+                    crate::log!("Websocket closed by peer: {:?}", reason);
+                    break None;
+                }
+                Either::First(Ok(_)) => {}
+                Either::First(Err(error)) => {
+                    crate::log!("Websocket error: {:?}", error);
+                    break Some((error.code(), "Websocket Error"));
+                }
+                Either::Second(()) => {
+                    let Ok(now) = self.clock.now() else {
+                        continue;
+                    };
+                    let frame: String<128> =
+                        serde_json_core::to_string(&TimeResponse::from(now)).unwrap();
+                    tx.send_text(&frame).await?;
+                }
+            }
+        };
+
+        tx.close(close_reason).await
+    }
+}
+
+/// A websocket streaming the log lines
+///
+/// The buffered lines are replayed first. A client too slow to keep up
+/// misses lines, which is reported with a `[N lines dropped]` frame.
+struct LogTail;
+
+impl ws::WebSocketCallback for LogTail {
+    async fn run<R: Read, W: picoserve::io::Write<Error = R::Error>>(
+        self,
+        mut rx: ws::SocketRx<R>,
+        mut tx: ws::SocketTx<W>,
+    ) -> Result<(), W::Error> {
+        let Some(mut new_lines) = log_sink::subscribe() else {
+            return tx.close(Some((1013, "Too many log clients"))).await;
+        };
+
+        let mut buffer = [0; 128];
+        let mut next_sequence = log_sink::oldest_sequence();
+
+        let close_reason = loop {
+            while let Some((dropped, (sequence, line))) = log_sink::line_from(next_sequence) {
+                if dropped > 0 {
+                    let mut marker = String::<32>::new();
+                    write!(marker, "[{} lines dropped]", dropped).unwrap();
+                    tx.send_text(&marker).await?;
+                }
+                tx.send_text(&line).await?;
+                next_sequence = sequence.wrapping_add(1);
+            }
+
+            match select(rx.next_message(&mut buffer), new_lines.changed()).await {
+                Either::First(Ok(ws::Message::Ping(data))) => tx.send_pong(data).await?,
+                Either::First(Ok(ws::Message::Close(_))) => break None,
+                Either::First(Ok(_)) => {}
+                Either::First(Err(error)) => break Some((error.code(), "Websocket Error")),
+                Either::Second(_) => {}
+            }
+        };
+
+        tx.close(close_reason).await
+    }
+}
+
+/// JSON data of a `status` event, also returned by `/api/v1/status`
+#[derive(Serialize)]
+pub struct StatusEvent {
+    /// Seconds since boot
+    pub uptime: u64,
+
+    /// Current time, if available
+    pub time: Option<TimeResponse>,
+
+    /// Free heap in bytes
+    pub free_heap: usize,
+}
+
+impl StatusEvent {
+    /// Read the current status
+    pub fn current(clock: &Clock) -> Self {
+        Self {
+            uptime: clock.time_since_boot(),
+            time: clock.now().ok().map(TimeResponse::from),
+            free_heap: esp_alloc::HEAP.free(),
+        }
+    }
+}
+
+/// A stream of device status events
+struct StatusEvents {
+    clock: Clock,
+}
+
+impl EventSource for StatusEvents {
+    async fn write_events<W: picoserve::io::Write>(
+        self,
+        writer: &mut EventWriter<W>,
+    ) -> Result<(), W::Error> {
+        let mut ticker = Ticker::every(EVENTS_PERIOD);
+        loop {
+            let status = StatusEvent::current(&self.clock);
+            let data: String<192> = serde_json_core::to_string(&status).unwrap();
+            writer.write_event("status", &data).await?;
+
+            ticker.next().await;
+        }
+    }
+}
+
+/// Data of the `rssi` events of `/events/rssi`
+#[derive(Serialize)]
+pub struct RssiEvent {
+    /// Signal strength in dBm
+    pub rssi: i32,
+}
+
+/// A stream of the RSSI samples of the connection task
+///
+/// The stream ends with a `disconnected` event when the association is
+/// lost, or right away if there is none.
+struct RssiEvents {
+    receiver: wifi::RssiReceiver,
+}
+
+impl EventSource for RssiEvents {
+    async fn write_events<W: picoserve::io::Write>(
+        mut self,
+        writer: &mut EventWriter<W>,
+    ) -> Result<(), W::Error> {
+        let mut rssi = self.receiver.try_get().flatten();
+        while let Some(dbm) = rssi {
+            let data: String<32> = serde_json_core::to_string(&RssiEvent { rssi: dbm }).unwrap();
+            writer.write_event("rssi", &data).await?;
+
+            rssi = self.receiver.changed().await;
+        }
+        writer.write_event("disconnected", "{}").await
+    }
+}
+
+/// Listening and connection options of the web server
+#[derive(Clone, Debug)]
+pub struct WebAppConfig {
+    /// TCP port to listen on
+    pub port: u16,
+
+    /// Whether to keep connections alive between requests
+    pub keep_alive: bool,
+
+    /// Timeout for the first request on a new connection
+    pub start_read_request_timeout: Option<Duration>,
+
+    /// Timeout for subsequent requests on a kept-alive connection
+    pub persistent_start_read_request_timeout: Option<Duration>,
+
+    /// Requests served on a kept-alive connection before closing it, `None`
+    /// to serve as many as the client sends, see [`keep_alive`]
+    pub max_requests_per_connection: Option<usize>,
+
+    /// Timeout for reading the rest of a request
+    pub read_request_timeout: Option<Duration>,
+
+    /// Timeout for writing a response
+    pub write_timeout: Option<Duration>,
+
+    /// Largest request body accepted, larger ones are rejected with 413
+    pub max_request_body_size: usize,
+
+    /// Whether to redirect `GET` requests for non-canonical paths, see
+    /// [`NormalizedPath`]
+    pub normalize_paths: bool,
+
+    /// Routes whose responses are served from a [`ResponseCache`]
+    pub cached_routes: &'static [CachedRoute],
+
+    /// Whether connectivity checks are redirected to the provisioning page,
+    /// see [`captive`]
+    pub captive_portal: bool,
+
+    /// TCP port serving the admin surface alone, see [`AdminApplication`]
+    ///
+    /// The admin paths are served on [`WebAppConfig::port`] when this is
+    /// unset.
+    pub admin_port: Option<u16>,
+
+    /// Client addresses allowed on the admin paths, others get 403
+    pub admin_allowlist: &'static [Cidr],
+
+    /// Smallest dynamic response compressed for clients accepting gzip,
+    /// `None` to never compress, see [`crate::compression`]
+    pub compression_threshold: Option<usize>,
+}
+
+impl Default for WebAppConfig {
+    fn default() -> Self {
+        // Idle keep-alive connections are dropped after one second, and busy
+        // ones after 100 requests, so a few browsers holding connections open
+        // cannot occupy every web task.
+        Self {
+            port: 80,
+            keep_alive: true,
+            start_read_request_timeout: Some(Duration::from_secs(5)),
+            persistent_start_read_request_timeout: Some(Duration::from_secs(1)),
+            max_requests_per_connection: Some(DEFAULT_MAX_REQUESTS_PER_CONNECTION),
+            read_request_timeout: Some(Duration::from_secs(1)),
+            write_timeout: Some(Duration::from_secs(1)),
+            max_request_body_size: DEFAULT_MAX_REQUEST_BODY_SIZE,
+            normalize_paths: true,
+            cached_routes: DEFAULT_CACHED_ROUTES,
+            captive_portal: false,
+            admin_port: None,
+            admin_allowlist: LAN_RANGES,
+            compression_threshold: Some(DEFAULT_COMPRESSION_THRESHOLD),
+        }
+    }
+}
+
+pub struct WebApp {
+    pub router: &'static Router<<Application as AppWithStateBuilder>::PathRouter, AppState>,
+    pub config: &'static picoserve::Config<Duration>,
+    pub state: &'static AppState,
+    pub buffers: &'static mut [TaskBuffers; WEB_TASK_POOL_SIZE],
+    pub port: u16,
+
+    /// Router of the admin surface, served by [`admin_web_task`]
+    pub admin_router: &'static AppRouter<AdminApplication>,
+
+    /// Buffers of the admin web tasks
+    pub admin_buffers: &'static mut [TaskBuffers; ADMIN_TASK_POOL_SIZE],
+
+    /// Port of the admin surface, the admin web tasks are only spawned when
+    /// it is set
+    pub admin_port: Option<u16>,
+
+    /// Buffers of the web tasks serving the access point kept alongside the
+    /// station, see [`access_point_web_task`]
+    #[cfg(feature = "ap-sta")]
+    pub access_point_buffers: &'static mut [TaskBuffers; ACCESS_POINT_TASK_POOL_SIZE],
+}
+
+// impl Default for WebApp {
+//     fn default() -> Self {
+//         // Create a default clock for the default implementation
+//         let default_clock = Clock::new(0);
+//         Self::new_with_clock(default_clock, Stack::default())
+//     }
+// }
+
+/// Hardware handles made available to the routes
+#[derive(Default)]
+pub struct Hardware {
+    /// Output pins driven by `/api/v1/gpio`
+    pub gpio: GpioOutputs,
+
+    /// The LED driven by `/api/v1/led`
+    pub led: Option<Output<'static>>,
+
+    /// The key-value store served at `/api/v1/kv`
+    pub storage: Option<&'static Storage>,
+
+    /// The files served at `/files`
+    pub files: Option<&'static FileStore>,
+
+    /// The RTC sampled at `/debug/timers`
+    pub rtc: Option<&'static Rtc<'static>>,
+}
+
+impl WebApp {
+    /// Start configuring a web app, with the same defaults as
+    /// [`WebAppConfig::default`]
+    pub fn builder() -> WebAppBuilder {
+        WebAppBuilder::default()
+    }
+
+    pub fn new_with_clock(
+        clock: Clock,
+        stack: Stack<'static>,
+        rng: RngWrapper,
+        web_config: WebAppConfig,
+    ) -> Self {
+        Self::new_with_hardware(clock, stack, rng, Hardware::default(), web_config)
+    }
+
+    pub fn new_with_hardware(
+        clock: Clock,
+        stack: Stack<'static>,
+        rng: RngWrapper,
+        hardware: Hardware,
+        web_config: WebAppConfig,
+    ) -> Self {
+        // A zero write timeout fails every response, the server then looks
+        // dead without any error
+        debug_assert!(
+            web_config.write_timeout != Some(Duration::from_ticks(0)),
+            "The write timeout must not be zero"
+        );
+
+        let router = picoserve::make_static!(AppRouter<Application>, Application.build_app());
+        let admin_router = picoserve::make_static!(AppRouter<AdminApplication>, AdminApplication.build_app());
+
+        let config = picoserve::Config::new(picoserve::Timeouts {
+            start_read_request: web_config.start_read_request_timeout,
+            persistent_start_read_request: web_config.persistent_start_read_request_timeout,
+            read_request: web_config.read_request_timeout,
+            write: web_config.write_timeout,
+        });
+        let config = if web_config.keep_alive {
+            config.keep_connection_alive()
+        } else {
+            config.close_connection_after_response()
+        };
+        let config = picoserve::make_static!(picoserve::Config<Duration>, config);
+
+        let state = picoserve::make_static!(
+            AppState,
+            AppState {
+                clock,
+                stack,
+                credentials: Credentials::default(),
+                api_token: picoserve::make_static!(ApiTokenStore, ApiTokenStore::new(rng.clone())),
+                rng: picoserve::make_static!(SharedRng, Mutex::new(RefCell::new(rng.clone()))),
+                sessions: picoserve::make_static!(SessionStore, SessionStore::new(rng)),
+                metrics: picoserve::make_static!(Metrics, Metrics::new()),
+                stats: picoserve::make_static!(Stats, Stats::new()),
+                netstats: picoserve::make_static!(NetStats, NetStats::new()),
+                gpio: picoserve::make_static!(GpioOutputs, hardware.gpio),
+                led: picoserve::make_static!(Led, hardware.led.map_or(Led::absent(), Led::new)),
+                adc: picoserve::make_static!(AdcReadings, AdcReadings::new()),
+                settings: picoserve::make_static!(Settings, Settings::new()),
+                max_request_body_size: web_config.max_request_body_size,
+                normalize_paths: web_config.normalize_paths,
+                captive_portal: web_config.captive_portal,
+                admin_port: web_config.admin_port,
+                cache: picoserve::make_static!(ResponseCache, ResponseCache::new(web_config.cached_routes)),
+                storage: hardware.storage,
+                schedule: picoserve::make_static!(Schedule, Schedule::new(hardware.storage)),
+                webhooks: picoserve::make_static!(Webhooks, Webhooks::new(hardware.storage)),
+                files: hardware.files,
+                rtc: hardware.rtc,
+                read_request_timeout: web_config.read_request_timeout,
+                control: picoserve::make_static!(ServerControl, ServerControl::new()),
+                remote_endpoint: None,
+                web_task: 0,
+                admin_allowlist: web_config.admin_allowlist,
+                compression_threshold: web_config.compression_threshold,
+                max_requests_per_connection: web_config.max_requests_per_connection,
+            }
+        );
+
+        let buffers = picoserve::make_static!(
+            [TaskBuffers; WEB_TASK_POOL_SIZE],
+            [const { TaskBuffers::new() }; WEB_TASK_POOL_SIZE]
+        );
+        let admin_buffers = picoserve::make_static!(
+            [TaskBuffers; ADMIN_TASK_POOL_SIZE],
+            [const { TaskBuffers::new() }; ADMIN_TASK_POOL_SIZE]
+        );
+        #[cfg(feature = "ap-sta")]
+        let access_point_buffers = picoserve::make_static!(
+            [TaskBuffers; ACCESS_POINT_TASK_POOL_SIZE],
+            [const { TaskBuffers::new() }; ACCESS_POINT_TASK_POOL_SIZE]
+        );
+
+        Self {
+            router,
+            config,
+            state,
+            buffers,
+            port: web_config.port,
+            admin_router,
+            admin_buffers,
+            admin_port: web_config.admin_port,
+            #[cfg(feature = "ap-sta")]
+            access_point_buffers,
+        }
+    }
+
+    /// Stop accepting new connections, e.g. while flash is being written
+    pub fn pause(&self) {
+        self.state.control.pause();
+    }
+
+    /// Accept new connections again
+    pub fn resume(&self) {
+        self.state.control.resume();
+    }
+
+    /// Return a handle to pause and resume the server from other tasks
+    pub fn control(&self) -> &'static ServerControl {
+        self.state.control
+    }
+}
+
+/// A builder for [`WebApp`]
+///
+/// ```ignore
+/// let web_app = WebApp::builder()
+///     .clock(clock)
+///     .write_timeout(Duration::from_secs(2))
+///     .keep_alive(false)
+///     .build(stack, rng);
+/// ```
+#[derive(Default)]
+pub struct WebAppBuilder {
+    clock: Option<Clock>,
+    hardware: Hardware,
+    config: WebAppConfig,
+}
+
+impl WebAppBuilder {
+    /// Set the clock, an unsynchronized clock is used otherwise
+    pub fn clock(self, clock: Clock) -> Self {
+        Self {
+            clock: Some(clock),
+            ..self
+        }
+    }
+
+    /// Set the hardware handles made available to the routes
+    pub fn hardware(self, hardware: Hardware) -> Self {
+        Self { hardware, ..self }
+    }
+
+    /// Set the TCP port to listen on
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    /// Set whether to keep connections alive between requests
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.config.keep_alive = keep_alive;
+        self
+    }
+
+    /// Set the timeout for the first request on a new connection
+    pub fn start_read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.start_read_request_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the timeout for subsequent requests on a kept-alive connection
+    pub fn persistent_start_read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.persistent_start_read_request_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the requests served on a kept-alive connection before closing it,
+    /// `None` for no limit
+    pub fn max_requests_per_connection(mut self, max: Option<usize>) -> Self {
+        self.config.max_requests_per_connection = max;
+        self
+    }
+
+    /// Set the timeout for reading the rest of a request
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.read_request_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the timeout for writing a response
+    ///
+    /// Panics in debug builds if the timeout is zero.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        debug_assert!(timeout.as_ticks() > 0, "The write timeout must not be zero");
+        self.config.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the largest request body accepted
+    pub fn max_request_body_size(mut self, size: usize) -> Self {
+        self.config.max_request_body_size = size;
+        self
+    }
+
+    /// Set whether non-canonical paths are redirected, disable it to match
+    /// paths exactly
+    pub fn normalize_paths(mut self, enabled: bool) -> Self {
+        self.config.normalize_paths = enabled;
+        self
+    }
+
+    /// Set the routes whose responses are cached, at most
+    /// [`MAX_CACHED_ROUTES`](crate::cache::MAX_CACHED_ROUTES)
+    pub fn cached_routes(mut self, routes: &'static [CachedRoute]) -> Self {
+        self.config.cached_routes = routes;
+        self
+    }
+
+    /// Set whether connectivity checks open the provisioning page, for
+    /// provisioning over the access point
+    pub fn captive_portal(mut self, enabled: bool) -> Self {
+        self.config.captive_portal = enabled;
+        self
+    }
+
+    /// Serve the admin surface on its own port, e.g. one restricted by a
+    /// firewall, see [`AdminApplication`]
+    pub fn admin_port(mut self, port: u16) -> Self {
+        self.config.admin_port = Some(port);
+        self
+    }
+
+    /// Set the client addresses allowed on the admin paths, the private and
+    /// link-local ranges by default
+    pub fn admin_allowlist(mut self, allowlist: &'static [Cidr]) -> Self {
+        self.config.admin_allowlist = allowlist;
+        self
+    }
+
+    /// Set the smallest dynamic response compressed, `None` to never
+    /// compress
+    pub fn compression_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.config.compression_threshold = threshold;
+        self
+    }
+
+    /// Set all listening and connection options at once
+    pub fn config(self, config: WebAppConfig) -> Self {
+        Self { config, ..self }
+    }
+
+    /// Allocate the router, configuration and state
+    ///
+    /// This can only be called once, the allocations are static.
+    pub fn build(self, stack: Stack<'static>, rng: RngWrapper) -> WebApp {
+        let clock = self.clock.unwrap_or_else(Clock::unsynchronized);
+        WebApp::new_with_hardware(clock, stack, rng, self.hardware, self.config)
+    }
+}
+
+
+/// Serve the main router on `port`, see [`serve`]
+#[embassy_executor::task(pool_size = WEB_TASK_POOL_SIZE)]
+pub async fn web_task(
+    id: usize,
+    stack: Stack<'static>,
+    router: &'static AppRouter<Application>,
+    config: &'static picoserve::Config<Duration>,
+    state: &'static AppState,
+    buffers: &'static mut TaskBuffers,
+    port: u16,
+) -> ! {
+    serve(id, stack, router, config, state, buffers, port).await
+}
+
+/// Serve the admin surface on `port`, see [`serve`]
+///
+/// IDs follow the ones of the web tasks, so log lines can be told apart.
+#[embassy_executor::task(pool_size = ADMIN_TASK_POOL_SIZE)]
+pub async fn admin_web_task(
+    id: usize,
+    stack: Stack<'static>,
+    router: &'static AppRouter<AdminApplication>,
+    config: &'static picoserve::Config<Duration>,
+    state: &'static AppState,
+    buffers: &'static mut TaskBuffers,
+    port: u16,
+) -> ! {
+    serve(id, stack, router, config, state, buffers, port).await
+}
+
+/// Serve the web app on the stack of the access point kept alongside the
+/// station, see [`serve`]
+///
+/// IDs follow the ones of the admin web tasks.
+#[cfg(feature = "ap-sta")]
+#[embassy_executor::task(pool_size = ACCESS_POINT_TASK_POOL_SIZE)]
+pub async fn access_point_web_task(
+    id: usize,
+    stack: Stack<'static>,
+    router: &'static AppRouter<Application>,
+    config: &'static picoserve::Config<Duration>,
+    state: &'static AppState,
+    buffers: &'static mut TaskBuffers,
+    port: u16,
+) -> ! {
+    serve(id, stack, router, config, state, buffers, port).await
+}
+
+/// Accept connections on `port` and serve them with `router`, forever
+///
+/// Tasks cannot be generic, so every router gets a task calling this, and
+/// the groups of tasks share the [`AppState`].
+pub async fn serve(
+    id: usize,
+    stack: Stack<'static>,
+    router: &'static Router<impl routing::PathRouter<AppState>, AppState>,
+    config: &'static picoserve::Config<Duration>,
+    state: &'static AppState,
+    buffers: &'static mut TaskBuffers,
+    port: u16,
+) -> ! {
+    let mut paused = state
+        .control
+        .changes
+        .receiver()
+        .expect("One receiver per web task");
+
+    loop {
+        crate::heartbeat!("web_task", id);
+
+        // Park while paused, without any socket listening on the port
+        tasks::beating("web_task", Some(id), paused.get_and(|paused| !*paused)).await;
+
+        let mut socket = TcpSocket::new(stack, &mut buffers.tcp_rx, &mut buffers.tcp_tx);
+
+        crate::log!("{}: Listening on TCP:{}...", id, port);
+        let accepted = select(socket.accept(port), paused.changed_and(|paused| *paused));
+        match tasks::beating("web_task", Some(id), accepted).await {
+            Either::First(Ok(())) => {}
+            Either::First(Err(error)) => {
+                crate::log!("{}: Accept error: {:?}", id, error);
+                continue;
+            }
+            Either::Second(_) => {
+                // Dropping the socket closes the listener
+                crate::log!("{}: Paused", id);
+                continue;
+            }
+        }
+
+        let remote_endpoint = socket.remote_endpoint();
+        crate::log!("{}: Received connection from {:?}", id, remote_endpoint);
+
+        #[cfg(feature = "tls")]
+        let socket = match crate::tls::accept(socket).await {
+            Ok(socket) => socket,
+            Err(error) => {
+                crate::log!("{}: TLS handshake failed: {:?}", id, error);
+                continue;
+            }
+        };
+
+        // Count the bytes actually sent, headers included
+        let mut socket = Some(CountingSocket::new(socket, state.metrics, id));
+        let _connection = state.metrics.open_connection();
+
+        // Layers and handlers see the client address through the state
+        let connection_state = AppState {
+            remote_endpoint,
+            web_task: id,
+            ..state.clone()
+        };
+
+        // Stuck handlers are dropped with the connection, releasing what they
+        // hold, and answered on the socket lent to picoserve. So are
+        // connections past their last request, once it is answered.
+        handler_timeout::disarm(id);
+        keep_alive::reset(id);
+        let served = picoserve::serve_with_state(
+            router,
+            config,
+            &mut buffers.http,
+            ReclaimableSocket(&mut socket),
+            &connection_state,
+        );
+        match select3(served, handler_timeout::expired(id), keep_alive::reached(id)).await {
+            Either3::First(Ok(handled_requests_count)) => {
+                crate::log!(
+                    "{}: {} requests handled from {:?}",
+                    id,
+                    handled_requests_count,
+                    remote_endpoint
+                );
+            }
+            Either3::First(Err(error)) => crate::log!("{}: Error: {:?}", id, error),
+            Either3::Second(path) => {
+                crate::log!(warn: "Warning: handler of {} timed out", path);
+                if let Some(socket) = socket.as_mut() {
+                    handler_timeout::write_timeout_response(socket).await;
+                }
+            }
+            Either3::Third(handled_requests_count) => {
+                crate::log!(
+                    "{}: {} requests handled from {:?}, closing",
+                    id,
+                    handled_requests_count,
+                    remote_endpoint
+                );
+                if let Some(socket) = socket.as_mut() {
+                    keep_alive::close(socket).await;
+                }
+            }
+        }
+    }
+}
+
+struct TimedResponseWriter<'r, W> {
+    request_id: RequestId,
+    method: &'r str,
+    path: Path<'r>,
+    metrics: &'static Metrics,
+    stats: &'static Stats,
+    netstats: &'static NetStats,
+    web_task: usize,
+    thresholds: TimeLayer,
+    timestamp: u64,
+    received_at: Option<OffsetDateTime>,
+    start_time: Instant,
+    response_writer: W,
+}
+
+impl<'r, W: ResponseWriter> ResponseWriter for TimedResponseWriter<'r, W> {
+    type Error = W::Error;
+
+    async fn write_response<
+        R: Read<Error = Self::Error>,
+        H: picoserve::response::HeadersIter,
+        B: picoserve::response::Body,
+    >(
+        self,
+        connection: picoserve::response::Connection<'_, R>,
+        response: picoserve::response::Response<H, B>,
+    ) -> Result<picoserve::ResponseSent, Self::Error> {
+        let status_code = response.status_code();
+        let bytes_before = self.metrics.task_bytes_written(self.web_task);
+
+        let result = self
+            .response_writer
+            .write_response(connection, response)
+            .await;
+
+        let duration = self.start_time.elapsed();
+        let duration_ms = duration.as_millis();
+        let bytes = self.metrics.task_bytes_written(self.web_task).wrapping_sub(bytes_before);
+        self.metrics.record_response(status_code);
+        self.stats.record(self.path.encoded(), status_code.as_u16(), duration_ms);
+        self.netstats.record(status_code.as_u16(), bytes);
+
+        // Fall back to the uptime when the wall-clock time is unknown
+        let mut received_at = String::<40>::new();
+        match self.received_at {
+            Some(time) => write_iso8601(&mut received_at, &time).unwrap(),
+            None => write!(received_at, "+{}ms", self.start_time.as_millis()).unwrap(),
+        }
+
+        crate::log!(
+            debug: "{} [{}] {} {}; Status Code: {}; Response Time: {}ms",
+            received_at,
+            self.request_id,
+            self.method,
+            self.path,
+            status_code,
+            duration_ms
+        );
+
+        if duration > self.thresholds.slow_after || bytes as usize > self.thresholds.large_after {
+            self.metrics.record_slow_request();
+            crate::log!(
+                warn: "Warning: slow request [{}] {} {}; Response Time: {}ms; Response Size: {} bytes",
+                self.request_id,
+                self.method,
+                self.path,
+                duration_ms,
+                bytes
+            );
+        }
+
+        request_log::record(RequestRecord {
+            sequence: 0,
+            timestamp: self.timestamp,
+            uptime_ms: self.start_time.as_millis(),
+            method: request_log::truncated(self.method),
+            path: request_log::truncated(self.path),
+            status: status_code.as_u16(),
+            duration_ms,
+        });
+
+        result
+    }
+}
+
+/// Response time above which [`TimeLayer`] warns unless configured otherwise
+pub const DEFAULT_SLOW_AFTER: Duration = Duration::from_millis(500);
+
+/// Response size above which [`TimeLayer`] warns unless configured otherwise
+pub const DEFAULT_LARGE_AFTER: usize = 32 * 1024;
+
+/// A layer logging and timing every response
+///
+/// Responses slower than [`TimeLayer::slow_after`] or larger than
+/// [`TimeLayer::large_after`] are logged once more as a warning and counted
+/// in `picoserve_slow_requests_total`. The size is what was actually written
+/// to the socket, headers included. Long-lived responses like event streams
+/// and websockets always end up over the time threshold.
+#[derive(Clone, Copy)]
+pub struct TimeLayer {
+    slow_after: Duration,
+    large_after: usize,
+}
+
+impl TimeLayer {
+    /// Create a layer with the default thresholds
+    pub const fn new() -> Self {
+        Self {
+            slow_after: DEFAULT_SLOW_AFTER,
+            large_after: DEFAULT_LARGE_AFTER,
+        }
+    }
+
+    /// Warn about responses taking longer than `duration`
+    pub const fn slow_after(self, duration: Duration) -> Self {
+        Self {
+            slow_after: duration,
+            ..self
+        }
+    }
+
+    /// Warn about responses larger than `bytes`
+    pub const fn large_after(self, bytes: usize) -> Self {
+        Self {
+            large_after: bytes,
+            ..self
+        }
+    }
+}
+
+impl Default for TimeLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for TimeLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let path = request_parts.path();
+        let received_at = state
+            .clock
+            .is_synchronized()
+            .then(|| state.clock.now().ok())
+            .flatten();
+
+        next.run(
+            state,
+            path_parameters,
+            TimedResponseWriter {
+                request_id: RequestId::current(state.web_task),
+                method: request_parts.method(),
+                path,
+                metrics: state.metrics,
+                stats: state.stats,
+                netstats: state.netstats,
+                web_task: state.web_task,
+                thresholds: *self,
+                timestamp: state.clock.now_as_epoch(),
+                received_at,
+                start_time: Instant::now(),
+                response_writer,
+            },
+        )
+        .await
+    }
+}
+
+/// A layer assigning a [`RequestId`] to each request
+///
+/// The ID is passed to the inner layers and handlers in the slot of the web
+/// task, see [`RequestId::current`], and returned in the `X-Request-Id`
+/// response header. This must be the
+/// outermost layer, so every log line of the request can include the ID.
+struct RequestIdLayer;
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for RequestIdLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let request_id = request_parts
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.as_str().ok())
+            .and_then(RequestId::from_header)
+            .unwrap_or_else(RequestId::next);
+
+        request_id.clone().set_current(state.web_task);
+        let response_writer = AppendHeaderWriter {
+            name: REQUEST_ID_HEADER,
+            value: request_id,
+            response_writer,
+        };
+        next.run(state, path_parameters, response_writer).await
+    }
+}
+
+/// Number of uploads written to flash at once
+///
+/// The file store holds the flash for the whole upload, a second upload
+/// would tie up a web task waiting for it.
+pub const UPLOAD_PERMITS: usize = 1;
+
+/// Requests writing to flash, see [`UPLOAD_PERMITS`]
+pub const UPLOAD_ROUTES: &[(&str, &str)] = &[("POST", "/upload"), ("PUT", "/files")];
+
+/// Seconds a client is asked to wait before retrying a rejected upload
+const UPLOAD_RETRY_AFTER_S: u32 = 5;
+
+/// Permits of the requests of [`UPLOAD_ROUTES`]
+static UPLOADS: GreedySemaphore<CriticalSectionRawMutex> = GreedySemaphore::new(UPLOAD_PERMITS);
+
+/// A layer running at most a number of requests of a group of routes at once
+///
+/// Routes are given as a method and a path prefix. A request of the group
+/// takes a permit of the semaphore until its response is sent, and is
+/// rejected with `503 Service Unavailable` and a `Retry-After` header when
+/// none is left, rather than waiting for one. The permit is released when
+/// the handler returns, fails or is dropped because the client went away.
+pub struct SemaphoreLayer {
+    semaphore: &'static GreedySemaphore<CriticalSectionRawMutex>,
+    routes: &'static [(&'static str, &'static str)],
+    retry_after_s: u32,
+}
+
+impl SemaphoreLayer {
+    /// Limit the requests of `routes` to the permits of `semaphore`
+    pub const fn limiting(
+        semaphore: &'static GreedySemaphore<CriticalSectionRawMutex>,
+        routes: &'static [(&'static str, &'static str)],
+        retry_after_s: u32,
+    ) -> Self {
+        Self { semaphore, routes, retry_after_s }
+    }
+
+    /// Limit the uploads to flash to [`UPLOAD_PERMITS`]
+    pub const fn uploads() -> Self {
+        Self::limiting(&UPLOADS, UPLOAD_ROUTES, UPLOAD_RETRY_AFTER_S)
+    }
+}
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for SemaphoreLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let limited = self.routes.iter().any(|&(method, prefix)| {
+            request_parts.method() == method && path_has_prefix(request_parts.path().encoded(), prefix)
+        });
+        if !limited {
+            return next.run(state, path_parameters, response_writer).await;
+        }
+
+        // Released when dropped, whichever way the request ends
+        let Some(_permit) = self.semaphore.try_acquire(1) else {
+            crate::log!(warn: "Warning: rejected concurrent request to {}", request_parts.path());
+            state.stats.record_busy_rejection();
+            let error = ApiError::service_unavailable("busy").with_message("Another request is running, retry later");
+            let response = picoserve::response::Response::new(error.status_code, picoserve::response::Json(error))
+                .with_header("Retry-After", self.retry_after_s);
+            return response_writer
+                .write_response(next.into_connection(), response)
+                .await;
+        };
+        next.run(state, path_parameters, response_writer).await
+    }
+}
+
+/// Timeouts of the handlers of route groups, as path prefix and timeout
+///
+/// Uploads to flash, echoes of large bodies and scans take longer than the
+/// [`DEFAULT_HANDLER_TIMEOUT`]. Long polls wait on purpose and get none, they
+/// answer by themselves after [`MAX_POLL_WAIT`].
+pub const HANDLER_TIMEOUTS: &[(&str, Option<Duration>)] = &[
+    ("/files", Some(Duration::from_secs(30))),
+    ("/upload", Some(Duration::from_secs(30))),
+    ("/poll", None),
+    ("/echo", Some(Duration::from_secs(10))),
+    ("/wifi/scan", Some(Duration::from_secs(15))),
+    ("/wifi/disconnect", Some(Duration::from_secs(wifi::COMMAND_TIMEOUT.as_secs() + 5))),
+    ("/wifi/reconnect", Some(Duration::from_secs(wifi::COMMAND_TIMEOUT.as_secs() + 5))),
+];
+
+/// Paths of the WebSocket routes, which read and write at the same time
+pub const WEBSOCKET_PATHS: &[&str] = &["/ws/time", "/ws/logs"];
+
+/// A layer rejecting the WebSocket routes when the web server runs over TLS
+///
+/// Both halves of a [`TlsSocket`](crate::tls::TlsSocket) share the session,
+/// so a WebSocket waiting for a message would keep its handler from ever
+/// writing. The routes answer `501 Not Implemented` instead; server-sent
+/// events only write, and are served.
+pub struct HalfDuplexLayer;
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for HalfDuplexLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let path = request_parts.path().encoded();
+        if !cfg!(feature = "tls") || !WEBSOCKET_PATHS.contains(&path) {
+            return next.run(state, path_parameters, response_writer).await;
+        }
+        ApiError::new(StatusCode::NOT_IMPLEMENTED, "websocket_unsupported", "WebSockets are not served over HTTPS")
+            .write_to(next.into_connection(), response_writer)
+            .await
+    }
+}
+
+/// Routes calling out to the network, as method and path prefix
+pub const NETWORK_ROUTES: &[(&str, &str)] = &[("POST", "/time/sync")];
+
+/// A layer rejecting requests to routes depending on the network while
+/// Wi-Fi is down
+///
+/// Requests of the routes are answered with `503 Service Unavailable` while
+/// the device is not associated or has no address, as published in
+/// [`wifi::state`], instead of failing deep in the handler. The body tells
+/// when the connection task tries again, if it is waiting to. Other routes
+/// are served from the device alone and are passed through untouched.
+pub struct RequiresNetworkLayer {
+    routes: &'static [(&'static str, &'static str)],
+}
+
+impl RequiresNetworkLayer {
+    /// Require the network for `routes`
+    pub const fn routes(routes: &'static [(&'static str, &'static str)]) -> Self {
+        Self { routes }
+    }
+}
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for RequiresNetworkLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let requires_network = self.routes.iter().any(|&(method, prefix)| {
+            request_parts.method() == method && path_has_prefix(request_parts.path().encoded(), prefix)
+        });
+        if !requires_network || wifi::state().has_ip() {
+            return next.run(state, path_parameters, response_writer).await;
+        }
+
+        crate::log!(warn: "Warning: rejected {} while Wi-Fi is down", request_parts.path());
+        let mut error = ApiError::service_unavailable("wifi_reconnecting").with_message("Wi-Fi reconnecting, retry later");
+        if let Some(delay) = wifi::next_attempt_in() {
+            // Rounded up, retrying right at the attempt would be too early
+            error = error.with_retry_after(delay.as_millis().div_ceil(1000));
+        }
+        error.write_to(next.into_connection(), response_writer).await
+    }
+}
+
+/// Maximal size of decoded `user:password` credentials
+const MAX_CREDENTIALS_SIZE: usize = 96;
+
+/// Prefixes of the paths protected by [`BasicAuthLayer`]
+pub const BASIC_AUTH_PREFIXES: &[&str] = &[
+    "/admin",
+    "/restart",
+    "/files",
+    "/upload",
+    "/loglevel",
+    "/logs",
+    "/ws/logs",
+    "/schedule",
+    "/webhooks",
+    "/config",
+    "/time/sync",
+    "/timezone",
+    "/stats/reset",
+    "/netstats/reset",
+    "/wifi/networks",
+    "/wifi/disconnect",
+    "/wifi/reconnect",
+    "/wifi/scan",
+];
+
+/// Prefix of the paths protected by [`SessionLayer`]
+pub const SESSION_PREFIX: &str = "/settings";
+
+/// A layer requiring HTTP Basic authentication for paths under prefixes
+///
+/// Requests to other paths are passed through untouched, so the layer can be
+/// applied to the whole router while only protecting a group of routes.
+pub struct BasicAuthLayer {
+    prefixes: &'static [&'static str],
+}
+
+impl BasicAuthLayer {
+    /// Protect every path equal to or below one of `prefixes`
+    pub const fn protecting(prefixes: &'static [&'static str]) -> Self {
+        Self { prefixes }
+    }
+}
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for BasicAuthLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let path = request_parts.path().encoded();
+        let protected = self.prefixes.iter().any(|prefix| path_has_prefix(path, prefix));
+        let authorized = !protected
+            || request_parts
+                .headers()
+                .get("Authorization")
+                .and_then(|value| value.as_str().ok())
+                .is_some_and(|value| check_basic_auth(value, &state.credentials));
+
+        if authorized {
+            next.run(state, path_parameters, response_writer).await
+        } else {
+            crate::log!("Rejected unauthenticated request to {}", request_parts.path());
+            let response = picoserve::response::Response::new(StatusCode::UNAUTHORIZED, "Unauthorized")
+                .with_header("WWW-Authenticate", "Basic realm=\"esp32c3\", charset=\"UTF-8\"");
+            response_writer
+                .write_response(next.into_connection(), response)
+                .await
+        }
+    }
+}
+
+/// A layer requiring a session cookie for paths under a prefix
+///
+/// Requests without a live session are redirected to `/login`, which is
+/// friendlier than a Basic prompt for the HTML pages. Requests to other
+/// paths are passed through untouched, like with [`BasicAuthLayer`].
+pub struct SessionLayer {
+    prefix: &'static str,
+}
+
+impl SessionLayer {
+    /// Protect every path equal to or below `prefix`
+    pub const fn protecting(prefix: &'static str) -> Self {
+        Self { prefix }
+    }
+}
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for SessionLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let authorized = !path_has_prefix(request_parts.path().encoded(), self.prefix)
+            || SessionCookie::parse(&request_parts)
+                .0
+                .is_some_and(|id| state.sessions.touch(&id));
+
+        if authorized {
+            next.run(state, path_parameters, response_writer).await
+        } else {
+            crate::log!("Redirected request without a session to {} to the login page", request_parts.path());
+            Redirect::see_other("/login")
+                .write_to(next.into_connection(), response_writer)
+                .await
+        }
+    }
+}
+
+/// Check an `Authorization: Basic ...` header value against credentials
+fn check_basic_auth(header: &str, credentials: &Credentials) -> bool {
+    let Some(encoded) = header.strip_prefix("Basic ") else {
+        return false;
+    };
+
+    let mut decoded = [0_u8; MAX_CREDENTIALS_SIZE];
+    let Ok(length) = base64::engine::general_purpose::STANDARD
+        .decode_slice(encoded.trim(), &mut decoded)
+    else {
+        return false;
+    };
+    let decoded = &decoded[..length];
+
+    let Some(separator) = decoded.iter().position(|&byte| byte == b':') else {
+        return false;
+    };
+    let (username, password) = (&decoded[..separator], &decoded[separator + 1..]);
+
+    // Evaluate both comparisons to avoid leaking which one failed
+    let username_matches = constant_time_eq(username, credentials.username.as_bytes());
+    let password_matches = constant_time_eq(password, credentials.password.as_bytes());
+    username_matches & password_matches
+}
+
+/// Compare two byte strings in time depending only on their lengths
+pub fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    left.iter()
+        .zip(right)
+        .fold(0_u8, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
+
+/// A layer requiring a valid `Authorization: Bearer <token>` header for
+/// every route of the router it is applied to
+///
+/// The token is checked against the [`ApiTokenStore`] in the app state.
+pub struct ApiTokenLayer;
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for ApiTokenLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let authorized = request_parts
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.as_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| state.api_token.matches(token.trim()));
+
+        if authorized {
+            next.run(state, path_parameters, response_writer).await
+        } else {
+            crate::log!("Rejected request without valid API token to {}", request_parts.path());
+            let error = ApiError::unauthorized("invalid_token").with_message("Missing or invalid API token");
+            let response = picoserve::response::Response::new(error.status_code, picoserve::response::Json(error))
+                .with_header("WWW-Authenticate", "Bearer");
+            response_writer
+                .write_response(next.into_connection(), response)
+                .await
+        }
+    }
+}
+
+/// Check whether a path is equal to or below a prefix
+pub(crate) fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Decode a hexadecimal string filling exactly the output buffer
+pub(crate) fn decode_hex(hex: &str, output: &mut [u8]) -> bool {
+    let hex = hex.as_bytes();
+    if hex.len() != 2 * output.len() {
+        return false;
+    }
+    for (byte, pair) in output.iter_mut().zip(hex.chunks_exact(2)) {
+        let (Some(high), Some(low)) = (hex_digit(pair[0]), hex_digit(pair[1])) else {
+            return false;
+        };
+        *byte = high << 4 | low;
+    }
+    true
+}
+
+/// Decode a single hexadecimal digit
+fn hex_digit(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Fallback service answering unknown paths with a JSON 404
+pub struct NotFound;
+
+impl picoserve::routing::PathRouterService<AppState> for NotFound {
+    async fn call_request_handler_service<R: Read, W: ResponseWriter<Error = R::Error>>(
+        &self,
+        _state: &AppState,
+        _path_parameters: (),
+        path: Path<'_>,
+        request: picoserve::request::Request<'_, R>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let body = NotFoundResponse::new(path);
+        let connection = request.body_connection.finalize().await?;
+        (StatusCode::NOT_FOUND, Json(body))
+            .write_to(connection, response_writer)
+            .await
+    }
+}
+
+/// Service answering unknown paths under `/.well-known/` with an empty 404
+///
+/// Scanners probe these paths constantly, the JSON body of [`NotFound`]
+/// would only cost time.
+pub struct WellKnownNotFound;
+
+impl picoserve::routing::PathRouterService<AppState> for WellKnownNotFound {
+    async fn call_request_handler_service<R: Read, W: ResponseWriter<Error = R::Error>>(
+        &self,
+        _state: &AppState,
+        _path_parameters: (),
+        _path: Path<'_>,
+        request: picoserve::request::Request<'_, R>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let connection = request.body_connection.finalize().await?;
+        (StatusCode::NOT_FOUND, "")
+            .write_to(connection, response_writer)
+            .await
+    }
+}
+
+/// A response writer replacing 405 responses with a JSON body and an
+/// `Allow` header
+struct MethodNotAllowedWriter<'r, W> {
+    path: Path<'r>,
+    response_writer: W,
+}
+
+impl<'r, W: ResponseWriter> ResponseWriter for MethodNotAllowedWriter<'r, W> {
+    type Error = W::Error;
+
+    async fn write_response<
+        R: Read<Error = Self::Error>,
+        H: picoserve::response::HeadersIter,
+        B: picoserve::response::Body,
+    >(
+        self,
+        connection: picoserve::response::Connection<'_, R>,
+        response: picoserve::response::Response<H, B>,
+    ) -> Result<picoserve::ResponseSent, Self::Error> {
+        if response.status_code() != StatusCode::METHOD_NOT_ALLOWED {
+            return self.response_writer.write_response(connection, response).await;
+        }
+
+        let allow = allowed_methods_on(self.path.encoded()).unwrap_or_default();
+        let error = ApiError::method_not_allowed();
+        let response = picoserve::response::Response::new(error.status_code, picoserve::response::Json(error))
+            .with_header("Allow", AllowHeader(allow));
+        self.response_writer.write_response(connection, response).await
+    }
+}
+
+/// A layer adding a JSON body and an `Allow` header to 405 responses
+struct MethodNotAllowedLayer;
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for MethodNotAllowedLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        next.run(
+            state,
+            path_parameters,
+            MethodNotAllowedWriter {
+                path: request_parts.path(),
+                response_writer,
+            },
+        )
+        .await
+    }
+}
+
+/// A layer answering `OPTIONS` requests to known routes with `204 No Content`
+/// and an `Allow` header, from [`routes`]
+///
+/// It goes outside the authentication layers, CORS preflight requests carry
+/// no credentials. Requests to unknown paths are passed through and get a
+/// 404.
+struct OptionsLayer {
+    prefixes: Option<&'static [&'static str]>,
+}
+
+impl OptionsLayer {
+    /// Answer for every route
+    const fn all() -> Self {
+        Self { prefixes: None }
+    }
+
+    /// Answer only for the routes equal to or below one of `prefixes`
+    const fn under(prefixes: &'static [&'static str]) -> Self {
+        Self {
+            prefixes: Some(prefixes),
+        }
+    }
+
+    /// Return the methods allowed on a path served by the router
+    fn methods_on(&self, path: &str) -> Option<&'static str> {
+        let served = self
+            .prefixes
+            .is_none_or(|prefixes| prefixes.iter().any(|prefix| path_has_prefix(path, prefix)));
+        served.then(|| allowed_methods_on(path)).flatten()
+    }
+}
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for OptionsLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let methods = if request_parts.method() == "OPTIONS" {
+            self.methods_on(request_parts.path().encoded())
+        } else {
+            None
+        };
+
+        match methods {
+            Some(methods) => {
+                let response = picoserve::response::Response::new(StatusCode::NO_CONTENT, "")
+                    .with_header("Allow", AllowHeader(methods));
+                response_writer
+                    .write_response(next.into_connection(), response)
+                    .await
+            }
+            None => next.run(state, path_parameters, response_writer).await,
+        }
+    }
+}
+
+/// Convert a file store error to a response
+fn file_error(error: files::Error) -> ApiError {
+    match error {
+        files::Error::InvalidName => ApiError::bad_request("invalid_name").with_message("Invalid file name"),
+        files::Error::TooLarge => ApiError::payload_too_large("file_too_large").with_message("File too large"),
+        files::Error::NoSpace => ApiError::new(StatusCode::INSUFFICIENT_STORAGE, "no_space", "Every file slot is used"),
+        files::Error::NotFound => ApiError::not_found("file_not_found").with_message("File not found"),
+        files::Error::Torn => {
+            ApiError::new(StatusCode::CONFLICT, "torn_file", "The last upload of the file did not complete")
+        }
+        files::Error::LengthMismatch => {
+            ApiError::bad_request("incomplete_body").with_message("The body ended before its announced length")
+        }
+        files::Error::CrcMismatch => ApiError::bad_request("crc_mismatch").with_message("CRC-32 mismatch"),
+        files::Error::Flash => ApiError::internal("flash_error").with_message("Flash error"),
+    }
+}
+
+/// Convert a schedule error to a response
+fn schedule_error(error: scheduler::Error) -> ApiError {
+    match error {
+        scheduler::Error::Full => ApiError::new(StatusCode::CONFLICT, "schedule_full", "The schedule is full"),
+        scheduler::Error::NotFound => ApiError::not_found("entry_not_found").with_message("Schedule entry not found"),
+        scheduler::Error::Storage(_) => ApiError::internal("flash_error").with_message("Flash error"),
+    }
+}
+
+/// Convert a stored networks error to a response
+fn networks_error(error: NetworksError) -> ApiError {
+    match error {
+        NetworksError::Full => ApiError::new(StatusCode::CONFLICT, "networks_full", "Too many stored networks"),
+        NetworksError::NotFound => ApiError::not_found("network_not_found").with_message("No stored network at this position"),
+        NetworksError::InvalidOrder => ApiError::bad_request("invalid_order").with_message("ssids must list every stored network once"),
+        NetworksError::LastNetwork => ApiError::new(StatusCode::CONFLICT, "last_network", "The only stored network cannot be removed"),
+        NetworksError::Storage(_) => ApiError::internal("flash_error").with_message("Flash error"),
+    }
+}
+
+/// Convert a Wi-Fi command error to a response
+fn command_error(error: wifi::CommandError) -> ApiError {
+    match error {
+        wifi::CommandError::NotStation => {
+            ApiError::new(StatusCode::CONFLICT, "not_station", "Wi-Fi runs the provisioning access point")
+        }
+        wifi::CommandError::Busy => ApiError::service_unavailable("wifi_busy").with_message("Wi-Fi commands pending, retry later"),
+        wifi::CommandError::Timeout => {
+            ApiError::new(StatusCode::GATEWAY_TIMEOUT, "wifi_timeout", "The Wi-Fi connection did not change in time")
+        }
+        wifi::CommandError::OtherNetwork => {
+            ApiError::new(StatusCode::CONFLICT, "other_network", "Joined another Wi-Fi network")
+        }
+    }
+}
+
+/// Convert a Wi-Fi credentials error to a response
+fn credentials_error(error: CredentialsError) -> ApiError {
+    match error {
+        CredentialsError::InvalidSsid => ApiError::bad_request("invalid_ssid").with_message("ssid must not be empty"),
+        CredentialsError::InvalidPassword => ApiError::bad_request("invalid_password")
+            .with_message("password must be 8 to 64 characters, or empty for an open network"),
+    }
+}
+
+/// Convert a webhook registry error to a response
+fn webhook_error(error: webhooks::Error) -> ApiError {
+    match error {
+        webhooks::Error::Full => ApiError::new(StatusCode::CONFLICT, "webhooks_full", "Too many webhooks"),
+        webhooks::Error::NotFound => ApiError::not_found("webhook_not_found").with_message("Webhook not found"),
+        webhooks::Error::Storage(_) => ApiError::internal("flash_error").with_message("Flash error"),
+    }
+}
+
+/// A failed upload
+enum UploadError<E> {
+    /// The socket failed, no response can be sent
+    Socket(E),
+
+    /// The upload was rejected
+    Rejected(ApiError),
+}
+
+impl<E> From<ApiError> for UploadError<E> {
+    fn from(error: ApiError) -> Self {
+        Self::Rejected(error)
+    }
+}
+
+impl<E> From<files::Error> for UploadError<E> {
+    fn from(error: files::Error) -> Self {
+        Self::Rejected(file_error(error))
+    }
+}
+
+impl<E> From<multipart::Error<E>> for UploadError<E> {
+    fn from(error: multipart::Error<E>) -> Self {
+        let error = match error {
+            multipart::Error::Io(error) => return Self::Socket(error),
+            multipart::Error::Timeout => {
+                ApiError::new(StatusCode::REQUEST_TIMEOUT, "body_timeout", "Timed out reading the request body")
+            }
+            multipart::Error::Truncated => {
+                ApiError::bad_request("missing_terminator").with_message("The multipart body is not terminated")
+            }
+            multipart::Error::Malformed => {
+                ApiError::bad_request("malformed_multipart").with_message("Malformed multipart body")
+            }
+            multipart::Error::HeaderTooLong => {
+                ApiError::bad_request("header_too_long").with_message("Multipart header too long")
+            }
+        };
+        Self::Rejected(error)
+    }
+}
+
+/// Service storing the first file of a `multipart/form-data` body in the
+/// file store, at `POST /upload`
+///
+/// The file is named after the file name sent by the browser, and replaces
+/// the file of the same name. Like [`FileUpload`] the content is streamed to
+/// flash, but its length is unknown until the closing delimiter, and there
+/// is no CRC-32 to check it against. The file is only committed once the
+/// whole body was parsed.
+struct MultipartUpload;
+
+impl MultipartUpload {
+    /// Stream the first file of the body into the store
+    async fn receive<R: Read>(
+        state: &AppState,
+        content_type: Option<&str>,
+        body_connection: &mut picoserve::request::RequestBodyConnection<'_, R>,
+    ) -> Result<FileResponse, UploadError<R::Error>> {
+        let store = state.files.ok_or(NO_FILE_STORE)?;
+        let boundary = content_type.and_then(multipart::boundary).ok_or(
+            ApiError::bad_request("invalid_boundary").with_message("Expected multipart/form-data with a boundary"),
+        )?;
+        let mut multipart = Multipart::new(body_connection.body().reader(), boundary, state.read_request_timeout);
+
+        let name = loop {
+            let part = multipart.next_part().await?.ok_or(
+                ApiError::bad_request("no_file").with_message("The body holds no file"),
+            )?;
+            if let Some(filename) = part.filename {
+                break String::<{ files::MAX_NAME_SIZE }>::try_from(filename.as_str())
+                    .map_err(|()| files::Error::InvalidName)?;
+            }
+        };
+
+        let mut upload = store.begin_unsized(&name).await?;
+        while let Some(chunk) = multipart.next_chunk().await? {
+            upload.write(chunk)?;
+        }
+        // The remaining parts are skipped, but must be well-formed
+        while multipart.next_part().await?.is_some() {}
+
+        let file = upload.finish_unsized()?;
+        Ok(FileResponse { name, length: file.len() })
+    }
+}
+
+impl picoserve::routing::RequestHandlerService<AppState> for MultipartUpload {
+    async fn call_request_handler_service<R: Read, W: ResponseWriter<Error = R::Error>>(
+        &self,
+        state: &AppState,
+        _path_parameters: (),
+        mut request: picoserve::request::Request<'_, R>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let content_type = request.parts.headers().get("Content-Type").and_then(|value| value.as_str().ok());
+        let result = Self::receive(state, content_type, &mut request.body_connection).await;
+        let connection = request.body_connection.finalize().await?;
+        match result {
+            Ok(file) => {
+                crate::log!("[{}] File {} uploaded as multipart ({} bytes)", RequestId::current(state.web_task), file.name, file.length);
+                (StatusCode::CREATED, Json(file))
+                    .write_to(connection, response_writer)
+                    .await
+            }
+            Err(UploadError::Rejected(error)) => {
+                crate::log!("[{}] Multipart upload failed: {}", RequestId::current(state.web_task), error.message);
+                error.write_to(connection, response_writer).await
+            }
+            Err(UploadError::Socket(error)) => Err(error),
+        }
+    }
+}
+
+/// Size of the chunks files are read from the request body and from flash
+const FILE_CHUNK_SIZE: usize = 512;
+
+/// Size of the CRC-32 trailing an uploaded file
+const FILE_CRC_SIZE: usize = 4;
+
+/// A file served from flash, whole or the single range requested
+///
+/// See [`crate::range`] for the supported `Range` headers.
+struct FlashFile {
+    store: &'static FileStore,
+    file: files::File,
+    content_type: &'static str,
+    range: ByteRange,
+}
+
+impl IntoResponse for FlashFile {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: picoserve::response::Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let length = self.file.len();
+        let body = |range: core::ops::Range<u32>| FlashFileBody {
+            store: self.store,
+            file: self.file,
+            content_type: self.content_type,
+            range,
+        };
+        match self.range.clone() {
+            ByteRange::Full => {
+                let response = picoserve::response::Response::new(StatusCode::OK, body(0..length))
+                    .with_header("Accept-Ranges", "bytes");
+                response_writer.write_response(connection, response).await
+            }
+            ByteRange::Partial(range) => {
+                let content_range = ContentRange { range: Some(range.clone()), length };
+                let response = picoserve::response::Response::new(StatusCode::PARTIAL_CONTENT, body(range))
+                    .with_header("Accept-Ranges", "bytes")
+                    .with_header("Content-Range", content_range);
+                response_writer.write_response(connection, response).await
+            }
+            ByteRange::Unsatisfiable => {
+                let error = ApiError::new(
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    "range_not_satisfiable",
+                    "The range is past the end of the file",
+                );
+                let response = picoserve::response::Response::new(error.status_code, picoserve::response::Json(error))
+                    .with_header("Content-Range", ContentRange { range: None, length });
+                response_writer.write_response(connection, response).await
+            }
+        }
+    }
+}
+
+/// The bytes of a range of a file served from flash
+///
+/// The range is read in chunks while it is written to the socket. If the file
+/// is replaced meanwhile, the response stops short of its `Content-Length`
+/// and the client sees a truncated response rather than a mix of both files.
+struct FlashFileBody {
+    store: &'static FileStore,
+    file: files::File,
+    content_type: &'static str,
+    range: core::ops::Range<u32>,
+}
+
+impl picoserve::response::Content for FlashFileBody {
+    fn content_type(&self) -> &'static str {
+        self.content_type
+    }
+
+    fn content_length(&self) -> usize {
+        self.range.len()
+    }
+
+    async fn write_content<W: picoserve::io::Write>(self, mut writer: W) -> Result<(), W::Error> {
+        let mut chunk = [0; FILE_CHUNK_SIZE];
+        let mut offset = self.range.start;
+        while offset < self.range.end {
+            let wanted = chunk.len().min((self.range.end - offset) as usize);
+            let Ok(length) = self.store.read(&self.file, offset, &mut chunk[..wanted]).await else {
+                crate::log!("File changed while it was served");
+                break;
+            };
+            if length == 0 {
+                break;
+            }
+            writer.write_all(&chunk[..length]).await?;
+            offset += length as u32;
+        }
+        Ok(())
+    }
+}
+
+/// A file upload, `PUT /files/{name}`
+///
+/// The body is the content of the file followed by its CRC-32, as computed
+/// by zlib, in little-endian:
+///
+/// ```text
+/// python3 -c 'import sys, zlib; d = open(sys.argv[1], "rb").read(); \
+///     sys.stdout.buffer.write(d + zlib.crc32(d).to_bytes(4, "little"))' page.html \
+///     | curl -u admin -T - -H 'Content-Length: ...' http://device/files/page.html
+/// ```
+///
+/// The body is streamed to flash in chunks as it is read, so it can be much
+/// larger than the HTTP buffer of the web task, up to
+/// [`MAX_FILE_SIZE`](files::MAX_FILE_SIZE). Each chunk must arrive within the
+/// read timeout of the server. The request needs a `Content-Length`, and is
+/// exempt from the [`BodyLimitLayer`].
+struct FileUpload;
+
+impl FileUpload {
+    /// Stream the body into the store
+    ///
+    /// Socket errors are returned as is, upload errors as the inner error.
+    async fn receive<R: Read>(
+        state: &AppState,
+        name: &str,
+        body_connection: &mut picoserve::request::RequestBodyConnection<'_, R>,
+    ) -> Result<Result<files::File, ApiError>, R::Error> {
+        let Some(store) = state.files else {
+            return Ok(Err(NO_FILE_STORE));
+        };
+        let content_length = body_connection.content_length();
+        let Some(length) = content_length.checked_sub(FILE_CRC_SIZE) else {
+            return Ok(Err(ApiError::bad_request("missing_crc").with_message("The body must end with a CRC-32")));
+        };
+        let Ok(length) = u32::try_from(length) else {
+            return Ok(Err(file_error(files::Error::TooLarge)));
+        };
+        let mut upload = match store.begin(name, length).await {
+            Ok(upload) => upload,
+            Err(error) => return Ok(Err(file_error(error))),
+        };
+
+        let mut reader = body_connection.body().reader();
+        let mut chunk = [0; FILE_CHUNK_SIZE];
+        let mut crc = [0; FILE_CRC_SIZE];
+        let mut received = 0;
+        while received < content_length {
+            let read = match state.read_request_timeout {
+                Some(timeout) => match embassy_time::with_timeout(timeout, reader.read(&mut chunk)).await {
+                    Ok(read) => read?,
+                    Err(_) => {
+                        return Ok(Err(ApiError::new(
+                            StatusCode::REQUEST_TIMEOUT,
+                            "body_timeout",
+                            "Timed out reading the request body",
+                        )));
+                    }
+                },
+                None => reader.read(&mut chunk).await?,
+            };
+            if read == 0 {
+                break;
+            }
+            let chunk = &chunk[..read];
+            // Split the chunk between the content and the trailing CRC-32
+            let content_end = (length as usize).saturating_sub(received).min(read);
+            if let Err(error) = upload.write(&chunk[..content_end]) {
+                return Ok(Err(file_error(error)));
+            }
+            for (index, &byte) in chunk[content_end..].iter().enumerate() {
+                crc[received + content_end + index - length as usize] = byte;
+            }
+            received += read;
+        }
+        if received < content_length {
+            return Ok(Err(file_error(files::Error::LengthMismatch)));
+        }
+
+        Ok(upload.finish(u32::from_le_bytes(crc)).map_err(file_error))
+    }
+}
+
+impl picoserve::routing::RequestHandlerService<AppState, String<{ files::MAX_NAME_SIZE }>> for FileUpload {
+    async fn call_request_handler_service<R: Read, W: ResponseWriter<Error = R::Error>>(
+        &self,
+        state: &AppState,
+        name: String<{ files::MAX_NAME_SIZE }>,
+        mut request: picoserve::request::Request<'_, R>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let result = Self::receive(state, &name, &mut request.body_connection).await?;
+        let connection = request.body_connection.finalize().await?;
+        match result {
+            Ok(file) => {
+                crate::log!("[{}] File {} uploaded ({} bytes)", RequestId::current(state.web_task), name, file.len());
+                (StatusCode::CREATED, Json(FileResponse { name, length: file.len() }))
+                    .write_to(connection, response_writer)
+                    .await
+            }
+            Err(error) => {
+                crate::log!("[{}] Upload of file {} failed: {}", RequestId::current(state.web_task), name, error.message);
+                error.write_to(connection, response_writer).await
+            }
+        }
+    }
+}
+
+/// JSON body returned by `PUT /files/{name}`
+#[derive(Serialize)]
+pub struct FileResponse {
+    /// Name of the file
+    pub name: String<{ files::MAX_NAME_SIZE }>,
+
+    /// Length of the file in bytes
+    pub length: u32,
+}
+
+/// Size of the chunks the body of `POST /echo` is read in
+const ECHO_CHUNK_SIZE: usize = 256;
+
+/// Largest body sent back by `POST /echo?body=1`, larger bodies only get the
+/// report
+pub const MAX_ECHO_RESPONSE_SIZE: usize = 1024;
+
+/// JSON body returned by `POST /echo`
+#[derive(Serialize)]
+pub struct EchoResponse {
+    /// Number of bytes received
+    pub bytes: usize,
+
+    /// CRC-32 of the body as computed by zlib, in hexadecimal
+    pub crc32: String<8>,
+}
+
+impl EchoResponse {
+    /// Report a body of `bytes` bytes
+    pub fn new(bytes: usize, crc: &files::Crc32) -> Self {
+        let mut crc32 = String::new();
+        // Cannot fail, a u32 has 8 hexadecimal digits
+        let _ = write!(crc32, "{:08x}", crc.finish());
+        Self { bytes, crc32 }
+    }
+}
+
+/// A loopback for diagnosing links and clients, `POST /echo`
+///
+/// The body is read in chunks and reported with its length and CRC-32, or
+/// sent back as is with `?body=1` when it fits
+/// [`MAX_ECHO_RESPONSE_SIZE`]. Bodies are bounded by the [`BodyLimitLayer`].
+///
+/// A body shorter than its `Content-Length` is told apart from a client that
+/// went away: the first stalls until the read timeout and gets `408`, the
+/// second closes the connection and is only logged, since there is no one
+/// left to answer.
+struct Echo;
+
+/// The body of `POST /echo`, read
+struct EchoBody {
+    /// Length and CRC-32 of the body
+    report: EchoResponse,
+
+    /// The body, if it is to be sent back
+    echoed: Option<Bytes<MAX_ECHO_RESPONSE_SIZE>>,
+}
+
+impl Echo {
+    /// Read the body, keeping it if `echo` and it fits
+    ///
+    /// Socket errors are returned as is, incomplete bodies as the inner
+    /// error.
+    async fn receive<R: Read>(
+        state: &AppState,
+        echo: bool,
+        body_connection: &mut picoserve::request::RequestBodyConnection<'_, R>,
+    ) -> Result<Result<EchoBody, ApiError>, R::Error> {
+        let content_length = body_connection.content_length();
+        let mut echoed = (echo && content_length <= MAX_ECHO_RESPONSE_SIZE).then(heapless::Vec::new);
+
+        let mut reader = body_connection.body().reader();
+        let mut chunk = [0; ECHO_CHUNK_SIZE];
+        let mut crc = files::Crc32::new();
+        let mut received = 0;
+        while received < content_length {
+            let read = match state.read_request_timeout {
+                Some(timeout) => match embassy_time::with_timeout(timeout, reader.read(&mut chunk)).await {
+                    Ok(read) => read?,
+                    Err(_) => {
+                        crate::log!(
+                            warn: "[{}] Echo body stalled after {} of {} bytes",
+                            RequestId::current(state.web_task),
+                            received,
+                            content_length
+                        );
+                        return Ok(Err(ApiError::new(
+                            StatusCode::REQUEST_TIMEOUT,
+                            "length_mismatch",
+                            "The body is shorter than its Content-Length",
+                        )));
+                    }
+                },
+                None => reader.read(&mut chunk).await?,
+            };
+            if read == 0 {
+                crate::log!(
+                    warn: "[{}] Client aborted the echo body after {} of {} bytes",
+                    RequestId::current(state.web_task),
+                    received,
+                    content_length
+                );
+                return Ok(Err(ApiError::bad_request("client_aborted")
+                    .with_message("The connection closed before the end of the body")));
+            }
+            let chunk = &chunk[..read];
+            crc.update(chunk);
+            if let Some(echoed) = &mut echoed {
+                // Cannot fail, the whole body fits
+                let _ = echoed.extend_from_slice(chunk);
+            }
+            received += read;
+        }
+
+        Ok(Ok(EchoBody {
+            report: EchoResponse::new(received, &crc),
+            echoed: echoed.map(Bytes),
+        }))
+    }
+}
+
+impl picoserve::routing::RequestHandlerService<AppState> for Echo {
+    async fn call_request_handler_service<R: Read, W: ResponseWriter<Error = R::Error>>(
+        &self,
+        state: &AppState,
+        _path_parameters: (),
+        mut request: picoserve::request::Request<'_, R>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let echo = request
+            .parts
+            .query()
+            .is_some_and(|query| QueryParams::parse(query.0).get("body") == Some("1"));
+        let result = Self::receive(state, echo, &mut request.body_connection).await?;
+        let connection = request.body_connection.finalize().await?;
+        match result {
+            Ok(EchoBody { echoed: Some(body), .. }) => body.write_to(connection, response_writer).await,
+            Ok(EchoBody { report, .. }) => Json(report).write_to(connection, response_writer).await,
+            Err(error) => error.write_to(connection, response_writer).await,
+        }
+    }
+}
+
+/// Request body size allowed unless configured otherwise
+pub const DEFAULT_MAX_REQUEST_BODY_SIZE: usize = 1024;
+
+/// A JSON body served from the [`ResponseCache`]
+struct CachedJson(heapless::Vec<u8, CACHE_BODY_SIZE>);
+
+impl picoserve::response::Content for CachedJson {
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn content_length(&self) -> usize {
+        self.0.len()
+    }
+
+    async fn write_content<W: picoserve::io::Write>(self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(&self.0).await
+    }
+}
+
+/// A layer answering `GET` requests to cached routes from the
+/// [`ResponseCache`], and invalidating the cache on other requests to them
+///
+/// Only routes at the root are cached: this layer runs before the API token
+/// check of the API group, which must not be bypassed.
+struct CacheLayer;
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for CacheLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let path = request_parts.path();
+        let path = path.encoded();
+        if !state.cache.is_cached(path) || path_has_prefix(path, API_V1_PREFIX) {
+            return next.run(state, path_parameters, response_writer).await;
+        }
+
+        if request_parts.method() != "GET" {
+            state.cache.invalidate(path);
+            return next.run(state, path_parameters, response_writer).await;
+        }
+
+        match state.cache.get(path) {
+            Some(body) => {
+                state.stats.record_cache(true);
+                let response = picoserve::response::Response::new(StatusCode::OK, CachedJson(body))
+                    .with_header("X-Cache", "HIT");
+                response_writer
+                    .write_response(next.into_connection(), response)
+                    .await
+            }
+            None => {
+                state.stats.record_cache(false);
+                next.run(state, path_parameters, response_writer).await
+            }
+        }
+    }
+}
+
+/// Check whether a `Content-Length` header value exceeds a limit
+///
+/// Values that are not a number are left for the HTTP parser to reject.
+pub fn body_too_large(content_length: &str, limit: usize) -> bool {
+    content_length
+        .trim()
+        .parse::<usize>()
+        .is_ok_and(|length| length > limit)
+}
+
+/// A layer answering 403 on the [`ADMIN_PATHS`] to clients outside of
+/// [`AppState::admin_allowlist`]
+///
+/// This guards against the device being port-forwarded by accident. Clients
+/// of unknown or IPv6 addresses are rejected.
+struct AdminSourceLayer;
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for AdminSourceLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let path = request_parts.path();
+        let is_admin = ADMIN_PATHS
+            .iter()
+            .any(|prefix| path_has_prefix(path.encoded(), prefix));
+        let allowed = match state.remote_endpoint.map(|endpoint| endpoint.addr) {
+            Some(IpAddress::Ipv4(address)) => cidr::is_allowed(state.admin_allowlist, address),
+            _ => false,
+        };
+
+        if is_admin && !allowed {
+            crate::log!("Rejected request to {} from {:?}", path, state.remote_endpoint);
+            let error = ApiError::new(
+                StatusCode::FORBIDDEN,
+                "forbidden_source",
+                "Admin routes are only reachable from the local network",
+            );
+            let response = picoserve::response::Response::new(error.status_code, picoserve::response::Json(error));
+            response_writer
+                .write_response(next.into_connection(), response)
+                .await
+        } else {
+            next.run(state, path_parameters, response_writer).await
+        }
+    }
+}
+
+/// A layer answering 404 for the [`ADMIN_PATHS`] when they are served on an
+/// admin port instead, see [`AdminApplication`]
+struct AdminPortLayer;
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for AdminPortLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let path = request_parts.path();
+        let hidden = state.admin_port.is_some()
+            && ADMIN_PATHS
+                .iter()
+                .any(|prefix| path_has_prefix(path.encoded(), prefix));
+
+        if hidden {
+            let response = picoserve::response::Response::new(
+                StatusCode::NOT_FOUND,
+                picoserve::response::Json(NotFoundResponse::new(path)),
+            );
+            response_writer
+                .write_response(next.into_connection(), response)
+                .await
+        } else {
+            next.run(state, path_parameters, response_writer).await
+        }
+    }
+}
+
+/// A layer rejecting requests with a body larger than
+/// [`AppState::max_request_body_size`] with 413, before any handler runs
+///
+/// The body is not read and the connection is closed, so a large upload does
+/// not tie up a web task until it times out. Requests without a
+/// `Content-Length` have no body for picoserve, so they always pass.
+///
+/// File uploads under `/files` and at `/upload` are streamed to flash rather
+/// than buffered, so they are exempt.
+struct BodyLimitLayer;
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for BodyLimitLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let path = request_parts.path().encoded();
+        let too_large = !path_has_prefix(path, "/files")
+            && !path_has_prefix(path, "/upload")
+            && request_parts
+                .headers()
+                .get("Content-Length")
+                .and_then(|value| value.as_str().ok())
+                .is_some_and(|value| body_too_large(value, state.max_request_body_size));
+
+        if too_large {
+            crate::log!("Rejected oversized request body to {}", request_parts.path());
+            let error = ApiError::payload_too_large("body_too_large").with_message("Request body too large");
+            let response = picoserve::response::Response::new(error.status_code, picoserve::response::Json(error))
+                .with_header("Connection", "close");
+            response_writer
+                .write_response(next.into_connection(), response)
+                .await
+        } else {
+            next.run(state, path_parameters, response_writer).await
+        }
+    }
+}
+
+/// The canonical form of a path, without duplicate or trailing slashes
+///
+/// The canonical form is written by the `Display` implementation, without
+/// copying the path.
+pub struct NormalizedPath<'a>(&'a str);
+
+impl<'a> NormalizedPath<'a> {
+    /// Return the canonical form of a path, or `None` if the path is already
+    /// canonical
+    ///
+    /// The root `/` is canonical, as are paths not starting with `/`.
+    pub fn new(path: &'a str) -> Option<Self> {
+        let canonical =
+            path == "/" || !path.starts_with('/') || !(path.ends_with('/') || path.contains("//"));
+        (!canonical).then_some(Self(path))
+    }
+}
+
+impl core::fmt::Display for NormalizedPath<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut segments = self.0.split('/').filter(|segment| !segment.is_empty()).peekable();
+        if segments.peek().is_none() {
+            return f.write_char('/');
+        }
+        segments.try_for_each(|segment| write!(f, "/{}", segment))
+    }
+}
+
+/// The location of a redirect to a canonical path, keeping the query string
+struct CanonicalLocation<'a> {
+    path: NormalizedPath<'a>,
+    query: Option<&'a str>,
+}
+
+impl core::fmt::Display for CanonicalLocation<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.path)?;
+        match self.query {
+            Some(query) => write!(f, "?{}", query),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A layer redirecting `GET` and `HEAD` requests for non-canonical paths,
+/// such as `/time/` or `//time`, to their [`NormalizedPath`] with 301
+///
+/// Picoserve routes on the path of the request line, which a layer cannot
+/// rewrite, so the client is redirected instead. Other methods are left
+/// alone, as clients may follow a 301 with a `GET`. Disabled when
+/// [`AppState::normalize_paths`] is false.
+struct NormalizePathLayer;
+
+impl<PathParameters> picoserve::routing::Layer<AppState, PathParameters> for NormalizePathLayer {
+    type NextState = AppState;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: picoserve::routing::Next<'a, R, Self::NextState, Self::NextPathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &AppState,
+        path_parameters: PathParameters,
+        request_parts: picoserve::request::RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        let redirected = state.normalize_paths && matches!(request_parts.method(), "GET" | "HEAD");
+        let path = request_parts.path();
+        match NormalizedPath::new(path.encoded()).filter(|_| redirected) {
+            Some(path) => {
+                let location = CanonicalLocation {
+                    path,
+                    query: request_parts.query().map(|query| query.0),
+                };
+                Redirect::permanent(location)
+                    .write_to(next.into_connection(), response_writer)
+                    .await
+            }
+            None => next.run(state, path_parameters, response_writer).await,
+        }
+    }
+}
+
+/// Maximal number of parameters kept from a query string
+pub const MAX_QUERY_PARAMS: usize = 8;
+
+/// Maximal length of a decoded query parameter name
+pub const MAX_QUERY_KEY: usize = 16;
+
+/// Maximal length of a decoded query parameter value
+pub const MAX_QUERY_VALUE: usize = 64;
+
+/// Parameters of a URL-encoded query string
+///
+/// Parameters without `=` have an empty value. Parameters that cannot be
+/// decoded or do not fit the bounds are dropped, as are parameters past
+/// [`MAX_QUERY_PARAMS`].
+#[derive(Debug, Default)]
+pub struct QueryParams {
+    params: heapless::Vec<(String<MAX_QUERY_KEY>, String<MAX_QUERY_VALUE>), MAX_QUERY_PARAMS>,
+}
+
+impl QueryParams {
+    /// Parse a query string, without the leading `?`
+    pub fn parse(query: &str) -> Self {
+        let mut params = heapless::Vec::new();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let (Some(key), Some(value)) = (percent_decode(key), percent_decode(value)) else {
+                continue;
+            };
+            if params.push((key, value)).is_err() {
+                break;
+            }
+        }
+        Self { params }
+    }
+
+    /// Return the value of the first parameter with a name
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Iterate over all parameters in order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+impl<'r, State> picoserve::extract::FromRequestParts<'r, State> for QueryParams {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        _state: &'r State,
+        request_parts: &picoserve::request::RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(request_parts
+            .query()
+            .map(|query| Self::parse(query.0))
+            .unwrap_or_default())
+    }
+}
+
+/// Decode a URL-encoded string, with `+` standing for a space
+///
+/// Returns `None` on invalid escapes, invalid UTF-8 or overflow.
+pub fn percent_decode<const N: usize>(input: &str) -> Option<String<N>> {
+    let mut bytes = heapless::Vec::<u8, N>::new();
+    let mut input = input.bytes();
+    while let Some(byte) = input.next() {
+        let decoded = match byte {
+            b'+' => b' ',
+            b'%' => {
+                let high = hex_digit(input.next()?)?;
+                let low = hex_digit(input.next()?)?;
+                high << 4 | low
+            }
+            byte => byte,
+        };
+        bytes.push(decoded).ok()?;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// A raw request body of at most `N` bytes, or a binary response
+pub struct Bytes<const N: usize>(pub heapless::Vec<u8, N>);
+
+impl<const N: usize> Bytes<N> {
+    /// Copy a slice of at most `N` bytes
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        Self(heapless::Vec::from_slice(&bytes[..bytes.len().min(N)]).unwrap_or_default())
+    }
+}
+
+impl<'r, State, const N: usize> picoserve::extract::FromRequest<'r, State> for Bytes<N> {
+    type Rejection = ApiError;
+
+    async fn from_request<R: Read>(
+        _state: &'r State,
+        _request_parts: picoserve::request::RequestParts<'r>,
+        request_body: picoserve::request::RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        let too_large = ApiError::payload_too_large("body_too_large").with_message("Request body too large");
+        if request_body.content_length() > N {
+            return Err(too_large);
+        }
+        let body = request_body.read_all().await.map_err(|_| {
+            ApiError::bad_request("unreadable_body").with_message("Failed to read request body")
+        })?;
+        heapless::Vec::from_slice(body).map(Self).map_err(|()| too_large)
+    }
+}
+
+impl<const N: usize> picoserve::response::Content for Bytes<N> {
+    fn content_type(&self) -> &'static str {
+        "application/octet-stream"
+    }
+
+    fn content_length(&self) -> usize {
+        self.0.len()
+    }
+
+    async fn write_content<W: picoserve::io::Write>(self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(&self.0).await
+    }
+}
+
+/// Maximal size of a form request body
+pub const MAX_FORM_BODY_SIZE: usize = 512;
+
+/// A form request body encoded as `application/x-www-form-urlencoded`
+///
+/// The body is decoded like a query string, see [`QueryParams`]. Forms
+/// posted from the pages of a session use [`CsrfForm`] instead.
+pub struct Form(pub QueryParams);
+
+impl<'r, State> picoserve::extract::FromRequest<'r, State> for Form {
+    type Rejection = FormRejection;
+
+    async fn from_request<R: Read>(
+        _state: &'r State,
+        request_parts: picoserve::request::RequestParts<'r>,
+        request_body: picoserve::request::RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        let is_form = request_parts
+            .headers()
+            .get("Content-Type")
+            .and_then(|value| value.as_str().ok())
+            .is_some_and(|content_type| {
+                let media_type = content_type.split(';').next().unwrap_or("").trim();
+                media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+            });
+        if !is_form {
+            return Err(FormRejection::UnsupportedMediaType);
+        }
+        // Reject oversized bodies before reading them
+        if request_body.content_length() > MAX_FORM_BODY_SIZE {
+            return Err(FormRejection::PayloadTooLarge);
+        }
+        let body = request_body
+            .read_all()
+            .await
+            .map_err(|_| FormRejection::Malformed)?;
+        parse_form_body(body).map(Self)
+    }
+}
+
+/// Parse a form request body
+pub fn parse_form_body(body: &[u8]) -> Result<QueryParams, FormRejection> {
+    if body.len() > MAX_FORM_BODY_SIZE {
+        return Err(FormRejection::PayloadTooLarge);
+    }
+    let body = core::str::from_utf8(body).map_err(|_| FormRejection::Malformed)?;
+    Ok(QueryParams::parse(body.trim_end()))
+}
+
+/// A reason for rejecting a form request body
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormRejection {
+    /// The body is not `application/x-www-form-urlencoded`
+    UnsupportedMediaType,
+
+    /// The body is larger than [`MAX_FORM_BODY_SIZE`]
+    PayloadTooLarge,
+
+    /// The body is not valid UTF-8
+    Malformed,
+
+    /// The CSRF token is missing or does not match the session, see
+    /// [`CsrfForm`]
+    CsrfTokenMismatch,
+}
+
+impl From<FormRejection> for ApiError {
+    fn from(rejection: FormRejection) -> Self {
+        match rejection {
+            FormRejection::UnsupportedMediaType => ApiError::unsupported_media_type("unsupported_media_type")
+                .with_message("Expected a form request body"),
+            FormRejection::PayloadTooLarge => {
+                ApiError::payload_too_large("body_too_large").with_message("Request body too large")
+            }
+            FormRejection::Malformed => {
+                ApiError::bad_request("malformed_form").with_message("Malformed form request body")
+            }
+            FormRejection::CsrfTokenMismatch => {
+                ApiError::new(StatusCode::FORBIDDEN, "csrf_token_mismatch", "Missing or invalid CSRF token")
+            }
+        }
+    }
+}
+
+impl IntoResponse for FormRejection {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: picoserve::response::Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        ApiError::from(self).write_to(connection, response_writer).await
+    }
+}
+
+/// A URL-encoded form request body posted from a page of a session
+///
+/// The form must carry the CSRF token of the session in the
+/// [`CSRF_FIELD`](session::CSRF_FIELD) field, otherwise the request is
+/// rejected with 403. This keeps other sites from posting the form with the
+/// session cookie of the browser. Requests authenticated with a valid API
+/// token in an `Authorization: Bearer` header are exempt, because browsers
+/// never add one on their own; the token is checked like in
+/// [`ApiTokenLayer`], so a made-up one does not skip the check.
+pub struct CsrfForm(pub QueryParams);
+
+impl<'r> picoserve::extract::FromRequest<'r, AppState> for CsrfForm {
+    type Rejection = FormRejection;
+
+    async fn from_request<R: Read>(
+        state: &'r AppState,
+        request_parts: picoserve::request::RequestParts<'r>,
+        request_body: picoserve::request::RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        let bearer = request_parts
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.as_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| state.api_token.matches(token.trim()));
+        let session = SessionCookie::parse(&request_parts).0;
+        let Form(form) =
+            <Form as picoserve::extract::FromRequest<'r, AppState>>::from_request(state, request_parts, request_body)
+                .await?;
+
+        let valid = bearer
+            || session.is_some_and(|id| {
+                let token = form.get(session::CSRF_FIELD).unwrap_or_default();
+                state.sessions.check_csrf_token(&id, token)
+            });
+        if valid {
+            Ok(Self(form))
+        } else {
+            crate::log!("Rejected form without a valid CSRF token");
+            Err(FormRejection::CsrfTokenMismatch)
+        }
+    }
+}
+
+/// A JSON request body or response
+///
+/// As an extractor, the body is limited to [`MAX_JSON_BODY_SIZE`] bytes and
+/// deserialized with `serde-json-core`. As a response, the value is
+/// serialized with `Content-Type: application/json`.
+pub struct Json<T>(pub T);
+
+impl<'r, State, T: DeserializeOwned> picoserve::extract::FromRequest<'r, State> for Json<T> {
+    type Rejection = JsonRejection;
+
+    async fn from_request<R: Read>(
+        _state: &'r State,
+        _request_parts: picoserve::request::RequestParts<'r>,
+        request_body: picoserve::request::RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        // Reject oversized bodies before reading them
+        if request_body.content_length() > MAX_JSON_BODY_SIZE {
+            return Err(JsonRejection::PayloadTooLarge);
+        }
+        let body = request_body
+            .read_all()
+            .await
+            .map_err(|_| JsonRejection::Malformed)?;
+        parse_json_body(body).map(Self)
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: picoserve::response::Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        picoserve::response::Json(self.0)
+            .write_to(connection, response_writer)
+            .await
+    }
+}
+
+/// Parse a JSON request body
+///
+/// The body must contain exactly one JSON value, optionally surrounded by
+/// whitespace.
+pub fn parse_json_body<T: DeserializeOwned>(body: &[u8]) -> Result<T, JsonRejection> {
+    if body.len() > MAX_JSON_BODY_SIZE {
+        return Err(JsonRejection::PayloadTooLarge);
+    }
+    // Trailing characters are rejected by the deserializer
+    let (value, _) = serde_json_core::from_slice(body).map_err(|_| JsonRejection::Malformed)?;
+    Ok(value)
+}
+
+/// The reason a JSON request body was rejected
+#[derive(Debug, PartialEq, Eq)]
+pub enum JsonRejection {
+    /// The body is larger than [`MAX_JSON_BODY_SIZE`]
+    PayloadTooLarge,
+
+    /// The body is not valid JSON for the expected type
+    Malformed,
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::PayloadTooLarge => {
+                ApiError::payload_too_large("body_too_large").with_message("Request body too large")
+            }
+            JsonRejection::Malformed => {
+                ApiError::bad_request("malformed_json").with_message("Malformed JSON request body")
+            }
+        }
+    }
+}
+
+impl IntoResponse for JsonRejection {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: picoserve::response::Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<picoserve::ResponseSent, W::Error> {
+        ApiError::from(self).write_to(connection, response_writer).await
+    }
+}
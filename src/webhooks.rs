@@ -0,0 +1,427 @@
+//! Outbound notifications of device events
+//!
+//! Events like a boot, the loss of the Wi-Fi association or a change of a
+//! GPIO input are pushed with [`notify`] onto a queue, so producers never
+//! wait on the network. [`webhook_task`] takes them off the queue and POSTs
+//! a JSON payload to every registered URL, retrying once after
+//! [`RETRY_DELAY`]. Deliveries wait for the station to have an address, so
+//! events of an outage are delivered once it is over. Events pushed while
+//! the queue is full are dropped and counted.
+//!
+//! URLs are managed at `/webhooks` and saved in the key-value store, one key
+//! per URL since a value holds a single URL at most.
+
+use core::cell::RefCell;
+use core::fmt::Write as _;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use heapless::{String, Vec};
+use portable_atomic::{AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
+
+use crate::http::SharedClient;
+use crate::stats::Stats;
+use crate::storage::{self, Storage};
+use crate::wifi::{self, WifiStateReceiver};
+
+/// Maximal number of registered URLs
+pub const MAX_WEBHOOKS: usize = 4;
+
+/// Maximal length of a URL
+pub const MAX_URL_SIZE: usize = storage::MAX_VALUE_SIZE;
+
+/// Prefix of the keys of the URLs in the key-value store
+pub const STORAGE_KEY_PREFIX: &str = "webhook";
+
+/// Number of events waiting for delivery before new ones are dropped
+pub const EVENT_QUEUE_SIZE: usize = 8;
+
+/// Time allowed for a delivery, including waiting for the network and the
+/// client
+pub const DELIVERY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Time to wait before retrying a failed delivery
+pub const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Maximal size of a JSON payload
+const PAYLOAD_SIZE: usize = 128;
+
+/// Events waiting for delivery
+static EVENTS: Channel<CriticalSectionRawMutex, Event, EVENT_QUEUE_SIZE> = Channel::new();
+
+/// Number of events dropped because the queue was full
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// A device event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The device booted
+    Boot {
+        /// Number of boots since power-up
+        boot_count: u32,
+    },
+
+    /// The Wi-Fi association was lost
+    WifiDisconnected,
+
+    /// The station lost its address
+    LinkLost {
+        /// Seconds since boot when it was lost
+        uptime: u64,
+    },
+
+    /// The station got an address back after losing it
+    LinkRestored {
+        /// Seconds since boot when it was restored
+        uptime: u64,
+
+        /// Seconds without an address
+        down_for: u64,
+    },
+
+    /// The Wi-Fi signal dropped below the low threshold
+    SignalLow {
+        /// Sampled RSSI in dBm
+        rssi: i32,
+    },
+
+    /// The Wi-Fi signal rose back above the recovery threshold
+    SignalRecovered {
+        /// Sampled RSSI in dBm
+        rssi: i32,
+    },
+
+    /// A GPIO input changed level
+    GpioInput {
+        /// Number of the pin
+        pin: u8,
+
+        /// New level of the pin
+        high: bool,
+    },
+}
+
+/// Queue an event for delivery, without waiting
+///
+/// The event is dropped if the queue is full.
+pub fn notify(event: Event) {
+    if EVENTS.try_send(event).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        crate::log!(warn: "Warning: webhook queue full, dropped {:?}", event);
+    }
+}
+
+/// Return the number of events dropped because the queue was full
+pub fn dropped_events() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// JSON body POSTed to the webhooks
+#[derive(Debug, Serialize)]
+pub struct Payload {
+    /// Name of the event, e.g. `boot`
+    pub event: &'static str,
+
+    /// Seconds since boot, when the event happened for the link events and
+    /// when it was delivered for the others
+    pub uptime: u64,
+
+    /// Number of boots since power-up, for `boot`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boot_count: Option<u32>,
+
+    /// Number of the pin, for `gpio_input`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin: Option<u8>,
+
+    /// New level of the pin, for `gpio_input`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub high: Option<bool>,
+
+    /// Sampled RSSI in dBm, for `signal_low` and `signal_recovered`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rssi: Option<i32>,
+
+    /// Seconds without an address, for `link_restored`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub down_for: Option<u64>,
+}
+
+impl Payload {
+    /// Describe an event that happened `uptime` seconds after boot
+    pub fn new(event: Event, uptime: u64) -> Self {
+        let mut payload = Self {
+            event: "",
+            uptime,
+            boot_count: None,
+            pin: None,
+            high: None,
+            rssi: None,
+            down_for: None,
+        };
+        match event {
+            Event::Boot { boot_count } => {
+                payload.event = "boot";
+                payload.boot_count = Some(boot_count);
+            }
+            Event::WifiDisconnected => payload.event = "wifi_disconnected",
+            Event::LinkLost { uptime } => {
+                payload.event = "link_lost";
+                payload.uptime = uptime;
+            }
+            Event::LinkRestored { uptime, down_for } => {
+                payload.event = "link_restored";
+                payload.uptime = uptime;
+                payload.down_for = Some(down_for);
+            }
+            Event::SignalLow { rssi } => {
+                payload.event = "signal_low";
+                payload.rssi = Some(rssi);
+            }
+            Event::SignalRecovered { rssi } => {
+                payload.event = "signal_recovered";
+                payload.rssi = Some(rssi);
+            }
+            Event::GpioInput { pin, high } => {
+                payload.event = "gpio_input";
+                payload.pin = Some(pin);
+                payload.high = Some(high);
+            }
+        }
+        payload
+    }
+}
+
+/// Check whether a URL can be delivered to
+pub fn is_valid_url(url: &str) -> bool {
+    let Some(rest) = url
+        .strip_prefix("http://")
+        .or_else(|| url.strip_prefix("https://"))
+    else {
+        return false;
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    !host.is_empty() && url.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+/// An error of the webhook registry
+#[derive(Debug)]
+pub enum Error {
+    /// [`MAX_WEBHOOKS`] URLs are registered
+    Full,
+
+    /// No URL has the identifier
+    NotFound,
+
+    /// The URLs could not be saved
+    Storage(storage::Error),
+}
+
+/// The registered URLs, identified by their slot
+pub struct Webhooks {
+    urls: Mutex<CriticalSectionRawMutex, RefCell<[Option<String<MAX_URL_SIZE>>; MAX_WEBHOOKS]>>,
+    storage: Option<&'static Storage>,
+}
+
+impl Webhooks {
+    /// Create an empty registry, saved in `storage` if any
+    pub fn new(storage: Option<&'static Storage>) -> Self {
+        Self {
+            urls: Mutex::new(RefCell::new([const { None }; MAX_WEBHOOKS])),
+            storage,
+        }
+    }
+
+    /// Return every URL with its identifier
+    pub fn urls(&self) -> Vec<(u8, String<MAX_URL_SIZE>), MAX_WEBHOOKS> {
+        self.urls.lock(|urls| {
+            urls.borrow()
+                .iter()
+                .zip(0..)
+                .filter_map(|(url, id)| Some((id, url.clone()?)))
+                .collect()
+        })
+    }
+
+    /// Register a URL and return its identifier
+    pub async fn add(&self, url: String<MAX_URL_SIZE>) -> Result<u8, Error> {
+        let id = self.urls.lock(|urls| {
+            let mut urls = urls.borrow_mut();
+            let (slot, id) = urls
+                .iter_mut()
+                .zip(0..)
+                .find(|(slot, _)| slot.is_none())
+                .ok_or(Error::Full)?;
+            *slot = Some(url.clone());
+            Ok(id)
+        })?;
+        if let Some(storage) = self.storage {
+            storage
+                .set(&storage_key(id), url.as_bytes())
+                .await
+                .map_err(Error::Storage)?;
+        }
+        Ok(id)
+    }
+
+    /// Unregister a URL
+    pub async fn remove(&self, id: u8) -> Result<(), Error> {
+        self.urls.lock(|urls| {
+            urls.borrow_mut()
+                .get_mut(usize::from(id))
+                .and_then(Option::take)
+                .map(|_| ())
+                .ok_or(Error::NotFound)
+        })?;
+        if let Some(storage) = self.storage {
+            storage
+                .delete(&storage_key(id))
+                .await
+                .map_err(Error::Storage)?;
+        }
+        Ok(())
+    }
+
+    /// Replace every URL, which must have been checked with [`is_valid_url`]
+    ///
+    /// URLs get the identifier of their position, and only the slots that
+    /// changed are written to the key-value store.
+    pub async fn replace(&self, urls: &[String<MAX_URL_SIZE>]) -> Result<(), Error> {
+        if urls.len() > MAX_WEBHOOKS {
+            return Err(Error::Full);
+        }
+        let mut slots = [const { None }; MAX_WEBHOOKS];
+        for (slot, url) in slots.iter_mut().zip(urls) {
+            *slot = Some(url.clone());
+        }
+        let previous = self
+            .urls
+            .lock(|urls| core::mem::replace(&mut *urls.borrow_mut(), slots.clone()));
+
+        let Some(storage) = self.storage else {
+            return Ok(());
+        };
+        for ((id, slot), previous) in (0..).zip(&slots).zip(&previous) {
+            if slot == previous {
+                continue;
+            }
+            let key = storage_key(id);
+            match slot {
+                Some(url) => storage.set(&key, url.as_bytes()).await,
+                None => storage.delete(&key).await,
+            }
+            .map_err(Error::Storage)?;
+        }
+        Ok(())
+    }
+
+    /// Load the URLs from the key-value store
+    ///
+    /// Values that are not valid URLs are dropped.
+    pub async fn load(&self) {
+        let Some(storage) = self.storage else {
+            return;
+        };
+        let mut value = [0; storage::MAX_VALUE_SIZE];
+        for id in 0..MAX_WEBHOOKS as u8 {
+            match storage.get(&storage_key(id), &mut value).await {
+                Ok(Some(length)) => {
+                    let url = core::str::from_utf8(&value[..length])
+                        .ok()
+                        .filter(|url| is_valid_url(url))
+                        .and_then(|url| String::try_from(url).ok());
+                    self.urls
+                        .lock(|urls| urls.borrow_mut()[usize::from(id)] = url);
+                }
+                Ok(None) => {}
+                Err(error) => crate::log!("Failed to load webhook {}: {:?}", id, error),
+            }
+        }
+        crate::log!("Loaded {} webhooks", self.urls().len());
+    }
+}
+
+/// Return the key of a URL in the key-value store
+fn storage_key(id: u8) -> String<{ storage::MAX_KEY_SIZE }> {
+    let mut key = String::new();
+    // Cannot fail, the prefix and a 3-digit number fit in a key
+    let _ = write!(key, "{STORAGE_KEY_PREFIX}{id}");
+    key
+}
+
+/// Deliver queued events to the registered URLs
+#[embassy_executor::task]
+pub async fn webhook_task(
+    webhooks: &'static Webhooks,
+    client: &'static SharedClient,
+    mut link: WifiStateReceiver,
+    stats: &'static Stats,
+) {
+    webhooks.load().await;
+
+    loop {
+        crate::heartbeat!("webhook_task");
+        let event = crate::tasks::beating("webhook_task", None, EVENTS.receive()).await;
+
+        let payload = Payload::new(event, Instant::now().as_secs());
+        let mut body = [0; PAYLOAD_SIZE];
+        let Ok(length) = serde_json_core::to_slice(&payload, &mut body) else {
+            crate::log!("Failed to encode webhook payload {:?}", payload);
+            continue;
+        };
+
+        for (id, url) in webhooks.urls() {
+            let delivery = deliver(client, &mut link, &url, &body[..length]);
+            let delivered = crate::tasks::beating("webhook_task", None, delivery).await;
+            if !delivered {
+                crate::log!(warn: "Warning: webhook {} failed for event {}", id, payload.event);
+            }
+            stats.record_webhook(delivered);
+        }
+    }
+}
+
+/// POST a body to a URL, retrying once, and return whether it succeeded
+///
+/// Each attempt waits for the station to have an address first, without a
+/// timeout.
+async fn deliver(
+    client: &SharedClient,
+    link: &mut WifiStateReceiver,
+    url: &str,
+    body: &[u8],
+) -> bool {
+    for attempt in 0..2 {
+        if attempt > 0 {
+            Timer::after(RETRY_DELAY).await;
+        }
+        wifi::wait_for_connection(link).await;
+        let post = async { client.lock().await.post_json(url, body).await };
+        match with_timeout(DELIVERY_TIMEOUT, post).await {
+            Ok(Ok(())) => return true,
+            Ok(Err(error)) => crate::log!("Webhook delivery to {} failed: {:?}", url, error),
+            Err(_) => crate::log!("Webhook delivery to {} timed out", url),
+        }
+    }
+    false
+}
+
+/// JSON body of `POST /webhooks`
+#[derive(Deserialize)]
+pub struct WebhookRequest {
+    /// URL to POST the events to
+    pub url: String<MAX_URL_SIZE>,
+}
+
+/// A URL returned by the `/webhooks` routes
+#[derive(Serialize)]
+pub struct WebhookResponse {
+    /// Identifier of the URL, for `DELETE /webhooks/{id}`
+    pub id: u8,
+
+    /// URL the events are POSTed to
+    pub url: String<MAX_URL_SIZE>,
+}
@@ -0,0 +1,88 @@
+//! Conventional files asked for by crawlers and scanners
+//!
+//! Devices exposed to the internet get crawled, so `/robots.txt` asks
+//! crawlers to stay away and `/.well-known/security.txt` (RFC 9116) tells
+//! researchers who to contact. Other paths under `/.well-known/` are probed
+//! constantly, e.g. for ACME challenges or app links, and get an empty 404.
+//!
+//! The contact is set at build time with `SECURITY_CONTACT`, a `mailto:` or
+//! `https:` URI, and optionally `SECURITY_EXPIRES`, an RFC 3339 date. Without
+//! a contact there is no `security.txt`.
+
+use picoserve::io::Write;
+use picoserve::response::Content;
+
+/// Path of the robots exclusion file
+pub const ROBOTS_PATH: &str = "/robots.txt";
+
+/// Prefix of the well-known URIs
+pub const WELL_KNOWN_PREFIX: &str = "/.well-known";
+
+/// Path of the security contact file
+pub const SECURITY_TXT_PATH: &str = "/.well-known/security.txt";
+
+/// Body of `/robots.txt`, disallowing everything
+pub const ROBOTS_TXT: &str = "User-agent: *\nDisallow: /\n";
+
+/// Contact written in `security.txt`
+pub const SECURITY_CONTACT: Option<&str> = option_env!("SECURITY_CONTACT");
+
+/// Expiry date written in `security.txt`
+pub const SECURITY_EXPIRES: Option<&str> = option_env!("SECURITY_EXPIRES");
+
+/// Body of `/.well-known/security.txt`
+#[derive(Clone, Copy, Debug)]
+pub struct SecurityTxt {
+    /// `Contact` field
+    pub contact: &'static str,
+
+    /// `Expires` field, if any
+    pub expires: Option<&'static str>,
+}
+
+impl SecurityTxt {
+    /// Return the file configured at build time, if a contact was set
+    pub const fn configured() -> Option<Self> {
+        // The variables are set, but empty, when left blank in `.env`
+        let expires = match SECURITY_EXPIRES {
+            Some(expires) if !expires.is_empty() => Some(expires),
+            _ => None,
+        };
+        match SECURITY_CONTACT {
+            Some(contact) if !contact.is_empty() => Some(Self { contact, expires }),
+            _ => None,
+        }
+    }
+
+    /// Return the lines of the file, without line endings
+    fn fields(&self) -> [(&'static str, Option<&'static str>); 2] {
+        [
+            ("Contact: ", Some(self.contact)),
+            ("Expires: ", self.expires),
+        ]
+    }
+}
+
+impl Content for SecurityTxt {
+    fn content_type(&self) -> &'static str {
+        "text/plain; charset=utf-8"
+    }
+
+    fn content_length(&self) -> usize {
+        self.fields()
+            .iter()
+            .filter_map(|(name, value)| Some(name.len() + value?.len() + 1))
+            .sum()
+    }
+
+    async fn write_content<W: Write>(self, mut writer: W) -> Result<(), W::Error> {
+        for (name, value) in self.fields() {
+            if let Some(value) = value {
+                writer.write_all(name.as_bytes()).await?;
+                writer.write_all(value.as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+            }
+        }
+        Ok(())
+    }
+}
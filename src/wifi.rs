@@ -1,104 +1,1789 @@
+use core::cell::RefCell;
+
 use embassy_executor::Spawner;
-use embassy_net::{DhcpConfig, Runner, Stack, StackResources};
-use embassy_time::{Duration, Timer};
+use embassy_futures::select::{select, select3, select4, Either, Either3, Either4};
+use embassy_net::{
+    ConfigV4, ConfigV6, DhcpConfig, IpAddress, Ipv4Address, Ipv4Cidr, Runner, Stack, StackResources,
+    StaticConfigV4,
+};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_sync::watch::{Receiver, Watch};
+use embassy_time::{with_timeout, Duration, Instant, Ticker, Timer};
+use esp_hal::ram;
 use esp_hal::rng::Rng;
 use esp_hal::rtc_cntl::Rtc;
-use rtt_target::rprintln;
-use esp_wifi::wifi::{self, WifiController, WifiDevice, WifiEvent, WifiState};
+use esp_wifi::wifi::{
+    self, AccessPointInfo, AuthMethod, ScanConfig, WifiController, WifiDevice, WifiError, WifiEvent,
+};
+use heapless::{HistoryBuffer, Vec};
 use esp_wifi::EspWifiController;
+use portable_atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
+use rand_core::RngCore as _;
+use serde::Serialize;
 
 use crate::mk_static;
+use crate::provisioning::{
+    self, NetworksError, StoredNetworks, WifiCredentials, MAX_SSID_SIZE, MAX_STORED_NETWORKS,
+};
+use crate::random::RngWrapper;
+use crate::settings::MAX_HOSTNAME_SIZE;
+use crate::storage::{self, Storage};
+
+/// SSID set at build time with `WIFI_SSID`, empty to provision the
+/// credentials from a browser
+///
+/// The build script checks the SSID and the password, so they are valid
+/// when set.
+const SSID: &str = match option_env!("WIFI_SSID") {
+    Some(ssid) => ssid,
+    None => "",
+};
 
-const SSID: &str = env!("SSID");
-const PASSWORD: &str = env!("PASSWORD");
+/// Password set at build time with `WIFI_PASSWORD`
+const PASSWORD: &str = match option_env!("WIFI_PASSWORD") {
+    Some(password) => password,
+    None => "",
+};
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Period between two RSSI samples while connected
+pub const RSSI_PERIOD: Duration = Duration::from_secs(1);
+
+/// Last sampled RSSI in dBm, `None` while disconnected
+///
+/// Every sample is sent, even when unchanged, so receivers wake once per
+/// [`RSSI_PERIOD`].
+static RSSI: Watch<CriticalSectionRawMutex, Option<i32>, { crate::web::SERVER_TASK_COUNT }> =
+    Watch::new();
+
+/// A receiver of the RSSI samples, see [`rssi_receiver`]
+pub type RssiReceiver =
+    Receiver<'static, CriticalSectionRawMutex, Option<i32>, { crate::web::SERVER_TASK_COUNT }>;
+
+/// Return the last sampled RSSI in dBm, if connected
+pub fn rssi() -> Option<i32> {
+    RSSI.try_get().flatten()
+}
+
+/// Return a receiver of the RSSI samples
+///
+/// There is one receiver per web task at most, `None` when they are all in
+/// use.
+pub fn rssi_receiver() -> Option<RssiReceiver> {
+    RSSI.receiver()
+}
+
+/// Period between two samples of the signal history
+pub const SIGNAL_PERIOD: Duration = Duration::from_secs(10);
+
+/// Number of samples kept in the signal history, 10 minutes
+pub const SIGNAL_HISTORY_SIZE: usize = 60;
+
+/// RSSI thresholds of the low-signal events, see [`SignalMonitor`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SignalThresholds {
+    /// The signal is low below this RSSI in dBm
+    pub low: i32,
+
+    /// A low signal recovers at or above this RSSI in dBm
+    pub recovered: i32,
+}
+
+impl SignalThresholds {
+    /// Default thresholds, below which web requests start to time out
+    pub const DEFAULT: Self = Self { low: -80, recovered: -72 };
+
+    /// Create thresholds, `None` unless `recovered` is above `low` so a
+    /// signal hovering around a threshold does not repeat events
+    pub const fn new(low: i32, recovered: i32) -> Option<Self> {
+        if recovered > low {
+            Some(Self { low, recovered })
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for SignalThresholds {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The recent RSSI samples, and whether the signal is low
+struct SignalHistory {
+    samples: HistoryBuffer<i32, SIGNAL_HISTORY_SIZE>,
+    thresholds: SignalThresholds,
+    low: bool,
+}
+
+/// Track the signal strength of the association
+///
+/// Samples are recorded in a rolling history, and the crossings of the
+/// thresholds are reported with hysteresis: the signal becomes low below
+/// [`SignalThresholds::low`] and recovers at or above
+/// [`SignalThresholds::recovered`].
+pub struct SignalMonitor {
+    history: blocking_mutex::Mutex<CriticalSectionRawMutex, RefCell<SignalHistory>>,
+}
+
+impl SignalMonitor {
+    /// Create an empty history
+    pub const fn new(thresholds: SignalThresholds) -> Self {
+        Self {
+            history: blocking_mutex::Mutex::new(RefCell::new(SignalHistory {
+                samples: HistoryBuffer::new(),
+                thresholds,
+                low: false,
+            })),
+        }
+    }
+
+    /// Change the thresholds, for the next samples
+    pub fn set_thresholds(&self, thresholds: SignalThresholds) {
+        self.history.lock(|history| history.borrow_mut().thresholds = thresholds);
+    }
+
+    /// Record a sample in dBm, dropping the oldest one when full
+    ///
+    /// Return the event to notify if a threshold was crossed.
+    pub fn record(&self, rssi: i32) -> Option<crate::webhooks::Event> {
+        self.history.lock(|history| {
+            let mut history = history.borrow_mut();
+            history.samples.write(rssi);
+            if !history.low && rssi < history.thresholds.low {
+                history.low = true;
+                Some(crate::webhooks::Event::SignalLow { rssi })
+            } else if history.low && rssi >= history.thresholds.recovered {
+                history.low = false;
+                Some(crate::webhooks::Event::SignalRecovered { rssi })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Return the samples in dBm, oldest first
+    pub fn samples(&self) -> Vec<i32, SIGNAL_HISTORY_SIZE> {
+        self.history
+            .lock(|history| history.borrow().samples.oldest_ordered().copied().collect())
+    }
+
+    /// Check whether the signal is low
+    pub fn is_low(&self) -> bool {
+        self.history.lock(|history| history.borrow().low)
+    }
+}
+
+/// Signal history of the station, recorded by [`signal_task`]
+pub static SIGNAL: SignalMonitor = SignalMonitor::new(SignalThresholds::DEFAULT);
+
+/// State of the Wi-Fi connection, see [`state`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WifiState {
+    /// The station is not associated, e.g. waiting before the next attempt
+    Disconnected,
+
+    /// The station is trying to associate with a network
+    Associating,
+
+    /// The station is associated, without an address yet
+    Associated,
+
+    /// The station has an IPv4 address or a global IPv6 address, the network
+    /// is usable
+    GotIp { addr: IpAddress },
+
+    /// The device runs the provisioning access point
+    ApMode,
+}
+
+impl WifiState {
+    /// Check whether the network is usable, see [`WifiState::GotIp`]
+    pub fn has_ip(self) -> bool {
+        matches!(self, Self::GotIp { .. })
+    }
+}
+
+/// Maximal number of receivers of the state, see [`state_receiver`]
+pub const MAX_STATE_RECEIVERS: usize = 6;
+
+/// State of the Wi-Fi connection, published by the connection task on every
+/// transition
+static STATE: Watch<CriticalSectionRawMutex, WifiState, MAX_STATE_RECEIVERS> = Watch::new();
+
+/// A receiver of the state transitions, see [`state_receiver`]
+pub type WifiStateReceiver =
+    Receiver<'static, CriticalSectionRawMutex, WifiState, MAX_STATE_RECEIVERS>;
+
+/// Return the state of the Wi-Fi connection
+pub fn state() -> WifiState {
+    STATE.try_get().unwrap_or(WifiState::Disconnected)
+}
+
+/// Return a receiver of the state transitions
+///
+/// There are [`MAX_STATE_RECEIVERS`] receivers at most, `None` when they
+/// are all in use. Dropping a receiver frees it.
+pub fn state_receiver() -> Option<WifiStateReceiver> {
+    STATE.receiver()
+}
+
+/// Number of times the station lost its address since boot
+static LINK_LOSSES: AtomicU32 = AtomicU32::new(0);
+
+/// Time the station lost its address in ticks, 0 while it has one
+static LINK_LOST_AT: AtomicU64 = AtomicU64::new(0);
+
+/// Return the number of times the station lost its address since boot
+pub fn link_losses() -> u32 {
+    LINK_LOSSES.load(Ordering::Relaxed)
+}
+
+/// Publish a state, unless it is the current one
+///
+/// Losing the address and getting one back after a loss are logged and
+/// notified as [`crate::webhooks::Event::LinkLost`] and
+/// [`crate::webhooks::Event::LinkRestored`].
+fn set_state(state: WifiState) {
+    let previous = STATE.try_get();
+    if previous == Some(state) {
+        return;
+    }
+    crate::log!("Wi-Fi state: {:?}", state);
+    STATE.sender().send(state);
+
+    let now = Instant::now();
+    let had_ip = previous.is_some_and(WifiState::has_ip);
+    if had_ip && !state.has_ip() {
+        LINK_LOSSES.add(1, Ordering::Relaxed);
+        LINK_LOST_AT.store(now.as_ticks().max(1), Ordering::Relaxed);
+        crate::log!(warn: "Warning: Wi-Fi link lost");
+        crate::webhooks::notify(crate::webhooks::Event::LinkLost { uptime: now.as_secs() });
+    } else if !had_ip && state.has_ip() {
+        let lost_at = LINK_LOST_AT.swap(0, Ordering::Relaxed);
+        if lost_at != 0 {
+            let down_for = now.saturating_duration_since(Instant::from_ticks(lost_at));
+            crate::log!("Wi-Fi link restored after {} s", down_for.as_secs());
+            crate::webhooks::notify(crate::webhooks::Event::LinkRestored {
+                uptime: now.as_secs(),
+                down_for: down_for.as_secs(),
+            });
+        }
+    }
+}
+
+/// Details of the current association
+#[derive(Clone)]
+pub struct WifiStatus {
+    /// SSID of the network
+    pub ssid: heapless::String<MAX_SSID_SIZE>,
+
+    /// BSSID of the access point, if it could be found by a scan
+    pub bssid: Option<[u8; 6]>,
+
+    /// Channel of the access point, if it could be found by a scan
+    pub channel: Option<u8>,
+
+    /// Time of the association
+    pub connected_since: Instant,
+}
+
+/// Details of the current association, `None` while disconnected
+static STATUS: Watch<CriticalSectionRawMutex, Option<WifiStatus>, 1> = Watch::new();
+
+/// Return the details of the current association, if connected
+pub fn status() -> Option<WifiStatus> {
+    STATUS.try_get().flatten()
+}
+
+/// Maximal number of networks returned by [`scan`]
+pub const MAX_SCAN_RESULTS: usize = 16;
+
+/// Time the results of a scan are reused for
+///
+/// Scanning while associated leaves the channel of the access point for a
+/// while and disrupts traffic, so scans are not repeated more often.
+pub const SCAN_CACHE_TIME: Duration = Duration::from_secs(30);
+
+/// Time to wait for the task owning the controller to scan
+const SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Security of a network found by a scan
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMode {
+    Open,
+    Wep,
+    Wpa,
+    Wpa2,
+    WpaWpa2,
+    Wpa2Enterprise,
+    Wpa3,
+    Wpa2Wpa3,
+    Wapi,
+}
+
+impl From<AuthMethod> for AuthMode {
+    fn from(method: AuthMethod) -> Self {
+        match method {
+            AuthMethod::None => Self::Open,
+            AuthMethod::WEP => Self::Wep,
+            AuthMethod::WPA => Self::Wpa,
+            AuthMethod::WPA2Personal => Self::Wpa2,
+            AuthMethod::WPAWPA2Personal => Self::WpaWpa2,
+            AuthMethod::WPA2Enterprise => Self::Wpa2Enterprise,
+            AuthMethod::WPA3Personal => Self::Wpa3,
+            AuthMethod::WPA2WPA3Personal => Self::Wpa2Wpa3,
+            AuthMethod::WAPIPersonal => Self::Wapi,
+        }
+    }
+}
+
+/// A network found by [`scan`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ScanResult {
+    /// SSID of the network
+    pub ssid: heapless::String<MAX_SSID_SIZE>,
+
+    /// Signal strength in dBm
+    pub rssi: i8,
+
+    /// Channel of the access point
+    pub channel: u8,
+
+    /// Security of the network, if the access point tells
+    pub auth_mode: Option<AuthMode>,
+}
+
+impl From<&AccessPointInfo> for ScanResult {
+    fn from(access_point: &AccessPointInfo) -> Self {
+        Self {
+            // Cannot fail, SSIDs are at most 32 bytes
+            ssid: access_point.ssid.as_str().try_into().unwrap_or_default(),
+            rssi: access_point.signal_strength,
+            channel: access_point.channel,
+            auth_mode: access_point.auth_method.map(AuthMode::from),
+        }
+    }
+}
+
+/// The networks found by a scan, strongest first
+pub type ScanResults = Vec<ScanResult, MAX_SCAN_RESULTS>;
+
+/// Keep the strongest access point of each network, strongest first
+///
+/// Networks with several access points, or one on several channels, are
+/// seen once per access point. Hidden networks cannot be joined by name and
+/// are skipped. Only the [`MAX_SCAN_RESULTS`] strongest networks are kept.
+pub fn strongest_networks(access_points: impl IntoIterator<Item = ScanResult>) -> ScanResults {
+    let mut networks = ScanResults::new();
+    for access_point in access_points {
+        if access_point.ssid.is_empty() {
+            continue;
+        }
+        let same_network = networks
+            .iter()
+            .position(|network| network.ssid == access_point.ssid);
+        let replaced = match same_network {
+            Some(index) => (networks[index].rssi < access_point.rssi).then_some(index),
+            None if networks.is_full() => (0..networks.len())
+                .min_by_key(|&index| networks[index].rssi)
+                .filter(|&index| networks[index].rssi < access_point.rssi),
+            None => {
+                // Cannot fail, there is room left
+                let _ = networks.push(access_point);
+                continue;
+            }
+        };
+        if let Some(index) = replaced {
+            networks[index] = access_point;
+        }
+    }
+    networks.sort_unstable_by(|a, b| b.rssi.cmp(&a.rssi).then_with(|| a.ssid.cmp(&b.ssid)));
+    networks
+}
+
+/// Why [`scan`] failed
+#[derive(Clone, Copy, Debug)]
+pub enum ScanError {
+    /// No task could scan in time, e.g. the station is connecting
+    Unavailable,
+
+    /// The controller failed to scan
+    Failed(WifiError),
+}
+
+/// Results of the last scan and their time, locked during a scan
+static SCAN_CACHE: Mutex<CriticalSectionRawMutex, Option<(Instant, ScanResults)>> =
+    Mutex::new(None);
+
+/// A scan requested from the task owning the controller
+static SCAN_REQUEST: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Results of the requested scan
+static SCAN_DONE: Signal<CriticalSectionRawMutex, Result<ScanResults, WifiError>> = Signal::new();
+
+/// Return the nearby networks, strongest first
+///
+/// The scan is run by the task owning the controller: the connection task
+/// while connected or waiting to reconnect, or the provisioning access point
+/// task. Results are reused for [`SCAN_CACHE_TIME`], and concurrent callers
+/// wait for the running scan.
+pub async fn scan() -> Result<ScanResults, ScanError> {
+    let mut cache = SCAN_CACHE.lock().await;
+    if let Some((scanned_at, results)) = cache.as_ref() {
+        if scanned_at.elapsed() < SCAN_CACHE_TIME {
+            return Ok(results.clone());
+        }
+    }
+
+    SCAN_DONE.reset();
+    SCAN_REQUEST.signal(());
+    let Ok(results) = with_timeout(SCAN_TIMEOUT, SCAN_DONE.wait()).await else {
+        SCAN_REQUEST.reset();
+        return Err(ScanError::Unavailable);
+    };
+    let results = results.map_err(ScanError::Failed)?;
+    *cache = Some((Instant::now(), results.clone()));
+    Ok(results)
+}
+
+/// Scan with the controller, for [`scan`] or the provisioning form
+async fn scan_networks(controller: &mut WifiController<'static>) -> Result<ScanResults, WifiError> {
+    let access_points = controller
+        .scan_with_config_async(ScanConfig::default())
+        .await?;
+    Ok(strongest_networks(access_points.iter().map(ScanResult::from)))
+}
+
+/// Beacon interval of most access points, 100 time units of 1024 µs
+pub const BEACON_INTERVAL: Duration = Duration::from_micros(102_400);
+
+/// Beacons between two wake-ups of the station in [`PowerSave::Maximum`]
+///
+/// esp-wifi reads it at build time from `ESP_WIFI_CONFIG_LISTEN_INTERVAL`,
+/// set in `.cargo/config.toml`, and tells the access point when
+/// associating.
+pub const LISTEN_INTERVAL: u16 = match option_env!("ESP_WIFI_CONFIG_LISTEN_INTERVAL") {
+    Some(interval) => parse_listen_interval(interval),
+    None => 3,
+};
+
+/// Parse [`LISTEN_INTERVAL`], failing the build if it is not a number
+const fn parse_listen_interval(interval: &str) -> u16 {
+    let digits = interval.as_bytes();
+    assert!(!digits.is_empty(), "ESP_WIFI_CONFIG_LISTEN_INTERVAL is empty");
+    let mut value: u16 = 0;
+    let mut index = 0;
+    while index < digits.len() {
+        let digit = digits[index];
+        assert!(digit.is_ascii_digit(), "ESP_WIFI_CONFIG_LISTEN_INTERVAL is not a number");
+        value = value * 10 + (digit - b'0') as u16;
+        index += 1;
+    }
+    value
+}
+
+/// Power saving of the station, see [`set_power_save`]
+///
+/// While the modem sleeps, the access point buffers the packets of the
+/// station until it wakes up for a beacon, so power is saved at the cost of
+/// latency, see [`PowerSave::max_wake_delay`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerSave {
+    /// The radio stays on
+    #[default]
+    None,
+
+    /// The modem sleeps between DTIM beacons
+    Minimum,
+
+    /// The modem sleeps for [`LISTEN_INTERVAL`] beacons
+    Maximum,
+}
+
+impl PowerSave {
+    /// Return the longest delay added to the first packet sent to the
+    /// station after it went idle, e.g. a request to the web server
+    ///
+    /// This assumes [`BEACON_INTERVAL`] and a DTIM every beacon, the
+    /// defaults of most access points. Later packets are not delayed while
+    /// traffic keeps the modem awake.
+    pub fn max_wake_delay(self) -> Duration {
+        match self {
+            Self::None => Duration::from_ticks(0),
+            Self::Minimum => BEACON_INTERVAL,
+            Self::Maximum => BEACON_INTERVAL * u32::from(LISTEN_INTERVAL.max(1)),
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Minimum,
+            2 => Self::Maximum,
+            _ => Self::None,
+        }
+    }
+}
+
+impl From<PowerSave> for esp_wifi::config::PowerSaveMode {
+    fn from(mode: PowerSave) -> Self {
+        match mode {
+            PowerSave::None => Self::None,
+            PowerSave::Minimum => Self::Minimum,
+            PowerSave::Maximum => Self::Maximum,
+        }
+    }
+}
+
+/// Power saving asked for with [`set_power_save`] or the configuration
+static REQUESTED_POWER_SAVE: AtomicU8 = AtomicU8::new(PowerSave::None as u8);
+
+/// Power saving applied by the connection task
+static POWER_SAVE: AtomicU8 = AtomicU8::new(PowerSave::None as u8);
+
+/// Set when the requested power saving changed
+static POWER_SAVE_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Change the power saving of the station
+///
+/// The connection task applies it right away when the station is started,
+/// without leaving the network, or else once started. See [`power_save`]
+/// for the applied mode.
+pub fn set_power_save(mode: PowerSave) {
+    REQUESTED_POWER_SAVE.store(mode as u8, Ordering::Relaxed);
+    POWER_SAVE_CHANGED.signal(());
+}
+
+/// Return the power saving applied to the station
+pub fn power_save() -> PowerSave {
+    PowerSave::from_u8(POWER_SAVE.load(Ordering::Relaxed))
+}
+
+/// Apply the requested power saving, the station must be started
+fn apply_power_save(controller: &mut WifiController<'static>) {
+    let mode = PowerSave::from_u8(REQUESTED_POWER_SAVE.load(Ordering::Relaxed));
+    if mode == power_save() {
+        return;
+    }
+    match controller.set_power_saving(mode.into()) {
+        Ok(()) => {
+            POWER_SAVE.store(mode as u8, Ordering::Relaxed);
+            crate::log!("Wi-Fi power saving: {:?}", mode);
+        }
+        Err(error) => {
+            crate::log!(warn: "Warning: failed to set the Wi-Fi power saving to {:?}: {:?}", mode, error)
+        }
+    }
+}
+
+/// Delay before the first attempt to reconnect after a failure or a
+/// disconnection, doubled after each attempt in a row
+pub const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest delay between two attempts to reconnect
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Time an association must last for the delay to start over from
+/// [`MIN_RECONNECT_DELAY`]
+///
+/// An access point dropping the station right after associating does not
+/// reset the backoff.
+pub const STABLE_CONNECTION_TIME: Duration = Duration::from_secs(60);
+
+/// Delays between attempts to reconnect, growing exponentially
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Backoff {
+    attempts: u32,
+}
+
+impl Backoff {
+    /// Start from [`MIN_RECONNECT_DELAY`]
+    pub const fn new() -> Self {
+        Self { attempts: 0 }
+    }
+
+    /// Return the backoff after a number of attempts in a row
+    pub const fn after(attempts: u32) -> Self {
+        Self { attempts }
+    }
+
+    /// Return the number of attempts in a row
+    pub const fn attempts(self) -> u32 {
+        self.attempts
+    }
+
+    /// Return the delay before the next attempt, without jitter
+    pub fn delay(self) -> Duration {
+        // Already past the maximal delay, and keeps the shift in range
+        let exponent = self.attempts.min(16);
+        (MIN_RECONNECT_DELAY * (1u32 << exponent)).min(MAX_RECONNECT_DELAY)
+    }
+
+    /// Return the backoff after one more attempt
+    pub const fn next(self) -> Self {
+        Self {
+            attempts: self.attempts.saturating_add(1),
+        }
+    }
+}
+
+/// Spread a delay over its upper half with a random number
+///
+/// Devices losing the same access point do not all try to reconnect at
+/// once when it comes back.
+pub fn jittered(delay: Duration, random: u32) -> Duration {
+    let half = delay.as_ticks() / 2;
+    delay - Duration::from_ticks((half * u64::from(random)) >> 32)
+}
+
+/// Attempts to reconnect in a row, see [`backoff`]
+static BACKOFF_ATTEMPTS: AtomicU32 = AtomicU32::new(0);
+
+/// Attempts to reconnect since boot
+static RECONNECT_ATTEMPTS: AtomicU32 = AtomicU32::new(0);
+
+/// Return the current backoff of the connection task
+pub fn backoff() -> Backoff {
+    Backoff::after(BACKOFF_ATTEMPTS.load(Ordering::Relaxed))
+}
+
+/// Return the number of attempts to reconnect since boot
+pub fn reconnect_attempts() -> u32 {
+    RECONNECT_ATTEMPTS.load(Ordering::Relaxed)
+}
+
+/// Start the backoff over from [`MIN_RECONNECT_DELAY`]
+fn reset_backoff() {
+    BACKOFF_ATTEMPTS.store(0, Ordering::Relaxed);
+}
+
+/// Time of the next connection attempt in ticks since boot, 0 unless the
+/// connection task is waiting to reconnect
+static NEXT_ATTEMPT: AtomicU64 = AtomicU64::new(0);
+
+/// Return the time left until the next connection attempt, if the
+/// connection task is waiting to reconnect
+pub fn next_attempt_in() -> Option<Duration> {
+    let ticks = NEXT_ATTEMPT.load(Ordering::Relaxed);
+    if ticks == 0 {
+        return None;
+    }
+    Some(Instant::from_ticks(ticks).saturating_duration_since(Instant::now()))
+}
+
+/// The reason a wait before a connection attempt ended early
+enum Interrupted {
+    /// The networks changed, see [`update_networks`]
+    Networks(StoredNetworks),
+
+    /// A command was received
+    Command(WifiCommand),
+}
+
+/// Wait before the next connection attempt for the jittered delay of the
+/// backoff, publishing its time
+///
+/// Networks changed with [`update_networks`] and commands meanwhile end the
+/// wait, and are returned. Scans and power saving requested meanwhile are
+/// run and applied.
+async fn wait_before_reconnecting(
+    controller: &mut WifiController<'static>,
+    rng: &mut RngWrapper,
+) -> Option<Interrupted> {
+    let backoff = backoff();
+    let delay = jittered(backoff.delay(), rng.next_u32());
+    BACKOFF_ATTEMPTS.store(backoff.next().attempts(), Ordering::Relaxed);
+    RECONNECT_ATTEMPTS.add(1, Ordering::Relaxed);
+    crate::log!("Reconnecting in {} ms", delay.as_millis());
+
+    let attempt = Instant::now() + delay;
+    NEXT_ATTEMPT.store(attempt.as_ticks().max(1), Ordering::Relaxed);
+    let interrupted = loop {
+        let requests = select3(SCAN_REQUEST.wait(), POWER_SAVE_CHANGED.wait(), COMMANDS.receive());
+        match select3(Timer::at(attempt), NEW_NETWORKS.wait(), requests).await {
+            Either3::First(()) => break None,
+            Either3::Second(networks) => break Some(Interrupted::Networks(networks)),
+            Either3::Third(Either3::First(())) => SCAN_DONE.signal(scan_networks(controller).await),
+            Either3::Third(Either3::Second(())) => apply_power_save(controller),
+            Either3::Third(Either3::Third(command)) => break Some(Interrupted::Command(command)),
+        }
+    };
+    NEXT_ATTEMPT.store(0, Ordering::Relaxed);
+    interrupted
+}
+
+/// Failed rounds of connection attempts in a row before falling back to the
+/// provisioning access point by default
+pub const DEFAULT_MAX_FAILURES: u32 = 10;
+
+/// Time the provisioning access point runs after a fallback before
+/// restarting to try the stored networks again
+pub const PROVISIONING_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Marker of a requested fallback to provisioning in RTC memory
+const FALLBACK_MAGIC: u32 = 0xFA11_BAC4;
+
+/// Whether the next boot starts the provisioning access point, set to
+/// [`FALLBACK_MAGIC`] before resetting
+///
+/// This is placed in the RTC Fast memory and survives the reset.
+#[ram(rtc_fast, persistent)]
+static mut PROVISIONING_FALLBACK: u32 = 0;
+
+/// New networks to connect to, see [`update_networks`]
+static NEW_NETWORKS: Signal<CriticalSectionRawMutex, StoredNetworks> = Signal::new();
+
+/// Held while the stored networks are changed, so concurrent changes do not
+/// overwrite each other
+static NETWORKS_LOCK: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+/// Number of commands waiting for the connection task before new ones are
+/// rejected
+pub const COMMAND_QUEUE_SIZE: usize = 2;
+
+/// Time allowed for the transition requested by [`disconnect`],
+/// [`reconnect`] or [`reassociate_to`]
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// A command to the connection task, which owns the controller
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum WifiCommand {
+    /// Leave the network and stay disconnected
+    Disconnect,
+
+    /// Leave the network, if associated, and connect again right away
+    Reconnect,
+
+    /// Like [`WifiCommand::Reconnect`], trying a network first
+    ReassociateTo(heapless::String<MAX_SSID_SIZE>),
+}
+
+/// Commands not yet handled by the connection task
+static COMMANDS: Channel<CriticalSectionRawMutex, WifiCommand, COMMAND_QUEUE_SIZE> =
+    Channel::new();
+
+/// The reason a command could not be carried out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    /// The station is not running, the device runs the provisioning access
+    /// point
+    NotStation,
+
+    /// [`COMMAND_QUEUE_SIZE`] commands are waiting, or every receiver of the
+    /// state is in use
+    Busy,
+
+    /// The transition was not observed within [`COMMAND_TIMEOUT`]
+    Timeout,
+
+    /// The station joined another network than the one asked for, which may
+    /// be out of reach or not stored
+    OtherNetwork,
+}
+
+/// Leave the network and stay disconnected until [`reconnect`] or
+/// [`reassociate_to`]
+///
+/// Return once the station is disconnected.
+pub async fn disconnect() -> Result<WifiState, CommandError> {
+    let mut receiver = send_command(WifiCommand::Disconnect)?;
+    let disconnected = receiver.get_and(|state| *state == WifiState::Disconnected);
+    with_timeout(COMMAND_TIMEOUT, disconnected)
+        .await
+        .map_err(|_| CommandError::Timeout)
+}
+
+/// Leave the network, if associated, and connect again without waiting for
+/// the backoff
+///
+/// Return once the station has an address again.
+pub async fn reconnect() -> Result<WifiState, CommandError> {
+    let mut receiver = send_command(WifiCommand::Reconnect)?;
+    with_timeout(COMMAND_TIMEOUT, reconnected(&mut receiver))
+        .await
+        .map_err(|_| CommandError::Timeout)
+}
+
+/// Like [`reconnect`], trying the stored network `ssid` first
+///
+/// The other networks are tried next, in the usual order, if it cannot be
+/// joined. The network is not saved as the last one joined, so the override
+/// does not outlive a restart.
+pub async fn reassociate_to(ssid: &str) -> Result<WifiState, CommandError> {
+    // An SSID that is too long cannot be stored, so cannot be joined either
+    let ssid = heapless::String::try_from(ssid).map_err(|_| CommandError::OtherNetwork)?;
+    let mut receiver = send_command(WifiCommand::ReassociateTo(ssid.clone()))?;
+    let state = with_timeout(COMMAND_TIMEOUT, reconnected(&mut receiver))
+        .await
+        .map_err(|_| CommandError::Timeout)?;
+    if status().is_some_and(|status| status.ssid == ssid) {
+        Ok(state)
+    } else {
+        Err(CommandError::OtherNetwork)
+    }
+}
+
+/// Queue a command for the connection task, and return a receiver of the
+/// transitions from now on
+fn send_command(command: WifiCommand) -> Result<WifiStateReceiver, CommandError> {
+    if mode() != WifiMode::Station {
+        return Err(CommandError::NotStation);
+    }
+    let mut receiver = state_receiver().ok_or(CommandError::Busy)?;
+    // Marks the current state as seen
+    let _ = receiver.try_get();
+    COMMANDS
+        .try_send(command)
+        .map_err(|_| CommandError::Busy)?;
+    Ok(receiver)
+}
+
+/// Wait until the station lost its address, if it had one, and got one again
+async fn reconnected(receiver: &mut WifiStateReceiver) -> WifiState {
+    receiver.changed_and(|state| !state.has_ip()).await;
+    receiver.changed_and(|state| state.has_ip()).await
+}
+
+/// Overrides of the connection task set by commands
+#[derive(Default)]
+struct Commanded {
+    /// Whether to stay disconnected
+    held: bool,
+
+    /// Network to try first, until the station associates or a round fails
+    preferred: Option<heapless::String<MAX_SSID_SIZE>>,
+}
+
+impl Commanded {
+    /// Apply a command
+    fn apply(&mut self, command: WifiCommand) {
+        crate::log!("Wi-Fi command: {:?}", command);
+        match command {
+            WifiCommand::Disconnect => self.held = true,
+            WifiCommand::Reconnect => self.held = false,
+            WifiCommand::ReassociateTo(ssid) => {
+                self.held = false;
+                self.preferred = Some(ssid);
+            }
+        }
+    }
+}
+
+/// Stay disconnected until a command asks to connect again
+///
+/// Networks changed with [`update_networks`] meanwhile are stored in
+/// `networks`. Scans and power saving requested meanwhile are run and
+/// applied.
+async fn hold(
+    controller: &mut WifiController<'static>,
+    networks: &mut StoredNetworks,
+    commanded: &mut Commanded,
+) {
+    crate::log!("Staying disconnected until asked to reconnect");
+    while commanded.held {
+        let requests = select(SCAN_REQUEST.wait(), POWER_SAVE_CHANGED.wait());
+        let wait = select3(NEW_NETWORKS.wait(), requests, COMMANDS.receive());
+        match crate::tasks::beating("connection_task", None, wait).await {
+            Either3::First(new_networks) => *networks = new_networks,
+            Either3::Second(Either::First(())) => SCAN_DONE.signal(scan_networks(controller).await),
+            Either3::Second(Either::Second(())) => apply_power_save(controller),
+            Either3::Third(command) => commanded.apply(command),
+        }
+    }
+}
+
+/// Leave the network, the disconnection event follows
+async fn leave(controller: &mut WifiController<'static>) {
+    if let Err(error) = controller.disconnect_async().await {
+        crate::log!(warn: "Warning: failed to disconnect: {:?}", error);
+    }
+}
+
+/// Configuration of the Wi-Fi connection, given to [`start_wifi`]
+#[derive(Clone)]
+pub struct WifiConfig {
+    /// Networks to join in priority order, none to start the provisioning
+    /// access point
+    pub networks: StoredNetworks,
+
+    /// SSID of the network joined last, tried first
+    pub last_network: Option<heapless::String<MAX_SSID_SIZE>>,
+
+    /// Key-value store the network joined last is saved in, if any
+    pub storage: Option<&'static Storage>,
+
+    /// Failed rounds of connection attempts in a row before restarting as a
+    /// provisioning access point, `None` to retry forever
+    pub max_failures: Option<u32>,
+
+    /// Time the provisioning access point runs before restarting to join the
+    /// networks stored in `storage`, `None` to run until provisioned
+    pub provisioning_timeout: Option<Duration>,
+
+    /// Power saving of the station until [`set_power_save`] changes it
+    pub power_save: PowerSave,
+
+    /// Thresholds of the low-signal events
+    pub signal_thresholds: SignalThresholds,
+}
+
+impl Default for WifiConfig {
+    fn default() -> Self {
+        Self::provisioning()
+    }
+}
+
+impl WifiConfig {
+    /// Join the network set at build time, if any
+    ///
+    /// Credentials are read from the `WIFI_SSID` and `WIFI_PASSWORD`
+    /// environment variables or the `.env` file by the build script.
+    pub fn from_build_env() -> Self {
+        if SSID.is_empty() {
+            return Self::provisioning();
+        }
+        match WifiCredentials::new(SSID, PASSWORD) {
+            Ok(credentials) => Self::with_networks(StoredNetworks::single(credentials)),
+            Err(error) => {
+                crate::log!(warn: "Warning: ignoring the Wi-Fi credentials set at build time: {:?}", error);
+                Self::provisioning()
+            }
+        }
+    }
+
+    /// Join the networks stored in `storage`, e.g. provisioned from a
+    /// browser, starting with the one joined last
+    ///
+    /// The device falls back to the provisioning access point after
+    /// [`DEFAULT_MAX_FAILURES`] failed rounds, see [`WifiConfig::fallback`].
+    /// Without stored networks, this is [`WifiConfig::from_build_env`], which
+    /// retries forever.
+    pub async fn load(storage: &'static Storage) -> Self {
+        let networks = provisioning::load_networks(storage).await;
+        if networks.is_empty() {
+            return Self::from_build_env();
+        }
+        Self {
+            last_network: provisioning::load_last_network(storage).await,
+            storage: Some(storage),
+            max_failures: Some(DEFAULT_MAX_FAILURES),
+            ..Self::with_networks(networks)
+        }
+    }
+
+    /// Start the provisioning access point after failing to join the
+    /// networks stored in `storage`, and restart to try them again after
+    /// [`PROVISIONING_TIMEOUT`]
+    pub const fn fallback(storage: &'static Storage) -> Self {
+        Self {
+            storage: Some(storage),
+            provisioning_timeout: Some(PROVISIONING_TIMEOUT),
+            ..Self::provisioning()
+        }
+    }
+
+    /// Join one of a list of networks
+    pub fn with_networks(networks: StoredNetworks) -> Self {
+        Self {
+            networks,
+            ..Self::provisioning()
+        }
+    }
+
+    /// Start the provisioning access point
+    pub const fn provisioning() -> Self {
+        Self {
+            networks: StoredNetworks::new(),
+            last_network: None,
+            storage: None,
+            max_failures: None,
+            provisioning_timeout: None,
+            power_save: PowerSave::None,
+            signal_thresholds: SignalThresholds::DEFAULT,
+        }
+    }
+
+    /// Change the power saving of the station
+    pub const fn with_power_save(self, power_save: PowerSave) -> Self {
+        Self { power_save, ..self }
+    }
+
+    /// Change the thresholds of the low-signal events
+    pub const fn with_signal_thresholds(self, signal_thresholds: SignalThresholds) -> Self {
+        Self {
+            signal_thresholds,
+            ..self
+        }
+    }
+
+    /// Change the failed connection attempts in a row before falling back to
+    /// the provisioning access point, `None` to retry forever
+    pub const fn with_max_failures(self, max_failures: Option<u32>) -> Self {
+        Self {
+            max_failures,
+            ..self
+        }
+    }
+}
+
+/// Check whether the previous boot gave up connecting and asked for the
+/// provisioning access point, and clear the request
+///
+/// This must be called once, early in `main`, before [`start_wifi`].
+pub fn take_provisioning_fallback() -> bool {
+    // SAFETY:
+    // This runs once before any task is spawned, the connection task only
+    // writes the flag right before resetting
+    unsafe {
+        let requested = PROVISIONING_FALLBACK == FALLBACK_MAGIC;
+        PROVISIONING_FALLBACK = 0;
+        requested
+    }
+}
+
+/// Give up connecting and restart as a provisioning access point
+///
+/// The stored networks are kept, so the device tries them again after
+/// [`PROVISIONING_TIMEOUT`] or the next restart if no other network is
+/// provisioned.
+fn fall_back_to_provisioning(failures: u32) -> ! {
+    crate::log!(
+        error: "Failed to connect to every network {} times in a row, restarting as a provisioning access point",
+        failures
+    );
+    // SAFETY:
+    // Only the connection task writes the flag, and the device resets next
+    unsafe {
+        PROVISIONING_FALLBACK = FALLBACK_MAGIC;
+    }
+    esp_hal::system::software_reset()
+}
+
+/// Change the networks stored in the key-value store with `update`, and
+/// hand them to the connection task
+///
+/// The station stays connected if the network it joined is kept unchanged,
+/// otherwise it joins one of the new networks. In provisioning mode there is
+/// no connection task, the networks are tried after a restart.
+pub async fn update_networks<T>(
+    storage: &Storage,
+    update: impl FnOnce(&mut StoredNetworks) -> Result<T, NetworksError>,
+) -> Result<T, NetworksError> {
+    let _lock = NETWORKS_LOCK.lock().await;
+    let mut networks = provisioning::load_networks(storage).await;
+    let output = update(&mut networks)?;
+    provisioning::save_networks(storage, &networks)
+        .await
+        .map_err(NetworksError::Storage)?;
+    crate::log!("{} Wi-Fi networks saved", networks.len());
+    NEW_NETWORKS.signal(networks);
+    Ok(output)
+}
+
+/// Return the positions of the networks to try in a round, in order
+///
+/// The network joined last goes first, then the others by priority. With
+/// the results of a scan, the networks it did not find are skipped, unless
+/// it found none of them: the network may be hidden, or out of range only
+/// for a moment.
+pub fn connection_order(
+    networks: &StoredNetworks,
+    last_network: Option<&str>,
+    visible: Option<&ScanResults>,
+) -> Vec<usize, MAX_STORED_NETWORKS> {
+    let last = last_network.and_then(|ssid| networks.position(ssid));
+    let order = last
+        .into_iter()
+        .chain((0..networks.len()).filter(|&index| Some(index) != last));
+    let found: Vec<usize, MAX_STORED_NETWORKS> = order
+        .clone()
+        .filter(|&index| {
+            visible.is_none_or(|results| {
+                let ssid = &networks.as_slice()[index].ssid;
+                results.iter().any(|result| result.ssid == *ssid)
+            })
+        })
+        .collect();
+    if found.is_empty() {
+        order.collect()
+    } else {
+        found
+    }
+}
+
+/// The mode the Wi-Fi interface runs in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WifiMode {
+    /// Joined, or joining, the configured network
+    Station,
+
+    /// Running the provisioning access point
+    AccessPoint,
+}
+
+/// Return the mode the Wi-Fi interface runs in
+pub fn mode() -> WifiMode {
+    if provisioning::is_active() {
+        WifiMode::AccessPoint
+    } else {
+        WifiMode::Station
+    }
+}
+
+/// Address of the device on the network of its provisioning access point
+pub const ACCESS_POINT_ADDRESS: Ipv4Address = Ipv4Address::new(192, 168, 4, 1);
+
+/// Number of sockets available in the network stack
+const SOCKET_COUNT: usize =
+    crate::web::SERVER_TASK_COUNT - crate::web::ACCESS_POINT_TASK_POOL_SIZE + 8;
+
+/// Number of sockets available in the network stack of the access point
+/// kept alongside the station, one per access point web task and one for
+/// the DHCP server
+#[cfg(feature = "ap-sta")]
+const ACCESS_POINT_SOCKET_COUNT: usize = crate::web::ACCESS_POINT_TASK_POOL_SIZE + 1;
+
+/// The network stacks started by [`start_wifi`]
+///
+/// The state of the connection is read with [`state`] and
+/// [`state_receiver`], and changed with the commands of this module, see
+/// [`disconnect`].
+pub struct WifiHandle {
+    /// Stack of the station, or of the provisioning access point
+    pub stack: Stack<'static>,
+
+    /// The mode the interface runs in
+    pub mode: WifiMode,
+
+    /// Stack of the access point kept alongside the station, see
+    /// [`ACCESS_POINT_ADDRESS`]
+    #[cfg(feature = "ap-sta")]
+    pub access_point: Option<Stack<'static>>,
+}
+
+/// Return the configuration of the access point of the device
+///
+/// Wi-Fi must have been started, so the MAC address is known, and the
+/// password loaded, see [`provisioning::load_access_point_password`].
+fn access_point_configuration() -> wifi::AccessPointConfiguration {
+    let mac = crate::identity::mac_address().expect("Wi-Fi was started");
+    wifi::AccessPointConfiguration {
+        ssid: crate::identity::device_name(&mac).as_str().try_into().unwrap(),
+        auth_method: wifi::AuthMethod::WPA2Personal,
+        password: crate::identity::access_point_password()
+            .expect("the password was loaded")
+            .try_into()
+            .unwrap(),
+        ..Default::default()
+    }
+}
+
+/// Return the configuration joining a network
+///
+/// With the `ap-sta` feature the access point of the device keeps running
+/// alongside the station.
+fn station_configuration(credentials: &WifiCredentials) -> wifi::Configuration {
+    let client_config = wifi::ClientConfiguration {
+        ssid: credentials.ssid.as_str().try_into().unwrap(),
+        password: credentials.password.as_str().try_into().unwrap(),
+        ..Default::default()
+    };
+    if cfg!(feature = "ap-sta") {
+        wifi::Configuration::Mixed(client_config, access_point_configuration())
+    } else {
+        wifi::Configuration::Client(client_config)
+    }
+}
+
+/// Start Wi-Fi and return the network stack once it has an address, with
+/// the mode the interface runs in
+///
+/// With the `ap-sta` feature, the access point of the device also runs
+/// while the station is connected, with a second network stack at
+/// [`ACCESS_POINT_ADDRESS`].
+///
+/// Without networks in the configuration the device starts a WPA2 access
+/// point named after the device instead, with the password of
+/// [`provisioning::load_access_point_password`], which must have been
+/// loaded, and serves the provisioning form, see [`provisioning`].
 pub async fn start_wifi(
     esp_wifi_ctrl: &'static EspWifiController<'static>,
     wifi: esp_hal::peripherals::WIFI<'static>,
     mut rng: Rng,
+    config: &'static WifiConfig,
     spawner: &Spawner,
-) -> Stack<'static> {
+) -> WifiHandle {
     let (controller, interfaces) = esp_wifi::wifi::new(&esp_wifi_ctrl, wifi).unwrap();
     let wifi_interface = interfaces.sta;
+    let mac = wifi_interface.mac_address();
+    crate::identity::set_mac_address(mac);
     let net_seed = rng.random() as u64 | ((rng.random() as u64) << 32);
+    if config.networks.is_empty() {
+        let name = crate::identity::device_name(&mac);
+        let password = crate::identity::access_point_password().expect("the password was loaded");
+        let stack = start_access_point(
+            controller,
+            interfaces.ap,
+            &name,
+            password,
+            config.storage.zip(config.provisioning_timeout),
+            net_seed,
+            spawner,
+        )
+        .await;
+        return WifiHandle {
+            stack,
+            mode: WifiMode::AccessPoint,
+            #[cfg(feature = "ap-sta")]
+            access_point: None,
+        };
+    }
+
+    REQUESTED_POWER_SAVE.store(config.power_save as u8, Ordering::Relaxed);
+    SIGNAL.set_thresholds(config.signal_thresholds);
 
-    let dhcp_config = DhcpConfig::default();
-    let net_config = embassy_net::Config::dhcpv4(dhcp_config);
+    // Dual stack, the IPv6 address comes from the router advertisements. The
+    // settings are not loaded yet, the DHCP hostname is the device name until
+    // they are.
+    // Cannot fail, device names are shorter than hostnames
+    let hostname = heapless::String::try_from(crate::identity::device_name(&mac).as_str()).unwrap();
+    let mut net_config = embassy_net::Config::dhcpv4(dhcp_config(&hostname));
+    net_config.ipv6 = ConfigV6::Static(crate::slaac::link_local_config(&mac));
 
     // Init network stack
+    // One socket per web task, plus DHCP, DNS, mDNS, the router
+    // advertisements, the HTTP client and the two connections of the webhook
+    // client
     let (stack, runner) = embassy_net::new(
         wifi_interface,
         net_config,
-        mk_static!(StackResources<6>, StackResources::<6>::new()),
+        mk_static!(
+            StackResources<SOCKET_COUNT>,
+            StackResources::<SOCKET_COUNT>::new()
+        ),
         net_seed,
     );
 
-    spawner.spawn(connection_task(controller)).ok();
+    spawner
+        .spawn(connection_task(controller, stack, config, RngWrapper::from(rng)))
+        .ok();
     spawner.spawn(net_task(runner)).ok();
+    spawner.spawn(signal_task()).ok();
+    spawner.spawn(dhcp_hostname_task(stack, mac, hostname)).ok();
+    spawner.spawn(crate::slaac::slaac_task(stack, mac)).ok();
 
-    wait_for_connection(stack).await;
+    // The access point has a fixed address, and hands out the addresses of
+    // its clients
+    #[cfg(feature = "ap-sta")]
+    let access_point = {
+        let net_config = embassy_net::Config::ipv4_static(StaticConfigV4 {
+            address: Ipv4Cidr::new(ACCESS_POINT_ADDRESS, 24),
+            gateway: None,
+            dns_servers: Default::default(),
+        });
+        let (stack, runner) = embassy_net::new(
+            interfaces.ap,
+            net_config,
+            mk_static!(
+                StackResources<ACCESS_POINT_SOCKET_COUNT>,
+                StackResources::<ACCESS_POINT_SOCKET_COUNT>::new()
+            ),
+            net_seed.wrapping_add(1),
+        );
+        spawner.spawn(access_point_net_task(runner)).ok();
+        spawner.spawn(crate::dhcp_server::dhcp_server_task(stack)).ok();
+        stack
+    };
 
-    stack
+    // Cannot fail, this is the first receiver
+    let mut state = state_receiver().unwrap();
+    wait_for_connection(&mut state).await;
+
+    WifiHandle {
+        stack,
+        mode: WifiMode::Station,
+        #[cfg(feature = "ap-sta")]
+        access_point: Some(access_point),
+    }
 }
 
+/// Start the provisioning access point, after scanning for the networks
+/// listed on the form
+///
+/// The station interface is kept in the configuration for the scan, but
+/// never connects. With a timeout, the device restarts to join the networks
+/// of the storage once it runs out, see [`access_point_task`].
+async fn start_access_point(
+    mut controller: WifiController<'static>,
+    wifi_interface: WifiDevice<'static>,
+    name: &str,
+    password: &str,
+    timeout: Option<(&'static Storage, Duration)>,
+    net_seed: u64,
+    spawner: &Spawner,
+) -> Stack<'static> {
+    let config = wifi::Configuration::Mixed(Default::default(), access_point_configuration());
+    controller.set_configuration(&config).unwrap();
+    controller.start_async().await.unwrap();
 
-async fn wait_for_connection(stack: Stack<'_>) {
-    rprintln!("Waiting for link to be up");
-    loop {
-        if stack.is_link_up() {
-            break;
+    match scan_networks(&mut controller).await {
+        Ok(networks) => {
+            provisioning::set_networks(networks.iter().map(|network| network.ssid.as_str()));
+            *SCAN_CACHE.lock().await = Some((Instant::now(), networks));
         }
-        Timer::after(Duration::from_millis(500)).await;
+        Err(error) => crate::log!(warn: "Warning: failed to scan for networks: {:?}", error),
     }
 
-    rprintln!("Waiting to get IP address...");
+    let net_config = embassy_net::Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(ACCESS_POINT_ADDRESS, 24),
+        gateway: None,
+        dns_servers: Default::default(),
+    });
+    // The DHCP and DNS servers take the sockets of the DHCP client and the
+    // DNS resolver of the station
+    let (stack, runner) = embassy_net::new(
+        wifi_interface,
+        net_config,
+        mk_static!(
+            StackResources<SOCKET_COUNT>,
+            StackResources::<SOCKET_COUNT>::new()
+        ),
+        net_seed,
+    );
+
+    provisioning::set_active();
+    set_state(WifiState::ApMode);
+    crate::log!(
+        "No Wi-Fi credentials, provision them on access point {} at http://{}{}",
+        name,
+        ACCESS_POINT_ADDRESS,
+        provisioning::PROVISIONING_PATH
+    );
+    // Only on the serial console, which takes physical access to the device,
+    // log lines are served over HTTP
+    rtt_target::rprintln!("Password of access point {}: {}", name, password);
+
+    spawner.spawn(access_point_task(controller, timeout)).ok();
+    spawner.spawn(net_task(runner)).ok();
+    spawner.spawn(crate::dhcp_server::dhcp_server_task(stack)).ok();
+    spawner.spawn(crate::dns_server::dns_server_task(stack)).ok();
+
+    stack
+}
+
+/// Keep the provisioning access point running, and scan for [`scan`]
+///
+/// The networks found also refresh the ones listed on the provisioning form.
+/// With a timeout, the device restarts once it runs out if networks are
+/// stored, so it joins them again without a power cycle. The timeout starts
+/// over whenever a station joins, not to cut off a client on the form.
+#[embassy_executor::task]
+async fn access_point_task(
+    mut controller: WifiController<'static>,
+    timeout: Option<(&'static Storage, Duration)>,
+) {
+    let mut deadline = timeout.map(|(_, timeout)| Instant::now() + timeout);
     loop {
-        if let Some(config) = stack.config_v4() {
-            rprintln!("Got IP: {}", config.address);
-            break;
+        crate::heartbeat!("access_point_task");
+        let expired = async {
+            match deadline {
+                Some(deadline) => Timer::at(deadline).await,
+                None => core::future::pending().await,
+            }
+        };
+        let event = select3(
+            controller.wait_for_event(WifiEvent::ApStaconnected),
+            SCAN_REQUEST.wait(),
+            expired,
+        );
+        match crate::tasks::beating("access_point_task", None, event).await {
+            Either3::First(()) => {
+                crate::log!("A station joined the access point");
+                deadline = timeout.map(|(_, timeout)| Instant::now() + timeout);
+            }
+            Either3::Second(()) => {
+                let results = scan_networks(&mut controller).await;
+                if let Ok(networks) = &results {
+                    provisioning::set_networks(networks.iter().map(|network| network.ssid.as_str()));
+                }
+                SCAN_DONE.signal(results);
+            }
+            Either3::Third(()) => {
+                deadline = None;
+                let Some((storage, _)) = timeout else { continue };
+                if provisioning::load_networks(storage).await.is_empty() {
+                    crate::log!(warn: "Warning: no stored networks to join, keeping the access point");
+                    continue;
+                }
+                crate::log!("Provisioning timed out, restarting to join the stored networks");
+                crate::restart::request_restart(Duration::from_secs(0));
+            }
         }
-        Timer::after(Duration::from_millis(500)).await;
     }
 }
 
+/// Wait until the station has an address, IPv4 or global IPv6
+///
+/// Tasks using the network wait for it after a link loss rather than failing
+/// over and over.
+pub async fn wait_for_connection(state: &mut WifiStateReceiver) {
+    if state.try_get().is_some_and(WifiState::has_ip) {
+        return;
+    }
+    crate::log!("Waiting to get IP address...");
+    if let WifiState::GotIp { addr } = state
+        .get_and(|state| matches!(state, WifiState::GotIp { .. }))
+        .await
+    {
+        crate::log!("Got IP: {}", addr);
+    }
+}
+
+/// Return the address of a stack that can reach other networks, the IPv4
+/// address or else the global IPv6 address
+pub fn address(stack: Stack<'_>) -> Option<IpAddress> {
+    stack
+        .config_v4()
+        .map(|config| IpAddress::Ipv4(config.address.address()))
+        .or_else(|| {
+            crate::slaac::global_config(stack).map(|config| IpAddress::Ipv6(config.address.address()))
+        })
+}
+
+/// Return whether a stack can reach other networks, with an IPv4 address
+/// or a global IPv6 address
+pub fn has_address(stack: Stack<'_>) -> bool {
+    address(stack).is_some()
+}
+
+/// Keep the station connected to one of the networks of the configuration,
+/// or of the latest ones given to [`update_networks`]
+///
+/// Each round tries the networks once, in the order of
+/// [`connection_order`] with a scan when the station is started. The network
+/// joined is saved in the key-value store of the configuration, to be tried
+/// first after a restart. After `max_failures` failed rounds in a row, the
+/// device restarts as a provisioning access point. Rounds are spaced by an
+/// exponential [`Backoff`], jittered with `rng`.
+///
+/// Every transition is published, see [`state`]. While associated, the
+/// address of `stack` is checked at each RSSI sample. Commands, see
+/// [`disconnect`], [`reconnect`] and [`reassociate_to`], skip the backoff.
 #[embassy_executor::task]
-async fn connection_task(mut controller: WifiController<'static>) {
-    rprintln!("start connection task");
-    rprintln!("Device capabilities: {:?}", controller.capabilities());
+async fn connection_task(
+    mut controller: WifiController<'static>,
+    stack: Stack<'static>,
+    config: &'static WifiConfig,
+    mut rng: RngWrapper,
+) {
+    crate::log!("start connection task");
+    crate::log!("Device capabilities: {:?}", controller.capabilities());
+    let mut networks = config.networks.clone();
+    let mut last_network = config.last_network.clone();
+    // Positions of the networks left to try in this round
+    let mut round: Vec<usize, MAX_STORED_NETWORKS> = Vec::new();
+    // Network the station is configured for
+    let mut configured: Option<WifiCredentials> = None;
+    let mut commanded = Commanded::default();
+    let mut failures = 0;
     loop {
+        crate::heartbeat!("connection_task");
         match esp_wifi::wifi::wifi_state() {
-            WifiState::StaConnected => {
-                // wait until we're no longer connected
-                controller.wait_for_event(WifiEvent::StaDisconnected).await;
-                Timer::after(Duration::from_millis(5000)).await
+            wifi::WifiState::StaConnected => {
+                // wait until we're no longer connected, sampling RSSI meanwhile
+                let mut ticker = Ticker::every(RSSI_PERIOD);
+                let networks_changed = loop {
+                    crate::heartbeat!("connection_task");
+                    if let Ok(rssi) = controller.rssi() {
+                        RSSI.sender().send(Some(rssi));
+                    }
+                    set_state(match address(stack) {
+                        Some(addr) => WifiState::GotIp { addr },
+                        None => WifiState::Associated,
+                    });
+                    match select4(
+                        controller.wait_for_event(WifiEvent::StaDisconnected),
+                        ticker.next(),
+                        NEW_NETWORKS.wait(),
+                        select3(SCAN_REQUEST.wait(), POWER_SAVE_CHANGED.wait(), COMMANDS.receive()),
+                    )
+                    .await
+                    {
+                        Either4::First(()) => {
+                            // Events are latched, one left over from a failed
+                            // attempt or reported around a scan must not drop
+                            // a live connection
+                            if matches!(esp_wifi::wifi::wifi_state(), wifi::WifiState::StaConnected) {
+                                crate::log!(warn: "Warning: ignoring a disconnection event while connected");
+                                continue;
+                            }
+                            break false;
+                        }
+                        Either4::Second(()) => {}
+                        Either4::Third(new_networks) => {
+                            // Stay on a network that is kept unchanged
+                            let kept = configured
+                                .as_ref()
+                                .is_some_and(|joined| new_networks.as_slice().contains(joined));
+                            networks = new_networks;
+                            round.clear();
+                            if !kept {
+                                break true;
+                            }
+                        }
+                        Either4::Fourth(Either3::First(())) => {
+                            SCAN_DONE.signal(scan_networks(&mut controller).await)
+                        }
+                        Either4::Fourth(Either3::Second(())) => apply_power_save(&mut controller),
+                        Either4::Fourth(Either3::Third(command)) => {
+                            commanded.apply(command);
+                            leave(&mut controller).await;
+                            break true;
+                        }
+                    }
+                };
+                if status().is_some_and(|status| {
+                    status.connected_since.elapsed() >= STABLE_CONNECTION_TIME
+                }) {
+                    reset_backoff();
+                }
+                set_state(WifiState::Disconnected);
+                RSSI.sender().send(None);
+                STATUS.sender().send(None);
+                crate::changes::notify();
+                crate::webhooks::notify(crate::webhooks::Event::WifiDisconnected);
+                let networks_changed = networks_changed
+                    || match wait_before_reconnecting(&mut controller, &mut rng).await {
+                        Some(Interrupted::Networks(new_networks)) => {
+                            networks = new_networks;
+                            true
+                        }
+                        Some(Interrupted::Command(command)) => {
+                            commanded.apply(command);
+                            true
+                        }
+                        None => false,
+                    };
+                if networks_changed {
+                    round.clear();
+                    failures = 0;
+                    reset_backoff();
+                }
             }
             _ => {}
         }
-        if !matches!(controller.is_started(), Ok(true)) {
-            let client_config = wifi::Configuration::Client(wifi::ClientConfiguration {
-                ssid: SSID.try_into().unwrap(),
-                password: PASSWORD.try_into().unwrap(),
-                ..Default::default()
-            });
-            controller.set_configuration(&client_config).unwrap();
-            rprintln!("Starting wifi");
+
+        // Commands received in the middle of a round
+        while let Ok(command) = COMMANDS.try_receive() {
+            commanded.apply(command);
+            round.clear();
+        }
+        if commanded.held {
+            set_state(WifiState::Disconnected);
+            hold(&mut controller, &mut networks, &mut commanded).await;
+            round.clear();
+            failures = 0;
+            reset_backoff();
+        }
+
+        if round.is_empty() {
+            let visible = if matches!(controller.is_started(), Ok(true)) {
+                scan_networks(&mut controller).await.ok()
+            } else {
+                None
+            };
+            let first = commanded.preferred.as_deref().or(last_network.as_deref());
+            round = connection_order(&networks, first, visible.as_ref());
+        }
+        // Cannot panic, there is always a network to try: the connection task
+        // only runs with some, and the last one cannot be removed
+        let credentials = networks.as_slice()[round.remove(0)].clone();
+
+        let started = matches!(controller.is_started(), Ok(true));
+        if !started || configured.as_ref() != Some(&credentials) {
+            if started {
+                restart_station(&mut controller).await;
+            }
+            controller
+                .set_configuration(&station_configuration(&credentials))
+                .unwrap();
+            crate::log!("Starting wifi");
             controller.start_async().await.unwrap();
-            rprintln!("Wifi started!");
+            crate::log!("Wifi started!");
+            // Applied again, in case stopping the station reset it
+            POWER_SAVE.store(PowerSave::None as u8, Ordering::Relaxed);
+            apply_power_save(&mut controller);
+            configured = Some(credentials.clone());
         }
-        rprintln!("About to connect...");
+        crate::log!("About to connect to {}...", credentials.ssid);
+        set_state(WifiState::Associating);
 
         match controller.connect_async().await {
-            Ok(_) => rprintln!("Wifi connected!"),
+            Ok(_) => {
+                crate::log!("Wifi connected!");
+                set_state(WifiState::Associated);
+                failures = 0;
+                round.clear();
+                // A network asked for with a command is joined for now only
+                let preferred = commanded.preferred.take().is_some_and(|ssid| ssid == credentials.ssid);
+                if !preferred && last_network.as_deref() != Some(credentials.ssid.as_str()) {
+                    if let Some(storage) = config.storage {
+                        if let Err(error) = provisioning::save_last_network(storage, &credentials.ssid).await {
+                            crate::log!(warn: "Warning: failed to save the last Wi-Fi network: {:?}", error);
+                        }
+                    }
+                    last_network = Some(credentials.ssid.clone());
+                }
+                let status = associated_status(&mut controller, &credentials.ssid).await;
+                STATUS.sender().send(Some(status));
+                crate::changes::notify();
+            }
             Err(e) => {
-                rprintln!("Failed to connect to wifi: {:?}", e);
-                Timer::after(Duration::from_millis(5000)).await
+                crate::log!("Failed to connect to {}: {:?}", credentials.ssid, e);
+                set_state(WifiState::Disconnected);
+                if !round.is_empty() {
+                    continue;
+                }
+                commanded.preferred = None;
+                failures += 1;
+                if config.max_failures.is_some_and(|max_failures| failures >= max_failures) {
+                    fall_back_to_provisioning(failures);
+                }
+                match wait_before_reconnecting(&mut controller, &mut rng).await {
+                    Some(Interrupted::Networks(new_networks)) => networks = new_networks,
+                    Some(Interrupted::Command(command)) => commanded.apply(command),
+                    None => continue,
+                }
+                failures = 0;
+                reset_backoff();
+            }
+        }
+    }
+}
+
+/// Stop the station, so it is configured again before the next attempt
+async fn restart_station(controller: &mut WifiController<'static>) {
+    if let Err(error) = controller.stop_async().await {
+        crate::log!(warn: "Warning: failed to stop the station: {:?}", error);
+    }
+}
+
+/// Build the status of a fresh association
+///
+/// The controller does not report the access point it associated with, so
+/// it is looked up with a scan restricted to the SSID.
+async fn associated_status(
+    controller: &mut WifiController<'static>,
+    ssid: &heapless::String<MAX_SSID_SIZE>,
+) -> WifiStatus {
+    let connected_since = Instant::now();
+    let scan_config = ScanConfig {
+        ssid: Some(ssid.as_str()),
+        ..Default::default()
+    };
+    let access_point = controller
+        .scan_with_config_async(scan_config)
+        .await
+        .ok()
+        .and_then(|access_points| access_points.into_iter().next());
+    WifiStatus {
+        ssid: ssid.clone(),
+        bssid: access_point.as_ref().map(|access_point| access_point.bssid),
+        channel: access_point.as_ref().map(|access_point| access_point.channel),
+        connected_since,
+    }
+}
+
+/// Delay before a new DHCP hostname is applied, so the response to the
+/// request that changed it is sent first
+const DHCP_HOSTNAME_DELAY: Duration = Duration::from_secs(1);
+
+/// Return the DHCP configuration of the station, announcing `hostname`
+fn dhcp_config(hostname: &str) -> DhcpConfig {
+    let mut config = DhcpConfig::default();
+    // Hostnames are as long as the DHCP option allows at most
+    config.hostname = heapless::String::try_from(hostname).ok();
+    config
+}
+
+/// Send the hostname of the settings to the DHCP server of the station
+///
+/// The hostname `current` is sent until the configuration is loaded. A
+/// change of the hostname restarts the DHCP client, so the server learns it
+/// with a new lease request; the address is dropped until the lease is
+/// granted, usually the same one again.
+#[embassy_executor::task]
+async fn dhcp_hostname_task(
+    stack: Stack<'static>,
+    mac: [u8; 6],
+    mut current: heapless::String<MAX_HOSTNAME_SIZE>,
+) {
+    let Some(mut receiver) = crate::config::receiver() else {
+        crate::log!(warn: "Warning: no configuration receiver left, the DHCP hostname stays {}", current);
+        return;
+    };
+    crate::tasks::beating("dhcp_hostname_task", None, async {
+        loop {
+            let config = receiver.changed().await;
+            let hostname = crate::identity::hostname(config.hostname, &mac);
+            if hostname == current {
+                continue;
             }
+            Timer::after(DHCP_HOSTNAME_DELAY).await;
+            crate::log!("DHCP hostname: {}", hostname);
+            stack.set_config_v4(ConfigV4::Dhcp(dhcp_config(&hostname)));
+            current = hostname;
+        }
+    })
+    .await
+}
+
+/// Sample the RSSI into [`SIGNAL`] every [`SIGNAL_PERIOD`], notifying the
+/// low-signal events
+///
+/// The RSSI is read from the samples of the connection task, which owns the
+/// controller. No sample is recorded while the station is not associated,
+/// e.g. in the middle of a reconnection.
+#[embassy_executor::task]
+async fn signal_task() {
+    let mut ticker = Ticker::every(SIGNAL_PERIOD);
+    loop {
+        crate::heartbeat!("signal_task");
+        ticker.next().await;
+        let associated = matches!(state(), WifiState::Associated | WifiState::GotIp { .. });
+        let Some(rssi) = rssi().filter(|_| associated) else {
+            continue;
+        };
+        match SIGNAL.record(rssi) {
+            Some(event @ crate::webhooks::Event::SignalLow { .. }) => {
+                crate::log!(warn: "Warning: low Wi-Fi signal, {} dBm", rssi);
+                crate::webhooks::notify(event);
+            }
+            Some(event) => {
+                crate::log!("Wi-Fi signal recovered, {} dBm", rssi);
+                crate::webhooks::notify(event);
+            }
+            None => {}
         }
     }
 }
 
 #[embassy_executor::task]
 async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
-    runner.run().await
+    crate::tasks::beating("net_task", None, runner.run()).await
+}
+
+/// Run the network stack of the access point kept alongside the station
+#[cfg(feature = "ap-sta")]
+#[embassy_executor::task]
+async fn access_point_net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
+    crate::tasks::beating("access_point_net_task", None, runner.run()).await
 }
\ No newline at end of file
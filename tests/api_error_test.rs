@@ -0,0 +1,23 @@
+//! Tests for the `Retry-After` hint of the JSON error bodies
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::web::ApiError;
+    use heapless::String;
+
+    #[test]
+    fn error_body_has_retry_after() {
+        let error = ApiError::service_unavailable("wifi_reconnecting")
+            .with_message("Retry")
+            .with_retry_after(3);
+        let body: String<80> = serde_json_core::to_string(&error).unwrap();
+        assert_eq!(
+            body,
+            r#"{"code":"wifi_reconnecting","message":"Retry","retry_after_s":3}"#
+        );
+    }
+}
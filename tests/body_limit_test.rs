@@ -0,0 +1,28 @@
+//! Tests for the `Content-Length` check of the request body size limit
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::web::{body_too_large, DEFAULT_MAX_REQUEST_BODY_SIZE};
+
+    #[test]
+    fn large_body_rejected() {
+        assert!(body_too_large("10240", DEFAULT_MAX_REQUEST_BODY_SIZE));
+        assert!(body_too_large(" 1025 ", 1024));
+    }
+
+    #[test]
+    fn body_within_limit_accepted() {
+        assert!(!body_too_large("0", DEFAULT_MAX_REQUEST_BODY_SIZE));
+        assert!(!body_too_large("1024", 1024));
+    }
+
+    #[test]
+    fn invalid_length_left_to_parser() {
+        assert!(!body_too_large("lots", 1024));
+        assert!(!body_too_large("-1", 1024));
+    }
+}
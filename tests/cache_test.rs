@@ -0,0 +1,61 @@
+//! Tests for the response cache
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use embassy_time::{Duration, Timer};
+    use esp32c3_embassy_picoserve::cache::{CachedRoute, ResponseCache, CACHE_BODY_SIZE};
+    use esp_hal::timer::systimer::SystemTimer;
+
+    const ROUTES: &[CachedRoute] = &[CachedRoute::new("/wifi", Duration::from_millis(50))];
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+    }
+
+    #[test]
+    fn stored_body_served() {
+        let cache = ResponseCache::new(ROUTES);
+        assert!(cache.get("/wifi").is_none());
+        cache.store("/wifi", &[1, 2, 3]);
+        assert_eq!(cache.get("/wifi").unwrap().as_slice(), b"[1,2,3]");
+    }
+
+    #[test]
+    fn uncached_path_ignored() {
+        let cache = ResponseCache::new(ROUTES);
+        assert!(!cache.is_cached("/time"));
+        cache.store("/time", &1);
+        assert!(cache.get("/time").is_none());
+    }
+
+    #[test]
+    fn invalidated_entry_dropped() {
+        let cache = ResponseCache::new(ROUTES);
+        cache.store("/wifi", &true);
+        cache.invalidate("/wifi");
+        assert!(cache.get("/wifi").is_none());
+    }
+
+    #[test]
+    fn oversized_body_not_stored() {
+        let cache = ResponseCache::new(ROUTES);
+        cache.store("/wifi", &[0_u8; CACHE_BODY_SIZE]);
+        assert!(cache.get("/wifi").is_none());
+    }
+
+    #[test]
+    async fn entry_expires() {
+        let cache = ResponseCache::new(ROUTES);
+        cache.store("/wifi", &true);
+        Timer::after(Duration::from_millis(60)).await;
+        assert!(cache.get("/wifi").is_none());
+    }
+}
@@ -0,0 +1,27 @@
+//! Tests for the connectivity check paths
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::captive::PROBE_PATHS;
+    use esp32c3_embassy_picoserve::web::{routes, NormalizedPath};
+
+    #[test]
+    fn probe_paths_canonical() {
+        // A redirect to the canonical path would be taken for a captive
+        // portal even outside of provisioning
+        for path in PROBE_PATHS {
+            assert!(NormalizedPath::new(path).is_none());
+        }
+    }
+
+    #[test]
+    fn probe_paths_routed() {
+        for path in PROBE_PATHS {
+            assert!(routes().any(|route| route.path == path && route.methods == "GET"));
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! Tests for the state sequence number used by long polling
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use embassy_futures::join::join;
+    use embassy_time::{Duration, Timer};
+    use esp32c3_embassy_picoserve::changes;
+    use esp_hal::timer::systimer::SystemTimer;
+
+    const WAIT: Duration = Duration::from_millis(50);
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+    }
+
+    #[test]
+    async fn unchanged_state_times_out() {
+        let since = changes::sequence();
+        assert_eq!(changes::wait_for_change(since, WAIT).await, None);
+    }
+
+    #[test]
+    async fn past_change_returns_immediately() {
+        let since = changes::sequence();
+        changes::notify();
+        assert_eq!(changes::wait_for_change(since, WAIT).await, Some(since + 1));
+    }
+
+    #[test]
+    async fn every_poller_wakes_on_a_change() {
+        let since = changes::sequence();
+        let notify = async {
+            Timer::after(Duration::from_millis(10)).await;
+            changes::notify();
+        };
+        let pollers = join(
+            changes::wait_for_change(since, WAIT),
+            changes::wait_for_change(since, WAIT),
+        );
+        let ((first, second), ()) = join(pollers, notify).await;
+        assert_eq!(first, Some(since + 1));
+        assert_eq!(second, Some(since + 1));
+    }
+}
@@ -0,0 +1,67 @@
+//! Tests for the CIDR matcher of the admin allowlist
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use core::net::Ipv4Addr;
+
+    use esp32c3_embassy_picoserve::cidr::{is_allowed, Cidr, LAN_RANGES};
+
+    #[test]
+    fn prefix_matched() {
+        let range = Cidr::new(Ipv4Addr::new(192, 168, 1, 0), 24);
+        assert!(range.contains(Ipv4Addr::new(192, 168, 1, 0)));
+        assert!(range.contains(Ipv4Addr::new(192, 168, 1, 255)));
+        assert!(!range.contains(Ipv4Addr::new(192, 168, 2, 1)));
+    }
+
+    #[test]
+    fn host_bits_ignored() {
+        let range = Cidr::new(Ipv4Addr::new(10, 1, 2, 3), 8);
+        assert!(range.contains(Ipv4Addr::new(10, 200, 0, 1)));
+    }
+
+    #[test]
+    fn zero_prefix_matches_everything() {
+        let range = Cidr::new(Ipv4Addr::new(1, 2, 3, 4), 0);
+        assert!(range.contains(Ipv4Addr::new(0, 0, 0, 0)));
+        assert!(range.contains(Ipv4Addr::new(255, 255, 255, 255)));
+    }
+
+    #[test]
+    fn slash_31_matches_a_pair() {
+        let range = Cidr::new(Ipv4Addr::new(192, 0, 2, 4), 31);
+        assert!(range.contains(Ipv4Addr::new(192, 0, 2, 4)));
+        assert!(range.contains(Ipv4Addr::new(192, 0, 2, 5)));
+        assert!(!range.contains(Ipv4Addr::new(192, 0, 2, 3)));
+        assert!(!range.contains(Ipv4Addr::new(192, 0, 2, 6)));
+    }
+
+    #[test]
+    fn slash_32_matches_one_address() {
+        let range = Cidr::new(Ipv4Addr::new(192, 0, 2, 4), 32);
+        assert!(range.contains(Ipv4Addr::new(192, 0, 2, 4)));
+        assert!(!range.contains(Ipv4Addr::new(192, 0, 2, 5)));
+    }
+
+    #[test]
+    fn oversized_prefix_clamped() {
+        assert_eq!(
+            Cidr::new(Ipv4Addr::new(192, 0, 2, 4), 40),
+            Cidr::new(Ipv4Addr::new(192, 0, 2, 4), 32)
+        );
+    }
+
+    #[test]
+    fn lan_ranges() {
+        assert!(is_allowed(LAN_RANGES, Ipv4Addr::new(10, 0, 0, 1)));
+        assert!(is_allowed(LAN_RANGES, Ipv4Addr::new(172, 31, 255, 255)));
+        assert!(!is_allowed(LAN_RANGES, Ipv4Addr::new(172, 32, 0, 1)));
+        assert!(is_allowed(LAN_RANGES, Ipv4Addr::new(192, 168, 0, 10)));
+        assert!(is_allowed(LAN_RANGES, Ipv4Addr::new(169, 254, 1, 1)));
+        assert!(!is_allowed(LAN_RANGES, Ipv4Addr::new(8, 8, 8, 8)));
+    }
+}
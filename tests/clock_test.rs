@@ -0,0 +1,32 @@
+//! Tests for the UTC offset shared by the clocks
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::clock::Clock;
+    use time::UtcOffset;
+
+    #[test]
+    fn new_clock_keeps_the_offset() {
+        let offset = UtcOffset::from_whole_seconds(2 * 3600).unwrap();
+        Clock::unsynchronized().set_offset(offset);
+
+        let clock = Clock::new(1_700_000_000);
+        assert_eq!(clock.offset(), offset);
+    }
+
+    #[test]
+    fn offset_shared_by_every_clock() {
+        let clock = Clock::unsynchronized();
+        let clone = clock.clone();
+        let other = Clock::unsynchronized();
+
+        let offset = UtcOffset::from_whole_seconds(-5 * 3600).unwrap();
+        clock.set_offset(offset);
+        assert_eq!(clone.offset(), offset);
+        assert_eq!(other.offset(), offset);
+    }
+}
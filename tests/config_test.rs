@@ -0,0 +1,93 @@
+//! Tests for the parsing and the validation of configuration documents
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::config::{parse_config, AuthConfig, DeviceConfig};
+    use heapless::{String, Vec};
+
+    const DOCUMENT: &str = r#"{
+        "offset_minutes": 60,
+        "hostname": "garden",
+        "blink_period_ms": 250,
+        "webhooks": ["http://192.168.1.10/hook"],
+        "auth": {"session_idle_minutes": 30}
+    }"#;
+
+    fn config() -> DeviceConfig {
+        let mut webhooks = Vec::new();
+        webhooks
+            .push(String::try_from("http://192.168.1.10/hook").unwrap())
+            .unwrap();
+        DeviceConfig {
+            offset_minutes: 60,
+            hostname: String::try_from("garden").unwrap(),
+            blink_period_ms: 250,
+            webhooks,
+            auth: AuthConfig {
+                session_idle_minutes: 30,
+            },
+        }
+    }
+
+    #[test]
+    fn document_parsed() {
+        let parsed = parse_config(DOCUMENT.as_bytes()).unwrap();
+        assert_eq!(parsed, config());
+        assert!(parsed.validate().is_ok());
+    }
+
+    #[test]
+    fn unknown_field_reported() {
+        let body = r#"{"offset_minutes":0,"hostnme":"esp","blink_period_ms":500,"webhooks":[],"auth":{"session_idle_minutes":5}}"#;
+        let error = parse_config(body.as_bytes()).unwrap_err();
+        assert_eq!(error.code, "unknown_field");
+        assert_eq!(error.field.as_deref(), Some("hostnme"));
+    }
+
+    #[test]
+    fn unknown_nested_field_reported() {
+        let body = r#"{"offset_minutes":0,"hostname":"esp","blink_period_ms":500,"webhooks":[],"auth":{"session_idle_minute":5}}"#;
+        let error = parse_config(body.as_bytes()).unwrap_err();
+        assert_eq!(error.code, "unknown_field");
+        assert_eq!(error.field.as_deref(), Some("auth.session_idle_minute"));
+    }
+
+    #[test]
+    fn missing_field_rejected() {
+        let body = r#"{"offset_minutes":0,"hostname":"esp","blink_period_ms":500,"webhooks":[]}"#;
+        let error = parse_config(body.as_bytes()).unwrap_err();
+        assert_eq!(error.code, "malformed_config");
+        assert_eq!(error.field, None);
+    }
+
+    #[test]
+    fn invalid_value_reported() {
+        let mut invalid = config();
+        invalid.hostname = String::try_from("-garden").unwrap();
+        assert_eq!(
+            invalid.validate().unwrap_err().field.as_deref(),
+            Some("hostname")
+        );
+
+        let mut invalid = config();
+        invalid.auth.session_idle_minutes = 0;
+        assert_eq!(
+            invalid.validate().unwrap_err().field.as_deref(),
+            Some("auth.session_idle_minutes")
+        );
+    }
+
+    #[test]
+    fn scalars_round_trip() {
+        let decoded = DeviceConfig::decode(&config().encode()).unwrap();
+        assert_eq!(decoded.hostname, "garden");
+        assert_eq!(decoded.offset_minutes, 60);
+        assert_eq!(decoded.blink_period_ms, 250);
+        assert_eq!(decoded.auth.session_idle_minutes, 30);
+        assert!(decoded.webhooks.is_empty());
+    }
+}
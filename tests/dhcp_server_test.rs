@@ -0,0 +1,158 @@
+//! Tests for the DHCP server of the access point
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use embassy_net::Ipv4Address;
+    use embassy_time::{Duration, Instant};
+    use esp32c3_embassy_picoserve::dhcp_server::{DhcpServer, LEASE_TIME, MAX_MESSAGE_SIZE, POOL_SIZE};
+
+    const DISCOVER: u8 = 1;
+    const OFFER: u8 = 2;
+    const REQUEST: u8 = 3;
+    const ACK: u8 = 5;
+    const NAK: u8 = 6;
+
+    const MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+    const OTHER_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x02];
+
+    /// Build a client message, with the options of a `DHCPREQUEST` if given
+    fn message(
+        message_type: u8,
+        mac: [u8; 6],
+        requested: Option<Ipv4Address>,
+        server: Option<Ipv4Address>,
+    ) -> ([u8; 300], usize) {
+        let mut message = [0; 300];
+        message[0] = 1;
+        message[1] = 1;
+        message[2] = 6;
+        message[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        message[28..34].copy_from_slice(&mac);
+        message[236..240].copy_from_slice(&[99, 130, 83, 99]);
+        let mut length = 240;
+        message[length..length + 3].copy_from_slice(&[53, 1, message_type]);
+        length += 3;
+        for (code, address) in [(50, requested), (54, server)] {
+            if let Some(address) = address {
+                message[length..length + 2].copy_from_slice(&[code, 4]);
+                message[length + 2..length + 6].copy_from_slice(&address.octets());
+                length += 6;
+            }
+        }
+        message[length] = 255;
+        (message, length + 1)
+    }
+
+    /// Return the message type and the offered address of a reply
+    fn parse_reply(reply: &[u8]) -> (u8, Ipv4Address) {
+        assert_eq!(reply[0], 2);
+        assert_eq!(reply[4..8], [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(reply[236..240], [99, 130, 83, 99]);
+        assert_eq!(reply[240..242], [53, 1]);
+        let address = Ipv4Address::new(reply[16], reply[17], reply[18], reply[19]);
+        (reply[242], address)
+    }
+
+    fn send(
+        server: &mut DhcpServer,
+        message_type: u8,
+        mac: [u8; 6],
+        requested: Option<Ipv4Address>,
+        now: Instant,
+    ) -> Option<(u8, Ipv4Address)> {
+        let (message, length) = message(message_type, mac, requested, None);
+        let mut reply = [0; MAX_MESSAGE_SIZE];
+        let reply_length = server.handle(&message[..length], now, &mut reply)?;
+        Some(parse_reply(&reply[..reply_length]))
+    }
+
+    #[test]
+    fn offer_and_acknowledge() {
+        let mut server = DhcpServer::new();
+        let now = Instant::from_secs(1);
+        let offered = Ipv4Address::new(192, 168, 4, 2);
+        assert_eq!(send(&mut server, DISCOVER, MAC, None, now), Some((OFFER, offered)));
+        assert_eq!(send(&mut server, REQUEST, MAC, Some(offered), now), Some((ACK, offered)));
+        assert_eq!(server.leased_address(&MAC, now), Some(offered));
+    }
+
+    #[test]
+    fn clients_get_their_own_address() {
+        let mut server = DhcpServer::new();
+        let now = Instant::from_secs(1);
+        send(&mut server, DISCOVER, MAC, None, now);
+        assert_eq!(
+            send(&mut server, DISCOVER, OTHER_MAC, None, now),
+            Some((OFFER, Ipv4Address::new(192, 168, 4, 3)))
+        );
+        // A client asking again is offered the same address
+        assert_eq!(
+            send(&mut server, DISCOVER, MAC, None, now),
+            Some((OFFER, Ipv4Address::new(192, 168, 4, 2)))
+        );
+    }
+
+    #[test]
+    fn foreign_address_is_refused() {
+        let mut server = DhcpServer::new();
+        let now = Instant::from_secs(1);
+        let reply = send(&mut server, REQUEST, MAC, Some(Ipv4Address::new(10, 0, 0, 7)), now);
+        assert_eq!(reply, Some((NAK, Ipv4Address::UNSPECIFIED)));
+
+        // Reserved for another client
+        send(&mut server, DISCOVER, OTHER_MAC, None, now);
+        let reply = send(&mut server, REQUEST, MAC, Some(Ipv4Address::new(192, 168, 4, 2)), now);
+        assert_eq!(reply.map(|(message_type, _)| message_type), Some(NAK));
+    }
+
+    #[test]
+    fn request_to_another_server_is_ignored() {
+        let mut server = DhcpServer::new();
+        let requested = Some(Ipv4Address::new(192, 168, 4, 2));
+        let (message, length) = message(REQUEST, MAC, requested, Some(Ipv4Address::new(192, 168, 1, 1)));
+        let mut reply = [0; MAX_MESSAGE_SIZE];
+        assert_eq!(server.handle(&message[..length], Instant::from_secs(1), &mut reply), None);
+    }
+
+    #[test]
+    fn full_pool_is_ignored() {
+        let mut server = DhcpServer::new();
+        let now = Instant::from_secs(1);
+        for client in 0..POOL_SIZE {
+            let mac = [0x02, 0, 0, 0, 1, client as u8];
+            assert!(send(&mut server, DISCOVER, mac, None, now).is_some());
+        }
+        assert_eq!(send(&mut server, DISCOVER, MAC, None, now), None);
+    }
+
+    #[test]
+    fn expired_lease_is_reused() {
+        let mut server = DhcpServer::new();
+        let now = Instant::from_secs(1);
+        for client in 0..POOL_SIZE {
+            let mac = [0x02, 0, 0, 0, 1, client as u8];
+            send(&mut server, DISCOVER, mac, None, now);
+        }
+        let later = now + LEASE_TIME + Duration::from_secs(1);
+        assert_eq!(
+            send(&mut server, DISCOVER, MAC, None, later),
+            Some((OFFER, Ipv4Address::new(192, 168, 4, 2)))
+        );
+    }
+
+    #[test]
+    fn malformed_messages_are_ignored() {
+        let mut server = DhcpServer::new();
+        let mut reply = [0; MAX_MESSAGE_SIZE];
+        assert_eq!(server.handle(&[1, 1, 6], Instant::from_secs(1), &mut reply), None);
+
+        let (mut message, length) = message(DISCOVER, MAC, None, None);
+        // A BOOTREPLY
+        message[0] = 2;
+        assert_eq!(server.handle(&message[..length], Instant::from_secs(1), &mut reply), None);
+    }
+}
@@ -0,0 +1,127 @@
+//! Tests for the DNS server of the provisioning access point
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use embassy_net::Ipv4Address;
+    use esp32c3_embassy_picoserve::dns_server::{answer, record_type, response_code, MAX_MESSAGE_SIZE, TTL};
+
+    const ADDRESS: Ipv4Address = Ipv4Address::new(192, 168, 4, 1);
+
+    /// `connectivitycheck.gstatic.com` in wire format
+    const NAME: &[u8] = b"\x11connectivitycheck\x07gstatic\x03com\x00";
+
+    /// Build a query for `NAME` with a record type
+    fn query(record_type: u16) -> ([u8; 64], usize) {
+        let mut query = [0; 64];
+        query[..12].copy_from_slice(&[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        query[12..12 + NAME.len()].copy_from_slice(NAME);
+        let mut length = 12 + NAME.len();
+        query[length..length + 2].copy_from_slice(&record_type.to_be_bytes());
+        query[length + 2..length + 4].copy_from_slice(&[0, 1]);
+        length += 4;
+        (query, length)
+    }
+
+    #[test]
+    fn a_query_is_answered_with_the_address() {
+        let (query, length) = query(record_type::A);
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        let response_length = answer(&query[..length], ADDRESS, &mut response).unwrap();
+        let response = &response[..response_length];
+
+        // Same id, a response with recursion desired echoed, one answer
+        assert_eq!(response[..12], [0x12, 0x34, 0x85, 0x00, 0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(response[12..length], query[12..length]);
+        let mut record = [0; 16];
+        record[..10].copy_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 0]);
+        record[6..10].copy_from_slice(&TTL.to_be_bytes());
+        record[10..12].copy_from_slice(&[0, 4]);
+        record[12..].copy_from_slice(&ADDRESS.octets());
+        assert_eq!(response[length..], record);
+    }
+
+    #[test]
+    fn aaaa_query_gets_an_empty_answer() {
+        let (query, length) = query(record_type::AAAA);
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        let response_length = answer(&query[..length], ADDRESS, &mut response).unwrap();
+        assert_eq!(response_length, length);
+        assert_eq!(response[3], response_code::NO_ERROR);
+        assert_eq!(response[6..8], [0, 0]);
+        assert_eq!(response[12..length], query[12..length]);
+    }
+
+    #[test]
+    fn compressed_name_is_expanded() {
+        // `www` followed by a pointer to the last byte of the header, zero
+        // like the root label
+        let mut query = [0; 22];
+        query[..12].copy_from_slice(&[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        query[12..18].copy_from_slice(b"\x03www\xc0\x0b");
+        query[18..22].copy_from_slice(&[0, 1, 0, 1]);
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        let response_length = answer(&query, ADDRESS, &mut response).unwrap();
+        assert_eq!(response[7], 1);
+        assert_eq!(response[12..21], *b"\x03www\x00\x00\x01\x00\x01");
+        assert_eq!(response[21..23], [0xc0, 0x0c]);
+        assert_eq!(response_length, 21 + 16);
+    }
+
+    #[test]
+    fn pointer_loop_is_refused() {
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        for pointer in [
+            // To the start of the name
+            [0xc0, 0x0c],
+            // Forward, to itself
+            [0xc0, 0x10],
+        ] {
+            let mut query = [0; 22];
+            query[..12].copy_from_slice(&[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+            query[12..16].copy_from_slice(b"\x03www");
+            query[16..18].copy_from_slice(&pointer);
+            query[18..22].copy_from_slice(&[0, 1, 0, 1]);
+            assert_eq!(answer(&query, ADDRESS, &mut response), Some(12));
+            assert_eq!(response[3], response_code::FORMAT_ERROR);
+        }
+    }
+
+    #[test]
+    fn response_is_ignored() {
+        let (mut query, length) = query(record_type::A);
+        query[2] |= 0x80;
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        assert_eq!(answer(&query[..length], ADDRESS, &mut response), None);
+    }
+
+    #[test]
+    fn malformed_query_is_refused() {
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        assert_eq!(answer(&[0x12, 0x34, 0x01], ADDRESS, &mut response), None);
+
+        let (query, length) = query(record_type::A);
+        // The name runs past the end
+        assert_eq!(answer(&query[..20], ADDRESS, &mut response), Some(12));
+        assert_eq!(response[3], response_code::FORMAT_ERROR);
+        // No question
+        let mut empty = query;
+        empty[5] = 0;
+        assert_eq!(answer(&empty[..length], ADDRESS, &mut response), Some(12));
+        assert_eq!(response[3], response_code::FORMAT_ERROR);
+    }
+
+    #[test]
+    fn other_opcodes_are_not_implemented() {
+        let (mut query, length) = query(record_type::A);
+        // A status request
+        query[2] = 0x10;
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        assert_eq!(answer(&query[..length], ADDRESS, &mut response), Some(12));
+        assert_eq!(response[2], 0x80 | 0x04 | 0x10);
+        assert_eq!(response[3], response_code::NOT_IMPLEMENTED);
+    }
+}
@@ -0,0 +1,27 @@
+//! Tests for the report of the bodies received at `/echo`
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::files::Crc32;
+    use esp32c3_embassy_picoserve::web::EchoResponse;
+    use heapless::String;
+
+    #[test]
+    fn echo_report_has_length_and_crc() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        let body: String<64> = serde_json_core::to_string(&EchoResponse::new(9, &crc)).unwrap();
+        assert_eq!(body, r#"{"bytes":9,"crc32":"cbf43926"}"#);
+    }
+
+    #[test]
+    fn empty_body_reported() {
+        let body: String<64> =
+            serde_json_core::to_string(&EchoResponse::new(0, &Crc32::new())).unwrap();
+        assert_eq!(body, r#"{"bytes":0,"crc32":"00000000"}"#);
+    }
+}
@@ -0,0 +1,36 @@
+//! Tests for the `If-None-Match` matching used by conditional responses
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::etag::etag_matches;
+
+    #[test]
+    fn exact_match() {
+        assert!(etag_matches(r#"W/"0.1.0-abc""#, r#"W/"0.1.0-abc""#));
+    }
+
+    #[test]
+    fn weak_comparison() {
+        assert!(etag_matches(r#""0.1.0-abc""#, r#"W/"0.1.0-abc""#));
+    }
+
+    #[test]
+    fn list_of_tags() {
+        assert!(etag_matches(r#"W/"old", W/"0.1.0-abc""#, r#"W/"0.1.0-abc""#));
+    }
+
+    #[test]
+    fn wildcard() {
+        assert!(etag_matches(" * ", r#"W/"0.1.0-abc""#));
+    }
+
+    #[test]
+    fn mismatch() {
+        assert!(!etag_matches(r#"W/"0.1.0-def""#, r#"W/"0.1.0-abc""#));
+        assert!(!etag_matches("", r#"W/"0.1.0-abc""#));
+    }
+}
@@ -0,0 +1,47 @@
+//! Tests for the CRC-32 and the file names of the flash file store
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::files::{is_valid_name, Crc32};
+
+    #[test]
+    fn crc_check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn crc_of_nothing() {
+        assert_eq!(Crc32::new().finish(), 0);
+    }
+
+    #[test]
+    fn crc_incremental() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn valid_names() {
+        assert!(is_valid_name("index.html"));
+        assert!(is_valid_name("app-v2_min.js"));
+        assert!(is_valid_name("0123456789abcdef"));
+    }
+
+    #[test]
+    fn invalid_names() {
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name(".hidden"));
+        assert!(!is_valid_name("a/b"));
+        assert!(!is_valid_name("with space"));
+        assert!(!is_valid_name("0123456789abcdefg"));
+    }
+}
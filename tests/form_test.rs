@@ -0,0 +1,63 @@
+//! Tests for the form body parser used by the settings page
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::web::{
+        parse_form_body, FormRejection, SettingsUpdate, MAX_FORM_BODY_SIZE, MAX_QUERY_VALUE,
+    };
+
+    #[test]
+    fn plus_is_a_space() {
+        let form = parse_form_body(b"name=hello+world&other=a%2Bb").unwrap();
+        assert_eq!(form.get("name"), Some("hello world"));
+        assert_eq!(form.get("other"), Some("a+b"));
+    }
+
+    #[test]
+    fn first_repeated_key_wins() {
+        let form = parse_form_body(b"hostname=first&hostname=second").unwrap();
+        assert_eq!(form.get("hostname"), Some("first"));
+    }
+
+    #[test]
+    fn overlong_values_are_dropped() {
+        let mut body = [b'a'; 9 + MAX_QUERY_VALUE + 1];
+        body[..9].copy_from_slice(b"hostname=");
+        let form = parse_form_body(&body).unwrap();
+        assert_eq!(form.get("hostname"), None);
+    }
+
+    #[test]
+    fn oversized_body() {
+        let body = [b'a'; MAX_FORM_BODY_SIZE + 1];
+        assert_eq!(parse_form_body(&body).err(), Some(FormRejection::PayloadTooLarge));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        assert_eq!(parse_form_body(b"name=\xff").err(), Some(FormRejection::Malformed));
+    }
+
+    #[test]
+    fn valid_settings() {
+        let form = parse_form_body(b"offset_minutes=120&hostname=kitchen-1&blink_period_ms=250").unwrap();
+        let update = SettingsUpdate::parse(&form).unwrap();
+        assert_eq!(update.offset.whole_minutes(), 120);
+        assert_eq!(update.hostname.as_str(), "kitchen-1");
+        assert_eq!(update.blink_period_ms, 250);
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let form = parse_form_body(b"offset_minutes=120&hostname=-bad&blink_period_ms=250").unwrap();
+        assert!(SettingsUpdate::parse(&form).is_err());
+        let form = parse_form_body(b"offset_minutes=9999&hostname=ok&blink_period_ms=250").unwrap();
+        assert!(SettingsUpdate::parse(&form).is_err());
+        let form = parse_form_body(b"offset_minutes=0&hostname=ok&blink_period_ms=1").unwrap();
+        assert!(SettingsUpdate::parse(&form).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! Tests for the gzip encoder and the choice of coding
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use core::fmt::Write as _;
+
+    use esp32c3_embassy_picoserve::compression::Compression;
+    use esp32c3_embassy_picoserve::files::Crc32;
+    use esp32c3_embassy_picoserve::gzip::Encoder;
+    use heapless::{String, Vec};
+
+    /// Compress input written in pieces of `piece` bytes
+    fn compress(input: &[u8], piece: usize) -> Vec<u8, 1024> {
+        let mut encoder = Encoder::try_new().unwrap();
+        let mut output = Vec::new();
+        for mut chunk in input.chunks(piece) {
+            while !chunk.is_empty() {
+                let count = encoder.feed(chunk);
+                chunk = &chunk[count..];
+                while encoder.compress(false) {
+                    output.extend_from_slice(encoder.output()).unwrap();
+                    encoder.take_output();
+                }
+            }
+        }
+        while encoder.finish() {
+            output.extend_from_slice(encoder.output()).unwrap();
+            encoder.take_output();
+        }
+        output.extend_from_slice(encoder.output()).unwrap();
+        output
+    }
+
+    fn json_lines() -> String<8192> {
+        let mut lines = String::new();
+        for i in 0..200 {
+            write!(lines, "{{\"path\":\"/gpio/{}\",\"status\":200}}\n", i % 10).unwrap();
+        }
+        lines
+    }
+
+    #[init]
+    fn init() {
+        esp_alloc::heap_allocator!(size: 16 * 1024);
+    }
+
+    #[test]
+    fn empty_stream() {
+        assert_eq!(
+            compress(b"", 1).as_slice(),
+            [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn single_literal() {
+        assert_eq!(
+            compress(b"a", 1).as_slice(),
+            [
+                0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff, 0x4b, 0x04, 0x00, 0x43, 0xbe, 0xb7, 0xe8, 1,
+                0, 0, 0
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_json_shrinks() {
+        let lines = json_lines();
+        let compressed = compress(lines.as_bytes(), lines.len());
+        assert!(compressed.len() < lines.len() / 4);
+
+        let mut crc = Crc32::new();
+        crc.update(lines.as_bytes());
+        let trailer = &compressed[compressed.len() - 8..];
+        assert_eq!(trailer[..4], crc.finish().to_le_bytes());
+        assert_eq!(trailer[4..], (lines.len() as u32).to_le_bytes());
+    }
+
+    #[test]
+    fn output_independent_of_writes() {
+        let lines = json_lines();
+        let whole = compress(lines.as_bytes(), lines.len());
+        assert_eq!(compress(lines.as_bytes(), 1), whole);
+        assert_eq!(compress(lines.as_bytes(), 13), whole);
+        assert_eq!(compress(lines.as_bytes(), 300), whole);
+    }
+
+    #[test]
+    fn threshold_applies() {
+        let compression = Compression::new(Some(1024), true);
+        assert!(compression.applies(1024));
+        assert!(!compression.applies(1023));
+        assert!(!Compression::new(Some(1024), false).applies(4096));
+        assert!(!Compression::new(None, true).applies(4096));
+    }
+}
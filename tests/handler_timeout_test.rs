@@ -0,0 +1,43 @@
+//! Tests for the timeouts of the route groups
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use embassy_time::Duration;
+    use esp32c3_embassy_picoserve::handler_timeout::{HandlerTimeoutLayer, DEFAULT_HANDLER_TIMEOUT};
+    use esp32c3_embassy_picoserve::web::HANDLER_TIMEOUTS;
+
+    const LAYER: HandlerTimeoutLayer = HandlerTimeoutLayer::new(DEFAULT_HANDLER_TIMEOUT, HANDLER_TIMEOUTS);
+
+    #[test]
+    fn default_timeout() {
+        for path in ["/", "/time", "/stats", "/filesystem", "/uploads"] {
+            assert_eq!(LAYER.timeout(path), Some(DEFAULT_HANDLER_TIMEOUT), "{}", path);
+        }
+    }
+
+    #[test]
+    fn route_group_timeout() {
+        assert_eq!(LAYER.timeout("/files"), Some(Duration::from_secs(30)));
+        assert_eq!(LAYER.timeout("/files/firmware.bin"), Some(Duration::from_secs(30)));
+        assert_eq!(LAYER.timeout("/upload"), Some(Duration::from_secs(30)));
+        assert_eq!(LAYER.timeout("/wifi/scan"), Some(Duration::from_secs(15)));
+        assert_eq!(LAYER.timeout("/wifi"), Some(DEFAULT_HANDLER_TIMEOUT));
+    }
+
+    #[test]
+    fn long_poll_has_no_timeout() {
+        assert_eq!(LAYER.timeout("/poll"), None);
+    }
+
+    #[test]
+    fn no_timeout() {
+        const ROUTES: &[(&str, Option<Duration>)] = &[("/events", None)];
+        let layer = HandlerTimeoutLayer::new(DEFAULT_HANDLER_TIMEOUT, ROUTES);
+        assert_eq!(layer.timeout("/events/rssi"), None);
+        assert_eq!(layer.timeout("/eventsource"), Some(DEFAULT_HANDLER_TIMEOUT));
+    }
+}
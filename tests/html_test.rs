@@ -0,0 +1,91 @@
+//! Tests for the escaping of text in HTML pages
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use core::fmt::Write;
+
+    use esp32c3_embassy_picoserve::html::{Escaped, Page};
+    use heapless::String;
+
+    fn escaped(text: &str) -> String<128> {
+        let mut output = String::new();
+        write!(output, "{}", Escaped(text)).unwrap();
+        output
+    }
+
+    #[test]
+    fn angle_brackets_escaped() {
+        assert_eq!(escaped("<script>alert(1)</script>"), "&lt;script&gt;alert(1)&lt;/script&gt;");
+    }
+
+    #[test]
+    fn ampersand_escaped() {
+        assert_eq!(escaped("a & b"), "a &amp; b");
+        assert_eq!(escaped("&lt;"), "&amp;lt;");
+    }
+
+    #[test]
+    fn quotes_escaped() {
+        assert_eq!(escaped("\"quoted\" 'text'"), "&quot;quoted&quot; &#39;text&#39;");
+    }
+
+    #[test]
+    fn plain_text_unchanged() {
+        assert_eq!(escaped("esp32c3-kitchen"), "esp32c3-kitchen");
+    }
+
+    #[test]
+    fn page_escapes_interpolated_text() {
+        let page = Page::<512>::new("<Title>")
+            .para("<b>bold</b>")
+            .table([("Name", "<script>")])
+            .form("/settings")
+            .text_input("hostname", "Hostname", 32, "\"><script>")
+            .submit("Save")
+            .render();
+        let html = page.0.as_str();
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<b>"));
+        assert!(html.contains("<title>&lt;Title&gt;</title>"));
+        assert!(html.contains("<td>&lt;script&gt;</td>"));
+        assert!(html.contains("value=\"&quot;&gt;&lt;script&gt;\""));
+        assert!(html.ends_with("</body></html>"));
+    }
+
+    #[test]
+    fn hidden_input_in_form() {
+        let page = Page::<512>::new("Settings")
+            .form("/settings")
+            .hidden_input("csrf_token", "0123abcd")
+            .submit("Save")
+            .render();
+        assert!(page
+            .0
+            .contains("<input name=\"csrf_token\" type=\"hidden\" value=\"0123abcd\">"));
+    }
+
+    #[test]
+    fn grid_rows_escaped_and_classed() {
+        let page = Page::<512>::new("Log")
+            .grid(
+                "log",
+                &["Path", "Status"],
+                [(None, ["/time", "200"]), (Some("error"), ["/<missing>", "404"])],
+            )
+            .render();
+        let html = page.0.as_str();
+        assert!(html.contains("<table id=\"log\"><tr><th>Path</th><th>Status</th></tr>"));
+        assert!(html.contains("<tr><td>/time</td><td>200</td></tr>"));
+        assert!(html.contains("<tr class=\"error\"><td>/&lt;missing&gt;</td><td>404</td></tr>"));
+    }
+
+    #[test]
+    fn script_before_end_of_body() {
+        let page = Page::<512>::new("Log").script("poll();").render();
+        assert!(page.0.ends_with("<script>poll();</script></body></html>"));
+    }
+}
@@ -0,0 +1,67 @@
+//! Tests for the names derived from the MAC address
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::identity::{
+        access_point_password, access_point_password_from, device_name, format_mac_address,
+        hostname, mac_address, set_access_point_password, set_mac_address,
+    };
+    use esp32c3_embassy_picoserve::settings::{is_valid_hostname, DEFAULT_HOSTNAME};
+    use heapless::String;
+
+    const MAC: [u8; 6] = [0x34, 0x85, 0x18, 0xa1, 0xb2, 0xc3];
+
+    #[test]
+    fn device_name_uses_last_bytes() {
+        assert_eq!(device_name(&MAC).as_str(), "picoserve-a1b2c3");
+    }
+
+    #[test]
+    fn hostname_defaults_to_device_name() {
+        let default = String::try_from(DEFAULT_HOSTNAME).unwrap();
+        assert_eq!(hostname(default, &MAC).as_str(), "picoserve-a1b2c3");
+        let garden = String::try_from("garden").unwrap();
+        assert_eq!(hostname(garden, &MAC).as_str(), "garden");
+    }
+
+    #[test]
+    fn hostnames_fit_the_dhcp_option() {
+        assert!(is_valid_hostname("garden-2"));
+        assert!(is_valid_hostname("abcdefghijklmnopqrstuvwxyz-01234"));
+        for invalid in ["", "-garden", "garden-", "garden.local", "garden_2", "jardín"] {
+            assert!(!is_valid_hostname(invalid), "{invalid}");
+        }
+        assert!(!is_valid_hostname("abcdefghijklmnopqrstuvwxyz-012345"));
+    }
+
+    #[test]
+    fn access_point_password_formatted_as_hexadecimal() {
+        let random = [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef];
+        assert_eq!(access_point_password_from(&random).as_str(), "0123456789abcdef");
+    }
+
+    #[test]
+    fn first_access_point_password_kept() {
+        assert_eq!(access_point_password(), None);
+        set_access_point_password(access_point_password_from(&[0x11; 8]));
+        set_access_point_password(access_point_password_from(&[0x22; 8]));
+        assert_eq!(access_point_password(), Some("1111111111111111"));
+    }
+
+    #[test]
+    fn mac_address_formatted_with_colons() {
+        assert_eq!(format_mac_address(&MAC).as_str(), "34:85:18:a1:b2:c3");
+    }
+
+    #[test]
+    fn first_mac_address_kept() {
+        assert_eq!(mac_address(), None);
+        set_mac_address(MAC);
+        set_mac_address([0; 6]);
+        assert_eq!(mac_address(), Some(MAC));
+    }
+}
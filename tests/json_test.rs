@@ -0,0 +1,71 @@
+//! Tests for the JSON request body parser used by the web routes
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::web::{
+        parse_json_body, ApiError, JsonRejection, TimezoneRequest, MAX_JSON_BODY_SIZE,
+    };
+    use heapless::String;
+
+    #[test]
+    fn valid_body() {
+        let request: TimezoneRequest = parse_json_body(br#"{"offset_minutes": 120}"#).unwrap();
+        assert_eq!(request.offset_minutes, 120);
+    }
+
+    #[test]
+    fn surrounding_whitespace() {
+        let request: TimezoneRequest =
+            parse_json_body(b"  {\"offset_minutes\": -60}\r\n").unwrap();
+        assert_eq!(request.offset_minutes, -60);
+    }
+
+    #[test]
+    fn truncated_body() {
+        let result = parse_json_body::<TimezoneRequest>(br#"{"offset_minutes": 12"#);
+        assert_eq!(result.err(), Some(JsonRejection::Malformed));
+    }
+
+    #[test]
+    fn empty_body() {
+        let result = parse_json_body::<TimezoneRequest>(b"");
+        assert_eq!(result.err(), Some(JsonRejection::Malformed));
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        let result = parse_json_body::<TimezoneRequest>(br#"{"offset_minutes": 120}xyz"#);
+        assert_eq!(result.err(), Some(JsonRejection::Malformed));
+    }
+
+    #[test]
+    fn trailing_second_value() {
+        let result =
+            parse_json_body::<TimezoneRequest>(br#"{"offset_minutes": 1}{"offset_minutes": 2}"#);
+        assert_eq!(result.err(), Some(JsonRejection::Malformed));
+    }
+
+    #[test]
+    fn oversized_body() {
+        let body = [b' '; MAX_JSON_BODY_SIZE + 1];
+        let result = parse_json_body::<TimezoneRequest>(&body);
+        assert_eq!(result.err(), Some(JsonRejection::PayloadTooLarge));
+    }
+
+    #[test]
+    fn error_body_has_code_and_message() {
+        let error = ApiError::service_unavailable("clock_unsynced").with_message("No time");
+        let body: String<64> = serde_json_core::to_string(&error).unwrap();
+        assert_eq!(body, r#"{"code":"clock_unsynced","message":"No time"}"#);
+    }
+
+    #[test]
+    fn rejection_mapped_to_error_code() {
+        assert_eq!(ApiError::from(JsonRejection::Malformed).code, "malformed_json");
+        assert_eq!(ApiError::from(JsonRejection::PayloadTooLarge).code, "body_too_large");
+    }
+}
@@ -0,0 +1,53 @@
+//! Tests for the limits of kept-alive connections
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use embassy_time::{with_timeout, Duration};
+    use esp32c3_embassy_picoserve::keep_alive::{self, DEFAULT_MAX_REQUESTS_PER_CONNECTION};
+    use esp32c3_embassy_picoserve::web::WebAppConfig;
+    use esp_hal::timer::systimer::SystemTimer;
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+    }
+
+    #[test]
+    async fn kept_alive_connections_are_limited_by_default() {
+        let config = WebAppConfig::default();
+        assert!(config.keep_alive);
+        assert_eq!(config.max_requests_per_connection, Some(DEFAULT_MAX_REQUESTS_PER_CONNECTION));
+        assert!(config.persistent_start_read_request_timeout.is_some());
+    }
+
+    #[test]
+    async fn connection_closed_after_max_requests() {
+        keep_alive::reset(0);
+        keep_alive::served(0, 3);
+        keep_alive::served(0, 3);
+        let early = with_timeout(Duration::from_millis(50), keep_alive::reached(0)).await;
+        assert!(early.is_err());
+
+        keep_alive::served(0, 3);
+        let count = with_timeout(Duration::from_millis(50), keep_alive::reached(0)).await;
+        assert_eq!(count.ok(), Some(3));
+    }
+
+    #[test]
+    async fn new_connection_counted_from_zero() {
+        keep_alive::reset(1);
+        keep_alive::served(1, 2);
+        keep_alive::served(1, 2);
+        keep_alive::reset(1);
+        keep_alive::served(1, 2);
+        let early = with_timeout(Duration::from_millis(50), keep_alive::reached(1)).await;
+        assert!(early.is_err());
+    }
+}
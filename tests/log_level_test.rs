@@ -0,0 +1,43 @@
+//! Tests for the runtime log level
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::log_sink::{enabled, level, set_level, LogLevel};
+
+    #[test]
+    fn info_by_default() {
+        assert_eq!(level(), LogLevel::Info);
+        assert!(enabled(LogLevel::Warn));
+        assert!(enabled(LogLevel::Info));
+        assert!(!enabled(LogLevel::Debug));
+    }
+
+    #[test]
+    fn names_parsed() {
+        assert_eq!("debug".parse::<LogLevel>(), Ok(LogLevel::Debug));
+        assert_eq!("OFF".parse::<LogLevel>(), Ok(LogLevel::Off));
+        assert!("verbose".parse::<LogLevel>().is_err());
+        for level in LogLevel::ALL {
+            assert_eq!(level.as_str().parse::<LogLevel>(), Ok(level));
+        }
+    }
+
+    #[test]
+    fn trace_enables_everything() {
+        set_level(LogLevel::Trace);
+        assert_eq!(level(), LogLevel::Trace);
+        assert!(enabled(LogLevel::Error));
+        assert!(enabled(LogLevel::Trace));
+    }
+
+    #[test]
+    fn off_disables_everything() {
+        set_level(LogLevel::Off);
+        assert!(!enabled(LogLevel::Error));
+        assert!(!enabled(LogLevel::Off));
+    }
+}
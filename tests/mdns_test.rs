@@ -0,0 +1,186 @@
+//! Tests for the mDNS responder
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use embassy_net::Ipv4Address;
+    use esp32c3_embassy_picoserve::mdns::{
+        hostname, record_type, Destination, Responder, CACHE_FLUSH, HOST_TTL, MAX_MESSAGE_SIZE,
+        UNICAST_RESPONSE,
+    };
+    use esp32c3_embassy_picoserve::settings::Settings;
+    use heapless::{String, Vec};
+
+    const MAC: [u8; 6] = [0x34, 0x85, 0x18, 0xa1, 0xb2, 0xc3];
+    const ADDRESS: Ipv4Address = Ipv4Address::new(192, 168, 1, 42);
+
+    fn responder() -> Responder<'static> {
+        Responder { hostname: "kitchen", address: Some(ADDRESS), port: 80 }
+    }
+
+    /// Build a query with an identifier and one question per name
+    fn query(id: u16, questions: &[(&[&str], u16, u16)]) -> Vec<u8, MAX_MESSAGE_SIZE> {
+        let mut query = Vec::new();
+        query.extend_from_slice(&id.to_be_bytes()).unwrap();
+        query.extend_from_slice(&[0, 0, 0, questions.len() as u8, 0, 0, 0, 0, 0, 0]).unwrap();
+        for (labels, record_type, class) in questions {
+            for label in *labels {
+                query.push(label.len() as u8).unwrap();
+                query.extend_from_slice(label.as_bytes()).unwrap();
+            }
+            query.push(0).unwrap();
+            query.extend_from_slice(&record_type.to_be_bytes()).unwrap();
+            query.extend_from_slice(&class.to_be_bytes()).unwrap();
+        }
+        query
+    }
+
+    fn count(response: &[u8], index: usize) -> u16 {
+        u16::from_be_bytes([response[index], response[index + 1]])
+    }
+
+    /// Return the offset of `needle` in `haystack`
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack.windows(needle.len()).position(|window| window == needle)
+    }
+
+    #[test]
+    fn hostname_defaults_to_the_device_name() {
+        let settings = Settings::new();
+        assert_eq!(hostname(&settings, &MAC).as_str(), "picoserve-a1b2c3");
+
+        let mut kitchen = String::new();
+        kitchen.push_str("kitchen").unwrap();
+        settings.set_hostname(kitchen);
+        assert_eq!(hostname(&settings, &MAC).as_str(), "kitchen");
+    }
+
+    #[test]
+    fn address_query_is_answered_to_the_group() {
+        let query = query(0x1234, &[(&["Kitchen", "local"], record_type::A, 1)]);
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        let answer = responder().answer(&query, false, &mut response).unwrap();
+        let response = &response[..answer.length];
+
+        assert_eq!(answer.destination, Destination::Multicast);
+        // No identifier, authoritative response without questions
+        assert_eq!(&response[..6], &[0, 0, 0x84, 0, 0, 0]);
+        assert_eq!(count(response, 6), 1);
+        assert_eq!(count(response, 10), 0);
+        let record = &response[12..];
+        assert_eq!(&record[..15], b"\x07kitchen\x05local\x00");
+        assert_eq!(count(record, 15), record_type::A);
+        assert_eq!(count(record, 17), 1 | CACHE_FLUSH);
+        assert_eq!(&record[19..23], &HOST_TTL.to_be_bytes());
+        assert_eq!(&record[23..29], &[0, 4, 192, 168, 1, 42]);
+    }
+
+    #[test]
+    fn unicast_bit_answers_the_sender() {
+        let query = query(0, &[(&["kitchen", "local"], record_type::A, 1 | UNICAST_RESPONSE)]);
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        let answer = responder().answer(&query, false, &mut response).unwrap();
+        assert_eq!(answer.destination, Destination::Unicast);
+
+        // Multicast as soon as one answered question asks for it
+        let query = query_both();
+        let answer = responder().answer(&query, false, &mut response).unwrap();
+        assert_eq!(answer.destination, Destination::Multicast);
+    }
+
+    fn query_both() -> Vec<u8, MAX_MESSAGE_SIZE> {
+        query(
+            0,
+            &[
+                (&["kitchen", "local"], record_type::A, 1 | UNICAST_RESPONSE),
+                (&["_http", "_tcp", "local"], record_type::PTR, 1),
+            ],
+        )
+    }
+
+    #[test]
+    fn other_names_are_ignored() {
+        let query = query(0, &[(&["pantry", "local"], record_type::A, 1)]);
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        assert_eq!(responder().answer(&query, false, &mut response), None);
+
+        // No address record without an address
+        let query = self::query(0, &[(&["kitchen", "local"], record_type::A, 1)]);
+        let responder = Responder { address: None, ..responder() };
+        assert_eq!(responder.answer(&query, false, &mut response), None);
+    }
+
+    #[test]
+    fn responses_are_ignored() {
+        let mut query = query(0, &[(&["kitchen", "local"], record_type::A, 1)]);
+        query[2] = 0x84;
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        assert_eq!(responder().answer(&query, false, &mut response), None);
+    }
+
+    #[test]
+    fn service_browsing_gets_the_location_in_additional_records() {
+        let query = query(0, &[(&["_http", "_tcp", "local"], record_type::PTR, 1)]);
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        let answer = responder().answer(&query, false, &mut response).unwrap();
+        let response = &response[..answer.length];
+
+        // The pointer to the instance, then its address, location and text
+        assert_eq!(count(response, 6), 1);
+        assert_eq!(count(response, 10), 3);
+        let instance = b"\x07kitchen\x05_http\x04_tcp\x05local\x00";
+        let pointer = find(response, b"\x05_http\x04_tcp\x05local\x00\x00\x0c").unwrap();
+        assert_eq!(pointer, 12);
+        assert!(find(&response[pointer..], instance).is_some());
+
+        // Priority, weight, port and target of the location
+        let location = find(response, b"\x00\x00\x00\x00\x00\x50\x07kitchen\x05local\x00").unwrap();
+        assert!(location > pointer);
+        assert!(find(response, b"\x06path=/").is_some());
+        assert!(find(response, &[192, 168, 1, 42]).is_some());
+    }
+
+    #[test]
+    fn service_types_are_enumerated() {
+        let query = query(0, &[(&["_services", "_dns-sd", "_udp", "local"], record_type::PTR, 1)]);
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        let answer = responder().answer(&query, false, &mut response).unwrap();
+        let response = &response[..answer.length];
+        assert_eq!(count(response, 6), 1);
+        assert!(response.ends_with(b"\x05_http\x04_tcp\x05local\x00"));
+    }
+
+    #[test]
+    fn plain_resolvers_get_a_unicast_dns_response() {
+        let query = query(0xbeef, &[(&["kitchen", "local"], record_type::A, 1)]);
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        let answer = responder().answer(&query, true, &mut response).unwrap();
+        let response = &response[..answer.length];
+
+        assert_eq!(answer.destination, Destination::Unicast);
+        // The identifier and the question are echoed
+        assert_eq!(&response[..6], &[0xbe, 0xef, 0x84, 0, 0, 1]);
+        assert_eq!(&response[12..31], &query[12..31]);
+        let record = &response[31..];
+        // No cache-flush bit, and a short TTL
+        assert_eq!(count(record, 17), 1);
+        assert_eq!(&record[19..23], &10u32.to_be_bytes());
+    }
+
+    #[test]
+    fn goodbye_has_zero_ttls() {
+        let mut response = [0; MAX_MESSAGE_SIZE];
+        let length = responder().announcement(false, &mut response).unwrap();
+        assert_eq!(count(&response, 6), 5);
+        let announced = find(&response[..length], b"\x07kitchen\x05local\x00\x00\x01\x80\x01").unwrap();
+        assert_eq!(&response[announced + 19..announced + 23], &HOST_TTL.to_be_bytes());
+
+        let length = responder().announcement(true, &mut response).unwrap();
+        assert_eq!(count(&response, 6), 5);
+        let withdrawn = find(&response[..length], b"\x07kitchen\x05local\x00\x00\x01\x80\x01").unwrap();
+        assert_eq!(&response[withdrawn + 19..withdrawn + 23], &[0, 0, 0, 0]);
+    }
+}
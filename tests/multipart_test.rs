@@ -0,0 +1,106 @@
+//! Tests for the streaming parser of `multipart/form-data` bodies
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use esp32c3_embassy_picoserve::multipart::{boundary, parameter, Error, Multipart};
+    use esp_hal::timer::systimer::SystemTimer;
+    use heapless::Vec;
+
+    const BODY: &[u8] = b"preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"comment\"\r\n\
+        \r\n\
+        hello\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"ca.pem\"\r\n\
+        Content-Type: application/x-pem-file\r\n\
+        \r\n\
+        line one\r\nline two\r\n--X\r\n\
+        --XyZ--\r\n";
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+    }
+
+    /// Read the whole content of the current part
+    async fn content(multipart: &mut Multipart<&[u8]>) -> Vec<u8, 64> {
+        let mut content = Vec::new();
+        while let Some(chunk) = multipart.next_chunk().await.unwrap() {
+            content.extend_from_slice(chunk).unwrap();
+        }
+        content
+    }
+
+    #[test]
+    async fn boundary_from_content_type() {
+        assert_eq!(boundary("multipart/form-data; boundary=XyZ"), Some("XyZ"));
+        assert_eq!(boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\""), Some("a b"));
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("text/plain; boundary=XyZ"), None);
+        assert_eq!(boundary("multipart/form-data; boundary="), None);
+    }
+
+    #[test]
+    async fn quoted_parameters() {
+        let parameters = "; name=\"file\"; filename=\"a;b.txt\"";
+        assert_eq!(parameter(parameters, "name"), Some("file"));
+        assert_eq!(parameter(parameters, "filename"), Some("a;b.txt"));
+        assert_eq!(parameter(parameters, "size"), None);
+    }
+
+    #[test]
+    async fn parts_iterated() {
+        let mut multipart = Multipart::new(BODY, "XyZ", None);
+
+        let part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.name.as_str(), "comment");
+        assert!(part.filename.is_none());
+        assert_eq!(content(&mut multipart).await.as_slice(), b"hello");
+
+        let part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.name.as_str(), "file");
+        assert_eq!(part.filename.as_deref(), Some("ca.pem"));
+        assert_eq!(part.content_type.as_deref(), Some("application/x-pem-file"));
+        assert_eq!(content(&mut multipart).await.as_slice(), b"line one\r\nline two\r\n--X");
+
+        assert!(multipart.next_part().await.unwrap().is_none());
+    }
+
+    #[test]
+    async fn unread_content_skipped() {
+        let mut multipart = Multipart::new(BODY, "XyZ", None);
+        multipart.next_part().await.unwrap().unwrap();
+        let part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.name.as_str(), "file");
+    }
+
+    #[test]
+    async fn missing_terminator_rejected() {
+        let body = &BODY[..BODY.len() - 9];
+        let mut multipart = Multipart::new(body, "XyZ", None);
+        multipart.next_part().await.unwrap().unwrap();
+        multipart.next_part().await.unwrap().unwrap();
+        assert!(matches!(multipart.next_part().await, Err(Error::Truncated)));
+    }
+
+    #[test]
+    async fn wrong_boundary_rejected() {
+        let mut multipart = Multipart::new(BODY, "other", None);
+        assert!(matches!(multipart.next_part().await, Err(Error::Truncated)));
+    }
+
+    #[test]
+    async fn part_without_name_rejected() {
+        let body: &[u8] = b"--XyZ\r\nContent-Type: text/plain\r\n\r\nhello\r\n--XyZ--";
+        let mut multipart = Multipart::new(body, "XyZ", None);
+        assert!(matches!(multipart.next_part().await, Err(Error::Malformed)));
+    }
+}
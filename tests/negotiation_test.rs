@@ -0,0 +1,40 @@
+//! Tests for the `Accept` header parsing used by negotiated routes
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::negotiation::Preference;
+
+    #[test]
+    fn json_requested() {
+        assert_eq!(Preference::from_header("application/json"), Preference::Json);
+        assert_eq!(
+            Preference::from_header("text/plain, application/json;q=0.9"),
+            Preference::Json
+        );
+    }
+
+    #[test]
+    fn text_requested() {
+        assert_eq!(Preference::from_header("text/plain"), Preference::Text);
+        assert_eq!(Preference::from_header("text/*"), Preference::Text);
+    }
+
+    #[test]
+    fn wildcard_has_no_preference() {
+        assert_eq!(Preference::from_header("*/*"), Preference::Any);
+        assert!(!Preference::Any.wants_json(false));
+        assert!(Preference::Any.wants_json(true));
+    }
+
+    #[test]
+    fn rejected_json_ignored() {
+        assert_eq!(
+            Preference::from_header("application/json;q=0, text/plain"),
+            Preference::Text
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Tests for the traffic counters served at `/netstats`
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use esp32c3_embassy_picoserve::netstats::NetStats;
+    use esp32c3_embassy_picoserve::web::{ADMIN_PATHS, BASIC_AUTH_PREFIXES};
+    use esp_hal::timer::systimer::SystemTimer;
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+    }
+
+    #[test]
+    async fn bytes_counted_per_status_class() {
+        let netstats = NetStats::new();
+        netstats.record(200, 100);
+        netstats.record(204, 50);
+        netstats.record(404, 30);
+
+        let snapshot = netstats.snapshot();
+        assert_eq!(snapshot.bytes_written, 180);
+        assert_eq!(snapshot.by_status_class.success.responses, 2);
+        assert_eq!(snapshot.by_status_class.success.bytes, 150);
+        assert_eq!(snapshot.by_status_class.client_error.bytes, 30);
+        assert_eq!(snapshot.by_status_class.server_error.responses, 0);
+    }
+
+    #[test]
+    async fn invalid_status_ignored() {
+        let netstats = NetStats::new();
+        netstats.record(0, 100);
+        netstats.record(600, 100);
+        assert_eq!(netstats.snapshot().bytes_written, 0);
+    }
+
+    #[test]
+    async fn reading_does_not_reset() {
+        let netstats = NetStats::new();
+        netstats.record(200, 100);
+        let _ = netstats.snapshot();
+        assert_eq!(netstats.snapshot().bytes_written, 100);
+    }
+
+    #[test]
+    async fn reset_zeroes_counters() {
+        let netstats = NetStats::new();
+        netstats.record(200, 100);
+        netstats.record(500, 10);
+        netstats.reset();
+
+        let snapshot = netstats.snapshot();
+        assert_eq!(snapshot.bytes_written, 0);
+        assert_eq!(snapshot.by_status_class.success.responses, 0);
+        assert_eq!(snapshot.seconds_since_reset, 0);
+    }
+
+    #[test]
+    fn reset_route_is_protected() {
+        assert!(ADMIN_PATHS.contains(&"/netstats/reset"));
+        assert!(BASIC_AUTH_PREFIXES.contains(&"/netstats/reset"));
+    }
+}
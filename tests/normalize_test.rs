@@ -0,0 +1,45 @@
+//! Tests for the canonical form of request paths
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use core::fmt::Write;
+
+    use esp32c3_embassy_picoserve::web::NormalizedPath;
+    use heapless::String;
+
+    fn normalized(path: &str) -> Option<String<64>> {
+        NormalizedPath::new(path).map(|path| {
+            let mut normalized = String::new();
+            write!(normalized, "{}", path).unwrap();
+            normalized
+        })
+    }
+
+    #[test]
+    fn duplicate_slashes_collapsed() {
+        assert_eq!(normalized("//time").as_deref(), Some("/time"));
+        assert_eq!(normalized("/gpio//4").as_deref(), Some("/gpio/4"));
+    }
+
+    #[test]
+    fn trailing_slash_stripped() {
+        assert_eq!(normalized("/time/").as_deref(), Some("/time"));
+        assert_eq!(normalized("/api/v1/status//").as_deref(), Some("/api/v1/status"));
+    }
+
+    #[test]
+    fn canonical_paths_unchanged() {
+        assert!(normalized("/").is_none());
+        assert!(normalized("/time").is_none());
+        assert!(normalized("/gpio/4").is_none());
+    }
+
+    #[test]
+    fn root_only_slashes() {
+        assert_eq!(normalized("//").as_deref(), Some("/"));
+    }
+}
@@ -0,0 +1,42 @@
+//! Tests for the `Allow` header of `OPTIONS` and 405 responses
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use core::fmt::Write as _;
+
+    use heapless::String;
+
+    use esp32c3_embassy_picoserve::web::{allowed_methods_on, AllowHeader};
+
+    fn allow(methods: &'static str) -> String<64> {
+        let mut header = String::new();
+        write!(header, "{}", AllowHeader(methods)).unwrap();
+        header
+    }
+
+    #[test]
+    fn literal_route() {
+        assert_eq!(allowed_methods_on("/config"), Some("GET, PUT"));
+    }
+
+    #[test]
+    fn parameter_route() {
+        assert_eq!(allowed_methods_on("/webhooks/2"), Some("DELETE"));
+        assert_eq!(allowed_methods_on("/api/v1/kv/name"), Some("GET, PUT, DELETE"));
+    }
+
+    #[test]
+    fn unknown_route() {
+        assert_eq!(allowed_methods_on("/nothing"), None);
+    }
+
+    #[test]
+    fn options_always_allowed() {
+        assert_eq!(allow("GET, PUT").as_str(), "GET, PUT, OPTIONS");
+        assert_eq!(allow("").as_str(), "OPTIONS");
+    }
+}
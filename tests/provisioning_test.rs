@@ -0,0 +1,87 @@
+//! Tests for the Wi-Fi credentials provisioned from a browser
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use core::fmt::Write;
+
+    use esp32c3_embassy_picoserve::provisioning::{
+        is_active, networks, set_networks, CredentialsError, WifiCredentials,
+    };
+    use heapless::String;
+
+    #[test]
+    fn credentials_round_trip() {
+        let credentials = WifiCredentials::new("home", "correct horse").unwrap();
+        let decoded = WifiCredentials::decode(&credentials.encode()).unwrap();
+        assert_eq!(decoded, credentials);
+    }
+
+    #[test]
+    fn open_network_round_trip() {
+        let credentials = WifiCredentials::new("cafe", "").unwrap();
+        let decoded = WifiCredentials::decode(&credentials.encode()).unwrap();
+        assert_eq!(decoded.password, "");
+    }
+
+    #[test]
+    fn longest_credentials_fit() {
+        let ssid = [b'a'; 32];
+        let password = [b'b'; 64];
+        let credentials = WifiCredentials::new(
+            core::str::from_utf8(&ssid).unwrap(),
+            core::str::from_utf8(&password).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            WifiCredentials::decode(&credentials.encode()),
+            Some(credentials)
+        );
+    }
+
+    #[test]
+    fn invalid_credentials_rejected() {
+        assert_eq!(
+            WifiCredentials::new("", "password"),
+            Err(CredentialsError::InvalidSsid)
+        );
+        assert_eq!(
+            WifiCredentials::new("a-network-name-longer-than-32-bytes", "password"),
+            Err(CredentialsError::InvalidSsid)
+        );
+        assert_eq!(
+            WifiCredentials::new("home", "short"),
+            Err(CredentialsError::InvalidPassword)
+        );
+    }
+
+    #[test]
+    fn truncated_value_rejected() {
+        assert_eq!(WifiCredentials::decode(&[]), None);
+        assert_eq!(WifiCredentials::decode(&[5, b'h', b'o']), None);
+        assert_eq!(WifiCredentials::decode(&[0]), None);
+    }
+
+    #[test]
+    fn password_not_printed() {
+        let credentials = WifiCredentials::new("home", "correct horse").unwrap();
+        let mut output = String::<128>::new();
+        write!(output, "{:?}", credentials).unwrap();
+        assert!(output.contains("home"));
+        assert!(!output.contains("correct horse"));
+    }
+
+    #[test]
+    fn networks_deduplicated() {
+        set_networks(["home", "", "office", "home"]);
+        assert_eq!(networks().as_slice(), ["home", "office"]);
+    }
+
+    #[test]
+    fn inactive_by_default() {
+        assert!(!is_active());
+    }
+}
@@ -0,0 +1,66 @@
+//! Tests for the query string parser used by the web routes
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::web::{percent_decode, QueryParams};
+    use heapless::String;
+
+    #[test]
+    fn parses_key_value_pairs() {
+        let query = QueryParams::parse("fmt=unix&offset=%2B0200");
+        assert_eq!(query.get("fmt"), Some("unix"));
+        assert_eq!(query.get("offset"), Some("+0200"));
+        assert_eq!(query.get("missing"), None);
+    }
+
+    #[test]
+    fn empty_values() {
+        let query = QueryParams::parse("fmt=&offset=");
+        assert_eq!(query.get("fmt"), Some(""));
+        assert_eq!(query.get("offset"), Some(""));
+    }
+
+    #[test]
+    fn missing_equal_sign() {
+        let query = QueryParams::parse("verbose&fmt=iso");
+        assert_eq!(query.get("verbose"), Some(""));
+        assert_eq!(query.get("fmt"), Some("iso"));
+    }
+
+    #[test]
+    fn empty_query_and_separators() {
+        assert_eq!(QueryParams::parse("").iter().count(), 0);
+        assert_eq!(QueryParams::parse("&&").iter().count(), 0);
+    }
+
+    #[test]
+    fn first_repeated_key_wins() {
+        let query = QueryParams::parse("fmt=unix&fmt=iso");
+        assert_eq!(query.get("fmt"), Some("unix"));
+        assert_eq!(query.iter().count(), 2);
+    }
+
+    #[test]
+    fn percent_encoded_characters() {
+        let decoded: Option<String<32>> = percent_decode("a%20b+c%26d%3D%C3%A9");
+        assert_eq!(decoded.as_deref(), Some("a b c&d=\u{e9}"));
+    }
+
+    #[test]
+    fn invalid_escapes_are_rejected() {
+        assert_eq!(percent_decode::<8>("%zz"), None);
+        assert_eq!(percent_decode::<8>("abc%2"), None);
+        assert_eq!(percent_decode::<8>("%C3"), None);
+    }
+
+    #[test]
+    fn invalid_parameters_are_dropped() {
+        let query = QueryParams::parse("bad=%zz&fmt=human");
+        assert_eq!(query.get("bad"), None);
+        assert_eq!(query.get("fmt"), Some("human"));
+    }
+}
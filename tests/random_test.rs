@@ -0,0 +1,70 @@
+//! Tests for the encoding of the bytes returned by `/random`
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use core::fmt::Write as _;
+
+    use esp32c3_embassy_picoserve::log_sink::{line_from, oldest_sequence};
+    use esp32c3_embassy_picoserve::random::{
+        encode_random, generate_api_token, RandomFormat, RngWrapper, MAX_RANDOM_BYTES,
+    };
+    use esp32c3_embassy_picoserve::web::BASIC_AUTH_PREFIXES;
+    use heapless::String;
+
+    #[init]
+    fn init() -> RngWrapper {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        RngWrapper::from(esp_hal::rng::Rng::new(peripherals.RNG))
+    }
+
+    #[test]
+    fn hex_is_lowercase() {
+        let text = encode_random(&[0x00, 0xab, 0x7f], RandomFormat::Hex).unwrap();
+        assert_eq!(text.as_str(), "00ab7f");
+    }
+
+    #[test]
+    fn base64_is_padded() {
+        let text = encode_random(b"fo", RandomFormat::Base64).unwrap();
+        assert_eq!(text.as_str(), "Zm8=");
+        let text = encode_random(b"foobar", RandomFormat::Base64).unwrap();
+        assert_eq!(text.as_str(), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn maximal_size_fits() {
+        let bytes = [0xff; MAX_RANDOM_BYTES];
+        assert_eq!(encode_random(&bytes, RandomFormat::Hex).unwrap().len(), 128);
+        assert_eq!(encode_random(&bytes, RandomFormat::Base64).unwrap().len(), 88);
+    }
+
+    #[test]
+    fn too_many_bytes_rejected() {
+        let bytes = [0; MAX_RANDOM_BYTES + 1];
+        assert!(encode_random(&bytes, RandomFormat::Hex).is_none());
+    }
+
+    #[test]
+    fn api_token_kept_out_of_the_log_sink(mut rng: RngWrapper) {
+        let token = generate_api_token(&mut rng);
+        let mut hex = String::<64>::new();
+        write!(hex, "{token}").unwrap();
+
+        let mut sequence = oldest_sequence();
+        while let Some((_, (line_sequence, line))) = line_from(sequence) {
+            assert!(!line.contains(hex.as_str()));
+            sequence = line_sequence + 1;
+        }
+    }
+
+    #[test]
+    fn log_routes_are_protected() {
+        for path in ["/logs", "/ws/logs"] {
+            assert!(BASIC_AUTH_PREFIXES.contains(&path), "{path}");
+        }
+    }
+}
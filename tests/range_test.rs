@@ -0,0 +1,70 @@
+//! Tests for the parsing of `Range` headers of flash file downloads
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use core::fmt::Write;
+
+    use heapless::String;
+
+    use esp32c3_embassy_picoserve::range::{parse_range, ByteRange, ContentRange};
+
+    #[test]
+    fn closed_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ByteRange::Partial(0..100));
+        assert_eq!(parse_range("bytes=500-500", 1000), ByteRange::Partial(500..501));
+    }
+
+    #[test]
+    fn last_position_clamped() {
+        assert_eq!(parse_range("bytes=900-2000", 1000), ByteRange::Partial(900..1000));
+    }
+
+    #[test]
+    fn open_ended_range() {
+        assert_eq!(parse_range("bytes=100-", 1000), ByteRange::Partial(100..1000));
+        assert_eq!(parse_range("bytes=999-", 1000), ByteRange::Partial(999..1000));
+    }
+
+    #[test]
+    fn suffix_range() {
+        assert_eq!(parse_range("bytes=-100", 1000), ByteRange::Partial(900..1000));
+        assert_eq!(parse_range("bytes=-5000", 1000), ByteRange::Partial(0..1000));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=2000-3000", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-100", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn multiple_ranges_served_whole() {
+        assert_eq!(parse_range("bytes=0-99,200-299", 1000), ByteRange::Full);
+    }
+
+    #[test]
+    fn malformed_ranges_ignored() {
+        assert_eq!(parse_range("items=0-99", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=abc", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=99-0", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=-", 1000), ByteRange::Full);
+    }
+
+    #[test]
+    fn content_range_header() {
+        let mut text = String::<32>::new();
+        write!(text, "{}", ContentRange { range: Some(900..1000), length: 1000 }).unwrap();
+        assert_eq!(text, "bytes 900-999/1000");
+
+        text.clear();
+        write!(text, "{}", ContentRange { range: None, length: 1000 }).unwrap();
+        assert_eq!(text, "bytes */1000");
+    }
+}
@@ -0,0 +1,44 @@
+//! Tests for the request IDs returned in `X-Request-Id`
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::request_id::RequestId;
+
+    #[test]
+    fn generated_ids_are_distinct() {
+        let first = RequestId::next();
+        let second = RequestId::next();
+        assert_ne!(first, second);
+        assert_eq!(first.as_str().len(), 8);
+        assert!(first.as_str().bytes().all(|byte| byte.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn client_id_kept() {
+        let id = RequestId::from_header("abc-123").unwrap();
+        assert_eq!(id.as_str(), "abc-123");
+    }
+
+    #[test]
+    fn invalid_client_id_rejected() {
+        assert!(RequestId::from_header("").is_none());
+        assert!(RequestId::from_header("with space").is_none());
+        assert!(RequestId::from_header("line\r\nbreak").is_none());
+        assert!(RequestId::from_header("0123456789abcdef0123456789abcdef0").is_none());
+    }
+
+    #[test]
+    fn current_id_kept_per_web_task() {
+        assert_eq!(RequestId::current(0).as_str(), "");
+        let first = RequestId::from_header("first").unwrap();
+        let second = RequestId::from_header("second").unwrap();
+        first.clone().set_current(0);
+        second.clone().set_current(1);
+        assert_eq!(RequestId::current(0), first);
+        assert_eq!(RequestId::current(1), second);
+    }
+}
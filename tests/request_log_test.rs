@@ -0,0 +1,85 @@
+//! Tests for the sequence numbers and filters of the request log
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::request_log::{
+        by_sequence, latest_sequence, record, truncated, RecordFilter, RequestRecord, StatusClass,
+        REQUEST_LOG_SIZE,
+    };
+
+    fn request(path: &str, status: u16) -> RequestRecord {
+        RequestRecord {
+            sequence: 0,
+            timestamp: 0,
+            uptime_ms: 0,
+            method: truncated("GET"),
+            path: truncated(path),
+            status,
+            duration_ms: 1,
+        }
+    }
+
+    #[test]
+    fn sequence_numbers_increase() {
+        assert_eq!(latest_sequence(), 0);
+        record(request("/time", 200));
+        record(request("/missing", 404));
+        assert_eq!(latest_sequence(), 2);
+        assert_eq!(by_sequence(1).unwrap().path, "/time");
+        assert_eq!(by_sequence(2).unwrap().status, 404);
+        assert!(by_sequence(3).is_none());
+    }
+
+    #[test]
+    fn dropped_records_not_found() {
+        for _ in 0..REQUEST_LOG_SIZE + 1 {
+            record(request("/time", 200));
+        }
+        assert!(by_sequence(1).is_none());
+        assert!(by_sequence(2).is_some());
+        assert_eq!(latest_sequence(), REQUEST_LOG_SIZE as u64 + 1);
+    }
+
+    #[test]
+    fn status_classes() {
+        assert!(StatusClass::ClientError.matches(404));
+        assert!(!StatusClass::ClientError.matches(500));
+        assert!(StatusClass::ServerError.matches(503));
+        assert!(!StatusClass::Success.matches(301));
+    }
+
+    #[test]
+    fn filters_combined() {
+        let filter = RecordFilter {
+            after: 1,
+            status: Some(StatusClass::ClientError),
+            path_prefix: Some(truncated("/api")),
+        };
+        let mut matching = request("/api/v1/led", 401);
+        matching.sequence = 2;
+        assert!(filter.matches(&matching));
+
+        let mut old = matching.clone();
+        old.sequence = 1;
+        assert!(!filter.matches(&old));
+
+        let mut success = matching.clone();
+        success.status = 200;
+        assert!(!filter.matches(&success));
+
+        let mut other_path = matching.clone();
+        other_path.path = truncated("/time");
+        assert!(!filter.matches(&other_path));
+    }
+
+    #[test]
+    fn default_filter_matches_everything() {
+        let mut record = request("/time", 500);
+        record.sequence = 1;
+        assert!(RecordFilter::default().matches(&record));
+    }
+}
@@ -0,0 +1,57 @@
+//! Tests for the route tables served at `/api` and `/help`
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::routes::{auth, RouteAuth};
+    use esp32c3_embassy_picoserve::web::routes;
+
+    #[test]
+    fn routes_described() {
+        for route in routes() {
+            assert!(
+                !route.description.is_empty(),
+                "{} has no description",
+                route.path
+            );
+            assert!(!route.methods.is_empty(), "{} has no methods", route.path);
+        }
+    }
+
+    #[test]
+    fn routes_listed_once() {
+        for (index, route) in routes().enumerate() {
+            assert!(
+                routes()
+                    .skip(index + 1)
+                    .all(|other| other.path != route.path),
+                "{} is listed twice",
+                route.path
+            );
+        }
+    }
+
+    #[test]
+    fn auth_derived_from_layers() {
+        assert_eq!(auth("/time"), RouteAuth::None);
+        assert_eq!(auth("/config"), RouteAuth::Basic);
+        assert_eq!(auth("/files/{name}"), RouteAuth::Basic);
+        assert_eq!(auth("/admin/token/rotate"), RouteAuth::Basic);
+        assert_eq!(auth("/settings"), RouteAuth::Session);
+        assert_eq!(auth("/api/v1/led/on"), RouteAuth::Token);
+        assert_eq!(auth("/api/status"), RouteAuth::None);
+    }
+
+    #[test]
+    fn route_serialized_with_auth() {
+        let route = routes().find(|route| route.path == "/config").unwrap();
+        let json: heapless::String<256> = serde_json_core::to_string(route).unwrap();
+        assert_eq!(
+            json,
+            r#"{"path":"/config","methods":"GET, PUT","auth":"basic","description":"Read or replace the configuration"}"#
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! Tests for the matching and the catch-up of the schedule entries
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::scheduler::{
+        format_time, minutes_to_run, parse_time, ActionKind, Entry, EVERY_DAY, MAX_CATCH_UP,
+    };
+
+    /// Local minute of Monday 2024-01-01 00:00, counted from the epoch
+    const MONDAY: i64 = 1_704_067_200 / 60;
+
+    fn entry(hour: u8, minute: u8, days: u8) -> Entry {
+        Entry {
+            id: 0,
+            hour,
+            minute,
+            days,
+            action: ActionKind::GpioOn,
+            pin: Some(5),
+        }
+    }
+
+    #[test]
+    fn matches_time_of_day() {
+        let entry = entry(7, 0, EVERY_DAY);
+        assert!(entry.matches(MONDAY + 7 * 60));
+        assert!(!entry.matches(MONDAY + 7 * 60 + 1));
+        assert!(entry.matches(MONDAY + 24 * 60 + 7 * 60));
+    }
+
+    #[test]
+    fn matches_days_of_week() {
+        // Monday and Sunday only
+        let entry = entry(22, 0, 0b100_0001);
+        assert!(entry.matches(MONDAY + 22 * 60));
+        assert!(!entry.matches(MONDAY + 24 * 60 + 22 * 60));
+        assert!(entry.matches(MONDAY + 6 * 24 * 60 + 22 * 60));
+    }
+
+    #[test]
+    fn first_run_is_current_minute() {
+        assert_eq!(minutes_to_run(None, 100), 100..101);
+    }
+
+    #[test]
+    fn late_wakeup_caught_up() {
+        assert_eq!(minutes_to_run(Some(100), 101), 101..102);
+        assert_eq!(minutes_to_run(Some(100), 103), 101..104);
+    }
+
+    #[test]
+    fn jump_forward_skips_minutes_in_between() {
+        assert_eq!(
+            minutes_to_run(Some(100), 101 + MAX_CATCH_UP),
+            101 + MAX_CATCH_UP..102 + MAX_CATCH_UP
+        );
+        assert_eq!(minutes_to_run(Some(0), MONDAY), MONDAY..MONDAY + 1);
+    }
+
+    #[test]
+    fn jump_backward_runs_nothing() {
+        assert!(minutes_to_run(Some(100), 100).is_empty());
+        assert!(minutes_to_run(Some(100), 40).is_empty());
+    }
+
+    #[test]
+    fn time_round_trip() {
+        assert_eq!(parse_time("07:05"), Some((7, 5)));
+        assert_eq!(format_time(7, 5), "07:05");
+        assert_eq!(parse_time("23:59"), Some((23, 59)));
+    }
+
+    #[test]
+    fn invalid_times_rejected() {
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("12:60"), None);
+        assert_eq!(parse_time("7:00"), None);
+        assert_eq!(parse_time("0700"), None);
+    }
+}
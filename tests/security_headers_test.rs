@@ -0,0 +1,98 @@
+//! Tests for the security headers added by the root and API layers
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::api::{self, API_V1_PREFIX};
+    use esp32c3_embassy_picoserve::cache_control::CachePolicy;
+    use esp32c3_embassy_picoserve::security_headers::SecurityHeaders;
+    use esp32c3_embassy_picoserve::static_assets::{Asset, ASSETS};
+    use esp32c3_embassy_picoserve::web;
+
+    /// Count the stacked layers adding headers to the response to a path
+    fn layers_applied(path: &str) -> usize {
+        let root = usize::from(web::SECURITY_HEADERS.applies_to(path));
+        let nested = match path.strip_prefix(API_V1_PREFIX) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                usize::from(api::SECURITY_HEADERS.applies_to(rest))
+            }
+            _ => 0,
+        };
+        root + nested
+    }
+
+    #[test]
+    fn headers_added_once_when_layers_stacked() {
+        for path in ["/", "/time", "/static/app.js", "/api/v1", "/api/v1/status", "/api/v1/kv/key", "/api/v10"] {
+            assert_eq!(layers_applied(path), 1, "{}", path);
+        }
+    }
+
+    #[test]
+    fn header_names_unique() {
+        for headers in [SecurityHeaders::HTML, SecurityHeaders::API] {
+            for (index, (name, _)) in headers.headers().enumerate() {
+                assert!(headers.headers().skip(index + 1).all(|(other, _)| other != name));
+            }
+        }
+    }
+
+    #[test]
+    fn api_not_cached() {
+        assert!(SecurityHeaders::API.headers().any(|header| header == ("Cache-Control", "no-store")));
+        assert!(SecurityHeaders::HTML.headers().all(|(name, _)| name != "Cache-Control"));
+    }
+
+    #[test]
+    fn relaxed_policy() {
+        let headers = SecurityHeaders::HTML.with_content_security_policy("default-src 'self' 'unsafe-inline'");
+        assert!(headers
+            .headers()
+            .any(|header| header == ("Content-Security-Policy", "default-src 'self' 'unsafe-inline'")));
+        assert!(headers.headers().any(|header| header == ("X-Content-Type-Options", "nosniff")));
+    }
+
+    /// Return the policies set on the response to a path, by the layers of
+    /// the route groups and the asset table
+    fn cache_policies(path: &str) -> impl Iterator<Item = CachePolicy> {
+        let root = web::SECURITY_HEADERS
+            .applies_to(path)
+            .then_some(SecurityHeaders::HTML.cache_control)
+            .flatten();
+        let nested = match path.strip_prefix(API_V1_PREFIX) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                api::SECURITY_HEADERS.applies_to(rest).then_some(SecurityHeaders::API.cache_control).flatten()
+            }
+            _ => None,
+        };
+        let asset = match path {
+            "/favicon.ico" => Asset::find("favicon.ico"),
+            _ => path.strip_prefix("/static/").and_then(Asset::find),
+        };
+        [root, nested, asset.map(|asset| asset.cache_control)].into_iter().flatten()
+    }
+
+    #[test]
+    fn cache_control_never_conflicts() {
+        for asset in ASSETS {
+            let mut path = heapless::String::<64>::new();
+            path.push_str("/static/").unwrap();
+            path.push_str(asset.name).unwrap();
+            assert_eq!(cache_policies(&path).count(), 1, "{}", path);
+        }
+        for route in web::routes() {
+            assert!(cache_policies(route.path).count() <= 1, "{}", route.path);
+        }
+        assert_eq!(cache_policies("/favicon.ico").count(), 1);
+    }
+
+    #[test]
+    fn cache_policies_of_groups() {
+        assert!(cache_policies("/api/v1/status").eq([CachePolicy::NO_STORE]));
+        assert!(cache_policies("/static/app.js").eq([CachePolicy::IMMUTABLE]));
+        assert!(cache_policies("/static/index.html").eq([CachePolicy::NO_CACHE]));
+    }
+}
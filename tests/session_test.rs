@@ -0,0 +1,77 @@
+//! Tests for the cookie parsing and the identifiers of admin sessions
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use core::fmt::Write;
+
+    use heapless::String;
+
+    use esp32c3_embassy_picoserve::session::{find_cookie, SessionId, SetCookie};
+
+    const ID: &str = "00112233445566778899aabbccddeeff";
+
+    #[test]
+    fn single_cookie() {
+        assert_eq!(find_cookie("sid=abc", "sid"), Some("abc"));
+    }
+
+    #[test]
+    fn among_other_cookies() {
+        assert_eq!(
+            find_cookie("theme=dark; sid=abc; lang=en", "sid"),
+            Some("abc")
+        );
+        assert_eq!(find_cookie("theme=dark;sid=abc", "sid"), Some("abc"));
+    }
+
+    #[test]
+    fn quoted_value() {
+        assert_eq!(find_cookie("sid=\"abc\"; lang=en", "sid"), Some("abc"));
+    }
+
+    #[test]
+    fn name_must_match_exactly() {
+        assert_eq!(find_cookie("xsid=abc; sidx=def", "sid"), None);
+        assert_eq!(find_cookie("", "sid"), None);
+        assert_eq!(find_cookie("sid", "sid"), None);
+    }
+
+    #[test]
+    fn first_cookie_wins() {
+        assert_eq!(find_cookie("sid=first; sid=second", "sid"), Some("first"));
+    }
+
+    #[test]
+    fn id_round_trip() {
+        let id = SessionId::parse(ID).unwrap();
+        let mut text = String::<32>::new();
+        write!(text, "{}", id).unwrap();
+        assert_eq!(text, ID);
+    }
+
+    #[test]
+    fn malformed_ids_rejected() {
+        assert!(SessionId::parse("").is_none());
+        assert!(SessionId::parse("0011").is_none());
+        assert!(SessionId::parse("zz112233445566778899aabbccddeeff").is_none());
+    }
+
+    #[test]
+    fn cookie_headers() {
+        let mut text = String::<96>::new();
+        write!(text, "{}", SetCookie::Start(SessionId::parse(ID).unwrap())).unwrap();
+        assert_eq!(
+            text,
+            "sid=00112233445566778899aabbccddeeff; Path=/; HttpOnly; SameSite=Strict"
+        );
+
+        text.clear();
+        write!(text, "{}", SetCookie::Clear).unwrap();
+        assert!(text.starts_with("sid=;"));
+        assert!(text.ends_with("Max-Age=0"));
+    }
+}
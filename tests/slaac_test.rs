@@ -0,0 +1,120 @@
+//! Tests for the IPv6 addresses configured from router advertisements
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use embassy_net::Ipv6Address;
+    use esp32c3_embassy_picoserve::slaac::{
+        checksum, interface_identifier, link_local_address, router_solicitation, Prefix, RouterAdvertisement,
+        SOLICITATION_SIZE,
+    };
+
+    const MAC: [u8; 6] = [0x34, 0x85, 0x18, 0xa1, 0xb2, 0xc3];
+    const ROUTER: Ipv6Address = Ipv6Address::new(0xfe80, 0, 0, 0, 0x1, 0x2, 0x3, 0x4);
+    const ALL_NODES: Ipv6Address = Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+    const PREFIX: Ipv6Address = Ipv6Address::new(0x2001, 0xdb8, 0x1, 0x2, 0, 0, 0, 0);
+    const DNS_SERVER: Ipv6Address = Ipv6Address::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53);
+
+    /// Build an advertisement of a prefix with a valid lifetime and a DNS
+    /// server, from `source` with a hop limit
+    fn advertisement(source: Ipv6Address, hop_limit: u8, valid_lifetime: u32) -> [u8; 112] {
+        let mut packet = [0; 112];
+        packet[0] = 0x60;
+        packet[4..6].copy_from_slice(&72u16.to_be_bytes());
+        packet[6] = 58;
+        packet[7] = hop_limit;
+        packet[8..24].copy_from_slice(&source.octets());
+        packet[24..40].copy_from_slice(&ALL_NODES.octets());
+
+        let message = &mut packet[40..];
+        message[0] = 134;
+        // Default router for 1800 s
+        message[6..8].copy_from_slice(&1800u16.to_be_bytes());
+        // Prefix information, on-link and autonomous
+        message[16..20].copy_from_slice(&[3, 4, 64, 0xc0]);
+        message[20..24].copy_from_slice(&valid_lifetime.to_be_bytes());
+        message[24..28].copy_from_slice(&valid_lifetime.to_be_bytes());
+        message[32..48].copy_from_slice(&PREFIX.octets());
+        // Recursive DNS server
+        message[48..50].copy_from_slice(&[25, 3]);
+        message[52..56].copy_from_slice(&600u32.to_be_bytes());
+        message[56..72].copy_from_slice(&DNS_SERVER.octets());
+        let sum = checksum(source, ALL_NODES, message);
+        message[2..4].copy_from_slice(&sum.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn addresses_of_the_mac() {
+        assert_eq!(interface_identifier(&MAC), [0x36, 0x85, 0x18, 0xff, 0xfe, 0xa1, 0xb2, 0xc3]);
+        assert_eq!(
+            link_local_address(&MAC),
+            Ipv6Address::new(0xfe80, 0, 0, 0, 0x3685, 0x18ff, 0xfea1, 0xb2c3)
+        );
+    }
+
+    #[test]
+    fn advertisement_configures_a_global_address() {
+        let advertisement = RouterAdvertisement::parse(&advertisement(ROUTER, 255, 86400)).unwrap();
+        assert_eq!(advertisement.router, ROUTER);
+        assert_eq!(advertisement.dns_servers, [DNS_SERVER]);
+        let prefix = advertisement.prefix.unwrap();
+        assert_eq!(prefix, Prefix { prefix: PREFIX, valid_lifetime: 86400 });
+
+        let config = advertisement.config(&prefix, &MAC);
+        assert_eq!(
+            config.address.address(),
+            Ipv6Address::new(0x2001, 0xdb8, 0x1, 0x2, 0x3685, 0x18ff, 0xfea1, 0xb2c3)
+        );
+        assert_eq!(config.address.prefix_len(), 64);
+        assert_eq!(config.gateway, Some(ROUTER));
+    }
+
+    #[test]
+    fn withdrawn_prefix() {
+        let advertisement = RouterAdvertisement::parse(&advertisement(ROUTER, 255, 0)).unwrap();
+        assert_eq!(advertisement.prefix.map(|prefix| prefix.valid_lifetime), Some(0));
+    }
+
+    #[test]
+    fn invalid_advertisements_are_ignored() {
+        // Routed from another network
+        assert!(RouterAdvertisement::parse(&advertisement(ROUTER, 64, 86400)).is_none());
+        // Not from a link-local address
+        assert!(RouterAdvertisement::parse(&advertisement(DNS_SERVER, 255, 86400)).is_none());
+        // Corrupted
+        let mut packet = advertisement(ROUTER, 255, 86400);
+        packet[60] ^= 1;
+        assert!(RouterAdvertisement::parse(&packet).is_none());
+        // Truncated
+        assert!(RouterAdvertisement::parse(&advertisement(ROUTER, 255, 86400)[..100]).is_none());
+    }
+
+    #[test]
+    fn zero_length_option_is_refused() {
+        let mut packet = advertisement(ROUTER, 255, 86400);
+        packet[57] = 0;
+        let sum = checksum(ROUTER, ALL_NODES, &{
+            let mut message = [0; 72];
+            message.copy_from_slice(&packet[40..]);
+            message[2..4].fill(0);
+            message
+        });
+        packet[42..44].copy_from_slice(&sum.to_be_bytes());
+        assert!(RouterAdvertisement::parse(&packet).is_none());
+    }
+
+    #[test]
+    fn solicitation_has_a_valid_checksum() {
+        let source = link_local_address(&MAC);
+        let mut packet = [0; SOLICITATION_SIZE];
+        router_solicitation(source, &MAC, &mut packet);
+        assert_eq!(packet[40], 133);
+        assert_eq!(packet[48..56], [1, 1, 0x34, 0x85, 0x18, 0xa1, 0xb2, 0xc3]);
+        let all_routers = Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+        assert_eq!(checksum(source, all_routers, &packet[40..]), 0);
+    }
+}
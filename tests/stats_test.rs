@@ -0,0 +1,80 @@
+//! Tests for the route matching and latency buckets of the request statistics
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::stats::{latency_bucket, route_matches, LatencyHistogram, Stats};
+    use esp32c3_embassy_picoserve::web::{ADMIN_PATHS, BASIC_AUTH_PREFIXES};
+
+    #[test]
+    fn literal_route() {
+        assert!(route_matches("/time", "/time"));
+        assert!(route_matches("/", "/"));
+        assert!(!route_matches("/time", "/timezone"));
+    }
+
+    #[test]
+    fn parameter_segment() {
+        assert!(route_matches("/gpio/{pin}", "/gpio/4"));
+        assert!(route_matches("/gpio/{pin}/{state}", "/gpio/4/on"));
+        assert!(!route_matches("/gpio/{pin}", "/gpio/"));
+    }
+
+    #[test]
+    fn segment_count_differs() {
+        assert!(!route_matches("/gpio/{pin}", "/gpio/4/on"));
+        assert!(!route_matches("/gpio/{pin}/{state}", "/gpio/4"));
+    }
+
+    #[test]
+    fn latency_buckets() {
+        assert_eq!(latency_bucket(0), 0);
+        assert_eq!(latency_bucket(9), 0);
+        assert_eq!(latency_bucket(10), 1);
+        assert_eq!(latency_bucket(199), 2);
+        assert_eq!(latency_bucket(999), 3);
+        assert_eq!(latency_bucket(1000), 4);
+        assert_eq!(latency_bucket(u64::MAX), 4);
+    }
+
+    #[test]
+    fn slowest_response_kept() {
+        let histogram = LatencyHistogram::new();
+        histogram.record(20);
+        histogram.record(4000);
+        histogram.record(30);
+        assert_eq!(histogram.max_ms(), 4000);
+        assert_eq!(histogram.max_since_boot_ms(), 4000);
+    }
+
+    #[test]
+    fn reset_keeps_maximum_since_boot() {
+        let histogram = LatencyHistogram::new();
+        histogram.record(4000);
+        histogram.reset();
+        histogram.record(20);
+        assert_eq!(histogram.max_ms(), 20);
+        assert_eq!(histogram.max_since_boot_ms(), 4000);
+        assert_eq!(histogram.buckets().iter().sum::<u64>(), 1);
+        assert_eq!(histogram.sum_ms(), 20);
+    }
+
+    #[test]
+    fn busy_rejections_counted() {
+        let stats = Stats::new();
+        stats.record_busy_rejection();
+        stats.record_busy_rejection();
+        assert_eq!(stats.snapshot().busy_rejections, 2);
+        stats.reset();
+        assert_eq!(stats.snapshot().busy_rejections, 0);
+    }
+
+    #[test]
+    fn reset_route_is_protected() {
+        assert!(ADMIN_PATHS.contains(&"/stats/reset"));
+        assert!(BASIC_AUTH_PREFIXES.contains(&"/stats/reset"));
+    }
+}
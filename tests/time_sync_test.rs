@@ -0,0 +1,45 @@
+//! Tests for the coalescing of synchronization requests and the shared clock
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::clock::Clock;
+    use esp32c3_embassy_picoserve::time_sync::{request, status};
+    use esp32c3_embassy_picoserve::web::{ADMIN_PATHS, BASIC_AUTH_PREFIXES};
+
+    #[test]
+    fn idle_before_first_request() {
+        let status = status();
+        assert!(!status.running);
+        assert!(status.last.is_none());
+    }
+
+    #[test]
+    fn requests_coalesce_while_running() {
+        assert!(request().running);
+        assert!(request().running);
+        assert!(status().running);
+        assert!(status().last.is_none());
+    }
+
+    #[test]
+    fn set_time_seen_by_clones() {
+        let clock = Clock::unsynchronized();
+        let clone = clock.clone();
+        assert!(!clone.is_synchronized());
+
+        clock.set_time(1_700_000_000);
+        assert!(clone.is_synchronized());
+        assert!(clone.now_as_epoch() >= 1_700_000_000);
+        assert!(clone.now_as_epoch() < 1_700_000_010);
+    }
+
+    #[test]
+    fn sync_routes_are_protected() {
+        assert!(ADMIN_PATHS.contains(&"/time/sync"));
+        assert!(BASIC_AUTH_PREFIXES.contains(&"/time/sync"));
+    }
+}
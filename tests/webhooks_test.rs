@@ -0,0 +1,75 @@
+//! Tests for the payloads and the URLs of the webhooks
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::webhooks::{is_valid_url, Event, Payload};
+    use heapless::String;
+
+    #[test]
+    fn boot_payload() {
+        let payload = Payload::new(Event::Boot { boot_count: 3 }, 12);
+        let body: String<128> = serde_json_core::to_string(&payload).unwrap();
+        assert_eq!(body, r#"{"event":"boot","uptime":12,"boot_count":3}"#);
+    }
+
+    #[test]
+    fn gpio_input_payload() {
+        let payload = Payload::new(
+            Event::GpioInput {
+                pin: 9,
+                high: false,
+            },
+            40,
+        );
+        let body: String<128> = serde_json_core::to_string(&payload).unwrap();
+        assert_eq!(
+            body,
+            r#"{"event":"gpio_input","uptime":40,"pin":9,"high":false}"#
+        );
+    }
+
+    #[test]
+    fn wifi_payload_has_no_details() {
+        let payload = Payload::new(Event::WifiDisconnected, 7);
+        let body: String<128> = serde_json_core::to_string(&payload).unwrap();
+        assert_eq!(body, r#"{"event":"wifi_disconnected","uptime":7}"#);
+    }
+
+    #[test]
+    fn link_payloads_have_the_time_of_the_event() {
+        let payload = Payload::new(Event::LinkLost { uptime: 300 }, 345);
+        let body: String<128> = serde_json_core::to_string(&payload).unwrap();
+        assert_eq!(body, r#"{"event":"link_lost","uptime":300}"#);
+
+        let payload = Payload::new(Event::LinkRestored { uptime: 340, down_for: 40 }, 345);
+        let body: String<128> = serde_json_core::to_string(&payload).unwrap();
+        assert_eq!(body, r#"{"event":"link_restored","uptime":340,"down_for":40}"#);
+    }
+
+    #[test]
+    fn signal_payload_has_the_rssi() {
+        let payload = Payload::new(Event::SignalLow { rssi: -83 }, 95);
+        let body: String<128> = serde_json_core::to_string(&payload).unwrap();
+        assert_eq!(body, r#"{"event":"signal_low","uptime":95,"rssi":-83}"#);
+    }
+
+    #[test]
+    fn http_urls_accepted() {
+        assert!(is_valid_url("http://192.168.1.10:8123/api/webhook/esp"));
+        assert!(is_valid_url("https://example.com"));
+        assert!(is_valid_url("http://server?device=esp"));
+    }
+
+    #[test]
+    fn other_urls_rejected() {
+        assert!(!is_valid_url("ftp://example.com/"));
+        assert!(!is_valid_url("example.com/hook"));
+        assert!(!is_valid_url("http:///hook"));
+        assert!(!is_valid_url("http://example.com/a b"));
+        assert!(!is_valid_url(""));
+    }
+}
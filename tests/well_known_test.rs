@@ -0,0 +1,35 @@
+//! Tests for the conventional files served to crawlers
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use picoserve::response::Content;
+
+    use esp32c3_embassy_picoserve::well_known::{SecurityTxt, ROBOTS_TXT};
+
+    #[test]
+    fn robots_disallow_everything() {
+        assert!(ROBOTS_TXT.lines().any(|line| line == "Disallow: /"));
+    }
+
+    #[test]
+    fn security_txt_length_counts_lines() {
+        let file = SecurityTxt {
+            contact: "mailto:a@b.c",
+            expires: None,
+        };
+        assert_eq!(file.content_length(), "Contact: mailto:a@b.c\n".len());
+
+        let file = SecurityTxt {
+            expires: Some("2027-01-01T00:00:00Z"),
+            ..file
+        };
+        assert_eq!(
+            file.content_length(),
+            "Contact: mailto:a@b.c\nExpires: 2027-01-01T00:00:00Z\n".len()
+        );
+    }
+}
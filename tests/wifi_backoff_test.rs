@@ -0,0 +1,43 @@
+//! Tests for the delays between attempts to reconnect to Wi-Fi
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use embassy_time::Duration;
+    use esp32c3_embassy_picoserve::wifi::{jittered, Backoff, MAX_RECONNECT_DELAY, MIN_RECONNECT_DELAY};
+
+    #[test]
+    fn delay_doubles() {
+        let mut backoff = Backoff::new();
+        for seconds in [1, 2, 4, 8, 16, 32, 64, 128, 256] {
+            assert_eq!(backoff.delay(), Duration::from_secs(seconds));
+            backoff = backoff.next();
+        }
+        assert_eq!(backoff.attempts(), 9);
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        assert_eq!(Backoff::after(9).delay(), MAX_RECONNECT_DELAY);
+        assert_eq!(Backoff::after(30).delay(), MAX_RECONNECT_DELAY);
+        assert_eq!(Backoff::after(u32::MAX).next().delay(), MAX_RECONNECT_DELAY);
+    }
+
+    #[test]
+    fn new_starts_from_the_minimum() {
+        assert_eq!(Backoff::new().delay(), MIN_RECONNECT_DELAY);
+        assert_eq!(Backoff::default(), Backoff::new());
+    }
+
+    #[test]
+    fn jitter_stays_in_the_upper_half() {
+        let delay = Duration::from_secs(8);
+        assert_eq!(jittered(delay, 0), delay);
+        assert_eq!(jittered(delay, 1 << 31), Duration::from_secs(6));
+        let shortest = jittered(delay, u32::MAX);
+        assert!(shortest > Duration::from_secs(4) && shortest < Duration::from_millis(4001));
+    }
+}
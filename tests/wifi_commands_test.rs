@@ -0,0 +1,39 @@
+//! Tests for the commands to the connection task and their routes
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::web::{ADMIN_PATHS, BASIC_AUTH_PREFIXES, HANDLER_TIMEOUTS};
+    use esp32c3_embassy_picoserve::wifi::{reassociate_to, CommandError, COMMAND_TIMEOUT};
+
+    const COMMAND_PATHS: [&str; 2] = ["/wifi/disconnect", "/wifi/reconnect"];
+
+    #[test]
+    async fn unstorable_ssid_cannot_be_joined() {
+        let ssid = "abcdefghijklmnopqrstuvwxyz-012345";
+        assert_eq!(reassociate_to(ssid).await, Err(CommandError::OtherNetwork));
+    }
+
+    #[test]
+    fn command_routes_are_protected() {
+        for path in COMMAND_PATHS {
+            assert!(ADMIN_PATHS.contains(&path), "{path}");
+            assert!(BASIC_AUTH_PREFIXES.contains(&path), "{path}");
+        }
+    }
+
+    #[test]
+    fn command_routes_outlast_the_transition() {
+        for path in COMMAND_PATHS {
+            let timeout = HANDLER_TIMEOUTS
+                .iter()
+                .find(|(prefix, _)| *prefix == path)
+                .and_then(|(_, timeout)| *timeout)
+                .unwrap();
+            assert!(timeout > COMMAND_TIMEOUT, "{path}");
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Tests for the stored Wi-Fi networks and the order they are tried in
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::provisioning::{
+        NetworksError, StoredNetworks, WifiCredentials, MAX_STORED_NETWORKS,
+    };
+    use esp32c3_embassy_picoserve::wifi::{connection_order, ScanResult, ScanResults, WifiConfig};
+
+    fn network(ssid: &str) -> WifiCredentials {
+        WifiCredentials::new(ssid, "correct horse").unwrap()
+    }
+
+    fn networks(ssids: &[&str]) -> StoredNetworks {
+        let mut networks = StoredNetworks::new();
+        for ssid in ssids {
+            networks.add(network(ssid)).unwrap();
+        }
+        networks
+    }
+
+    fn ssids(networks: &StoredNetworks) -> heapless::Vec<&str, MAX_STORED_NETWORKS> {
+        networks.as_slice().iter().map(|network| network.ssid.as_str()).collect()
+    }
+
+    fn scan(ssids: &[&str]) -> ScanResults {
+        ssids
+            .iter()
+            .map(|ssid| ScanResult {
+                ssid: (*ssid).try_into().unwrap(),
+                rssi: -50,
+                channel: 6,
+                auth_mode: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn networks_added_last() {
+        let mut networks = networks(&["home", "workshop"]);
+        assert_eq!(networks.add(network("cafe")).unwrap(), 2);
+        assert_eq!(ssids(&networks).as_slice(), &["home", "workshop", "cafe"]);
+
+        // Same SSID, new password, same priority
+        let updated = WifiCredentials::new("home", "").unwrap();
+        assert_eq!(networks.add(updated.clone()).unwrap(), 0);
+        assert_eq!(networks.as_slice()[0], updated);
+        assert_eq!(networks.len(), 3);
+
+        networks.add(network("office")).unwrap();
+        assert!(matches!(networks.add(network("garden")), Err(NetworksError::Full)));
+    }
+
+    #[test]
+    fn provisioned_network_goes_first() {
+        let mut networks = networks(&["home", "workshop"]);
+        networks.add_first(network("workshop"));
+        assert_eq!(ssids(&networks).as_slice(), &["workshop", "home"]);
+
+        // A full list drops its lowest priority network
+        let mut networks = self::networks(&["a", "b", "c", "d"]);
+        networks.add_first(network("e"));
+        assert_eq!(ssids(&networks).as_slice(), &["e", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn networks_removed() {
+        let mut networks = networks(&["home", "workshop"]);
+        assert!(matches!(networks.remove(2), Err(NetworksError::NotFound)));
+        assert_eq!(networks.remove(0).unwrap().ssid, "home");
+        assert!(matches!(networks.remove(0), Err(NetworksError::LastNetwork)));
+        assert_eq!(ssids(&networks).as_slice(), &["workshop"]);
+    }
+
+    #[test]
+    fn networks_reordered() {
+        let mut networks = networks(&["home", "workshop", "cafe"]);
+        networks.reorder(["cafe", "home", "workshop"]).unwrap();
+        assert_eq!(ssids(&networks).as_slice(), &["cafe", "home", "workshop"]);
+
+        for order in [
+            &["cafe", "home"][..],
+            &["cafe", "home", "home"],
+            &["cafe", "home", "office"],
+            &["cafe", "home", "workshop", "workshop"],
+        ] {
+            assert!(matches!(networks.reorder(order.iter().copied()), Err(NetworksError::InvalidOrder)));
+        }
+        assert_eq!(ssids(&networks).as_slice(), &["cafe", "home", "workshop"]);
+    }
+
+    #[test]
+    fn last_network_tried_first() {
+        let networks = networks(&["home", "workshop", "cafe"]);
+        assert_eq!(connection_order(&networks, None, None).as_slice(), &[0, 1, 2]);
+        assert_eq!(connection_order(&networks, Some("cafe"), None).as_slice(), &[2, 0, 1]);
+        // A network removed since
+        assert_eq!(connection_order(&networks, Some("office"), None).as_slice(), &[0, 1, 2]);
+    }
+
+    #[test]
+    fn networks_not_found_skipped() {
+        let networks = networks(&["home", "workshop", "cafe"]);
+        let visible = scan(&["neighbour", "cafe", "workshop"]);
+        assert_eq!(connection_order(&networks, None, Some(&visible)).as_slice(), &[1, 2]);
+        assert_eq!(connection_order(&networks, Some("cafe"), Some(&visible)).as_slice(), &[2, 1]);
+
+        // None found, maybe hidden: all are tried
+        let visible = scan(&["neighbour"]);
+        assert_eq!(connection_order(&networks, Some("cafe"), Some(&visible)).as_slice(), &[2, 0, 1]);
+    }
+
+    #[test]
+    fn networks_not_stored_retry_forever() {
+        let config = WifiConfig::with_networks(networks(&["home"]));
+        assert_eq!(config.max_failures, None);
+        assert_eq!(config.provisioning_timeout, None);
+    }
+}
@@ -0,0 +1,35 @@
+//! Tests for the power saving of the station and its latency
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use embassy_time::Duration;
+    use esp32c3_embassy_picoserve::wifi::{
+        power_save, PowerSave, WifiConfig, BEACON_INTERVAL, LISTEN_INTERVAL,
+    };
+
+    #[test]
+    fn radio_stays_on_by_default() {
+        assert_eq!(PowerSave::default(), PowerSave::None);
+        assert_eq!(WifiConfig::provisioning().power_save, PowerSave::None);
+        assert_eq!(power_save(), PowerSave::None);
+    }
+
+    #[test]
+    fn first_packet_delay_grows_with_sleep() {
+        assert_eq!(PowerSave::None.max_wake_delay(), Duration::from_ticks(0));
+        assert_eq!(PowerSave::Minimum.max_wake_delay(), BEACON_INTERVAL);
+        assert_eq!(LISTEN_INTERVAL, 3);
+        assert_eq!(PowerSave::Maximum.max_wake_delay().as_micros(), 307_200);
+    }
+
+    #[test]
+    fn mode_reported_in_snake_case() {
+        let mut buffer = [0; 16];
+        let length = serde_json_core::to_slice(&PowerSave::Minimum, &mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"\"minimum\"");
+    }
+}
@@ -0,0 +1,71 @@
+//! Tests for the networks kept from a Wi-Fi scan
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::web::BASIC_AUTH_PREFIXES;
+    use esp32c3_embassy_picoserve::wifi::{strongest_networks, AuthMode, ScanResult, MAX_SCAN_RESULTS};
+
+    fn network(ssid: &str, rssi: i8, channel: u8) -> ScanResult {
+        ScanResult {
+            ssid: ssid.try_into().unwrap(),
+            rssi,
+            channel,
+            auth_mode: Some(AuthMode::Wpa2),
+        }
+    }
+
+    #[test]
+    fn sorted_by_rssi() {
+        let networks = strongest_networks([network("b", -70, 1), network("a", -40, 6), network("c", -90, 11)]);
+        let rssi: heapless::Vec<i8, 3> = networks.iter().map(|network| network.rssi).collect();
+        assert_eq!(rssi, [-40, -70, -90]);
+    }
+
+    #[test]
+    fn duplicates_keep_the_strongest() {
+        let networks = strongest_networks([
+            network("home", -80, 1),
+            network("home", -50, 11),
+            network("home", -65, 6),
+            network("other", -60, 6),
+        ]);
+        assert_eq!(networks.len(), 2);
+        assert_eq!(networks[0], network("home", -50, 11));
+        assert_eq!(networks[1], network("other", -60, 6));
+    }
+
+    #[test]
+    fn hidden_networks_are_skipped() {
+        let networks = strongest_networks([network("", -30, 1), network("home", -50, 1)]);
+        assert_eq!(networks.len(), 1);
+        assert_eq!(networks[0].ssid, "home");
+    }
+
+    #[test]
+    fn strongest_are_kept_when_full() {
+        const NAMES: [&str; MAX_SCAN_RESULTS + 2] = [
+            "n00", "n01", "n02", "n03", "n04", "n05", "n06", "n07", "n08", "n09", "n10", "n11", "n12", "n13", "n14",
+            "n15", "n16", "n17",
+        ];
+        // The weakest come first, so they are replaced
+        let networks = strongest_networks(
+            NAMES
+                .iter()
+                .enumerate()
+                .map(|(index, name)| network(name, -90 + index as i8, 1)),
+        );
+        assert_eq!(networks.len(), MAX_SCAN_RESULTS);
+        assert_eq!(networks[0].ssid, "n17");
+        assert_eq!(networks[MAX_SCAN_RESULTS - 1].ssid, "n02");
+        assert!(networks.iter().all(|network| network.ssid != "n00" && network.ssid != "n01"));
+    }
+
+    #[test]
+    fn scan_route_is_protected() {
+        assert!(BASIC_AUTH_PREFIXES.contains(&"/wifi/scan"));
+    }
+}
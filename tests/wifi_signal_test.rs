@@ -0,0 +1,58 @@
+//! Tests for the signal history and the low-signal events
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use esp32c3_embassy_picoserve::webhooks::Event;
+    use esp32c3_embassy_picoserve::wifi::{SignalMonitor, SignalThresholds, SIGNAL_HISTORY_SIZE};
+
+    fn monitor() -> SignalMonitor {
+        SignalMonitor::new(SignalThresholds::new(-80, -72).unwrap())
+    }
+
+    #[test]
+    fn thresholds_need_a_gap() {
+        assert_eq!(SignalThresholds::new(-80, -80), None);
+        assert_eq!(SignalThresholds::new(-70, -80), None);
+        assert_eq!(SignalThresholds::new(-80, -72), Some(SignalThresholds::DEFAULT));
+    }
+
+    #[test]
+    fn history_keeps_the_latest_samples() {
+        let monitor = monitor();
+        assert!(monitor.samples().is_empty());
+        for rssi in 0..SIGNAL_HISTORY_SIZE as i32 + 5 {
+            monitor.record(-40 - rssi);
+        }
+        let samples = monitor.samples();
+        assert_eq!(samples.len(), SIGNAL_HISTORY_SIZE);
+        assert_eq!(samples.first(), Some(&-45));
+        assert_eq!(samples.last(), Some(&(-44 - SIGNAL_HISTORY_SIZE as i32)));
+    }
+
+    #[test]
+    fn low_signal_reported_once() {
+        let monitor = monitor();
+        assert_eq!(monitor.record(-60), None);
+        assert_eq!(monitor.record(-80), None);
+        assert_eq!(monitor.record(-81), Some(Event::SignalLow { rssi: -81 }));
+        assert!(monitor.is_low());
+        assert_eq!(monitor.record(-90), None);
+    }
+
+    #[test]
+    fn recovery_needs_the_upper_threshold() {
+        let monitor = monitor();
+        monitor.record(-85);
+        // Hovering between the thresholds does not recover
+        assert_eq!(monitor.record(-76), None);
+        assert_eq!(monitor.record(-82), None);
+        assert_eq!(monitor.record(-73), None);
+        assert_eq!(monitor.record(-72), Some(Event::SignalRecovered { rssi: -72 }));
+        assert!(!monitor.is_low());
+        assert_eq!(monitor.record(-79), None);
+    }
+}
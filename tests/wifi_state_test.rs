@@ -0,0 +1,39 @@
+//! Tests for the published state of the Wi-Fi connection
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests]
+mod tests {
+    use embassy_net::{IpAddress, Ipv4Address};
+    use esp32c3_embassy_picoserve::wifi::{
+        link_losses, state, state_receiver, WifiState, MAX_STATE_RECEIVERS,
+    };
+
+    #[test]
+    fn disconnected_before_wifi_starts() {
+        assert_eq!(state(), WifiState::Disconnected);
+        let mut receiver = state_receiver().unwrap();
+        assert_eq!(receiver.try_get(), None);
+    }
+
+    #[test]
+    fn network_usable_with_an_address_only() {
+        let addr = IpAddress::Ipv4(Ipv4Address::new(192, 168, 1, 42));
+        assert!(WifiState::GotIp { addr }.has_ip());
+        for state in [WifiState::Disconnected, WifiState::Associating, WifiState::Associated, WifiState::ApMode] {
+            assert!(!state.has_ip());
+        }
+        assert_eq!(link_losses(), 0);
+    }
+
+    #[test]
+    fn receivers_freed_when_dropped() {
+        let receivers: heapless::Vec<_, MAX_STATE_RECEIVERS> =
+            (0..MAX_STATE_RECEIVERS).map(|_| state_receiver().unwrap()).collect();
+        assert!(state_receiver().is_none());
+        drop(receivers);
+        assert!(state_receiver().is_some());
+    }
+}